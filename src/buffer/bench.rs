@@ -0,0 +1,107 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+// Reference the main crate
+extern crate aistore;
+
+use aistore::buffer::{BufferMgr, BufferRing, ReplacementKind, SCAN_RING_SIZE};
+use aistore::vfs::LocalFs;
+
+const POOL_SIZE: usize = 256;
+const HOT_PAGES: u64 = 192;
+const TABLE_PAGES: u64 = 4096;
+const SCAN_START: u64 = 1 << 32;
+
+const POLICIES: [ReplacementKind; 4] = [
+    ReplacementKind::Lru,
+    ReplacementKind::ClockSweep,
+    ReplacementKind::LruK { k: 2 },
+    ReplacementKind::TwoQ,
+];
+
+// Pages are never written, so reads come back as empty pages and the
+// workload measures the pool rather than the disk
+fn buffer_mgr(kind: ReplacementKind, dir: &tempfile::TempDir) -> BufferMgr {
+    BufferMgr::init(
+        POOL_SIZE,
+        Arc::new(LocalFs::new()),
+        dir.path().to_path_buf(),
+    )
+    .with_replacement_policy(kind)
+}
+
+// OLTP lookups over a hot set that fits in the pool, with a full table
+// scan every `scan_every` lookups
+fn run_workload(mgr: &BufferMgr, lookups: usize, scan_every: usize, ring: bool) {
+    let mut rng = StdRng::seed_from_u64(42);
+    for i in 0..lookups {
+        // Skewed towards the start of the hot set
+        let block = (rng.gen_range(0.0f64..1.0).powi(2) * HOT_PAGES as f64) as u64;
+        black_box(mgr.read_page(block).unwrap().page_id());
+
+        if (i + 1) % scan_every == 0 {
+            let mut scan_ring = BufferRing::new(SCAN_RING_SIZE);
+            for block in 0..TABLE_PAGES {
+                let page_id = SCAN_START + block;
+                let page = if ring {
+                    mgr.read_page_with_ring(page_id, &mut scan_ring)
+                } else {
+                    mgr.read_page(page_id)
+                };
+                black_box(page.unwrap().page_id());
+            }
+        }
+    }
+}
+
+// Print the hit ratio each policy reaches on the lookups alone
+fn report_hit_ratios() {
+    for ring in [false, true] {
+        for kind in POLICIES {
+            let dir = tempfile::TempDir::new().unwrap();
+            let mgr = buffer_mgr(kind, &dir);
+            // Warm the hot set up, then measure from a clean slate
+            run_workload(&mgr, 20_000, usize::MAX, ring);
+            mgr.reset_stats();
+
+            run_workload(&mgr, 20_000, 2_000, ring);
+            let stats = mgr.stats();
+            let scanned = 10 * TABLE_PAGES;
+            let lookup_misses = stats.misses.saturating_sub(scanned);
+            println!(
+                "{:<12} ring={:<5} lookup hit ratio {:.3}, evictions {}",
+                mgr.replacement_policy(),
+                ring,
+                1.0 - lookup_misses as f64 / 20_000.0,
+                stats.evictions
+            );
+        }
+    }
+}
+
+pub fn bench_replacement_policies(c: &mut Criterion) {
+    report_hit_ratios();
+
+    let mut group = c.benchmark_group("MixedOltpAndScan");
+    group.sample_size(10);
+    for ring in [false, true] {
+        for kind in POLICIES {
+            let dir = tempfile::TempDir::new().unwrap();
+            let mgr = buffer_mgr(kind, &dir);
+            let name = format!(
+                "{}{}",
+                mgr.replacement_policy(),
+                if ring { "+ring" } else { "" }
+            );
+            group.bench_function(name, |b: &mut criterion::Bencher| {
+                b.iter(|| run_workload(&mgr, 5_000, 2_500, ring))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_replacement_policies);
+criterion_main!(benches);
@@ -1,25 +1,151 @@
 //! Double Write Buffer - prevents partial page writes
+//!
+//! Before a batch of pages is written in place, the whole batch is written
+//! to a dedicated file and fsynced. If the machine crashes halfway through
+//! an in-place write, the page on disk fails its checksum and its intact
+//! copy is still in the double-write file.
+//!
+//! File layout: block 0 is a header listing the buffer tags of the batch,
+//! followed by one `PAGE_SIZE` block per page image, in the same order.
+//! Each tag takes 24 bytes: relation, block, tablespace and fork.
+//!
+//! ```text
+//! +----------------------------------+---------+---------+-----
+//! | magic | count | tag[0..count] .. | image 0 | image 1 | ...
+//! +----------------------------------+---------+---------+-----
+//! ```
+//!
+//! The file only ever holds the latest batch; the caller finishes writing
+//! a batch in place (and fsyncs it) before writing the next one.
 
-use crate::types::PageId;
+use crate::buffer::{BufferError, BufferTag};
+use crate::page::Page;
+use crate::types::PAGE_SIZE;
+use crate::vfs::{VfsError, VfsInterface};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-/// DoubleWriteBuffer placeholder
+const DOUBLE_WRITE_MAGIC: u32 = 0x44574246; // "DWBF"
+const DOUBLE_WRITE_HEADER_SIZE: usize = 8;
+
+/// Largest batch the header block can describe
+pub const MAX_DOUBLE_WRITE_PAGES: usize =
+    (PAGE_SIZE - DOUBLE_WRITE_HEADER_SIZE) / BufferTag::ENCODED_SIZE;
+
+/// Double-write area backed by a single file
 pub struct DoubleWriteBuffer {
+    /// Maximum number of pages per batch
     capacity: usize,
+    /// Virtual File System interface for disk I/O
+    vfs: Arc<dyn VfsInterface>,
+    /// Path of the double-write file
+    path: PathBuf,
 }
 
 impl DoubleWriteBuffer {
-    /// Create a new doublewrite buffer
-    pub fn new(capacity: usize) -> Self {
-        Self { capacity }
+    /// Create a double-write buffer of `capacity` pages in the file at `path`
+    ///
+    /// The file is created on the first batch.
+    pub fn new(capacity: usize, vfs: Arc<dyn VfsInterface>, path: PathBuf) -> Self {
+        assert!(
+            capacity > 0 && capacity <= MAX_DOUBLE_WRITE_PAGES,
+            "double-write capacity must be between 1 and {}",
+            MAX_DOUBLE_WRITE_PAGES
+        );
+        Self {
+            capacity,
+            vfs,
+            path,
+        }
     }
 
-    /// Prepare a page for writing
-    pub fn prepare(&self, _page_id: PageId, _data: &[u8]) -> bool {
-        true
+    /// Maximum number of pages per batch
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
     }
 
-    /// Flush all to disk
-    pub fn flush_all(&self) -> Result<(), crate::buffer::BufferError> {
+    /// Writes a batch of page images to the double-write file and fsyncs it
+    ///
+    /// The images must already carry their checksums.
+    pub fn write_batch(&self, pages: &[(BufferTag, Page)]) -> Result<(), BufferError> {
+        assert!(pages.len() <= self.capacity, "double-write batch too large");
+        let path = self.path.to_str().unwrap();
+
+        let mut buf = vec![0u8; (pages.len() + 1) * PAGE_SIZE];
+        buf[0..4].copy_from_slice(&DOUBLE_WRITE_MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&(pages.len() as u32).to_le_bytes());
+        for (i, (tag, page)) in pages.iter().enumerate() {
+            let entry = DOUBLE_WRITE_HEADER_SIZE + i * BufferTag::ENCODED_SIZE;
+            tag.encode(&mut buf[entry..entry + BufferTag::ENCODED_SIZE]);
+            let image = (i + 1) * PAGE_SIZE;
+            buf[image..image + PAGE_SIZE].copy_from_slice(page.as_bytes());
+        }
+
+        match self.vfs.open_file(path) {
+            Ok(handle) => handle.close()?,
+            Err(_) => self.vfs.create_file(path)?.close()?,
+        }
+        let n = self.vfs.pwrite(path, &buf, 0)?;
+        if n != buf.len() {
+            return Err(BufferError::Io(format!(
+                "short write to double-write file: {} of {} bytes",
+                n,
+                buf.len()
+            )));
+        }
+        self.vfs.fsync(path)?;
+
         Ok(())
     }
+
+    /// Returns the intact page images of the last batch
+    ///
+    /// Images that fail their checksum (the batch itself was torn) are left
+    /// out; a missing file holds no pages.
+    pub fn pages(&self) -> Result<Vec<(BufferTag, Page)>, BufferError> {
+        let path = self.path.to_str().unwrap();
+
+        let mut header = [0u8; PAGE_SIZE];
+        let n = match self.vfs.pread(path, &mut header, 0) {
+            Ok(n) => n,
+            Err(VfsError::SystemError(errno, _)) if errno == libc::ENOENT => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        if n < PAGE_SIZE || magic != DOUBLE_WRITE_MAGIC {
+            return Ok(Vec::new());
+        }
+        let count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+
+        let mut pages = Vec::new();
+        for i in 0..count.min(MAX_DOUBLE_WRITE_PAGES) {
+            let entry = DOUBLE_WRITE_HEADER_SIZE + i * BufferTag::ENCODED_SIZE;
+            let Some(tag) = BufferTag::decode(&header[entry..entry + BufferTag::ENCODED_SIZE])
+            else {
+                continue;
+            };
+
+            let mut page = Page::new();
+            let offset = ((i + 1) * PAGE_SIZE) as u64;
+            if self.vfs.pread(path, page.as_bytes_mut(), offset)? < PAGE_SIZE {
+                break;
+            }
+            if !page.is_zeroed()
+                && page.header().checksum == page.compute_checksum(tag.checksum_id())
+            {
+                pages.push((tag, page));
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Returns the copy of a page held in the double-write area
+    pub fn read_page(&self, tag: &BufferTag) -> Option<Page> {
+        self.pages()
+            .ok()?
+            .into_iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, page)| page)
+    }
 }
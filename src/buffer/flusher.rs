@@ -1,13 +1,23 @@
 //! Buffer pool persistence - flush policies
+//!
+//! `PageFlusher` runs a background thread that writes dirty pages out of
+//! the buffer pool whenever its `FlushPolicy` says so. It only writes pages
+//! whose last change is covered by the durable part of the WAL; the rest
+//! wait for a later round. A round that fails leaves its error for the
+//! owner to pick up with `take_error`.
 
-use std::time::Duration;
+use crate::buffer::{BufferError, BufferMgr};
+use crate::infrastructure::worker::BackgroundWorker;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Flush policy strategy
 #[derive(Debug, Clone)]
 pub enum FlushPolicy {
     /// Flush based on dirty page ratio
     DirtyRatio { threshold: f64 },
-    /// Flush based on time interval  
+    /// Flush based on time interval
     Interval { duration: Duration },
     /// Flush based on number of dirty pages
     DirtyCount { count: usize },
@@ -21,15 +31,206 @@ impl Default for FlushPolicy {
     }
 }
 
-/// Background page flusher (placeholder)
-pub struct PageFlusher;
+impl FlushPolicy {
+    /// Whether a pool of `capacity` buffers with `dirty` dirty pages, last
+    /// flushed `since_flush` ago, should be flushed now
+    pub fn should_flush(&self, dirty: usize, capacity: usize, since_flush: Duration) -> bool {
+        if dirty == 0 {
+            return false;
+        }
+        match *self {
+            FlushPolicy::DirtyRatio { threshold } => {
+                dirty as f64 >= threshold * capacity.max(1) as f64
+            }
+            FlushPolicy::Interval { duration } => since_flush >= duration,
+            FlushPolicy::DirtyCount { count } => dirty >= count,
+            FlushPolicy::Manual => false,
+        }
+    }
+}
+
+/// Background page flusher
+///
+/// Every `interval` the flusher thread counts the dirty pages and, if the
+/// policy calls for it, writes those whose LSN is below the durable LSN
+/// reported by the caller. `FlushPolicy::Manual` starts no thread.
+pub struct PageFlusher {
+    policy: FlushPolicy,
+    interval: Duration,
+    /// Pages written by the flusher thread so far
+    pages_written: Arc<AtomicU64>,
+    worker: BackgroundWorker<BufferError>,
+}
 
 impl PageFlusher {
-    /// Create a new flusher
-    pub fn new(_policy: FlushPolicy, _interval: Duration) -> Self {
-        Self
+    /// Create a new flusher that checks `policy` every `interval`
+    pub fn new(policy: FlushPolicy, interval: Duration) -> Self {
+        Self {
+            policy,
+            interval,
+            pages_written: Arc::new(AtomicU64::new(0)),
+            worker: BackgroundWorker::new(),
+        }
+    }
+
+    /// Starts the flusher thread for `buffer_mgr`
+    ///
+    /// `durable_lsn` returns the end of the durable WAL as a raw LSN; pages
+    /// at or past it are left dirty. Restarts the thread if it is running.
+    pub fn start<F>(&mut self, buffer_mgr: Arc<BufferMgr>, durable_lsn: F)
+    where
+        F: Fn() -> u64 + Send + 'static,
+    {
+        self.stop();
+        if matches!(self.policy, FlushPolicy::Manual) {
+            return;
+        }
+
+        let policy = self.policy.clone();
+        let pages_written = Arc::clone(&self.pages_written);
+        let mut last_flush = Instant::now();
+        self.worker.start(self.interval, move || {
+            let dirty = buffer_mgr.get_dirty_pages();
+            if !policy.should_flush(dirty.len(), buffer_mgr.buffer_size(), last_flush.elapsed()) {
+                return Ok(());
+            }
+            last_flush = Instant::now();
+            let n = buffer_mgr.flush_pages(&dirty, durable_lsn())?;
+            pages_written.fetch_add(n as u64, Ordering::Relaxed);
+            Ok(())
+        });
+    }
+
+    /// Returns true while the flusher thread is running
+    pub fn is_running(&self) -> bool {
+        self.worker.is_running()
+    }
+
+    /// Returns the number of pages the flusher thread has written
+    pub fn pages_written(&self) -> u64 {
+        self.pages_written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the error of the last round that failed, if any, and
+    /// clears it
+    pub fn take_error(&self) -> Option<BufferError> {
+        self.worker.take_error()
     }
 
     /// Stop the flusher
-    pub fn stop(&mut self) {}
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferTag;
+    use crate::vfs::LocalFs;
+    use std::thread;
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_flush_policy_triggers() {
+        let second = Duration::from_secs(1);
+        let ratio = FlushPolicy::DirtyRatio { threshold: 0.25 };
+        assert!(!ratio.should_flush(1, 8, second));
+        assert!(ratio.should_flush(2, 8, Duration::ZERO));
+
+        let count = FlushPolicy::DirtyCount { count: 3 };
+        assert!(!count.should_flush(2, 8, second));
+        assert!(count.should_flush(3, 8, Duration::ZERO));
+
+        let interval = FlushPolicy::Interval { duration: second };
+        assert!(!interval.should_flush(8, 8, Duration::from_millis(10)));
+        assert!(interval.should_flush(1, 8, second));
+        assert!(!interval.should_flush(0, 8, second));
+
+        assert!(!FlushPolicy::Manual.should_flush(8, 8, second));
+    }
+
+    #[test]
+    fn test_flusher_obeys_wal_rule() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = Arc::new(BufferMgr::init(
+            8,
+            Arc::new(LocalFs::new()),
+            dir.path().to_path_buf(),
+        ));
+        // Pages changed by WAL records at LSN 10, 20, 30 and 40
+        for block in 0..4u64 {
+            let mut page = mgr.new_page(block).unwrap();
+            page.header_mut().set_lsn((block + 1) * 10);
+        }
+
+        let durable = Arc::new(AtomicU64::new(25));
+        let mut flusher = PageFlusher::new(
+            FlushPolicy::DirtyCount { count: 1 },
+            Duration::from_millis(5),
+        );
+        let durable_lsn = Arc::clone(&durable);
+        flusher.start(Arc::clone(&mgr), move || durable_lsn.load(Ordering::SeqCst));
+        assert!(flusher.is_running());
+
+        wait_until(|| flusher.pages_written() == 2);
+        let mut dirty: Vec<_> = mgr.get_dirty_pages().iter().map(|t| t.page_id()).collect();
+        dirty.sort();
+        assert_eq!(dirty, vec![2, 3]);
+
+        // Once the log catches up the rest follows
+        durable.store(u64::MAX, Ordering::SeqCst);
+        wait_until(|| mgr.get_dirty_pages().is_empty());
+        assert_eq!(flusher.pages_written(), 4);
+
+        flusher.stop();
+        assert!(!flusher.is_running());
+        mgr.new_page(4).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(mgr.get_dirty_pages(), vec![BufferTag::from_page_id(4)]);
+    }
+
+    #[test]
+    fn test_failed_round_leaves_its_error() {
+        let dir = tempfile::TempDir::new().unwrap();
+        // Page files cannot be created under a regular file
+        let data_dir = dir.path().join("not_a_dir");
+        std::fs::write(&data_dir, b"").unwrap();
+        let mgr = Arc::new(BufferMgr::init(8, Arc::new(LocalFs::new()), data_dir));
+        mgr.new_page(1).unwrap();
+
+        let mut flusher = PageFlusher::new(
+            FlushPolicy::DirtyCount { count: 1 },
+            Duration::from_millis(5),
+        );
+        assert!(flusher.take_error().is_none());
+        flusher.start(Arc::clone(&mgr), || u64::MAX);
+        let mut error = None;
+        wait_until(|| {
+            error = flusher.take_error();
+            error.is_some()
+        });
+        assert!(matches!(error, Some(BufferError::Io(_))), "{:?}", error);
+        assert_eq!(flusher.pages_written(), 0);
+    }
+
+    #[test]
+    fn test_manual_policy_starts_no_thread() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = Arc::new(BufferMgr::init(
+            8,
+            Arc::new(LocalFs::new()),
+            dir.path().to_path_buf(),
+        ));
+        let mut flusher = PageFlusher::new(FlushPolicy::Manual, Duration::from_millis(5));
+        flusher.start(mgr, || u64::MAX);
+        assert!(!flusher.is_running());
+    }
 }
@@ -200,6 +200,22 @@ where
         }
     }
 
+    /// Remove an item from whichever list holds it
+    ///
+    /// Returns false if the item is not tracked.
+    pub fn remove(&mut self, data: &T) -> bool {
+        for list in [&mut self.hot_list, &mut self.cold_list, &mut self.free_list] {
+            if list.iter().any(|node| &node.data == data) {
+                *list = std::mem::take(list)
+                    .into_iter()
+                    .filter(|node| &node.data != data)
+                    .collect();
+                return true;
+            }
+        }
+        false
+    }
+
     /// Evict the least recently used item from the LRU
     pub fn evict(&mut self) -> Option<Node<T>> {
         // First try to evict from free list
@@ -1,24 +1,34 @@
 //! Buffer Pool implementation for the storage engine
 //!
-//! Provides page caching, LRU-based replacement, dirty page tracking,
+//! Provides page caching, pluggable page replacement, dirty page tracking,
 //! and VFS-based disk I/O.
 
 pub mod double_write;
 pub mod flusher;
 pub mod lru;
+pub mod replacement;
+pub mod warmup;
 
 pub use double_write::DoubleWriteBuffer;
 pub use flusher::{FlushPolicy, PageFlusher};
+pub use replacement::{BufferRing, ReplacementKind, ReplacementPolicy, SCAN_RING_SIZE};
+pub use warmup::{BufferWarmup, WarmupConfig, WarmupFile, WARMUP_FILE_NAME};
 
-use crate::infrastructure::hash::fnv1a_hash;
+use crate::infrastructure::hash::hash_u64;
+use crate::infrastructure::hash_table::{MappingKey, MappingTable};
 use crate::page::Page;
-use crate::types::{PageId, PAGE_SIZE};
+use crate::tablespace::TablespaceManager;
+use crate::types::{
+    BlockId, ForkNumber, PageId, SegmentId, TablespaceId, DEFAULT_TABLESPACE_ID, PAGE_SIZE,
+};
 use crate::vfs::{VfsError, VfsInterface};
-use lru::LruManager;
+use parking_lot::Mutex;
 use std::alloc;
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::{fmt, mem};
 
 /// Invalid page ID constant
@@ -27,51 +37,164 @@ pub const INVALID_PAGE_ID: PageId = PageId::MAX;
 /// State bit layout (64-bit AtomicU64):
 ///
 /// Bits 0:       Dirty flag (1 = modified, needs write-back)
-/// Bits 1-7:     Reserved (future use)
+/// Bits 1:       Valid flag (1 = frame holds the page named by the tag)
+/// Bits 2-7:     Reserved (future use)
 /// Bits 8-63:    Pin count (reference count)
 ///
-/// +---+-------+-------------------------------------------------------+
-/// | D | RSVD  |              Pin Count (56 bits)                      |
-/// +---+-------+-------------------------------------------------------+
-///  0   1-7                         8-63
+/// +---+---+-------+---------------------------------------------------+
+/// | D | V | RSVD  |              Pin Count (56 bits)                  |
+/// +---+---+-------+---------------------------------------------------+
+///  0   1   2-7                       8-63
 
 const DIRTY_BIT: u64 = 1 << 0;
+const VALID_BIT: u64 = 1 << 1;
 const PIN_COUNT_SHIFT: u8 = 8;
 
-/// BufferTag encapsulates a PageId for buffer identification
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Number of partitions the buffer hash table is split into
+///
+/// Each partition has its own lock, so lookups of pages that hash to
+/// different partitions never wait for each other.
+pub const NUM_BUFFER_PARTITIONS: usize = 16;
+
+/// Pages `flush_all` copies and writes at a time without a double-write area
+const FLUSH_BATCH_PAGES: usize = 64;
+
+/// Times a buffer claim waits a millisecond for the WAL to become durable
+/// when every evictable page is ahead of it
+const WAL_WAIT_ATTEMPTS: usize = 1000;
+
+/// Returns the end of the durable WAL as a raw LSN
+pub type DurableLsnFn = dyn Fn() -> u64 + Send + Sync;
+
+/// Outcome of trying to evict the page in a buffer
+enum Eviction {
+    /// The buffer is pinned exclusively and unmapped
+    Evicted(usize),
+    /// Someone is using the buffer
+    InUse,
+    /// The page is dirty and its last change is not in the durable WAL
+    NotLogged,
+}
+
+/// BufferTag names the disk block a buffer holds
+///
+/// A block is identified by its tablespace, relation (segment), fork and
+/// block number, so the same block number in two relations, or in two forks
+/// of one relation, never maps to the same buffer or file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BufferTag {
-    /// The PageId this buffer contains
-    pub page_id: PageId,
+    /// Tablespace holding the relation
+    pub tablespace_id: TablespaceId,
+    /// Fork of the relation
+    pub fork: ForkNumber,
+    /// Relation (segment) the block belongs to
+    pub relation_id: SegmentId,
+    /// Block number within the fork
+    pub block: BlockId,
 }
 
 impl BufferTag {
-    /// Creates a new BufferTag from a PageId
+    /// Bytes `encode` writes: relation, block, tablespace and fork
+    pub const ENCODED_SIZE: usize = 24;
+
+    /// Creates a new BufferTag
+    #[inline]
+    pub fn new(
+        tablespace_id: TablespaceId,
+        relation_id: SegmentId,
+        fork: ForkNumber,
+        block: BlockId,
+    ) -> Self {
+        Self {
+            tablespace_id,
+            fork,
+            relation_id,
+            block,
+        }
+    }
+
+    /// Tag of a main fork page in the default tablespace
+    ///
+    /// The high 32 bits of `page_id` are the relation and the low 32 bits
+    /// the block, as in `page_id()`.
+    #[inline]
+    pub fn from_page_id(page_id: PageId) -> Self {
+        Self::new(
+            DEFAULT_TABLESPACE_ID,
+            page_id >> 32,
+            ForkNumber::Main,
+            page_id & 0xFFFFFFFF,
+        )
+    }
+
+    /// Tag of no page, held by buffers that are not in use
+    #[inline]
+    pub fn invalid() -> Self {
+        Self::from_page_id(INVALID_PAGE_ID)
+    }
+
+    /// Returns false for `BufferTag::invalid()`
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        *self != Self::invalid()
+    }
+
+    /// Relation and block packed into a PageId
+    ///
+    /// Unique within one fork of one tablespace only.
     #[inline]
-    pub fn new(page_id: PageId) -> Self {
-        Self { page_id }
+    pub fn page_id(&self) -> PageId {
+        (self.relation_id << 32) | (self.block & 0xFFFFFFFF)
+    }
+
+    /// Id the page checksum is computed with
+    ///
+    /// Equals `page_id()` for the main fork in the default tablespace, and
+    /// mixes the tablespace and fork in otherwise, so a block written to
+    /// the file of another fork or tablespace fails its checksum.
+    #[inline]
+    pub fn checksum_id(&self) -> u64 {
+        let space = ((self.tablespace_id as u64) << 8) | self.fork as u64;
+        self.page_id() ^ hash_u64(space)
+    }
+
+    /// Writes the tag to the first `ENCODED_SIZE` bytes of `buf`
+    pub fn encode(&self, buf: &mut [u8]) {
+        buf[0..8].copy_from_slice(&self.relation_id.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.block.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.tablespace_id.to_le_bytes());
+        buf[20..24].copy_from_slice(&(self.fork as u32).to_le_bytes());
+    }
+
+    /// Reads a tag written by `encode`; `None` for an unknown fork
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let fork = u32::from_le_bytes(buf[20..24].try_into().unwrap());
+        Some(Self::new(
+            u32::from_le_bytes(buf[16..20].try_into().unwrap()),
+            u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            ForkNumber::from_u8(u8::try_from(fork).ok()?)?,
+            u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+        ))
     }
 }
 
-/// Hash table entry for the buffer hash table
-struct HashEntry {
-    /// The PageId this entry maps
-    tag: BufferTag,
-    /// Index into buffers[] and page_data[]
-    buffer_idx: usize,
-    /// Next entry in the chain (null if end of list)
-    next: *mut HashEntry,
+impl fmt::Display for BufferTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}{}/{}",
+            self.tablespace_id,
+            self.relation_id,
+            self.fork.file_suffix(),
+            self.block
+        )
+    }
 }
 
-impl HashEntry {
-    /// Create a new HashEntry
+impl MappingKey for BufferTag {
     #[inline]
-    fn new(tag: BufferTag, buffer_idx: usize) -> Self {
-        Self {
-            tag,
-            buffer_idx,
-            next: std::ptr::null_mut(),
-        }
+    fn hash_key(&self) -> u64 {
+        hash_u64(self.checksum_id())
     }
 }
 
@@ -80,13 +203,14 @@ impl HashEntry {
 #[cfg_attr(any(target_arch = "x86", target_arch = "x86_64"), repr(align(64)))]
 #[cfg_attr(any(target_arch = "arm", target_arch = "aarch64"), repr(align(128)))]
 pub struct BufferDesc {
-    /// Buffer tag
-    pub buf_tag: BufferTag,
-    /// 64-bit atomic state variable (dirty bit + pin count)
+    /// Buffer tag; only changed by the thread that has the buffer pinned
+    /// exclusively while holding the tag's hash partition lock
+    buf_tag: Mutex<BufferTag>,
+    /// 64-bit atomic state variable (dirty bit + valid bit + pin count)
     state: AtomicU64,
-    /// Read-write lock for controlling concurrent I/O access
+    /// Held exclusively while the page is read from or written to disk
     pub io_in_progress_lock: std::sync::RwLock<()>,
-    /// Lock for content access (serializes modifications)
+    /// Per-page content latch, shared for readers and exclusive for writers
     pub content_lock: std::sync::RwLock<()>,
 }
 
@@ -95,13 +219,24 @@ impl BufferDesc {
     #[inline]
     fn new() -> Self {
         Self {
-            buf_tag: BufferTag::new(INVALID_PAGE_ID),
+            buf_tag: Mutex::new(BufferTag::invalid()),
             state: AtomicU64::new(0),
             io_in_progress_lock: std::sync::RwLock::new(()),
             content_lock: std::sync::RwLock::new(()),
         }
     }
 
+    /// Returns the tag of the page held in this buffer
+    #[inline]
+    pub fn tag(&self) -> BufferTag {
+        *self.buf_tag.lock()
+    }
+
+    #[inline]
+    fn set_tag(&self, tag: BufferTag) {
+        *self.buf_tag.lock() = tag;
+    }
+
     /// Returns true if the buffer contains uncommitted modifications
     #[inline]
     pub fn is_dirty(&self) -> bool {
@@ -120,6 +255,22 @@ impl BufferDesc {
         self.state.fetch_and(!DIRTY_BIT, Ordering::Relaxed);
     }
 
+    /// Returns true once the page has been read in (or initialized)
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.state.load(Ordering::Acquire) & VALID_BIT != 0
+    }
+
+    #[inline]
+    fn set_valid(&self) {
+        self.state.fetch_or(VALID_BIT, Ordering::Release);
+    }
+
+    #[inline]
+    fn clear_valid(&self) {
+        self.state.fetch_and(!VALID_BIT, Ordering::Release);
+    }
+
     /// Increments pin count and returns new count
     #[inline]
     pub fn pin(&self) -> u32 {
@@ -142,6 +293,25 @@ impl BufferDesc {
         }
     }
 
+    /// Pins the buffer only if nobody else has it pinned
+    ///
+    /// A buffer pinned this way can be retagged: no other thread can reach
+    /// it until it is published in the hash table again.
+    #[inline]
+    fn try_pin_exclusive(&self) -> bool {
+        let old_state = self.state.load(Ordering::Acquire);
+        old_state >> PIN_COUNT_SHIFT == 0
+            && self
+                .state
+                .compare_exchange(
+                    old_state,
+                    old_state + (1 << PIN_COUNT_SHIFT),
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+    }
+
     /// Decrements pin count and returns new count
     #[inline]
     pub fn unpin(&self) -> u32 {
@@ -188,6 +358,16 @@ pub enum BufferError {
     PagePinned(PageId),
     /// Invalid page ID
     InvalidPageId(PageId),
+    /// Page read from disk does not match its checksum
+    Corrupted {
+        page_id: PageId,
+        /// Checksum stored in the page header
+        expected: u32,
+        /// Checksum computed over the page as read
+        actual: u32,
+    },
+    /// Reading or writing a page file failed
+    Io(String),
 }
 
 impl fmt::Display for BufferError {
@@ -197,6 +377,16 @@ impl fmt::Display for BufferError {
             BufferError::BufferPoolFull => write!(f, "Buffer pool is full"),
             BufferError::PagePinned(id) => write!(f, "Page {} is pinned", id),
             BufferError::InvalidPageId(id) => write!(f, "Invalid page ID: {}", id),
+            BufferError::Corrupted {
+                page_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Page {} is corrupted: checksum {:#010x}, computed {:#010x}",
+                page_id, expected, actual
+            ),
+            BufferError::Io(msg) => write!(f, "Page I/O error: {}", msg),
         }
     }
 }
@@ -204,30 +394,243 @@ impl fmt::Display for BufferError {
 impl std::error::Error for BufferError {}
 
 impl From<VfsError> for BufferError {
-    fn from(_err: VfsError) -> Self {
-        BufferError::PageNotFound(0) // VFS errors treated as page not found
+    fn from(err: VfsError) -> Self {
+        BufferError::Io(err.to_string())
+    }
+}
+
+/// What to do with a page that fails its checksum when read from disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BadPagePolicy {
+    /// Report `BufferError::Corrupted` to the caller
+    #[default]
+    Fail,
+    /// Restore the page from its copy in the double-write area and write
+    /// it back in place; fail if there is no intact copy
+    RepairFromDoubleWrite,
+}
+
+/// Buffer pool hit, miss and eviction counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BufferStats {
+    /// Page reads served from the pool
+    pub hits: u64,
+    /// Page reads that had to go to disk
+    pub misses: u64,
+    /// Pages taken out of the pool to make room for others
+    pub evictions: u64,
+    /// Pages that failed their checksum and were restored from their
+    /// double-write copy, at startup or when read
+    pub repaired: u64,
+}
+
+impl BufferStats {
+    /// Fraction of page reads served from the pool
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Checks the checksum of a page read from `tag`
+///
+/// A block that was never written is all zeroes and has no checksum yet.
+fn verify_page(tag: &BufferTag, page: &Page) -> Result<(), BufferError> {
+    let expected = page.header().checksum;
+    let actual = page.compute_checksum(tag.checksum_id());
+    if expected == actual || page.is_zeroed() {
+        Ok(())
+    } else {
+        Err(BufferError::Corrupted {
+            page_id: tag.page_id(),
+            expected,
+            actual,
+        })
+    }
+}
+
+/// Shared access to a page in the buffer pool
+///
+/// Holds a pin and the page's content latch in shared mode; both are
+/// released when the guard is dropped. Derefs to the page.
+pub struct ReadPageGuard<'a> {
+    mgr: &'a BufferMgr,
+    buffer_idx: usize,
+    tag: BufferTag,
+    latch: Option<std::sync::RwLockReadGuard<'a, ()>>,
+}
+
+impl<'a> ReadPageGuard<'a> {
+    /// Takes the content latch on a buffer the caller has already pinned
+    fn new(mgr: &'a BufferMgr, buffer_idx: usize, tag: BufferTag) -> Self {
+        let latch = mgr.desc(buffer_idx).content_lock.read().unwrap();
+        Self {
+            mgr,
+            buffer_idx,
+            tag,
+            latch: Some(latch),
+        }
+    }
+
+    /// Returns the id of the guarded page
+    #[inline]
+    pub fn page_id(&self) -> PageId {
+        self.tag.page_id()
+    }
+
+    /// Returns the tag of the guarded page
+    #[inline]
+    pub fn tag(&self) -> BufferTag {
+        self.tag
+    }
+}
+
+impl Deref for ReadPageGuard<'_> {
+    type Target = Page;
+
+    #[inline]
+    fn deref(&self) -> &Page {
+        // The shared latch keeps writers out while the guard lives
+        unsafe { &*self.mgr.frame(self.buffer_idx) }
+    }
+}
+
+impl Drop for ReadPageGuard<'_> {
+    fn drop(&mut self) {
+        self.latch.take();
+        self.mgr.desc(self.buffer_idx).unpin();
+    }
+}
+
+/// Exclusive access to a page in the buffer pool
+///
+/// Holds a pin and the page's content latch in exclusive mode. Writing
+/// through the guard marks the page dirty; the latch and pin are released
+/// when the guard is dropped.
+pub struct WritePageGuard<'a> {
+    mgr: &'a BufferMgr,
+    buffer_idx: usize,
+    tag: BufferTag,
+    modified: bool,
+    latch: Option<std::sync::RwLockWriteGuard<'a, ()>>,
+}
+
+impl<'a> WritePageGuard<'a> {
+    /// Takes the content latch on a buffer the caller has already pinned
+    fn new(mgr: &'a BufferMgr, buffer_idx: usize, tag: BufferTag) -> Self {
+        let latch = mgr.desc(buffer_idx).content_lock.write().unwrap();
+        Self {
+            mgr,
+            buffer_idx,
+            tag,
+            modified: false,
+            latch: Some(latch),
+        }
+    }
+
+    /// Returns the id of the guarded page
+    #[inline]
+    pub fn page_id(&self) -> PageId {
+        self.tag.page_id()
+    }
+
+    /// Returns the tag of the guarded page
+    #[inline]
+    pub fn tag(&self) -> BufferTag {
+        self.tag
+    }
+}
+
+impl Deref for WritePageGuard<'_> {
+    type Target = Page;
+
+    #[inline]
+    fn deref(&self) -> &Page {
+        unsafe { &*self.mgr.frame(self.buffer_idx) }
+    }
+}
+
+impl DerefMut for WritePageGuard<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Page {
+        // The exclusive latch makes this the only reference to the frame
+        self.modified = true;
+        unsafe { &mut *self.mgr.frame(self.buffer_idx) }
+    }
+}
+
+impl Drop for WritePageGuard<'_> {
+    fn drop(&mut self) {
+        let desc = self.mgr.desc(self.buffer_idx);
+        // Set before the latch is released so a concurrent flush that
+        // finds the page clean cannot miss this change
+        if self.modified {
+            desc.set_dirty();
+        }
+        self.latch.take();
+        desc.unpin();
     }
 }
 
 /// Buffer manager struct
+///
+/// All methods take `&self`; the pool is shared between threads as
+/// `Arc<BufferMgr>`. Pages are accessed through `ReadPageGuard` and
+/// `WritePageGuard`, which pin the buffer and hold its content latch.
+///
+/// The mapping table is split into `NUM_BUFFER_PARTITIONS` partitions,
+/// each guarding its bucket chains with its own lock. A buffer is only
+/// retagged by a thread that pinned it exclusively, under the partition
+/// lock of the tag it removes or adds, so a lookup that pins a buffer while
+/// holding the partition lock always gets the page it asked for.
 pub struct BufferMgr {
     /// Buffer pool size
     buffer_size: usize,
     /// Pointer to an array of BufferDesc structures
     buffers: *mut BufferDesc,
-    /// Buffer hash table, size is buffer_size
-    /// Each entry is a pointer to a linked list of HashEntry
-    buf_hash_table: *mut *mut HashEntry,
-    /// In-memory page data storage
-    page_data: Vec<Page>,
-    /// LRU manager tracking buffer access order (using buffer_idx as key)
-    lru: LruManager<usize>,
+    /// Maps buffer tags to buffer indexes, with one chain entry per buffer
+    /// allocated up front and a lock per partition
+    mapping: MappingTable<BufferTag>,
+    /// In-memory page frames, one Page per buffer
+    page_data: Vec<UnsafeCell<Page>>,
+    /// Buffers that have never held a page (or were released), used before evicting
+    free_buffers: Mutex<Vec<usize>>,
+    /// Replacement policy choosing which buffer to evict (keyed by buffer_idx)
+    replacement: Mutex<Box<dyn ReplacementPolicy>>,
+    /// Page reads served from the pool
+    hits: AtomicU64,
+    /// Page reads that went to disk
+    misses: AtomicU64,
+    /// Pages evicted to make room for others
+    evictions: AtomicU64,
+    /// Pages restored from the double-write area
+    repaired: AtomicU64,
     /// Virtual File System interface for disk I/O
     vfs: Arc<dyn VfsInterface>,
-    /// Base directory for page files
-    data_dir: PathBuf,
+    /// Maps a tag's tablespace, relation and fork to its page file under
+    /// the data directory
+    tablespaces: TablespaceManager,
+    /// How pages that fail their checksum are handled
+    bad_page_policy: BadPagePolicy,
+    /// Double-write area every page write goes through, and the source of
+    /// intact page copies for `BadPagePolicy::RepairFromDoubleWrite`
+    double_write: Option<DoubleWriteBuffer>,
+    /// Serializes writes to disk, so the double-write file only ever holds
+    /// the batch being written in place
+    write_lock: Mutex<()>,
+    /// End of the durable WAL; a dirty page at or past it is not evicted
+    durable_lsn: OnceLock<Box<DurableLsnFn>>,
 }
 
+// The raw descriptor array is only reached through the partition locks,
+// pins and content latches described above
+unsafe impl Send for BufferMgr {}
+unsafe impl Sync for BufferMgr {}
+
 impl BufferMgr {
     /// Creates a new BufferMgr with the specified buffer size
     ///
@@ -255,312 +658,724 @@ impl BufferMgr {
             ptr
         };
 
-        // Allocate hash table
-        let hash_table_size = mem::size_of::<*mut HashEntry>() * buffer_size;
-        let hash_table_align = mem::align_of::<*mut HashEntry>();
-        let hash_table_ptr = unsafe {
-            let ptr = alloc::alloc_zeroed(alloc::Layout::from_size_align_unchecked(
-                hash_table_size,
-                hash_table_align,
-            )) as *mut *mut HashEntry;
-
-            // Initialize each hash table entry to null pointer
-            for i in 0..buffer_size {
-                let entry_ptr = ptr.add(i);
-                std::ptr::write(entry_ptr, std::ptr::null_mut());
-            }
+        let mapping = MappingTable::new(buffer_size, NUM_BUFFER_PARTITIONS, BufferTag::invalid());
 
-            ptr
-        };
+        // Create page frame storage (Vec for safety)
+        let page_data = (0..buffer_size)
+            .map(|_| UnsafeCell::new(Page::new()))
+            .collect();
+        let free_buffers = (0..buffer_size).rev().collect();
 
-        // Create page data storage (Vec for safety)
-        let page_data = vec![Page::new(); buffer_size];
-
-        // LRU manager (hot=50%, cold=30%, free=20% of pool)
-        let hot_cap = buffer_size / 2;
-        let cold_cap = buffer_size * 3 / 10;
-        let free_cap = buffer_size / 5;
-        let lru = LruManager::new(hot_cap, cold_cap, free_cap);
+        let replacement = ReplacementKind::default().build(buffer_size);
 
         BufferMgr {
             buffer_size,
             buffers: buffers_ptr,
-            buf_hash_table: hash_table_ptr,
+            mapping,
             page_data,
-            lru,
+            free_buffers: Mutex::new(free_buffers),
+            replacement: Mutex::new(replacement),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            repaired: AtomicU64::new(0),
             vfs,
-            data_dir,
+            tablespaces: TablespaceManager::new(data_dir),
+            bad_page_policy: BadPagePolicy::Fail,
+            double_write: None,
+            write_lock: Mutex::new(()),
+            durable_lsn: OnceLock::new(),
         }
     }
 
-    /// Constructs the file path for a page
-    fn page_file_path(&self, page_id: PageId) -> PathBuf {
-        // High bits of page_id identify the file
-        let file_group = page_id >> 32;
-        self.data_dir.join(format!("page_{}.dat", file_group))
+    /// Sets how pages that fail their checksum on read are handled
+    pub fn with_bad_page_policy(mut self, policy: BadPagePolicy) -> Self {
+        self.bad_page_policy = policy;
+        self
+    }
+
+    /// Sets the double-write area pages are written through
+    pub fn with_double_write(mut self, double_write: DoubleWriteBuffer) -> Self {
+        self.double_write = Some(double_write);
+        self
+    }
+
+    /// Sets the policy that picks which page to evict
+    pub fn with_replacement_policy(mut self, kind: ReplacementKind) -> Self {
+        self.replacement = Mutex::new(kind.build(self.buffer_size));
+        self
+    }
+
+    /// Sets where eviction learns the end of the durable WAL
+    ///
+    /// From then on a dirty page is only written out to make room once
+    /// the WAL record of its last change is durable (the WAL rule); until
+    /// it is set, eviction writes any page. Only the first call has an
+    /// effect.
+    pub fn set_durable_lsn(&self, durable_lsn: impl Fn() -> u64 + Send + Sync + 'static) {
+        let _ = self.durable_lsn.set(Box::new(durable_lsn));
+    }
+
+    /// Returns how pages that fail their checksum on read are handled
+    #[inline]
+    pub fn bad_page_policy(&self) -> BadPagePolicy {
+        self.bad_page_policy
+    }
+
+    /// Returns the name of the replacement policy in use
+    pub fn replacement_policy(&self) -> &'static str {
+        self.replacement.lock().name()
+    }
+
+    /// Returns the hit, miss, eviction and repair counts since the pool
+    /// was created or the counters were last reset
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            repaired: self.repaired.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Resets the hit, miss, eviction and repair counters
+    pub fn reset_stats(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+        self.repaired.store(0, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn desc(&self, buffer_idx: usize) -> &BufferDesc {
+        unsafe { &*self.buffers.add(buffer_idx) }
+    }
+
+    #[inline]
+    fn frame(&self, buffer_idx: usize) -> *mut Page {
+        self.page_data[buffer_idx].get()
+    }
+
+    /// Constructs the file path for a page: one file per fork of each
+    /// relation, in the directory of its tablespace
+    fn page_file_path(&self, tag: &BufferTag) -> PathBuf {
+        self.tablespaces
+            .relation_path(tag.tablespace_id as u64, tag.relation_id, tag.fork)
     }
 
     /// Calculates the byte offset of a page within its file
-    fn page_offset(&self, page_id: PageId) -> u64 {
-        (page_id & 0xFFFFFFFF) as u64 * PAGE_SIZE as u64
+    fn page_offset(&self, tag: &BufferTag) -> u64 {
+        tag.block * PAGE_SIZE as u64
     }
 
-    /// Looks up a PageId in the hash table
+    /// Looks up a PageId in the mapping table
+    ///
+    /// Reads without taking the partition lock, so the buffer may hold
+    /// another page by the time the caller looks at it.
     ///
     /// # Returns
     /// * `Some(buffer_idx)` - Buffer index containing the page
     /// * `None` - Page not in buffer pool
     pub fn lookup(&self, page_id: PageId) -> Option<usize> {
-        unsafe {
-            // Hash the page_id as string
-            let page_id_str = page_id.to_string();
-            let hash = fnv1a_hash(&page_id_str);
-            let index = (hash as usize) % self.buffer_size;
-
-            // Traverse the chain
-            let mut entry_ptr = *self.buf_hash_table.add(index);
-            while !entry_ptr.is_null() {
-                let entry = &*entry_ptr;
-                if entry.tag.page_id == page_id {
-                    return Some(entry.buffer_idx);
-                }
-                entry_ptr = entry.next;
-            }
-        }
-        None
+        self.lookup_tag(&BufferTag::from_page_id(page_id))
     }
 
-    /// Inserts a page_id -> buffer_idx mapping
-    fn insert_hash_entry(&mut self, page_id: PageId, buffer_idx: usize) {
-        unsafe {
-            let page_id_str = page_id.to_string();
-            let hash = fnv1a_hash(&page_id_str);
-            let index = (hash as usize) % self.buffer_size;
-
-            // Allocate new entry
-            let entry_size = mem::size_of::<HashEntry>();
-            let entry_align = mem::align_of::<HashEntry>();
-            let new_entry_ptr = alloc::alloc(alloc::Layout::from_size_align_unchecked(
-                entry_size,
-                entry_align,
-            )) as *mut HashEntry;
-
-            // Initialize entry
-            std::ptr::write(
-                new_entry_ptr,
-                HashEntry::new(BufferTag::new(page_id), buffer_idx),
-            );
+    /// Looks up a tag in the mapping table, like `lookup`
+    pub fn lookup_tag(&self, tag: &BufferTag) -> Option<usize> {
+        self.mapping.get_optimistic(tag)
+    }
+
+    /// Reads the on-disk image of a page into `page`
+    ///
+    /// Bytes past the end of the file read as zeroes, so a block that was
+    /// allocated but never written comes back as an empty page; so does a
+    /// page file that was never written (its segment only exists in the
+    /// WAL so far).
+    fn read_image(&self, tag: &BufferTag, page: &mut Page) -> Result<(), BufferError> {
+        let file_path = self.page_file_path(tag);
+        let offset = self.page_offset(tag);
+
+        // Use VFS.pread to read at offset
+        let read_buf = page.as_bytes_mut();
+        let n = match self
+            .vfs
+            .pread(file_path.to_str().unwrap(), read_buf, offset)
+        {
+            Ok(n) => n,
+            Err(VfsError::SystemError(errno, _)) if errno == libc::ENOENT => 0,
+            Err(e) => return Err(e.into()),
+        };
+        read_buf[n..].fill(0);
+
+        Ok(())
+    }
 
-            // Insert at head
-            let head_ptr = self.buf_hash_table.add(index);
-            (*new_entry_ptr).next = *head_ptr;
-            *head_ptr = new_entry_ptr;
+    /// Reads a page from disk into the buffer pool and verifies its checksum
+    ///
+    /// The caller holds the buffer's I/O lock and the buffer is not valid
+    /// yet, so nobody else looks at the frame.
+    fn read_page_from_disk(&self, tag: &BufferTag, buffer_idx: usize) -> Result<(), BufferError> {
+        let page = unsafe { &mut *self.frame(buffer_idx) };
+        self.read_image(tag, page)?;
+
+        let err = match verify_page(tag, page) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if self.bad_page_policy == BadPagePolicy::Fail {
+            return Err(err);
+        }
+
+        let copy = self.double_write.as_ref().and_then(|dw| dw.read_page(tag));
+        match copy {
+            Some(copy) if verify_page(tag, &copy).is_ok() => {
+                *page = copy;
+                self.write_page_to_disk(tag, page)?;
+                self.repaired.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            _ => Err(err),
         }
     }
 
-    /// Removes a page_id from the hash table
-    fn remove_hash_entry(&mut self, page_id: PageId) {
-        unsafe {
-            let page_id_str = page_id.to_string();
-            let hash = fnv1a_hash(&page_id_str);
-            let index = (hash as usize) % self.buffer_size;
-
-            let mut entry_ptr = *self.buf_hash_table.add(index);
-            let mut prev_ptr: *mut HashEntry = std::ptr::null_mut();
-
-            while !entry_ptr.is_null() {
-                let entry = &*entry_ptr;
-                if entry.tag.page_id == page_id {
-                    let next = entry.next;
-                    if prev_ptr.is_null() {
-                        // Head of list
-                        *self.buf_hash_table.add(index) = next;
-                    } else {
-                        (*prev_ptr).next = next;
+    /// Writes a checksummed page image in place, creating the page file on
+    /// first use
+    fn write_image(&self, tag: &BufferTag, image: &Page) -> Result<(), BufferError> {
+        let file_path = self.page_file_path(tag);
+        let offset = self.page_offset(tag);
+        let path = file_path.to_str().unwrap();
+
+        match self.vfs.open_file(path) {
+            Ok(handle) => handle.close()?,
+            Err(_) => {
+                // Tablespaces other than the default get their directory
+                // when the first page is written to them
+                if tag.tablespace_id != DEFAULT_TABLESPACE_ID {
+                    let dir = self.tablespaces.relation_dir(tag.tablespace_id as u64);
+                    match self.vfs.create_dir(dir.to_str().unwrap()) {
+                        Ok(()) | Err(VfsError::AlreadyExists(_)) => {}
+                        Err(e) => return Err(e.into()),
                     }
-                    // Free the entry
-                    let entry_layout = alloc::Layout::new::<HashEntry>();
-                    alloc::dealloc(entry_ptr as *mut u8, entry_layout);
-                    return;
                 }
-                prev_ptr = entry_ptr;
-                entry_ptr = entry.next;
+                self.vfs.create_file(path)?.close()?
             }
         }
-    }
 
-    /// Reads a page from disk into the buffer pool
-    fn read_page_from_disk(
-        &mut self,
-        page_id: PageId,
-        buffer_idx: usize,
-    ) -> Result<(), BufferError> {
-        let file_path = self.page_file_path(page_id);
-        let offset = self.page_offset(page_id);
+        // Use VFS.pwrite to write at offset
+        let n = self.vfs.pwrite(path, image.as_bytes(), offset)?;
+        if n != PAGE_SIZE {
+            return Err(BufferError::Io(format!(
+                "short write of page {}: {} of {} bytes",
+                tag, n, PAGE_SIZE
+            )));
+        }
 
-        // Get exclusive access to the buffer
-        let buffer = unsafe { &mut *self.buffers.add(buffer_idx) };
-        let _io_guard = buffer.io_in_progress_lock.write().unwrap();
+        Ok(())
+    }
 
-        // Read raw bytes from VFS using pread
-        let page_ptr = &mut self.page_data[buffer_idx] as *mut Page as *mut u8;
-        let read_buf = unsafe { std::slice::from_raw_parts_mut(page_ptr, PAGE_SIZE) };
+    /// Writes checksummed page images to disk
+    ///
+    /// With a double-write area, each batch goes to the double-write file
+    /// first and is fsynced in place before the next batch replaces it.
+    /// The caller holds `write_lock`.
+    fn write_images(&self, images: &[(BufferTag, Page)]) -> Result<(), BufferError> {
+        let Some(dw) = &self.double_write else {
+            for (tag, image) in images {
+                self.write_image(tag, image)?;
+            }
+            return Ok(());
+        };
 
-        // Use VFS.pread to read at offset
-        self.vfs
-            .pread(file_path.to_str().unwrap(), read_buf, offset)?;
+        for batch in images.chunks(dw.capacity()) {
+            dw.write_batch(batch)?;
+            for (tag, image) in batch {
+                self.write_image(tag, image)?;
+            }
+            self.sync_page_files(batch.iter().map(|(tag, _)| tag))?;
+        }
+        Ok(())
+    }
 
+    /// Fsyncs the page files holding the given pages
+    fn sync_page_files<'t>(
+        &self,
+        tags: impl Iterator<Item = &'t BufferTag>,
+    ) -> Result<(), BufferError> {
+        let mut files: Vec<PathBuf> = tags.map(|tag| self.page_file_path(tag)).collect();
+        files.sort();
+        files.dedup();
+        for file in files {
+            self.vfs.fsync(file.to_str().unwrap())?;
+        }
         Ok(())
     }
 
     /// Writes a page from buffer to disk
-    fn write_page_to_disk(&self, page_id: PageId, page: &Page) -> Result<(), BufferError> {
-        let file_path = self.page_file_path(page_id);
-        let offset = self.page_offset(page_id);
-
-        // Use VFS.pwrite to write at offset
-        let page_ptr = page as *const Page as *const u8;
-        let write_buf = unsafe { std::slice::from_raw_parts(page_ptr, PAGE_SIZE) };
-
-        self.vfs
-            .pwrite(file_path.to_str().unwrap(), write_buf, offset)?;
-
-        Ok(())
+    ///
+    /// The checksum is computed on a copy: the caller may only hold the
+    /// frame's content latch in shared mode.
+    fn write_page_to_disk(&self, tag: &BufferTag, page: &Page) -> Result<(), BufferError> {
+        let mut image = page.clone();
+        image.update_checksum(tag.checksum_id());
+
+        let _writing = self.write_lock.lock();
+        self.write_images(&[(*tag, image)])
     }
 
-    /// Evicts a single page from the buffer pool
+    /// Writes a pinned buffer back to disk if it is dirty and its LSN is
+    /// below `durable_lsn`; returns false if it had to stay dirty
     ///
-    /// # Returns
-    /// * `Ok(Some(buffer_idx))` - Buffer index available for reuse
-    /// * `Ok(None)` - All buffers pinned, try again
-    fn evict_page(&mut self) -> Result<Option<usize>, BufferError> {
-        // Try to evict from LRU
-        if let Some(node) = self.lru.evict() {
-            let buffer_idx = node.data;
+    /// The shared content latch keeps writers out while the frame is copied
+    /// to disk, so clearing the dirty flag afterwards cannot lose a change.
+    /// It is taken before the I/O lock: a thread waiting for a write guard
+    /// never holds the I/O lock, so the write cannot stall behind it.
+    fn flush_buffer(&self, buffer_idx: usize, durable_lsn: u64) -> Result<bool, BufferError> {
+        let buffer = self.desc(buffer_idx);
+        let _content = buffer.content_lock.read().unwrap();
+        let _io_guard = buffer.io_in_progress_lock.write().unwrap();
+        if buffer.is_dirty() {
+            let page = unsafe { &*self.frame(buffer_idx) };
+            if page.header().glsn >= durable_lsn {
+                return Ok(false);
+            }
+            self.write_page_to_disk(&buffer.tag(), page)?;
+            buffer.clear_dirty();
+        }
+        Ok(true)
+    }
 
-            let buffer = unsafe { &mut *self.buffers.add(buffer_idx) };
+    /// Tries to take a buffer away from the page it holds
+    fn evict_page(&self, buffer_idx: usize) -> Result<Eviction, BufferError> {
+        let buffer = self.desc(buffer_idx);
+        if !buffer.try_pin_exclusive() {
+            return Ok(Eviction::InUse);
+        }
 
-            // Check if buffer is pinned
-            if !buffer.can_evict() {
-                // Can't evict pinned buffer, put it back
-                self.lru.add(buffer_idx);
-                return Ok(None);
-            }
+        let tag = buffer.tag();
+        if !tag.is_valid() {
+            return Ok(Eviction::Evicted(buffer_idx));
+        }
 
-            // Check if dirty and needs flush
-            if buffer.is_dirty() {
-                // Flush dirty page to disk
-                let page = &self.page_data[buffer_idx];
-                self.write_page_to_disk(buffer.buf_tag.page_id, page)?;
-                buffer.clear_dirty();
+        // Flush dirty page to disk while it is still mapped, so nobody can
+        // read the old copy back in the meantime
+        let durable_lsn = self.durable_lsn.get().map_or(u64::MAX, |f| f());
+        match self.flush_buffer(buffer_idx, durable_lsn) {
+            Ok(true) => {}
+            Ok(false) => {
+                buffer.unpin();
+                return Ok(Eviction::NotLogged);
             }
-
-            // Clear the hash table entry
-            if buffer.buf_tag.page_id != INVALID_PAGE_ID {
-                self.remove_hash_entry(buffer.buf_tag.page_id);
+            Err(e) => {
+                buffer.unpin();
+                return Err(e);
             }
+        }
 
-            // Reset buffer state
-            buffer.buf_tag = BufferTag::new(INVALID_PAGE_ID);
+        let mut partition = self.mapping.write_stripe(&tag);
+        // Someone may have found the page (and changed it) since
+        if buffer.pin_count() != 1 || buffer.is_dirty() {
+            buffer.unpin();
+            return Ok(Eviction::InUse);
+        }
+        partition.remove(&tag);
+        buffer.set_tag(BufferTag::invalid());
+        buffer.clear_valid();
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+
+        Ok(Eviction::Evicted(buffer_idx))
+    }
+
+    /// Finds a buffer for a new page, evicting one if necessary
+    ///
+    /// The returned buffer is pinned exclusively by the caller and not
+    /// mapped to any page.
+    fn claim_buffer(&self) -> Result<usize, BufferError> {
+        // Unused buffers are handed out before anything is evicted; one
+        // whose load just failed may still be pinned by a waiter, the sweep
+        // below picks it up later
+        loop {
+            let Some(buffer_idx) = self.free_buffers.lock().pop() else {
+                break;
+            };
+            if self.desc(buffer_idx).try_pin_exclusive() {
+                return Ok(buffer_idx);
+            }
+        }
 
-            return Ok(Some(buffer_idx));
+        // Pages ahead of the durable WAL become evictable once the group
+        // commit thread syncs the log, so wait for that a little
+        for _ in 0..WAL_WAIT_ATTEMPTS {
+            let mut not_logged = false;
+            if let Some(buffer_idx) = self.claim_victim(&mut not_logged)? {
+                return Ok(buffer_idx);
+            }
+            if !not_logged {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
         }
 
-        Ok(None)
+        Err(BufferError::BufferPoolFull)
     }
 
-    /// Allocates a buffer slot for the given page_id
-    fn allocate_buffer(&mut self, page_id: PageId) -> Result<usize, BufferError> {
-        // First, try to find an unpinned buffer
+    /// Evicts a page to free its buffer, setting `not_logged` if a page was
+    /// passed over because its WAL is not durable yet
+    fn claim_victim(&self, not_logged: &mut bool) -> Result<Option<usize>, BufferError> {
+        // Ask the replacement policy for an unpinned buffer
+        let unpinned = |buffer_idx: usize| self.desc(buffer_idx).pin_count() == 0;
         for _ in 0..self.buffer_size {
-            if let Some(buffer_idx) = self.evict_page()? {
-                // Initialize new buffer
-                unsafe {
-                    let buffer = &mut *self.buffers.add(buffer_idx);
-                    buffer.buf_tag = BufferTag::new(page_id);
-                    buffer.state.store(0, Ordering::Relaxed);
+            let Some(victim) = self.replacement.lock().victim(&unpinned) else {
+                break;
+            };
+            match self.evict_page(victim)? {
+                Eviction::Evicted(buffer_idx) => return Ok(Some(buffer_idx)),
+                // Pinned in the meantime, or not writable yet: put it back
+                eviction => {
+                    *not_logged |= matches!(eviction, Eviction::NotLogged);
+                    let tag = self.desc(victim).tag();
+                    if tag.is_valid() {
+                        self.replacement.lock().insert(victim, tag);
+                    }
                 }
-                return Ok(buffer_idx);
             }
         }
 
-        Err(BufferError::BufferPoolFull)
+        // The LRU lists drop entries on overflow; those pages are still
+        // resident, so fall back to a sweep for any unpinned buffer
+        for buffer_idx in 0..self.buffer_size {
+            match self.evict_page(buffer_idx)? {
+                Eviction::Evicted(buffer_idx) => {
+                    self.replacement.lock().remove(buffer_idx);
+                    return Ok(Some(buffer_idx));
+                }
+                Eviction::InUse => {}
+                Eviction::NotLogged => *not_logged = true,
+            }
+        }
+        Ok(None)
     }
 
-    /// Retrieves a page from the buffer pool
+    /// Finds a buffer for a page loaded through `ring`
     ///
-    /// # Arguments
-    /// * `page_id` - The PageId to retrieve
+    /// Once the ring is full its oldest buffer is reused, unless someone
+    /// pinned it or it was given to another page since.
+    fn claim_ring_buffer(&self, ring: &BufferRing) -> Result<usize, BufferError> {
+        let reusable = ring
+            .next_victim()
+            .filter(|&(victim, tag)| self.desc(victim).tag() == tag);
+        match reusable
+            .map(|(victim, _)| self.evict_page(victim))
+            .transpose()?
+        {
+            Some(Eviction::Evicted(buffer_idx)) => {
+                self.replacement.lock().remove(buffer_idx);
+                Ok(buffer_idx)
+            }
+            _ => self.claim_buffer(),
+        }
+    }
+
+    /// Finds or loads the buffer holding `tag` and pins it
     ///
-    /// # Returns
-    /// * `Ok(&mut Page)` - Mutable reference to the page
-    /// * `Err(BufferError)` - If page cannot be loaded
-    pub fn get_page(&mut self, page_id: PageId) -> Result<&mut Page, BufferError> {
-        // Try to find in hash table
-        if let Some(buffer_idx) = self.lookup(page_id) {
-            // HIT: Update LRU and return page
-            self.lru.access(&buffer_idx);
+    /// With `read` false a page that is not resident gets a buffer without
+    /// being read from disk; the caller initializes it. Pages loaded for a
+    /// `ring` recycle the ring's buffers, and hits through it leave the
+    /// replacement policy alone.
+    fn pin_buffer(
+        &self,
+        tag: BufferTag,
+        read: bool,
+        mut ring: Option<&mut BufferRing>,
+    ) -> Result<usize, BufferError> {
+        if !tag.is_valid() {
+            return Err(BufferError::InvalidPageId(tag.page_id()));
+        }
 
-            // Pin the buffer
-            let buffer = unsafe { &*self.buffers.add(buffer_idx) };
-            buffer.pin();
+        loop {
+            // Try to find in the mapping table; pinning under the partition
+            // lock keeps the buffer from being evicted in between
+            let hit = {
+                let partition = self.mapping.read_stripe(&tag);
+                let hit = partition.get(&tag);
+                if let Some(buffer_idx) = hit {
+                    self.desc(buffer_idx).pin();
+                }
+                hit
+            };
+
+            if let Some(buffer_idx) = hit {
+                let buffer = self.desc(buffer_idx);
+                // Wait for the thread reading the page in to finish
+                drop(buffer.io_in_progress_lock.read().unwrap());
+                if buffer.is_valid() {
+                    // HIT: Update the replacement policy
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    if ring.is_none() {
+                        self.replacement.lock().touch(buffer_idx);
+                    }
+                    return Ok(buffer_idx);
+                }
+                // The read failed and the mapping is gone again
+                buffer.unpin();
+                continue;
+            }
 
-            return Ok(&mut self.page_data[buffer_idx]);
+            // MISS: Need to load from disk
+            let buffer_idx = match ring.as_deref() {
+                Some(ring) => self.claim_ring_buffer(ring)?,
+                None => self.claim_buffer()?,
+            };
+            let buffer = self.desc(buffer_idx);
+            let io_guard = buffer.io_in_progress_lock.write().unwrap();
+            let raced = {
+                let mut partition = self.mapping.write_stripe(&tag);
+                let raced = partition.insert(tag, buffer_idx).is_err();
+                if !raced {
+                    buffer.set_tag(tag);
+                }
+                raced
+            };
+            if raced {
+                // Another thread loaded the page first; use its copy
+                drop(io_guard);
+                buffer.unpin();
+                self.free_buffers.lock().push(buffer_idx);
+                continue;
+            }
+
+            let loaded = if read {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.read_page_from_disk(&tag, buffer_idx)
+            } else {
+                unsafe { (*self.frame(buffer_idx)).as_bytes_mut().fill(0) };
+                Ok(())
+            };
+            if let Err(e) = loaded {
+                // Hand the buffer back so a failed read does not leak it
+                {
+                    let mut partition = self.mapping.write_stripe(&tag);
+                    partition.remove(&tag);
+                    buffer.set_tag(BufferTag::invalid());
+                }
+                drop(io_guard);
+                buffer.unpin();
+                self.free_buffers.lock().push(buffer_idx);
+                return Err(e);
+            }
+
+            buffer.set_valid();
+            drop(io_guard);
+            self.replacement.lock().insert(buffer_idx, tag);
+            if let Some(ring) = ring.as_deref_mut() {
+                ring.record(buffer_idx, tag);
+            }
+            return Ok(buffer_idx);
         }
+    }
 
-        // MISS: Need to load from disk
-        let buffer_idx = self.allocate_buffer(page_id)?;
-        self.read_page_from_disk(page_id, buffer_idx)?;
-        self.insert_hash_entry(page_id, buffer_idx);
-        self.lru.add(buffer_idx);
+    /// Pins a page and latches it for reading
+    ///
+    /// Any number of threads can hold read guards on the same page.
+    pub fn read_buffer(&self, tag: BufferTag) -> Result<ReadPageGuard<'_>, BufferError> {
+        let buffer_idx = self.pin_buffer(tag, true, None)?;
+        Ok(ReadPageGuard::new(self, buffer_idx, tag))
+    }
 
-        // Pin the buffer
-        let buffer = unsafe { &*self.buffers.add(buffer_idx) };
-        buffer.pin();
+    /// Pins a page for reading as part of a sequential scan
+    ///
+    /// Pages the scan has to load reuse the buffers of `ring` once it is
+    /// full, so a large scan does not push the rest of the pool out.
+    pub fn read_buffer_with_ring(
+        &self,
+        tag: BufferTag,
+        ring: &mut BufferRing,
+    ) -> Result<ReadPageGuard<'_>, BufferError> {
+        let buffer_idx = self.pin_buffer(tag, true, Some(ring))?;
+        Ok(ReadPageGuard::new(self, buffer_idx, tag))
+    }
 
-        Ok(&mut self.page_data[buffer_idx])
+    /// Pins a page and latches it for writing
+    ///
+    /// Blocks while other threads hold guards on the page. Must not be
+    /// called for a page the calling thread already holds a guard on.
+    pub fn write_buffer(&self, tag: BufferTag) -> Result<WritePageGuard<'_>, BufferError> {
+        let buffer_idx = self.pin_buffer(tag, true, None)?;
+        Ok(WritePageGuard::new(self, buffer_idx, tag))
     }
 
-    /// Marks a page as dirty (modified)
+    /// Installs a zeroed page in the buffer pool without reading it from disk
     ///
-    /// # Arguments
-    /// * `page_id` - The PageId to mark dirty
-    pub fn mark_dirty(&mut self, page_id: PageId) {
-        if let Some(buffer_idx) = self.lookup(page_id) {
-            let buffer = unsafe { &*self.buffers.add(buffer_idx) };
-            buffer.set_dirty();
-        }
+    /// Used when a caller extends a file with a freshly allocated block. The
+    /// returned page is latched for writing and already marked dirty.
+    pub fn new_buffer(&self, tag: BufferTag) -> Result<WritePageGuard<'_>, BufferError> {
+        let buffer_idx = self.pin_buffer(tag, false, None)?;
+        let mut guard = WritePageGuard::new(self, buffer_idx, tag);
+        guard.as_bytes_mut().fill(0);
+        Ok(guard)
+    }
+
+    /// `read_buffer` for a main fork page in the default tablespace
+    pub fn read_page(&self, page_id: PageId) -> Result<ReadPageGuard<'_>, BufferError> {
+        self.read_buffer(BufferTag::from_page_id(page_id))
+    }
+
+    /// `read_buffer_with_ring` for a main fork page in the default tablespace
+    pub fn read_page_with_ring(
+        &self,
+        page_id: PageId,
+        ring: &mut BufferRing,
+    ) -> Result<ReadPageGuard<'_>, BufferError> {
+        self.read_buffer_with_ring(BufferTag::from_page_id(page_id), ring)
+    }
+
+    /// `write_buffer` for a main fork page in the default tablespace
+    pub fn write_page(&self, page_id: PageId) -> Result<WritePageGuard<'_>, BufferError> {
+        self.write_buffer(BufferTag::from_page_id(page_id))
+    }
+
+    /// `new_buffer` for a main fork page in the default tablespace
+    pub fn new_page(&self, page_id: PageId) -> Result<WritePageGuard<'_>, BufferError> {
+        self.new_buffer(BufferTag::from_page_id(page_id))
     }
 
-    /// Releases a pin on a page
+    /// Flushes all dirty pages to disk
     ///
-    /// # Arguments
-    /// * `page_id` - The PageId to unpin
+    /// Dirty pages are copied in batches, each under the page's content
+    /// latch in shared mode, so the calling thread must not hold a write
+    /// guard. A batch stays pinned until it is written, and a page's dirty
+    /// flag is only cleared if nobody changed the page after it was copied.
+    pub fn flush_all(&self) -> Result<(), BufferError> {
+        let buffers = (0..self.buffer_size).map(|idx| (idx, self.desc(idx).tag()));
+        self.flush_buffers(buffers, u64::MAX).map(|_| ())
+    }
+
+    /// Flushes those of `tags` that are dirty and whose LSN is below
+    /// `durable_lsn`, returning how many were written
     ///
-    /// # Returns
-    /// * `Ok(())` - Successfully unpinned
-    /// * `Err(BufferError)` - Page not found
-    pub fn unpin_page(&mut self, page_id: PageId) -> Result<(), BufferError> {
-        if let Some(buffer_idx) = self.lookup(page_id) {
-            let buffer = unsafe { &*self.buffers.add(buffer_idx) };
-            buffer.unpin();
-            return Ok(());
+    /// Pages changed by WAL records that are not durable yet stay dirty, so
+    /// no page reaches disk ahead of its log (the WAL rule). Same latching
+    /// as `flush_all`.
+    pub fn flush_pages(&self, tags: &[BufferTag], durable_lsn: u64) -> Result<usize, BufferError> {
+        let buffers = tags
+            .iter()
+            .filter_map(|&tag| self.lookup_tag(&tag).map(|idx| (idx, tag)));
+        self.flush_buffers(buffers, durable_lsn)
+    }
+
+    /// Writes the dirty pages among `buffers` whose LSN is below
+    /// `durable_lsn`, in batches
+    fn flush_buffers(
+        &self,
+        buffers: impl Iterator<Item = (usize, BufferTag)>,
+        durable_lsn: u64,
+    ) -> Result<usize, BufferError> {
+        let batch_size = self
+            .double_write
+            .as_ref()
+            .map_or(FLUSH_BATCH_PAGES, |dw| dw.capacity());
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut images = Vec::with_capacity(batch_size);
+        let mut written = 0;
+
+        for (buffer_idx, tag) in buffers {
+            let buffer = self.desc(buffer_idx);
+            if !buffer.is_dirty() || !tag.is_valid() {
+                continue;
+            }
+
+            // Pin through the mapping table so the buffer keeps its page
+            {
+                let partition = self.mapping.read_stripe(&tag);
+                if partition.get(&tag) != Some(buffer_idx) {
+                    continue;
+                }
+                buffer.pin();
+            }
+
+            let image = {
+                let _content = buffer.content_lock.read().unwrap();
+                let page = unsafe { &*self.frame(buffer_idx) };
+                (page.header().glsn < durable_lsn).then(|| {
+                    let mut image = page.clone();
+                    image.update_checksum(tag.checksum_id());
+                    image
+                })
+            };
+            let Some(image) = image else {
+                buffer.unpin();
+                continue;
+            };
+            batch.push(buffer_idx);
+            images.push((tag, image));
+
+            if batch.len() == batch_size {
+                written += batch.len();
+                self.flush_batch(&mut batch, &mut images)?;
+            }
         }
-        Err(BufferError::PageNotFound(page_id))
+        written += batch.len();
+        self.flush_batch(&mut batch, &mut images)?;
+        Ok(written)
     }
 
-    /// Flushes all dirty pages to disk
-    pub fn flush_all(&mut self) -> Result<(), BufferError> {
-        for buffer_idx in 0..self.buffer_size {
-            let buffer = unsafe { &*self.buffers.add(buffer_idx) };
+    /// Writes a batch of copied pages and unpins their buffers
+    fn flush_batch(
+        &self,
+        buffers: &mut Vec<usize>,
+        images: &mut Vec<(BufferTag, Page)>,
+    ) -> Result<(), BufferError> {
+        let result = {
+            let _writing = self.write_lock.lock();
+            let result = self.write_images(images);
+            if result.is_ok() {
+                for (&buffer_idx, (_, image)) in buffers.iter().zip(images.iter()) {
+                    let buffer = self.desc(buffer_idx);
+                    // A thread holding the write latch is changing the page
+                    // anyway; waiting for it here could deadlock with one
+                    // that waits for write_lock while holding its latch
+                    if let Ok(_content) = buffer.content_lock.try_read() {
+                        let page = unsafe { &*self.frame(buffer_idx) };
+                        // Only the copy has its checksum field filled in
+                        if page.as_bytes()[4..] == image.as_bytes()[4..] {
+                            buffer.clear_dirty();
+                        }
+                    }
+                }
+            }
+            result
+        };
 
-            if buffer.is_dirty() {
-                let page = &self.page_data[buffer_idx];
-                self.write_page_to_disk(buffer.buf_tag.page_id, page)?;
-                buffer.clear_dirty();
+        for buffer_idx in buffers.drain(..) {
+            self.desc(buffer_idx).unpin();
+        }
+        images.clear();
+        result
+    }
+
+    /// Restores pages torn by a crash from the double-write area
+    ///
+    /// Every page in the last double-write batch whose copy in place fails
+    /// its checksum is overwritten with the intact copy. Runs at startup,
+    /// before WAL redo reads any page. Returns the number of pages restored.
+    pub fn restore_torn_pages(&self) -> Result<usize, BufferError> {
+        let Some(dw) = &self.double_write else {
+            return Ok(0);
+        };
+
+        let _writing = self.write_lock.lock();
+        let mut restored = Vec::new();
+        for (tag, copy) in dw.pages()? {
+            let mut page = Page::new();
+            self.read_image(&tag, &mut page)?;
+            if verify_page(&tag, &page).is_err() {
+                self.write_image(&tag, &copy)?;
+                restored.push(tag);
             }
         }
-        Ok(())
+        self.sync_page_files(restored.iter())?;
+        self.repaired
+            .fetch_add(restored.len() as u64, Ordering::Relaxed);
+        Ok(restored.len())
     }
 
     /// Returns the current number of buffers in the pool
@@ -569,25 +1384,126 @@ impl BufferMgr {
         self.buffer_size
     }
 
-    /// Returns all dirty pages
-    pub fn get_dirty_pages(&self) -> Vec<PageId> {
-        let mut dirty_pages = Vec::new();
+    /// Returns the number of buffers that have never held a page
+    pub fn free_buffer_count(&self) -> usize {
+        self.free_buffers.lock().len()
+    }
+
+    /// Returns the tags of the resident pages, hottest first
+    ///
+    /// Follows the replacement policy's ranking; pages the policy does not
+    /// track (`Lru` drops entries on overflow) come last.
+    pub fn resident_tags(&self) -> Vec<BufferTag> {
+        let ranked = self.replacement.lock().ranked();
+        let mut listed = vec![false; self.buffer_size];
+        let mut tags = Vec::with_capacity(self.buffer_size);
+        for buffer_idx in ranked.into_iter().chain(0..self.buffer_size) {
+            if mem::replace(&mut listed[buffer_idx], true) {
+                continue;
+            }
+            let buffer = self.desc(buffer_idx);
+            let tag = buffer.tag();
+            if tag.is_valid() && buffer.is_valid() {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
+
+    /// Reads pages into buffers nobody uses yet, returning how many it loaded
+    ///
+    /// Meant for warming the pool up: pages already resident are skipped
+    /// and it stops once the free list runs out, so it never evicts
+    /// anything. Pages that fail to load are skipped as well.
+    pub fn prefetch(&self, tags: &[BufferTag]) -> usize {
+        let mut loaded = 0;
+        for &tag in tags {
+            if self.free_buffers.lock().is_empty() {
+                break;
+            }
+            if self.lookup_tag(&tag).is_some() {
+                continue;
+            }
+            if let Ok(buffer_idx) = self.pin_buffer(tag, true, None) {
+                self.desc(buffer_idx).unpin();
+                loaded += 1;
+            }
+        }
+        loaded
+    }
+
+    /// Drops block `from.block` and every later block of its relation
+    /// fork, from the pool and from the page file
+    ///
+    /// The buffers are discarded without being written, so the caller must
+    /// be done with those pages. If one of them is pinned nothing is
+    /// dropped and `BufferError::PagePinned` is returned.
+    pub fn truncate_fork(&self, from: BufferTag) -> Result<(), BufferError> {
+        let _write = self.write_lock.lock();
+        let doomed = |tag: BufferTag| {
+            tag.is_valid()
+                && tag.tablespace_id == from.tablespace_id
+                && tag.relation_id == from.relation_id
+                && tag.fork == from.fork
+                && tag.block >= from.block
+        };
+
+        let mut claimed = Vec::new();
         for buffer_idx in 0..self.buffer_size {
-            let buffer = unsafe { &*self.buffers.add(buffer_idx) };
-            if buffer.is_dirty() && buffer.buf_tag.page_id != INVALID_PAGE_ID {
-                dirty_pages.push(buffer.buf_tag.page_id);
+            let buffer = self.desc(buffer_idx);
+            if !doomed(buffer.tag()) {
+                continue;
+            }
+            if !buffer.try_pin_exclusive() {
+                for &idx in &claimed {
+                    self.desc(idx).unpin();
+                }
+                return Err(BufferError::PagePinned(buffer.tag().page_id()));
+            }
+            // Evicted and given to another page before the pin
+            if !doomed(buffer.tag()) {
+                buffer.unpin();
+                continue;
             }
+            claimed.push(buffer_idx);
+        }
+
+        for buffer_idx in claimed {
+            let buffer = self.desc(buffer_idx);
+            let tag = buffer.tag();
+            {
+                let mut partition = self.mapping.write_stripe(&tag);
+                partition.remove(&tag);
+                buffer.set_tag(BufferTag::invalid());
+            }
+            buffer.clear_dirty();
+            buffer.clear_valid();
+            self.replacement.lock().remove(buffer_idx);
+            buffer.unpin();
+            self.free_buffers.lock().push(buffer_idx);
+        }
+
+        let path = self.page_file_path(&from);
+        match self
+            .vfs
+            .truncate(path.to_str().unwrap(), self.page_offset(&from))
+        {
+            Ok(()) | Err(VfsError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e.into()),
         }
-        dirty_pages
     }
 
-    /// Get page data for WAL logging
-    pub fn get_page_data(&self, page_id: PageId) -> Option<&Page> {
-        if let Some(buffer_idx) = self.lookup(page_id) {
-            Some(&self.page_data[buffer_idx])
-        } else {
-            None
+    /// Returns the tags of all dirty pages
+    pub fn get_dirty_pages(&self) -> Vec<BufferTag> {
+        let mut dirty_pages = Vec::new();
+        for buffer_idx in 0..self.buffer_size {
+            let buffer = self.desc(buffer_idx);
+            let tag = buffer.tag();
+            if buffer.is_dirty() && tag.is_valid() {
+                dirty_pages.push(tag);
+            }
         }
+        dirty_pages
     }
 }
 
@@ -597,25 +1513,10 @@ impl Drop for BufferMgr {
         let _ = self.flush_all();
 
         unsafe {
-            // Free hash table entries
+            // Free buffer array
             for i in 0..self.buffer_size {
-                let mut entry_ptr = *self.buf_hash_table.add(i);
-                while !entry_ptr.is_null() {
-                    let next = (*entry_ptr).next;
-                    let entry_layout = alloc::Layout::new::<HashEntry>();
-                    alloc::dealloc(entry_ptr as *mut u8, entry_layout);
-                    entry_ptr = next;
-                }
+                std::ptr::drop_in_place(self.buffers.add(i));
             }
-
-            // Free hash table
-            let hash_layout = alloc::Layout::from_size_align_unchecked(
-                mem::size_of::<*mut HashEntry>() * self.buffer_size,
-                mem::align_of::<*mut HashEntry>(),
-            );
-            alloc::dealloc(self.buf_hash_table as *mut u8, hash_layout);
-
-            // Free buffer array
             let buf_layout = alloc::Layout::from_size_align_unchecked(
                 mem::size_of::<BufferDesc>() * self.buffer_size,
                 mem::align_of::<BufferDesc>(),
@@ -665,6 +1566,15 @@ mod tests {
         fn pwrite(&self, _path: &str, _buf: &[u8], _offset: u64) -> crate::vfs::VfsResult<usize> {
             Ok(0)
         }
+        fn list_dir(&self, _path: &str) -> crate::vfs::VfsResult<Vec<String>> {
+            Ok(Vec::new())
+        }
+        fn rename(&self, _from: &str, _to: &str) -> crate::vfs::VfsResult<()> {
+            Ok(())
+        }
+        fn fsync(&self, _path: &str) -> crate::vfs::VfsResult<()> {
+            Ok(())
+        }
     }
 
     struct MockFileHandle;
@@ -692,8 +1602,18 @@ mod tests {
 
     #[test]
     fn test_buffer_tag_creation() {
-        let tag = BufferTag::new(12345);
-        assert_eq!(tag.page_id, 12345);
+        let tag = BufferTag::from_page_id((7 << 32) | 12345);
+        assert_eq!(tag, BufferTag::new(0, 7, ForkNumber::Main, 12345));
+        assert_eq!(tag.page_id(), (7 << 32) | 12345);
+        assert_eq!(tag.checksum_id(), tag.page_id());
+        assert!(!BufferTag::invalid().is_valid());
+
+        let fsm = BufferTag::new(0, 7, ForkNumber::Fsm, 12345);
+        let other_space = BufferTag::new(3, 7, ForkNumber::Main, 12345);
+        assert_eq!(fsm.page_id(), tag.page_id());
+        assert_ne!(fsm.checksum_id(), tag.checksum_id());
+        assert_ne!(other_space.checksum_id(), tag.checksum_id());
+        assert_ne!(fsm.checksum_id(), other_space.checksum_id());
     }
 
     #[test]
@@ -740,20 +1660,514 @@ mod tests {
     }
 
     #[test]
-    fn test_mark_dirty_nonexistent_page() {
+    fn test_invalid_page_id_is_rejected() {
         let vfs: Arc<dyn VfsInterface> = Arc::new(MockVfs);
-        let mut mgr = BufferMgr::init(8, vfs, PathBuf::from("/tmp/test"));
-        // Should not panic, just no-op
-        mgr.mark_dirty(99999);
+        let mgr = BufferMgr::init(8, vfs, PathBuf::from("/tmp/test"));
+        assert_eq!(
+            mgr.read_page(INVALID_PAGE_ID).err(),
+            Some(BufferError::InvalidPageId(INVALID_PAGE_ID))
+        );
     }
 
     #[test]
-    fn test_unpin_page_nonexistent_page() {
+    fn test_guards_pin_latch_and_mark_dirty() {
         let vfs: Arc<dyn VfsInterface> = Arc::new(MockVfs);
-        let mut mgr = BufferMgr::init(8, vfs, PathBuf::from("/tmp/test"));
-        let result = mgr.unpin_page(99999);
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), BufferError::PageNotFound(99999));
+        let mgr = BufferMgr::init(8, vfs, PathBuf::from("/tmp/test"));
+
+        let first = mgr.read_page(7).unwrap();
+        let second = mgr.read_page(7).unwrap();
+        let buffer_idx = mgr.lookup(7).unwrap();
+        let desc = mgr.desc(buffer_idx);
+        assert_eq!(desc.pin_count(), 2);
+        assert!(desc.content_lock.try_write().is_err());
+        drop(first);
+        drop(second);
+        assert_eq!(desc.pin_count(), 0);
+
+        // A write guard that only reads leaves the page clean
+        let guard = mgr.write_page(7).unwrap();
+        assert_eq!(guard.as_bytes()[0], 0);
+        drop(guard);
+        assert!(!desc.is_dirty());
+
+        let mut guard = mgr.write_page(7).unwrap();
+        guard.as_bytes_mut()[0] = 42;
+        assert!(desc.content_lock.try_read().is_err());
+        drop(guard);
+        assert!(desc.is_dirty());
+        assert_eq!(desc.pin_count(), 0);
+        assert_eq!(mgr.read_page(7).unwrap().as_bytes()[0], 42);
+    }
+
+    #[test]
+    fn test_buffer_pool_full_when_all_pages_pinned() {
+        let vfs: Arc<dyn VfsInterface> = Arc::new(MockVfs);
+        let mgr = BufferMgr::init(2, vfs, PathBuf::from("/tmp/test"));
+        let _a = mgr.read_page(1).unwrap();
+        let _b = mgr.read_page(2).unwrap();
+        assert_eq!(mgr.read_page(3).err(), Some(BufferError::BufferPoolFull));
+        drop(_a);
+        assert!(mgr.read_page(3).is_ok());
+        assert_eq!(mgr.lookup(1), None);
+    }
+
+    #[test]
+    fn test_evicted_pages_are_read_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = BufferMgr::init(
+            4,
+            Arc::new(crate::vfs::LocalFs::new()),
+            dir.path().to_path_buf(),
+        );
+        for block in 0..32u64 {
+            let mut page = mgr.new_page(block).unwrap();
+            page.payload_mut()[..8].copy_from_slice(&block.to_le_bytes());
+        }
+        for block in 0..32u64 {
+            let page = mgr.read_page(block).unwrap();
+            assert_eq!(page.payload()[..8], block.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_eviction_waits_for_the_wal() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = Arc::new(BufferMgr::init(
+            2,
+            Arc::new(crate::vfs::LocalFs::new()),
+            dir.path().to_path_buf(),
+        ));
+        let durable = Arc::new(AtomicU64::new(10));
+        let durable_lsn = Arc::clone(&durable);
+        mgr.set_durable_lsn(move || durable_lsn.load(Ordering::SeqCst));
+
+        // Block 0 is logged, block 1 is ahead of the durable WAL
+        for (block, lsn) in [(0u64, 5), (1, 20)] {
+            let mut page = mgr.new_page(block).unwrap();
+            page.header_mut().set_lsn(lsn);
+        }
+        mgr.read_page(2).unwrap();
+        assert_eq!(mgr.lookup(0), None);
+        assert!(mgr.lookup(1).is_some());
+
+        // With nothing else to evict, the claim waits for the log to catch up
+        let _pinned = mgr.read_page(2).unwrap();
+        let waiter = {
+            let mgr = Arc::clone(&mgr);
+            std::thread::spawn(move || mgr.read_page(3).map(|_| ()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        durable.store(21, Ordering::SeqCst);
+        waiter.join().unwrap().unwrap();
+        assert_eq!(mgr.lookup(1), None);
+        let glsn = mgr.read_page(1).unwrap().header().glsn;
+        assert_eq!(glsn, 20);
+    }
+
+    #[test]
+    fn test_forks_and_tablespaces_do_not_share_pages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let vfs: Arc<dyn VfsInterface> = Arc::new(crate::vfs::LocalFs::new());
+        let tags = [
+            BufferTag::new(0, 3, ForkNumber::Main, 1),
+            BufferTag::new(0, 3, ForkNumber::Fsm, 1),
+            BufferTag::new(5, 3, ForkNumber::Main, 1),
+        ];
+        let mgr = BufferMgr::init(4, Arc::clone(&vfs), dir.path().to_path_buf());
+        for (i, &tag) in tags.iter().enumerate() {
+            mgr.new_buffer(tag).unwrap().payload_mut()[0] = i as u8 + 1;
+        }
+        assert_eq!(mgr.lookup(tags[0].page_id()), mgr.lookup_tag(&tags[0]));
+        assert_ne!(mgr.lookup_tag(&tags[0]), mgr.lookup_tag(&tags[1]));
+        mgr.flush_all().unwrap();
+        drop(mgr);
+
+        for file in ["page_3.dat", "page_3_fsm.dat", "tablespace_5/page_3.dat"] {
+            let len = std::fs::metadata(dir.path().join(file)).unwrap().len();
+            assert_eq!(len, 2 * PAGE_SIZE as u64, "{}", file);
+        }
+        let mgr = BufferMgr::init(4, vfs, dir.path().to_path_buf());
+        for (i, &tag) in tags.iter().enumerate() {
+            assert_eq!(mgr.read_buffer(tag).unwrap().payload()[0], i as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn test_truncate_fork_drops_trailing_blocks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = BufferMgr::init(
+            8,
+            Arc::new(crate::vfs::LocalFs::new()),
+            dir.path().to_path_buf(),
+        );
+        let tag = |block| BufferTag::new(0, 3, ForkNumber::Main, block);
+        for block in 0..4 {
+            mgr.new_buffer(tag(block)).unwrap().payload_mut()[0] = block as u8 + 1;
+        }
+        mgr.flush_all().unwrap();
+        mgr.write_buffer(tag(3)).unwrap().payload_mut()[0] = 9;
+
+        {
+            let _pinned = mgr.read_buffer(tag(2)).unwrap();
+            assert_eq!(
+                mgr.truncate_fork(tag(2)),
+                Err(BufferError::PagePinned(tag(2).page_id()))
+            );
+        }
+        mgr.truncate_fork(tag(2)).unwrap();
+        assert_eq!(mgr.lookup_tag(&tag(3)), None);
+        assert!(mgr.get_dirty_pages().is_empty());
+        let len = std::fs::metadata(dir.path().join("page_3.dat"))
+            .unwrap()
+            .len();
+        assert_eq!(len, 2 * PAGE_SIZE as u64);
+
+        // Dropped blocks read back empty, the rest are untouched
+        assert_eq!(mgr.read_buffer(tag(1)).unwrap().payload()[0], 2);
+        assert!(mgr.read_buffer(tag(3)).unwrap().is_zeroed());
+    }
+
+    #[test]
+    fn test_every_replacement_policy_reads_evicted_pages_back() {
+        for kind in [
+            ReplacementKind::Lru,
+            ReplacementKind::ClockSweep,
+            ReplacementKind::LruK { k: 2 },
+            ReplacementKind::TwoQ,
+        ] {
+            let dir = tempfile::TempDir::new().unwrap();
+            let mgr = BufferMgr::init(
+                4,
+                Arc::new(crate::vfs::LocalFs::new()),
+                dir.path().to_path_buf(),
+            )
+            .with_replacement_policy(kind);
+            for block in 0..16u64 {
+                let mut page = mgr.new_page(block).unwrap();
+                page.payload_mut()[..8].copy_from_slice(&block.to_le_bytes());
+            }
+            for block in 0..16u64 {
+                let page = mgr.read_page(block).unwrap();
+                assert_eq!(page.payload()[..8], block.to_le_bytes());
+            }
+            let stats = mgr.stats();
+            assert_eq!(
+                stats.hits + stats.misses,
+                16,
+                "{}",
+                mgr.replacement_policy()
+            );
+            assert!(stats.evictions >= 12, "{}", mgr.replacement_policy());
+        }
+    }
+
+    #[test]
+    fn test_stats_count_hits_and_misses() {
+        let vfs: Arc<dyn VfsInterface> = Arc::new(MockVfs);
+        let mgr = BufferMgr::init(2, vfs, PathBuf::from("/tmp/test"));
+        drop(mgr.read_page(1).unwrap());
+        drop(mgr.read_page(1).unwrap());
+        drop(mgr.read_page(2).unwrap());
+        drop(mgr.read_page(3).unwrap());
+
+        let stats = mgr.stats();
+        assert_eq!(
+            stats,
+            BufferStats {
+                hits: 1,
+                misses: 3,
+                evictions: 1,
+                repaired: 0,
+            }
+        );
+        assert_eq!(stats.hit_ratio(), 0.25);
+
+        mgr.reset_stats();
+        assert_eq!(mgr.stats(), BufferStats::default());
+    }
+
+    #[test]
+    fn test_ring_scan_keeps_hot_pages_resident() {
+        let vfs: Arc<dyn VfsInterface> = Arc::new(MockVfs);
+        let mgr = BufferMgr::init(16, vfs, PathBuf::from("/tmp/test"));
+        for _ in 0..3 {
+            for block in 0..8u64 {
+                drop(mgr.read_page(block).unwrap());
+            }
+        }
+
+        let mut ring = BufferRing::new(4);
+        for block in 100..200u64 {
+            drop(mgr.read_page_with_ring(block, &mut ring).unwrap());
+        }
+        for block in 0..8u64 {
+            assert!(
+                mgr.lookup(block).is_some(),
+                "hot page {} was evicted",
+                block
+            );
+        }
+        let resident = (100..200u64).filter(|&b| mgr.lookup(b).is_some()).count();
+        assert_eq!(resident, 4);
+    }
+
+    #[test]
+    fn test_corrupted_page_is_reported() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let vfs: Arc<dyn VfsInterface> = Arc::new(crate::vfs::LocalFs::new());
+        let mgr = BufferMgr::init(4, Arc::clone(&vfs), dir.path().to_path_buf());
+        mgr.new_page(1).unwrap().payload_mut()[0] = 7;
+        mgr.flush_all().unwrap();
+        drop(mgr);
+
+        // Flip one payload byte of block 1 behind the pool's back
+        let path = dir.path().join("page_0.dat");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[PAGE_SIZE + 100] ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mgr = BufferMgr::init(4, vfs, dir.path().to_path_buf())
+            .with_bad_page_policy(BadPagePolicy::RepairFromDoubleWrite);
+        match mgr.read_page(1).err() {
+            Some(BufferError::Corrupted {
+                page_id, expected, ..
+            }) => {
+                assert_eq!(page_id, 1);
+                let mut page = Page::new();
+                page.payload_mut()[0] = 7;
+                assert_eq!(expected, page.compute_checksum(1));
+            }
+            other => panic!(
+                "expected a checksum error, got {:?}",
+                other.map(|e| e.to_string())
+            ),
+        }
+        // The failed read leaves nothing behind; unwritten blocks still read fine
+        assert_eq!(mgr.lookup(1), None);
+        assert!(mgr.read_page(2).unwrap().is_zeroed());
+    }
+
+    /// Local file system that tears an in-place page write in half and then
+    /// fails every write, like a machine losing power mid-flush
+    struct TornWriteVfs {
+        inner: crate::vfs::LocalFs,
+        /// Page writes that still go through before one is torn
+        writes_left: std::sync::atomic::AtomicUsize,
+        crashed: std::sync::atomic::AtomicBool,
+    }
+
+    impl TornWriteVfs {
+        fn new() -> Self {
+            Self {
+                inner: crate::vfs::LocalFs::new(),
+                writes_left: std::sync::atomic::AtomicUsize::new(usize::MAX),
+                crashed: std::sync::atomic::AtomicBool::new(false),
+            }
+        }
+
+        fn tear_after(&self, writes: usize) {
+            self.writes_left.store(writes, Ordering::SeqCst);
+        }
+    }
+
+    impl VfsInterface for TornWriteVfs {
+        fn create_dir(&self, path: &str) -> crate::vfs::VfsResult<()> {
+            self.inner.create_dir(path)
+        }
+        fn remove_dir(&self, path: &str) -> crate::vfs::VfsResult<()> {
+            self.inner.remove_dir(path)
+        }
+        fn create_file(
+            &self,
+            path: &str,
+        ) -> crate::vfs::VfsResult<Box<dyn crate::vfs::FileHandle>> {
+            self.inner.create_file(path)
+        }
+        fn open_file(&self, path: &str) -> crate::vfs::VfsResult<Box<dyn crate::vfs::FileHandle>> {
+            self.inner.open_file(path)
+        }
+        fn remove_file(&self, path: &str) -> crate::vfs::VfsResult<()> {
+            self.inner.remove_file(path)
+        }
+        fn truncate(&self, path: &str, length: u64) -> crate::vfs::VfsResult<()> {
+            self.inner.truncate(path, length)
+        }
+        fn pread(&self, path: &str, buf: &mut [u8], offset: u64) -> crate::vfs::VfsResult<usize> {
+            self.inner.pread(path, buf, offset)
+        }
+        fn pwrite(&self, path: &str, buf: &[u8], offset: u64) -> crate::vfs::VfsResult<usize> {
+            let crash = || VfsError::SystemError(libc::EIO, "crashed".to_string());
+            if self.crashed.load(Ordering::SeqCst) {
+                return Err(crash());
+            }
+            if path.contains("page_") {
+                let left = self.writes_left.load(Ordering::SeqCst);
+                if left == 0 {
+                    self.crashed.store(true, Ordering::SeqCst);
+                    self.inner.pwrite(path, &buf[..buf.len() / 2], offset)?;
+                    return Err(crash());
+                }
+                self.writes_left
+                    .store(left.saturating_sub(1), Ordering::SeqCst);
+            }
+            self.inner.pwrite(path, buf, offset)
+        }
+        fn list_dir(&self, path: &str) -> crate::vfs::VfsResult<Vec<String>> {
+            self.inner.list_dir(path)
+        }
+        fn rename(&self, from: &str, to: &str) -> crate::vfs::VfsResult<()> {
+            self.inner.rename(from, to)
+        }
+        fn fsync(&self, path: &str) -> crate::vfs::VfsResult<()> {
+            if self.crashed.load(Ordering::SeqCst) {
+                return Err(VfsError::SystemError(libc::EIO, "crashed".to_string()));
+            }
+            self.inner.fsync(path)
+        }
+    }
+
+    fn double_write(vfs: Arc<dyn VfsInterface>, dir: &tempfile::TempDir) -> DoubleWriteBuffer {
+        DoubleWriteBuffer::new(8, vfs, dir.path().join("doublewrite.dat"))
+    }
+
+    /// Writes blocks 0..4 of segment 0 twice; the second flush tears the
+    /// write of block 1 and nothing after it reaches the page file
+    fn tear_page_write(dir: &tempfile::TempDir) {
+        let vfs = Arc::new(TornWriteVfs::new());
+        let mgr = BufferMgr::init(8, vfs.clone(), dir.path().to_path_buf())
+            .with_double_write(double_write(vfs.clone(), dir));
+        for round in 1..=2u8 {
+            for block in 0..4u64 {
+                mgr.new_page(block).unwrap().payload_mut().fill(round);
+            }
+            if round == 2 {
+                vfs.tear_after(1);
+                assert!(matches!(mgr.flush_all(), Err(BufferError::Io(_))));
+            } else {
+                mgr.flush_all().unwrap();
+            }
+        }
+    }
+
+    /// Returns the byte the test filled the payload with
+    fn fill_byte(page: &Page) -> u8 {
+        *page.payload().last().unwrap()
+    }
+
+    #[test]
+    fn test_torn_page_is_restored_from_double_write() {
+        let dir = tempfile::TempDir::new().unwrap();
+        tear_page_write(&dir);
+
+        let vfs: Arc<dyn VfsInterface> = Arc::new(crate::vfs::LocalFs::new());
+        let mgr = BufferMgr::init(8, Arc::clone(&vfs), dir.path().to_path_buf())
+            .with_double_write(double_write(vfs, &dir));
+        assert!(matches!(
+            mgr.read_page(1).err(),
+            Some(BufferError::Corrupted { page_id: 1, .. })
+        ));
+
+        assert_eq!(mgr.restore_torn_pages().unwrap(), 1);
+        assert_eq!(mgr.restore_torn_pages().unwrap(), 0);
+        assert_eq!(mgr.stats().repaired, 1);
+        // Blocks 2 and 3 were never overwritten; WAL redo brings them forward
+        let rounds: Vec<u8> = (0..4)
+            .map(|b| fill_byte(&mgr.read_page(b).unwrap()))
+            .collect();
+        assert_eq!(rounds, vec![2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_bad_page_policy_repairs_from_double_write() {
+        let dir = tempfile::TempDir::new().unwrap();
+        tear_page_write(&dir);
+
+        let vfs: Arc<dyn VfsInterface> = Arc::new(crate::vfs::LocalFs::new());
+        let mgr = BufferMgr::init(8, Arc::clone(&vfs), dir.path().to_path_buf())
+            .with_double_write(double_write(Arc::clone(&vfs), &dir))
+            .with_bad_page_policy(BadPagePolicy::RepairFromDoubleWrite);
+        assert_eq!(fill_byte(&mgr.read_page(1).unwrap()), 2);
+        assert_eq!(mgr.stats().repaired, 1);
+        drop(mgr);
+
+        // The repaired page was written back in place
+        let mgr = BufferMgr::init(8, vfs, dir.path().to_path_buf());
+        assert_eq!(fill_byte(&mgr.read_page(1).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_double_write_skips_torn_copies() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dw = double_write(Arc::new(crate::vfs::LocalFs::new()), &dir);
+        assert!(dw.pages().unwrap().is_empty());
+
+        let tag = |block| BufferTag::new(2, 7, ForkNumber::Fsm, block);
+        let images: Vec<(BufferTag, Page)> = (0..3u64)
+            .map(|block| {
+                let mut page = Page::new();
+                page.payload_mut().fill(block as u8);
+                page.update_checksum(tag(block).checksum_id());
+                (tag(block), page)
+            })
+            .collect();
+        dw.write_batch(&images).unwrap();
+        assert_eq!(dw.pages().unwrap().len(), 3);
+
+        // Tear the copy of block 1 inside the double-write file
+        let path = dir.path().join("doublewrite.dat");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[2 * PAGE_SIZE + PAGE_SIZE / 2..3 * PAGE_SIZE].fill(0xEE);
+        std::fs::write(&path, bytes).unwrap();
+
+        let tags: Vec<BufferTag> = dw.pages().unwrap().iter().map(|(t, _)| *t).collect();
+        assert_eq!(tags, vec![tag(0), tag(2)]);
+        assert!(dw.read_page(&tag(1)).is_none());
+        assert_eq!(fill_byte(&dw.read_page(&tag(2)).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_threads_share_the_pool() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = Arc::new(BufferMgr::init(
+            16,
+            Arc::new(crate::vfs::LocalFs::new()),
+            dir.path().to_path_buf(),
+        ));
+
+        let threads: Vec<_> = (0..4u64)
+            .map(|t| {
+                let mgr = Arc::clone(&mgr);
+                std::thread::spawn(move || {
+                    for round in 0..10u64 {
+                        for block in 0..20u64 {
+                            // Every thread also bumps a counter on page 0
+                            let page_id = (t << 32) | block;
+                            let mut page = mgr.write_page(page_id).unwrap();
+                            page.payload_mut()[..8].copy_from_slice(&round.to_le_bytes());
+                            drop(page);
+
+                            let mut shared = mgr.write_page(0xFFFF << 32).unwrap();
+                            let count =
+                                u64::from_le_bytes(shared.payload()[..8].try_into().unwrap());
+                            shared.payload_mut()[..8].copy_from_slice(&(count + 1).to_le_bytes());
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        for t in 0..4u64 {
+            for block in 0..20u64 {
+                let page = mgr.read_page((t << 32) | block).unwrap();
+                assert_eq!(page.payload()[..8], 9u64.to_le_bytes());
+            }
+        }
+        let shared = mgr.read_page(0xFFFF << 32).unwrap();
+        assert_eq!(shared.payload()[..8], 800u64.to_le_bytes());
     }
 
     #[test]
@@ -0,0 +1,532 @@
+//! Buffer replacement policies
+//!
+//! A `ReplacementPolicy` decides which resident page the buffer pool gives
+//! up when it needs a buffer and the free list is empty. Policies track
+//! buffers by index; the pool tells them when a page is loaded, hit and
+//! dropped, and asks them for a victim. `BufferRing` is the access strategy
+//! for large sequential scans: the scan recycles a small ring of buffers
+//! instead of pushing the working set out of the pool.
+
+use crate::buffer::lru::LruManager;
+use crate::buffer::BufferTag;
+use linked_hash_map::LinkedHashMap;
+use std::collections::VecDeque;
+
+/// Number of buffers a sequential scan cycles through
+pub const SCAN_RING_SIZE: usize = 32;
+
+/// Highest usage count clock-sweep gives a buffer
+const MAX_USAGE_COUNT: u8 = 5;
+
+/// Decides which buffer to evict next
+///
+/// Calls happen under the pool's policy lock; `victim` gets a cheap check
+/// for whether a buffer is unpinned and must not do I/O.
+pub trait ReplacementPolicy: Send {
+    /// Short name used in stats and benchmarks
+    fn name(&self) -> &'static str;
+
+    /// The page named by `tag` was loaded into `buffer_idx`
+    fn insert(&mut self, buffer_idx: usize, tag: BufferTag);
+
+    /// The page in `buffer_idx` was hit
+    fn touch(&mut self, buffer_idx: usize);
+
+    /// `buffer_idx` no longer holds a page the policy should track
+    fn remove(&mut self, buffer_idx: usize);
+
+    /// Picks a buffer to evict among those `can_evict` accepts and stops
+    /// tracking it
+    fn victim(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize>;
+
+    /// Tracked buffers from the one the policy would keep longest to the
+    /// one it would evict next
+    fn ranked(&self) -> Vec<usize>;
+}
+
+/// The replacement policies the buffer pool can be configured with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplacementKind {
+    /// Hot/cold/free lists of `LruManager` (50/30/20 percent of the pool)
+    #[default]
+    Lru,
+    /// Clock sweep over usage counts
+    ClockSweep,
+    /// Evicts the page whose k-th most recent hit is oldest
+    LruK { k: usize },
+    /// 2Q: a FIFO for pages seen once, an LRU for pages seen again
+    TwoQ,
+}
+
+impl ReplacementKind {
+    /// Builds the policy for a pool of `capacity` buffers
+    pub fn build(self, capacity: usize) -> Box<dyn ReplacementPolicy> {
+        match self {
+            ReplacementKind::Lru => {
+                let hot_cap = capacity / 2;
+                let cold_cap = capacity * 3 / 10;
+                let free_cap = capacity / 5;
+                Box::new(LruManager::<usize>::new(hot_cap, cold_cap, free_cap))
+            }
+            ReplacementKind::ClockSweep => Box::new(ClockSweep::new(capacity)),
+            ReplacementKind::LruK { k } => Box::new(LruK::new(capacity, k)),
+            ReplacementKind::TwoQ => Box::new(TwoQ::new(capacity)),
+        }
+    }
+}
+
+impl ReplacementPolicy for LruManager<usize> {
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+
+    fn insert(&mut self, buffer_idx: usize, _tag: BufferTag) {
+        LruManager::remove(self, &buffer_idx);
+        self.add(buffer_idx);
+    }
+
+    fn touch(&mut self, buffer_idx: usize) {
+        self.access(&buffer_idx);
+    }
+
+    fn remove(&mut self, buffer_idx: usize) {
+        LruManager::remove(self, &buffer_idx);
+    }
+
+    fn victim(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize> {
+        // Eviction order: free, then cold, then hot, LRU end first
+        let buffer_idx = [&self.free_list, &self.cold_list, &self.hot_list]
+            .into_iter()
+            .find_map(|list| list.iter().rev().map(|n| n.data).find(|&i| can_evict(i)))?;
+        LruManager::remove(self, &buffer_idx);
+        Some(buffer_idx)
+    }
+
+    fn ranked(&self) -> Vec<usize> {
+        [&self.hot_list, &self.cold_list, &self.free_list]
+            .into_iter()
+            .flat_map(|list| list.iter().map(|n| n.data))
+            .collect()
+    }
+}
+
+/// Clock sweep
+///
+/// Every hit raises a buffer's usage count (up to `MAX_USAGE_COUNT`); the
+/// clock hand lowers the count of each unpinned buffer it passes and
+/// evicts the first one found at zero.
+pub struct ClockSweep {
+    usage: Vec<u8>,
+    tracked: Vec<bool>,
+    hand: usize,
+}
+
+impl ClockSweep {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            usage: vec![0; capacity],
+            tracked: vec![false; capacity],
+            hand: 0,
+        }
+    }
+}
+
+impl ReplacementPolicy for ClockSweep {
+    fn name(&self) -> &'static str {
+        "clock-sweep"
+    }
+
+    fn insert(&mut self, buffer_idx: usize, _tag: BufferTag) {
+        self.tracked[buffer_idx] = true;
+        self.usage[buffer_idx] = 1;
+    }
+
+    fn touch(&mut self, buffer_idx: usize) {
+        if self.tracked[buffer_idx] {
+            self.usage[buffer_idx] = (self.usage[buffer_idx] + 1).min(MAX_USAGE_COUNT);
+        }
+    }
+
+    fn remove(&mut self, buffer_idx: usize) {
+        self.tracked[buffer_idx] = false;
+    }
+
+    fn victim(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let capacity = self.usage.len();
+        // Enough turns to bring every usage count down to zero
+        for _ in 0..capacity * (MAX_USAGE_COUNT as usize + 1) {
+            let buffer_idx = self.hand;
+            self.hand = (self.hand + 1) % capacity;
+            if !self.tracked[buffer_idx] || !can_evict(buffer_idx) {
+                continue;
+            }
+            if self.usage[buffer_idx] == 0 {
+                self.tracked[buffer_idx] = false;
+                return Some(buffer_idx);
+            }
+            self.usage[buffer_idx] -= 1;
+        }
+        None
+    }
+
+    fn ranked(&self) -> Vec<usize> {
+        // Among equal counts, the hand reaches the nearest buffer first
+        let capacity = self.usage.len();
+        let mut ranked: Vec<usize> = (0..capacity).filter(|&i| self.tracked[i]).collect();
+        ranked.sort_by_key(|&i| {
+            let distance = (i + capacity - self.hand) % capacity;
+            std::cmp::Reverse((self.usage[i], distance))
+        });
+        ranked
+    }
+}
+
+/// LRU-K
+///
+/// Keeps the times of the last `k` references to each buffer and evicts
+/// the one whose k-th most recent reference is oldest. Buffers with fewer
+/// than `k` references go first, least recently used first, so pages
+/// touched once by a scan leave before the working set does. Victim
+/// selection scans every tracked buffer.
+pub struct LruK {
+    k: usize,
+    clock: u64,
+    /// Reference times per buffer, oldest first, at most `k`
+    history: Vec<Option<VecDeque<u64>>>,
+}
+
+impl LruK {
+    pub fn new(capacity: usize, k: usize) -> Self {
+        assert!(k > 0, "LRU-K needs k >= 1");
+        Self {
+            k,
+            clock: 0,
+            history: vec![None; capacity],
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// The buffer with the smallest key is evicted first
+    fn eviction_key(&self, refs: &VecDeque<u64>) -> (u8, u64) {
+        if refs.len() < self.k {
+            (0, *refs.back().unwrap())
+        } else {
+            (1, *refs.front().unwrap())
+        }
+    }
+}
+
+impl ReplacementPolicy for LruK {
+    fn name(&self) -> &'static str {
+        "lru-k"
+    }
+
+    fn insert(&mut self, buffer_idx: usize, _tag: BufferTag) {
+        let now = self.tick();
+        self.history[buffer_idx] = Some(VecDeque::from([now]));
+    }
+
+    fn touch(&mut self, buffer_idx: usize) {
+        let now = self.tick();
+        let k = self.k;
+        if let Some(refs) = self.history[buffer_idx].as_mut() {
+            refs.push_back(now);
+            if refs.len() > k {
+                refs.pop_front();
+            }
+        }
+    }
+
+    fn remove(&mut self, buffer_idx: usize) {
+        self.history[buffer_idx] = None;
+    }
+
+    fn victim(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let buffer_idx = self
+            .history
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, refs)| refs.as_ref().map(|refs| (idx, refs)))
+            .filter(|&(idx, _)| can_evict(idx))
+            .min_by_key(|&(_, refs)| self.eviction_key(refs))
+            .map(|(idx, _)| idx)?;
+        self.history[buffer_idx] = None;
+        Some(buffer_idx)
+    }
+
+    fn ranked(&self) -> Vec<usize> {
+        let mut ranked: Vec<(usize, (u8, u64))> = self
+            .history
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, refs)| refs.as_ref().map(|refs| (idx, self.eviction_key(refs))))
+            .collect();
+        ranked.sort_by_key(|&(_, key)| std::cmp::Reverse(key));
+        ranked.into_iter().map(|(idx, _)| idx).collect()
+    }
+}
+
+/// 2Q
+///
+/// New pages enter `a1in`, a FIFO holding about a quarter of the pool.
+/// Pages pushed out of it are remembered by tag in `a1out`; one that is
+/// loaded again while remembered has proven itself and goes to `am`, an
+/// LRU list. Hits in `a1in` do not promote, so a single pass over many
+/// pages only ever cycles through `a1in`.
+pub struct TwoQ {
+    a1in: LinkedHashMap<usize, BufferTag>,
+    am: LinkedHashMap<usize, ()>,
+    a1out: LinkedHashMap<BufferTag, ()>,
+    kin: usize,
+    kout: usize,
+}
+
+impl TwoQ {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            a1in: LinkedHashMap::new(),
+            am: LinkedHashMap::new(),
+            a1out: LinkedHashMap::new(),
+            kin: (capacity / 4).max(1),
+            kout: (capacity / 2).max(1),
+        }
+    }
+
+    fn evict_from_a1in(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let buffer_idx = self.a1in.keys().copied().find(|&idx| can_evict(idx))?;
+        let tag = self.a1in.remove(&buffer_idx).unwrap();
+        self.a1out.insert(tag, ());
+        while self.a1out.len() > self.kout {
+            self.a1out.pop_front();
+        }
+        Some(buffer_idx)
+    }
+
+    fn evict_from_am(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let buffer_idx = self.am.keys().copied().find(|&idx| can_evict(idx))?;
+        self.am.remove(&buffer_idx);
+        Some(buffer_idx)
+    }
+}
+
+impl ReplacementPolicy for TwoQ {
+    fn name(&self) -> &'static str {
+        "2q"
+    }
+
+    fn insert(&mut self, buffer_idx: usize, tag: BufferTag) {
+        self.remove(buffer_idx);
+        if self.a1out.remove(&tag).is_some() {
+            self.am.insert(buffer_idx, ());
+        } else {
+            self.a1in.insert(buffer_idx, tag);
+        }
+    }
+
+    fn touch(&mut self, buffer_idx: usize) {
+        self.am.get_refresh(&buffer_idx);
+    }
+
+    fn remove(&mut self, buffer_idx: usize) {
+        self.a1in.remove(&buffer_idx);
+        self.am.remove(&buffer_idx);
+    }
+
+    fn victim(&mut self, can_evict: &dyn Fn(usize) -> bool) -> Option<usize> {
+        if self.a1in.len() > self.kin || self.am.is_empty() {
+            self.evict_from_a1in(can_evict)
+                .or_else(|| self.evict_from_am(can_evict))
+        } else {
+            self.evict_from_am(can_evict)
+                .or_else(|| self.evict_from_a1in(can_evict))
+        }
+    }
+
+    fn ranked(&self) -> Vec<usize> {
+        // Reverse of the order `victim` would take them in
+        let a1in = self.a1in.keys().rev().copied();
+        let am = self.am.keys().rev().copied();
+        if self.a1in.len() > self.kin || self.am.is_empty() {
+            am.chain(a1in).collect()
+        } else {
+            a1in.chain(am).collect()
+        }
+    }
+}
+
+/// Ring of buffers a sequential scan recycles
+///
+/// Once the ring is full, each page the scan has to load replaces the
+/// oldest page the ring loaded, as long as that page is still in its
+/// buffer and unpinned. Hits through the ring do not count towards the
+/// replacement policy, so a scan neither floods the pool nor makes its
+/// pages look hot.
+pub struct BufferRing {
+    /// Buffers the ring loaded, with the page each one got
+    slots: Vec<(usize, BufferTag)>,
+    size: usize,
+    next: usize,
+}
+
+impl BufferRing {
+    /// Creates a ring of `size` buffers
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "buffer ring needs at least one buffer");
+        Self {
+            slots: Vec::with_capacity(size),
+            size,
+            next: 0,
+        }
+    }
+
+    /// The buffer to recycle for the next load, once the ring is full
+    pub(crate) fn next_victim(&self) -> Option<(usize, BufferTag)> {
+        (self.slots.len() == self.size).then(|| self.slots[self.next])
+    }
+
+    /// Records that the ring loaded the page named by `tag` into `buffer_idx`
+    pub(crate) fn record(&mut self, buffer_idx: usize, tag: BufferTag) {
+        if self.slots.len() < self.size {
+            self.slots.push((buffer_idx, tag));
+        } else {
+            self.slots[self.next] = (buffer_idx, tag);
+            self.next = (self.next + 1) % self.size;
+        }
+    }
+}
+
+impl Default for BufferRing {
+    fn default() -> Self {
+        Self::new(SCAN_RING_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ReplacementKind; 4] = [
+        ReplacementKind::Lru,
+        ReplacementKind::ClockSweep,
+        ReplacementKind::LruK { k: 2 },
+        ReplacementKind::TwoQ,
+    ];
+
+    fn tag(page_id: u64) -> BufferTag {
+        BufferTag::from_page_id(page_id)
+    }
+
+    fn evict_all(policy: &mut dyn ReplacementPolicy) -> Vec<usize> {
+        std::iter::from_fn(|| policy.victim(&|_| true)).collect()
+    }
+
+    #[test]
+    fn test_every_policy_evicts_each_tracked_buffer_once() {
+        for kind in ALL {
+            let mut policy = kind.build(20);
+            for idx in 0..4 {
+                policy.insert(idx, tag(idx as u64));
+            }
+            policy.touch(2);
+            policy.remove(1);
+
+            let mut victims = evict_all(policy.as_mut());
+            victims.sort();
+            assert_eq!(victims, vec![0, 2, 3], "{}", policy.name());
+        }
+    }
+
+    #[test]
+    fn test_ranked_ends_with_the_next_victim() {
+        for kind in ALL {
+            let mut policy = kind.build(20);
+            for idx in 0..4 {
+                policy.insert(idx, tag(idx as u64));
+            }
+            policy.touch(2);
+
+            let ranked = policy.ranked();
+            let mut tracked = ranked.clone();
+            tracked.sort();
+            assert_eq!(tracked, vec![0, 1, 2, 3], "{}", policy.name());
+            assert_eq!(
+                ranked.last().copied(),
+                policy.victim(&|_| true),
+                "{}",
+                policy.name()
+            );
+            if kind != ReplacementKind::TwoQ {
+                assert_eq!(ranked[0], 2, "{}", policy.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_pinned_buffers_are_skipped() {
+        for kind in ALL {
+            let mut policy = kind.build(20);
+            for idx in 0..3 {
+                policy.insert(idx, tag(idx as u64));
+            }
+            assert_eq!(policy.victim(&|idx| idx == 1), Some(1), "{}", policy.name());
+            assert_eq!(policy.victim(&|idx| idx == 1), None, "{}", policy.name());
+        }
+    }
+
+    #[test]
+    fn test_clock_sweep_spares_used_buffers() {
+        let mut clock = ClockSweep::new(4);
+        for idx in 0..4 {
+            clock.insert(idx, tag(idx as u64));
+        }
+        clock.touch(0);
+        clock.touch(0);
+        clock.touch(2);
+        assert_eq!(evict_all(&mut clock), vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn test_lru_k_evicts_pages_seen_once_first() {
+        let mut lru_k = LruK::new(4, 2);
+        lru_k.insert(0, tag(0));
+        lru_k.touch(0);
+        lru_k.insert(1, tag(1));
+        lru_k.insert(2, tag(2));
+        lru_k.touch(2);
+        lru_k.insert(3, tag(3));
+        // 1 and 3 were referenced once; then 0 before 2 by second reference
+        assert_eq!(evict_all(&mut lru_k), vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn test_two_q_promotes_pages_loaded_again() {
+        let mut two_q = TwoQ::new(4);
+        two_q.insert(0, tag(100));
+        two_q.insert(1, tag(101));
+        two_q.insert(2, tag(102));
+        // a1in holds more than its share, so the oldest entry goes first
+        assert_eq!(two_q.victim(&|_| true), Some(0));
+
+        // Page 100 comes back while remembered and lands in am
+        two_q.insert(0, tag(100));
+        assert!(two_q.am.contains_key(&0));
+        // a1in goes first while over its share, then am is preferred
+        assert_eq!(evict_all(&mut two_q), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_buffer_ring_recycles_oldest_slot() {
+        let mut ring = BufferRing::new(2);
+        assert_eq!(ring.next_victim(), None);
+        ring.record(5, tag(50));
+        assert_eq!(ring.next_victim(), None);
+        ring.record(6, tag(60));
+        assert_eq!(ring.next_victim(), Some((5, tag(50))));
+        ring.record(5, tag(51));
+        assert_eq!(ring.next_victim(), Some((6, tag(60))));
+    }
+}
@@ -0,0 +1,360 @@
+//! Buffer pool warm-up - dump and restore the resident page list
+//!
+//! `BufferWarmup` runs a background thread that keeps a `WarmupFile` with
+//! the tags of the pages resident in the pool, hottest first, and rewrites
+//! it every `dump_interval` and once more when it stops. On start the
+//! thread first reads the hottest pages listed by the previous run back
+//! into free buffers, sorted by page file and block, so a restarted engine
+//! does not begin cold.
+//!
+//! File layout (little endian), tags as written by `BufferTag::encode`:
+//!
+//! ```text
+//! +-------+-------+--------+----------+--------------------------
+//! | magic | count | crc32c | reserved | tag[0] | tag[1] | ...
+//! +-------+-------+--------+----------+--------------------------
+//!    4       4        4         4        24 bytes each
+//! ```
+//!
+//! The checksum covers the tags. The file is written under a temporary
+//! name and renamed into place, so a crash leaves either list intact.
+
+use crate::buffer::{BufferError, BufferMgr, BufferTag};
+use crate::vfs::{VfsError, VfsInterface};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Name of the warm-up file in the data directory
+pub const WARMUP_FILE_NAME: &str = "buffer_warmup.dat";
+
+const WARMUP_MAGIC: u32 = 0x4257524D; // "BWRM"
+const WARMUP_HEADER_SIZE: usize = 16;
+
+/// Pages prefetched between checks for a stop request
+const PREFETCH_BATCH: usize = 64;
+
+/// Whether and how often the resident page list is saved
+#[derive(Debug, Clone)]
+pub struct WarmupConfig {
+    /// Dump the page list and prefetch it on start; off, neither happens
+    pub enabled: bool,
+    /// Time between dumps while the engine runs
+    pub dump_interval: Duration,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dump_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl WarmupConfig {
+    /// Configuration that neither dumps nor prefetches anything
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// File holding the list of resident buffer tags
+pub struct WarmupFile {
+    /// Virtual File System interface for disk I/O
+    vfs: Arc<dyn VfsInterface>,
+    /// Path of the warm-up file
+    path: PathBuf,
+}
+
+impl WarmupFile {
+    /// Warm-up file at `path`; nothing is read or written yet
+    pub fn new(vfs: Arc<dyn VfsInterface>, path: PathBuf) -> Self {
+        Self { vfs, path }
+    }
+
+    /// Replaces the file with `tags`
+    pub fn write(&self, tags: &[BufferTag]) -> Result<(), BufferError> {
+        let mut buf = vec![0u8; WARMUP_HEADER_SIZE + tags.len() * BufferTag::ENCODED_SIZE];
+        for (i, tag) in tags.iter().enumerate() {
+            let entry = WARMUP_HEADER_SIZE + i * BufferTag::ENCODED_SIZE;
+            tag.encode(&mut buf[entry..entry + BufferTag::ENCODED_SIZE]);
+        }
+        let crc = crc32c::crc32c(&buf[WARMUP_HEADER_SIZE..]);
+        buf[0..4].copy_from_slice(&WARMUP_MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&(tags.len() as u32).to_le_bytes());
+        buf[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        let tmp_path = self.path.with_extension("tmp");
+        let tmp = tmp_path.to_str().unwrap();
+        self.vfs.create_file(tmp)?.close()?;
+        let n = self.vfs.pwrite(tmp, &buf, 0)?;
+        if n != buf.len() {
+            return Err(BufferError::Io(format!(
+                "short write to warm-up file: {} of {} bytes",
+                n,
+                buf.len()
+            )));
+        }
+        self.vfs.fsync(tmp)?;
+        self.vfs.rename(tmp, self.path.to_str().unwrap())?;
+        Ok(())
+    }
+
+    /// Returns the tags in the file, hottest first
+    ///
+    /// A missing file, or one that fails its checksum, holds no tags.
+    pub fn read(&self) -> Result<Vec<BufferTag>, BufferError> {
+        let path = self.path.to_str().unwrap();
+
+        let mut header = [0u8; WARMUP_HEADER_SIZE];
+        let n = match self.vfs.pread(path, &mut header, 0) {
+            Ok(n) => n,
+            Err(VfsError::SystemError(errno, _)) if errno == libc::ENOENT => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        if n < WARMUP_HEADER_SIZE || magic != WARMUP_MAGIC {
+            return Ok(Vec::new());
+        }
+        let count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[8..12].try_into().unwrap());
+
+        let mut entries = vec![0u8; count * BufferTag::ENCODED_SIZE];
+        let n = self
+            .vfs
+            .pread(path, &mut entries, WARMUP_HEADER_SIZE as u64)?;
+        if n < entries.len() || crc32c::crc32c(&entries) != crc {
+            return Ok(Vec::new());
+        }
+        Ok(entries
+            .chunks_exact(BufferTag::ENCODED_SIZE)
+            .filter_map(BufferTag::decode)
+            .collect())
+    }
+}
+
+/// Background thread that dumps the resident page list and prefetches it
+/// on start
+pub struct BufferWarmup {
+    config: WarmupConfig,
+    file: Arc<WarmupFile>,
+    /// Pages read in by the prefetch so far
+    pages_prefetched: Arc<AtomicU64>,
+    /// Dropped to wake the thread up and make it exit
+    stop_tx: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BufferWarmup {
+    /// Create a warm-up thread keeping `file`
+    pub fn new(config: WarmupConfig, file: WarmupFile) -> Self {
+        Self {
+            config,
+            file: Arc::new(file),
+            pages_prefetched: Arc::new(AtomicU64::new(0)),
+            stop_tx: None,
+            handle: None,
+        }
+    }
+
+    /// Starts the thread for `buffer_mgr`
+    ///
+    /// The thread prefetches the hottest of the listed pages, as many as
+    /// there are free buffers, reading them sorted by page file and block
+    /// so each file is read sequentially. It then dumps
+    /// the list every `dump_interval` until stopped. Does nothing when the
+    /// config is disabled; restarts the thread if it is running.
+    pub fn start(&mut self, buffer_mgr: Arc<BufferMgr>) {
+        self.stop();
+        if !self.config.enabled {
+            return;
+        }
+
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let interval = self.config.dump_interval;
+        let file = Arc::clone(&self.file);
+        let pages_prefetched = Arc::clone(&self.pages_prefetched);
+
+        self.stop_tx = Some(stop_tx);
+        self.handle = Some(thread::spawn(move || {
+            let mut tags = file.read().unwrap_or_else(|e| {
+                eprintln!("Buffer: cannot read the warm-up file: {}", e);
+                Vec::new()
+            });
+            // Only the hottest pages that fit in the free buffers are read
+            tags.retain(|tag| buffer_mgr.lookup_tag(tag).is_none());
+            tags.truncate(buffer_mgr.free_buffer_count());
+            tags.sort_by_key(|t| (t.tablespace_id, t.relation_id, t.fork as u8, t.block));
+            for batch in tags.chunks(PREFETCH_BATCH) {
+                // Stopped before the pool warmed up: keep the old list
+                if let Err(mpsc::TryRecvError::Disconnected) = stop_rx.try_recv() {
+                    return;
+                }
+                let n = buffer_mgr.prefetch(batch);
+                pages_prefetched.fetch_add(n as u64, Ordering::Relaxed);
+            }
+
+            loop {
+                let stopping = !matches!(
+                    stop_rx.recv_timeout(interval),
+                    Err(mpsc::RecvTimeoutError::Timeout)
+                );
+                if let Err(e) = file.write(&buffer_mgr.resident_tags()) {
+                    eprintln!("Buffer: cannot write the warm-up file: {}", e);
+                }
+                if stopping {
+                    return;
+                }
+            }
+        }));
+    }
+
+    /// Returns true while the warm-up thread is running
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Returns the number of pages the thread has prefetched
+    pub fn pages_prefetched(&self) -> u64 {
+        self.pages_prefetched.load(Ordering::Relaxed)
+    }
+
+    /// Stop the thread
+    ///
+    /// Wakes the thread up, which dumps the page list a last time unless it
+    /// was still prefetching, and waits for it to finish.
+    pub fn stop(&mut self) {
+        self.stop_tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for BufferWarmup {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ForkNumber;
+    use crate::vfs::LocalFs;
+    use std::time::Instant;
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn warmup_file(dir: &tempfile::TempDir) -> WarmupFile {
+        WarmupFile::new(Arc::new(LocalFs::new()), dir.path().join(WARMUP_FILE_NAME))
+    }
+
+    #[test]
+    fn test_warmup_file_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = warmup_file(&dir);
+        assert!(file.read().unwrap().is_empty());
+
+        let tags = vec![
+            BufferTag::new(0, 4, ForkNumber::Main, 9),
+            BufferTag::new(2, 1, ForkNumber::Fsm, 0),
+        ];
+        file.write(&tags).unwrap();
+        assert_eq!(file.read().unwrap(), tags);
+
+        // A damaged list is ignored rather than half-used
+        let path = dir.path().join(WARMUP_FILE_NAME);
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+        assert!(file.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_warmup_restores_hot_pages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let vfs = Arc::new(LocalFs::new());
+        let mgr = Arc::new(BufferMgr::init(8, vfs.clone(), dir.path().to_path_buf()));
+        for block in 0..8u64 {
+            mgr.new_page(block).unwrap().payload_mut()[0] = block as u8;
+        }
+        mgr.flush_all().unwrap();
+        let mut warmup = BufferWarmup::new(WarmupConfig::default(), warmup_file(&dir));
+        warmup.start(Arc::clone(&mgr));
+        warmup.stop();
+        drop(mgr);
+
+        // A smaller pool gets as many of the listed pages as it has room for
+        let mgr = Arc::new(BufferMgr::init(4, vfs, dir.path().to_path_buf()));
+        let mut warmup = BufferWarmup::new(WarmupConfig::default(), warmup_file(&dir));
+        warmup.start(Arc::clone(&mgr));
+        wait_until(|| warmup.pages_prefetched() == 4);
+        assert_eq!(mgr.free_buffer_count(), 0);
+        let resident = mgr.resident_tags();
+        assert_eq!(resident.len(), 4);
+        for tag in resident {
+            let page = mgr.read_buffer(tag).unwrap();
+            assert_eq!(page.payload()[0] as u64, tag.block);
+        }
+    }
+
+    #[test]
+    fn test_warmup_prefers_the_hottest_pages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let vfs = Arc::new(LocalFs::new());
+        {
+            let mgr = BufferMgr::init(8, vfs.clone(), dir.path().to_path_buf());
+            for block in 0..8u64 {
+                mgr.new_page(block).unwrap();
+            }
+            mgr.flush_all().unwrap();
+        }
+        let hottest_first: Vec<BufferTag> = (0..8u64)
+            .rev()
+            .map(|block| BufferTag::new(0, 0, ForkNumber::Main, block))
+            .collect();
+        warmup_file(&dir).write(&hottest_first).unwrap();
+
+        // The hottest page is already back; the free buffers go to the next
+        // hottest ones, not to the lowest blocks
+        let mgr = Arc::new(BufferMgr::init(4, vfs, dir.path().to_path_buf()));
+        mgr.read_buffer(hottest_first[0]).unwrap();
+        let mut warmup = BufferWarmup::new(WarmupConfig::default(), warmup_file(&dir));
+        warmup.start(Arc::clone(&mgr));
+        wait_until(|| warmup.pages_prefetched() == 3);
+        let mut blocks: Vec<u64> = mgr.resident_tags().iter().map(|t| t.block).collect();
+        blocks.sort_unstable();
+        assert_eq!(blocks, vec![4, 5, 6, 7]);
+        warmup.stop();
+    }
+
+    #[test]
+    fn test_disabled_warmup_starts_no_thread() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = Arc::new(BufferMgr::init(
+            8,
+            Arc::new(LocalFs::new()),
+            dir.path().to_path_buf(),
+        ));
+        mgr.new_page(1).unwrap();
+        let mut warmup = BufferWarmup::new(WarmupConfig::disabled(), warmup_file(&dir));
+        warmup.start(mgr);
+        assert!(!warmup.is_running());
+        warmup.stop();
+        assert!(!dir.path().join(WARMUP_FILE_NAME).exists());
+    }
+}
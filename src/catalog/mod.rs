@@ -1,5 +1,5 @@
 use crate::catalog::error::{CatalogError, CatalogResult};
-use crate::table::{Column, Table, TableBuilder, TableType};
+use crate::table::{Column, Table, TableBuilder, TableType, INITIAL_SCHEMA_VERSION};
 use crate::types::SegmentId;
 use parking_lot::RwLock;
 use std::collections::{HashMap, HashSet};
@@ -201,14 +201,15 @@ impl Catalog {
         let mut content = String::new();
 
         content.push_str(&format!(
-            "{}|{}|{}|{:?}|{}|{}|{}\n",
+            "{}|{}|{}|{:?}|{}|{}|{}|{}\n",
             table.table_id,
             table.table_name,
             table.segment_id,
             table.table_type,
             table.row_count,
             table.column_count,
-            table.created_at
+            table.created_at,
+            table.schema_version
         ));
 
         for col in table.columns() {
@@ -235,8 +236,9 @@ impl Catalog {
             _ => return Ok(None),
         };
 
+        // Files written before schema versions existed have 7 fields
         let parts: Vec<&str> = header.split('|').collect();
-        if parts.len() != 7 {
+        if parts.len() != 7 && parts.len() != 8 {
             return Err(CatalogError::ParseError(format!(
                 "Invalid table file format: {:?}",
                 path
@@ -265,6 +267,12 @@ impl Catalog {
         let created_at: u64 = parts[6]
             .parse::<u64>()
             .map_err(|e| CatalogError::ParseError(e.to_string()))?;
+        let schema_version: u32 = match parts.get(7) {
+            Some(version) => version
+                .parse::<u32>()
+                .map_err(|e| CatalogError::ParseError(e.to_string()))?,
+            None => INITIAL_SCHEMA_VERSION,
+        };
 
         let mut columns = Vec::new();
         for line in lines {
@@ -290,6 +298,7 @@ impl Catalog {
         table.set_columns(columns);
         table.row_count = row_count;
         table.created_at = created_at;
+        table.schema_version = schema_version;
 
         Ok(Some(Arc::new(table)))
     }
@@ -30,6 +30,29 @@ fn test_catalog_create_and_load() {
     assert_eq!(loaded_table.column_count(), 2);
 }
 
+#[test]
+fn test_catalog_schema_version_persistence() {
+    let temp_dir = TempDir::new().unwrap();
+    let catalog = Catalog::new(temp_dir.path()).unwrap();
+    let columns = vec![Column::new("id".to_string(), ColumnType::Int64, false, 0)];
+    let table = catalog.create_table("versioned", 100, columns).unwrap();
+    assert_eq!(table.schema_version(), INITIAL_SCHEMA_VERSION);
+
+    // A table file from before schema versions lacks the last header field
+    fs::write(
+        temp_dir.path().join("system").join("legacy.tbl"),
+        "7|legacy|200|User|0|1|0\nCOLUMN|id|Int64|false|0\n",
+    )
+    .unwrap();
+
+    let loaded_catalog = Catalog::load(temp_dir.path()).unwrap();
+    for name in ["versioned", "legacy"] {
+        let loaded = loaded_catalog.get_table(name).unwrap();
+        assert_eq!(loaded.schema_version(), INITIAL_SCHEMA_VERSION);
+        assert_eq!(loaded.column_count(), 1);
+    }
+}
+
 #[test]
 fn test_catalog_get_table_by_id() {
     let temp_dir = TempDir::new().unwrap();
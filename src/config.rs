@@ -0,0 +1,645 @@
+//! Storage engine configuration
+//!
+//! `AistoreConfig` collects the settings `StorageEngine::open` needs: the
+//! buffer pool size, the WAL settings (including the checkpoint interval
+//! and the commit sync mode), the lock timeout, the background flush
+//! policy, the bad page policy, buffer warm-up and autovacuum.
+//!
+//! Settings can be kept in the data directory, as `aistore.toml` or
+//! `aistore.json`. Keys left out keep their defaults:
+//!
+//! ```toml
+//! buffer_pool_size = 10000
+//! lock_timeout_secs = 30
+//! bad_page_policy = "fail"            # or "repair_from_double_write"
+//!
+//! [wal]
+//! sync_mode = "full"                  # or "normal"
+//! checkpoint_interval_sec = 60         # 0 checkpoints only on flush
+//! group_commit_timeout_ms = 10
+//!
+//! [flush]
+//! policy = "dirty_ratio"              # "interval", "dirty_count", "manual"
+//! threshold = 0.1                     # interval_ms / count for the others
+//!
+//! [warmup]
+//! enabled = true
+//! dump_interval_sec = 60
+//!
+//! [autovacuum]
+//! enabled = true
+//! naptime_sec = 60
+//! threshold = 50                      # dead rows, plus
+//! scale_factor = 0.2                  # this fraction of the live rows
+//! ```
+//!
+//! Both formats are deserialized into the same tree of values, so a
+//! setting is checked the same way whichever file it comes from.
+
+use crate::buffer::{BadPagePolicy, FlushPolicy, WarmupConfig};
+use crate::heap::AutovacuumConfig;
+use crate::wal::config::{SyncMode, WalConfig};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the TOML config file in the data directory
+pub const CONFIG_FILE_TOML: &str = "aistore.toml";
+
+/// Name of the JSON config file in the data directory
+pub const CONFIG_FILE_JSON: &str = "aistore.json";
+
+/// Largest buffer pool, in pages, the mapping table can index
+pub const MAX_BUFFER_POOL_SIZE: usize = (u32::MAX - 1) as usize;
+
+/// Smallest WAL file; a file must hold full-page images with room to spare
+pub const MIN_WAL_FILE_SIZE: u64 = 1 << 20;
+
+/// Configuration error
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The config file could not be read
+    Io(String),
+    /// The config file is not valid TOML or JSON
+    Parse(String),
+    /// A key the config does not have
+    UnknownKey(String),
+    /// A value of the wrong type or out of range
+    Invalid { key: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(msg) => write!(f, "Config I/O error: {}", msg),
+            ConfigError::Parse(msg) => write!(f, "Config parse error: {}", msg),
+            ConfigError::UnknownKey(key) => write!(f, "Unknown config key: {}", key),
+            ConfigError::Invalid { key, reason } => {
+                write!(f, "Invalid config value for {}: {}", key, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Configuration result
+pub type ConfigResult<T> = Result<T, ConfigError>;
+
+fn invalid(key: &str, reason: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        reason: reason.into(),
+    }
+}
+
+/// Storage engine configuration
+#[derive(Debug, Clone)]
+pub struct AistoreConfig {
+    /// Data directory path
+    pub data_dir: PathBuf,
+    /// Buffer pool size (in pages, default 10000)
+    pub buffer_pool_size: usize,
+    /// WAL settings; relative directories are taken from `data_dir`
+    pub wal: WalConfig,
+    /// Seconds a row or table lock request waits before timing out
+    /// (default 30)
+    pub lock_timeout_secs: u64,
+    /// When the background flusher writes dirty pages
+    pub flush_policy: FlushPolicy,
+    /// What to do with a page that fails its checksum
+    pub bad_page_policy: BadPagePolicy,
+    /// Buffer pool warm-up across restarts
+    pub warmup: WarmupConfig,
+    /// When tables with deleted rows are vacuumed without being asked
+    pub autovacuum: AutovacuumConfig,
+}
+
+impl Default for AistoreConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("./data"),
+            buffer_pool_size: 10000,
+            wal: WalConfig::default(),
+            lock_timeout_secs: 30,
+            flush_policy: FlushPolicy::default(),
+            bad_page_policy: BadPagePolicy::Fail,
+            warmup: WarmupConfig::default(),
+            autovacuum: AutovacuumConfig::default(),
+        }
+    }
+}
+
+impl AistoreConfig {
+    /// Default configuration for `data_dir`
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Configuration for `data_dir`, read from its config file
+    ///
+    /// `aistore.toml` is used if present, otherwise `aistore.json`; with
+    /// neither the defaults apply. The result is validated.
+    pub fn load(data_dir: impl Into<PathBuf>) -> ConfigResult<Self> {
+        let data_dir = data_dir.into();
+        let toml_path = data_dir.join(CONFIG_FILE_TOML);
+        let json_path = data_dir.join(CONFIG_FILE_JSON);
+        if toml_path.exists() {
+            Self::from_toml(data_dir, &read_file(&toml_path)?)
+        } else if json_path.exists() {
+            Self::from_json(data_dir, &read_file(&json_path)?)
+        } else {
+            let config = Self::new(data_dir);
+            config.validate()?;
+            Ok(config)
+        }
+    }
+
+    /// Configuration for `data_dir` with the settings in TOML `text`
+    pub fn from_toml(data_dir: impl Into<PathBuf>, text: &str) -> ConfigResult<Self> {
+        let value: Value = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        let mut config = Self::new(data_dir);
+        config.apply(&value)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Configuration for `data_dir` with the settings in JSON `text`
+    pub fn from_json(data_dir: impl Into<PathBuf>, text: &str) -> ConfigResult<Self> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        let mut config = Self::new(data_dir);
+        config.apply(&value)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Create a config with custom buffer pool size
+    pub fn with_buffer_pool_size(mut self, pages: usize) -> Self {
+        self.buffer_pool_size = pages;
+        self
+    }
+
+    /// Create a config with custom WAL settings
+    pub fn with_wal(mut self, wal: WalConfig) -> Self {
+        self.wal = wal;
+        self
+    }
+
+    /// Create a config with custom checkpoint interval
+    pub fn with_checkpoint_interval(mut self, interval_sec: u64) -> Self {
+        self.wal.checkpoint_interval_sec = interval_sec;
+        self
+    }
+
+    /// Create a config with custom commit sync mode
+    pub fn with_sync_mode(mut self, mode: SyncMode) -> Self {
+        self.wal.sync_mode = mode;
+        self
+    }
+
+    /// Create a config with custom lock timeout
+    pub fn with_lock_timeout(mut self, timeout_secs: u64) -> Self {
+        self.lock_timeout_secs = timeout_secs;
+        self
+    }
+
+    /// Create a config with custom flush policy
+    pub fn with_flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Create a config with custom bad page policy
+    pub fn with_bad_page_policy(mut self, policy: BadPagePolicy) -> Self {
+        self.bad_page_policy = policy;
+        self
+    }
+
+    /// Create a config with custom warm-up settings
+    pub fn with_warmup(mut self, warmup: WarmupConfig) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Create a config with custom autovacuum settings
+    pub fn with_autovacuum(mut self, autovacuum: AutovacuumConfig) -> Self {
+        self.autovacuum = autovacuum;
+        self
+    }
+
+    /// WAL settings with the log and archive directories resolved against
+    /// `data_dir`
+    pub fn wal_config(&self) -> WalConfig {
+        let mut wal = self.wal.clone();
+        wal.log_dir = self.data_dir.join(&wal.log_dir);
+        wal.archive_dir = wal.archive_dir.map(|dir| self.data_dir.join(dir));
+        wal
+    }
+
+    /// Check every setting is in range
+    pub fn validate(&self) -> ConfigResult<()> {
+        if self.buffer_pool_size == 0 || self.buffer_pool_size > MAX_BUFFER_POOL_SIZE {
+            return Err(invalid(
+                "buffer_pool_size",
+                format!("must be between 1 and {} pages", MAX_BUFFER_POOL_SIZE),
+            ));
+        }
+        if self.wal.max_file_size < MIN_WAL_FILE_SIZE {
+            return Err(invalid(
+                "wal.max_file_size",
+                format!("must be at least {} bytes", MIN_WAL_FILE_SIZE),
+            ));
+        }
+        if self.wal.buffer_size == 0 {
+            return Err(invalid("wal.buffer_size", "must not be zero"));
+        }
+        if self.wal.group_commit_batch == 0 {
+            return Err(invalid("wal.group_commit_batch", "must not be zero"));
+        }
+        if self.wal.group_commit_timeout_ms == 0 {
+            return Err(invalid("wal.group_commit_timeout_ms", "must not be zero"));
+        }
+        if self.lock_timeout_secs == 0 {
+            return Err(invalid("lock_timeout_secs", "must not be zero"));
+        }
+        match self.flush_policy {
+            FlushPolicy::DirtyRatio { threshold } => {
+                if !(threshold > 0.0 && threshold <= 1.0) {
+                    return Err(invalid("flush.threshold", "must be in (0, 1]"));
+                }
+            }
+            FlushPolicy::Interval { duration } => {
+                if duration.is_zero() {
+                    return Err(invalid("flush.interval_ms", "must not be zero"));
+                }
+            }
+            FlushPolicy::DirtyCount { count } => {
+                if count == 0 || count > self.buffer_pool_size {
+                    return Err(invalid(
+                        "flush.count",
+                        "must be between 1 and the buffer pool size",
+                    ));
+                }
+            }
+            FlushPolicy::Manual => {}
+        }
+        if self.warmup.enabled && self.warmup.dump_interval.is_zero() {
+            return Err(invalid("warmup.dump_interval_sec", "must not be zero"));
+        }
+        if self.autovacuum.enabled && self.autovacuum.naptime.is_zero() {
+            return Err(invalid("autovacuum.naptime_sec", "must not be zero"));
+        }
+        let scale_factor = self.autovacuum.scale_factor;
+        if !(scale_factor >= 0.0 && scale_factor.is_finite()) {
+            return Err(invalid("autovacuum.scale_factor", "must not be negative"));
+        }
+        Ok(())
+    }
+
+    /// Overwrite the settings present in `value`
+    fn apply(&mut self, value: &Value) -> ConfigResult<()> {
+        let root = value
+            .as_object()
+            .ok_or_else(|| ConfigError::Parse("expected a table of settings".to_string()))?;
+        for (key, value) in root {
+            match key.as_str() {
+                "buffer_pool_size" => self.buffer_pool_size = get_usize(key, value)?,
+                "lock_timeout_secs" => self.lock_timeout_secs = get_u64(key, value)?,
+                "bad_page_policy" => {
+                    self.bad_page_policy = match get_str(key, value)? {
+                        "fail" => BadPagePolicy::Fail,
+                        "repair_from_double_write" => BadPagePolicy::RepairFromDoubleWrite,
+                        other => return Err(invalid(key, format!("unknown policy \"{}\"", other))),
+                    }
+                }
+                "wal" => self.apply_wal(get_table(key, value)?)?,
+                "flush" => self.apply_flush(get_table(key, value)?)?,
+                "warmup" => self.apply_warmup(get_table(key, value)?)?,
+                "autovacuum" => self.apply_autovacuum(get_table(key, value)?)?,
+                _ => return Err(ConfigError::UnknownKey(key.clone())),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_wal(&mut self, table: &Map<String, Value>) -> ConfigResult<()> {
+        for (name, value) in table {
+            let key = &format!("wal.{}", name);
+            let wal = &mut self.wal;
+            match name.as_str() {
+                "enabled" => wal.enabled = get_bool(key, value)?,
+                "log_dir" => wal.log_dir = PathBuf::from(get_str(key, value)?),
+                "archive_dir" => wal.archive_dir = Some(PathBuf::from(get_str(key, value)?)),
+                "max_file_size" => wal.max_file_size = get_u64(key, value)?,
+                "buffer_size" => wal.buffer_size = get_usize(key, value)?,
+                "group_commit_batch" => wal.group_commit_batch = get_usize(key, value)?,
+                "group_commit_timeout_ms" => wal.group_commit_timeout_ms = get_u64(key, value)?,
+                "checkpoint_interval_sec" => wal.checkpoint_interval_sec = get_u64(key, value)?,
+                "sync_mode" => {
+                    wal.sync_mode = match get_str(key, value)? {
+                        "full" => SyncMode::Full,
+                        "normal" => SyncMode::Normal,
+                        other => return Err(invalid(key, format!("unknown mode \"{}\"", other))),
+                    }
+                }
+                _ => return Err(ConfigError::UnknownKey(key.clone())),
+            }
+        }
+        Ok(())
+    }
+
+    /// The `policy` key picks the variant, which then needs its own
+    /// parameter: `threshold`, `interval_ms` or `count`
+    fn apply_flush(&mut self, table: &Map<String, Value>) -> ConfigResult<()> {
+        let policy = match table.get("policy") {
+            Some(value) => get_str("flush.policy", value)?,
+            None => match self.flush_policy {
+                FlushPolicy::DirtyRatio { .. } => "dirty_ratio",
+                FlushPolicy::Interval { .. } => "interval",
+                FlushPolicy::DirtyCount { .. } => "dirty_count",
+                FlushPolicy::Manual => "manual",
+            },
+        };
+        let parameter = match policy {
+            "dirty_ratio" => Some("threshold"),
+            "interval" => Some("interval_ms"),
+            "dirty_count" => Some("count"),
+            "manual" => None,
+            other => {
+                return Err(invalid(
+                    "flush.policy",
+                    format!("unknown policy \"{}\"", other),
+                ))
+            }
+        };
+        for name in table.keys() {
+            match name.as_str() {
+                "policy" => {}
+                "threshold" | "interval_ms" | "count" if parameter != Some(name.as_str()) => {
+                    return Err(invalid(
+                        &format!("flush.{}", name),
+                        format!("does not apply to the {} policy", policy),
+                    ))
+                }
+                "threshold" | "interval_ms" | "count" => {}
+                _ => return Err(ConfigError::UnknownKey(format!("flush.{}", name))),
+            }
+        }
+
+        let Some(parameter) = parameter else {
+            self.flush_policy = FlushPolicy::Manual;
+            return Ok(());
+        };
+        let key = &format!("flush.{}", parameter);
+        let value = table
+            .get(parameter)
+            .ok_or_else(|| invalid(key, format!("required by the {} policy", policy)))?;
+        self.flush_policy = match parameter {
+            "threshold" => FlushPolicy::DirtyRatio {
+                threshold: get_f64(key, value)?,
+            },
+            "interval_ms" => FlushPolicy::Interval {
+                duration: Duration::from_millis(get_u64(key, value)?),
+            },
+            _ => FlushPolicy::DirtyCount {
+                count: get_usize(key, value)?,
+            },
+        };
+        Ok(())
+    }
+
+    fn apply_warmup(&mut self, table: &Map<String, Value>) -> ConfigResult<()> {
+        for (name, value) in table {
+            let key = &format!("warmup.{}", name);
+            match name.as_str() {
+                "enabled" => self.warmup.enabled = get_bool(key, value)?,
+                "dump_interval_sec" => {
+                    self.warmup.dump_interval = Duration::from_secs(get_u64(key, value)?)
+                }
+                _ => return Err(ConfigError::UnknownKey(key.clone())),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_autovacuum(&mut self, table: &Map<String, Value>) -> ConfigResult<()> {
+        for (name, value) in table {
+            let key = &format!("autovacuum.{}", name);
+            let autovacuum = &mut self.autovacuum;
+            match name.as_str() {
+                "enabled" => autovacuum.enabled = get_bool(key, value)?,
+                "naptime_sec" => autovacuum.naptime = Duration::from_secs(get_u64(key, value)?),
+                "threshold" => autovacuum.threshold = get_u64(key, value)?,
+                "scale_factor" => autovacuum.scale_factor = get_f64(key, value)?,
+                _ => return Err(ConfigError::UnknownKey(key.clone())),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> ConfigResult<String> {
+    std::fs::read_to_string(path).map_err(|e| ConfigError::Io(format!("{}: {}", path.display(), e)))
+}
+
+fn get_table<'a>(key: &str, value: &'a Value) -> ConfigResult<&'a Map<String, Value>> {
+    value
+        .as_object()
+        .ok_or_else(|| invalid(key, "expected a table"))
+}
+
+fn get_str<'a>(key: &str, value: &'a Value) -> ConfigResult<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(key, "expected a string"))
+}
+
+fn get_bool(key: &str, value: &Value) -> ConfigResult<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid(key, "expected true or false"))
+}
+
+fn get_u64(key: &str, value: &Value) -> ConfigResult<u64> {
+    value
+        .as_u64()
+        .ok_or_else(|| invalid(key, "expected a non-negative integer"))
+}
+
+fn get_usize(key: &str, value: &Value) -> ConfigResult<usize> {
+    usize::try_from(get_u64(key, value)?).map_err(|_| invalid(key, "value too large"))
+}
+
+fn get_f64(key: &str, value: &Value) -> ConfigResult<f64> {
+    value
+        .as_f64()
+        .ok_or_else(|| invalid(key, "expected a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_valid() {
+        let config = AistoreConfig::new("/tmp/aistore");
+        assert!(config.validate().is_ok());
+        assert_eq!(config.buffer_pool_size, 10000);
+        assert_eq!(config.lock_timeout_secs, 30);
+        assert_eq!(config.wal.sync_mode, SyncMode::Full);
+        assert_eq!(
+            config.wal_config().log_dir,
+            PathBuf::from("/tmp/aistore/./wal")
+        );
+    }
+
+    #[test]
+    fn test_config_from_toml() {
+        let text = r#"
+            # Small pool, relaxed commits
+            buffer_pool_size = 2_048
+            lock_timeout_secs = 5
+            bad_page_policy = "repair_from_double_write"
+
+            [wal]
+            sync_mode = "normal"      # the group commit thread syncs
+            checkpoint_interval_sec = 0
+            log_dir = '/var/lib/aistore/wal#1'
+
+            [flush]
+            policy = "dirty_ratio"
+            threshold = 0.25
+
+            [warmup]
+            enabled = false
+
+            [autovacuum]
+            naptime_sec = 5
+            scale_factor = 0.05
+        "#;
+        let config = AistoreConfig::from_toml("/data", text).unwrap();
+        assert_eq!(config.buffer_pool_size, 2048);
+        assert_eq!(config.lock_timeout_secs, 5);
+        assert_eq!(config.bad_page_policy, BadPagePolicy::RepairFromDoubleWrite);
+        assert_eq!(config.wal.sync_mode, SyncMode::Normal);
+        assert_eq!(config.wal.checkpoint_interval_sec, 0);
+        assert_eq!(
+            config.wal_config().log_dir,
+            PathBuf::from("/var/lib/aistore/wal#1")
+        );
+        assert!(matches!(
+            config.flush_policy,
+            FlushPolicy::DirtyRatio { threshold } if threshold == 0.25
+        ));
+        assert!(!config.warmup.enabled);
+        assert!(config.autovacuum.enabled);
+        assert_eq!(config.autovacuum.naptime, Duration::from_secs(5));
+        assert_eq!(config.autovacuum.threshold, 50);
+        assert_eq!(config.autovacuum.scale_factor, 0.05);
+    }
+
+    #[test]
+    fn test_config_from_json() {
+        let text = r#"{
+            "buffer_pool_size": 512,
+            "wal": { "group_commit_timeout_ms": 5 },
+            "flush": { "policy": "interval", "interval_ms": 250 }
+        }"#;
+        let config = AistoreConfig::from_json("/data", text).unwrap();
+        assert_eq!(config.buffer_pool_size, 512);
+        assert_eq!(config.wal.group_commit_timeout_ms, 5);
+        assert!(matches!(
+            config.flush_policy,
+            FlushPolicy::Interval { duration } if duration == Duration::from_millis(250)
+        ));
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let err = |text: &str| AistoreConfig::from_toml("/data", text).unwrap_err();
+
+        assert_eq!(
+            err("buffer_pool_size = 0"),
+            invalid(
+                "buffer_pool_size",
+                format!("must be between 1 and {} pages", MAX_BUFFER_POOL_SIZE)
+            )
+        );
+        assert_eq!(
+            err("lock_timeout_secs = -1"),
+            invalid("lock_timeout_secs", "expected a non-negative integer")
+        );
+        assert_eq!(
+            err("[wal]\nsync_mode = \"sometimes\""),
+            invalid("wal.sync_mode", "unknown mode \"sometimes\"")
+        );
+        assert_eq!(
+            err("[flush]\nthreshold = 1.5"),
+            invalid("flush.threshold", "must be in (0, 1]")
+        );
+        assert_eq!(
+            err("[flush]\npolicy = \"manual\"\ncount = 10"),
+            invalid("flush.count", "does not apply to the manual policy")
+        );
+        assert_eq!(
+            err("[flush]\npolicy = \"dirty_count\""),
+            invalid("flush.count", "required by the dirty_count policy")
+        );
+        assert_eq!(
+            err("[wal]\nmax_file_size = 4096"),
+            invalid(
+                "wal.max_file_size",
+                format!("must be at least {} bytes", MIN_WAL_FILE_SIZE)
+            )
+        );
+        assert_eq!(
+            err("[warmup]\nintervals = 3"),
+            ConfigError::UnknownKey("warmup.intervals".to_string())
+        );
+        assert_eq!(
+            err("[autovacuum]\nnaptime_sec = 0"),
+            invalid("autovacuum.naptime_sec", "must not be zero")
+        );
+        assert_eq!(
+            err("[autovacuum]\nscale_factor = -0.5"),
+            invalid("autovacuum.scale_factor", "must not be negative")
+        );
+        assert!(matches!(
+            err("buffer_pool_size = 10\nbuffer_pool_size = 20"),
+            ConfigError::Parse(_)
+        ));
+        assert!(matches!(err("[wal\nenabled = true"), ConfigError::Parse(_)));
+        assert!(matches!(
+            AistoreConfig::from_json("/data", "{ buffer_pool_size: 1 }"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_from_data_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = AistoreConfig::load(dir.path()).unwrap();
+        assert_eq!(config.buffer_pool_size, 10000);
+
+        std::fs::write(
+            dir.path().join(CONFIG_FILE_JSON),
+            r#"{"buffer_pool_size": 64}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            AistoreConfig::load(dir.path()).unwrap().buffer_pool_size,
+            64
+        );
+
+        // The TOML file wins when both are there
+        std::fs::write(dir.path().join(CONFIG_FILE_TOML), "buffer_pool_size = 32").unwrap();
+        let config = AistoreConfig::load(dir.path()).unwrap();
+        assert_eq!(config.buffer_pool_size, 32);
+        assert_eq!(config.data_dir, dir.path());
+    }
+}
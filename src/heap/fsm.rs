@@ -0,0 +1,239 @@
+//! Free space map - how much room each heap block has
+//!
+//! The map lives in the relation's `ForkNumber::Fsm` fork. Every map page
+//! holds one category byte per heap block: the block's free space in
+//! units of `FSM_UNIT` bytes, rounded down, so a block is never promised
+//! more room than it has. Heap block `b` is slot `b % FSM_SLOTS_PER_PAGE`
+//! of map block `b / FSM_SLOTS_PER_PAGE`.
+//!
+//! ```text
+//! +---------------+-------+----------+---------+---------+-----
+//! | PageHeader    | magic | reserved | slot[0] | slot[1] | ...
+//! +---------------+-------+----------+---------+---------+-----
+//!                     4        4         1         1
+//! ```
+//!
+//! The map is not WAL-logged. After a crash it can be stale either way;
+//! callers check the block they are given and record its real free space
+//! when it does not have the room.
+
+use super::{HeapError, HeapResult};
+use crate::buffer::{BufferMgr, BufferTag};
+use crate::page::page::PageHeader;
+use crate::types::{ForkNumber, SegmentId, DEFAULT_TABLESPACE_ID, PAGE_SIZE};
+use std::sync::Arc;
+
+/// Bytes of free space per category step
+pub const FSM_UNIT: usize = PAGE_SIZE / 256;
+
+const FSM_MAGIC: u32 = 0x46534D31; // "FSM1"
+const FSM_HEADER_SIZE: usize = 8;
+
+/// Heap blocks covered by one map page
+pub const FSM_SLOTS_PER_PAGE: usize =
+    PAGE_SIZE - std::mem::size_of::<PageHeader>() - FSM_HEADER_SIZE;
+
+/// Category recorded for a block with `free_bytes` of room
+#[inline]
+fn category(free_bytes: usize) -> u8 {
+    (free_bytes / FSM_UNIT).min(u8::MAX as usize) as u8
+}
+
+/// Free space map of one heap relation
+pub struct FreeSpaceMap {
+    buffer_mgr: Arc<BufferMgr>,
+    relation_id: SegmentId,
+    /// Per map page, an upper bound of its highest category; recording
+    /// raises it and a search that comes up empty lowers it
+    page_max: Vec<u8>,
+    /// Heap block the next search starts at, so consecutive inserts keep
+    /// filling the same block
+    next_block: u64,
+}
+
+impl FreeSpaceMap {
+    /// Empty map of `relation_id`; pages are initialized as blocks are
+    /// recorded, whatever the fork held before
+    pub fn new(buffer_mgr: Arc<BufferMgr>, relation_id: SegmentId) -> Self {
+        Self {
+            buffer_mgr,
+            relation_id,
+            page_max: Vec::new(),
+            next_block: 0,
+        }
+    }
+
+    /// Reads the map of `relation_id` covering heap blocks `0..=last_block`
+    ///
+    /// Returns None when a map page is missing or unreadable, in which
+    /// case the map has to be rebuilt from the heap.
+    pub fn load(
+        buffer_mgr: Arc<BufferMgr>,
+        relation_id: SegmentId,
+        last_block: u64,
+    ) -> Option<Self> {
+        let mut fsm = Self::new(buffer_mgr, relation_id);
+        let pages = (last_block as usize + 1).div_ceil(FSM_SLOTS_PER_PAGE);
+        for fsm_block in 0..pages {
+            let page = fsm.buffer_mgr.read_buffer(fsm.tag(fsm_block)).ok()?;
+            let payload = page.payload();
+            if u32::from_le_bytes(payload[0..4].try_into().unwrap()) != FSM_MAGIC {
+                return None;
+            }
+            let max = payload[FSM_HEADER_SIZE..].iter().max().copied();
+            fsm.page_max.push(max.unwrap_or(0));
+        }
+        Some(fsm)
+    }
+
+    fn tag(&self, fsm_block: usize) -> BufferTag {
+        BufferTag::new(
+            DEFAULT_TABLESPACE_ID,
+            self.relation_id,
+            ForkNumber::Fsm,
+            fsm_block as u64,
+        )
+    }
+
+    /// Records that heap `block` has `free_bytes` of room
+    pub fn record(&mut self, block: u64, free_bytes: usize) -> HeapResult<()> {
+        let fsm_block = block as usize / FSM_SLOTS_PER_PAGE;
+        let slot = block as usize % FSM_SLOTS_PER_PAGE;
+        while self.page_max.len() <= fsm_block {
+            let mut page = self
+                .buffer_mgr
+                .new_buffer(self.tag(self.page_max.len()))
+                .map_err(|e| HeapError::Other(e.to_string()))?;
+            page.payload_mut()[0..4].copy_from_slice(&FSM_MAGIC.to_le_bytes());
+            self.page_max.push(0);
+        }
+
+        let cat = category(free_bytes);
+        let mut page = self
+            .buffer_mgr
+            .write_buffer(self.tag(fsm_block))
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        page.payload_mut()[FSM_HEADER_SIZE + slot] = cat;
+        self.page_max[fsm_block] = self.page_max[fsm_block].max(cat);
+        Ok(())
+    }
+
+    /// Returns the category recorded for heap `block`
+    pub fn get(&self, block: u64) -> HeapResult<u8> {
+        let fsm_block = block as usize / FSM_SLOTS_PER_PAGE;
+        if fsm_block >= self.page_max.len() {
+            return Ok(0);
+        }
+        let page = self
+            .buffer_mgr
+            .read_buffer(self.tag(fsm_block))
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        Ok(page.payload()[FSM_HEADER_SIZE + block as usize % FSM_SLOTS_PER_PAGE])
+    }
+
+    /// Returns a heap block recorded with at least `needed` bytes of room
+    ///
+    /// The search starts at the block it returned last and wraps around,
+    /// skipping map pages whose bound says they have no such block.
+    pub fn search(&mut self, needed: usize) -> HeapResult<Option<u64>> {
+        let want = needed.max(1).div_ceil(FSM_UNIT);
+        if want > u8::MAX as usize || self.page_max.is_empty() {
+            return Ok(None);
+        }
+        let want = want as u8;
+
+        let pages = self.page_max.len();
+        let start_page = (self.next_block as usize / FSM_SLOTS_PER_PAGE).min(pages - 1);
+        for i in 0..pages {
+            let fsm_block = (start_page + i) % pages;
+            if self.page_max[fsm_block] < want {
+                continue;
+            }
+            let start_slot = if i == 0 {
+                self.next_block as usize % FSM_SLOTS_PER_PAGE
+            } else {
+                0
+            };
+
+            let page = self
+                .buffer_mgr
+                .read_buffer(self.tag(fsm_block))
+                .map_err(|e| HeapError::Other(e.to_string()))?;
+            let slots = &page.payload()[FSM_HEADER_SIZE..];
+            let found = slots[start_slot..]
+                .iter()
+                .position(|&c| c >= want)
+                .map(|s| s + start_slot)
+                .or_else(|| slots[..start_slot].iter().position(|&c| c >= want));
+            match found {
+                Some(slot) => {
+                    let block = (fsm_block * FSM_SLOTS_PER_PAGE + slot) as u64;
+                    self.next_block = block;
+                    return Ok(Some(block));
+                }
+                None => self.page_max[fsm_block] = slots.iter().max().copied().unwrap_or(0),
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::LocalFs;
+
+    fn buffer_mgr(dir: &tempfile::TempDir) -> Arc<BufferMgr> {
+        Arc::new(BufferMgr::init(
+            64,
+            Arc::new(LocalFs::new()),
+            dir.path().to_path_buf(),
+        ))
+    }
+
+    #[test]
+    fn test_search_finds_a_block_with_room() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut fsm = FreeSpaceMap::new(buffer_mgr(&dir), 7);
+        assert_eq!(fsm.search(100).unwrap(), None);
+
+        fsm.record(1, 50).unwrap();
+        fsm.record(2, 4000).unwrap();
+        fsm.record(3, 200).unwrap();
+        assert_eq!(fsm.search(100).unwrap(), Some(2));
+        // Rounding down never promises more than the block has
+        assert_eq!(fsm.get(3).unwrap() as usize * FSM_UNIT, 192);
+        assert_eq!(fsm.search(200).unwrap(), Some(2));
+
+        fsm.record(2, 0).unwrap();
+        assert_eq!(fsm.search(100).unwrap(), Some(3));
+        assert_eq!(fsm.search(1000).unwrap(), None);
+        assert_eq!(fsm.search(PAGE_SIZE).unwrap(), None);
+    }
+
+    #[test]
+    fn test_map_spans_pages_and_survives_reopen() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let far = (FSM_SLOTS_PER_PAGE * 2 + 5) as u64;
+        {
+            let mgr = buffer_mgr(&dir);
+            let mut fsm = FreeSpaceMap::new(Arc::clone(&mgr), 7);
+            fsm.record(4, 100).unwrap();
+            fsm.record(far, 3000).unwrap();
+            assert_eq!(fsm.search(2000).unwrap(), Some(far));
+            mgr.flush_all().unwrap();
+        }
+
+        let mgr = buffer_mgr(&dir);
+        let mut fsm = FreeSpaceMap::load(Arc::clone(&mgr), 7, far).unwrap();
+        assert_eq!(fsm.search(2000).unwrap(), Some(far));
+        assert_eq!(fsm.search(64).unwrap(), Some(far));
+        fsm.record(far, 0).unwrap();
+        assert_eq!(fsm.search(64).unwrap(), Some(4));
+
+        // Past the pages that were written, the map has to be rebuilt
+        let beyond = (FSM_SLOTS_PER_PAGE * 3) as u64;
+        assert!(FreeSpaceMap::load(Arc::clone(&mgr), 7, beyond).is_none());
+        assert!(FreeSpaceMap::load(mgr, 8, 1).is_none());
+    }
+}
@@ -1,14 +1,31 @@
 //! Heap storage module
 //! Provides heap table storage with BufferPool integration
 
-use crate::buffer::BufferMgr;
+use crate::buffer::{BufferMgr, BufferRing, SCAN_RING_SIZE};
+use crate::lock::TransactionId;
+use crate::page::page::{PageHeader, PageType, Slot, Special, SLOT_SIZE};
 use crate::page::Page;
 use crate::table::{Column, Table};
 use crate::types::{PageId, PAGE_SIZE};
-use parking_lot::RwLock;
+use crate::wal::log_record::{
+    CompensationAction, HeapSlotPayload, HeapTuplePayload, LogPayload, LogRecord,
+};
+use crate::wal::lsn::LSN;
+use crate::wal::{WalError, WalManager, WalResult};
 use std::collections::HashMap;
 use std::sync::Arc;
 
+pub mod fsm;
+pub mod tuple;
+pub mod vacuum;
+
+pub use fsm::FreeSpaceMap;
+pub use tuple::{ForwardPointer, Tuple, TupleHeader, Value, TUPLE_FORMAT_VERSION};
+
+pub use vacuum::{AutovacuumConfig, AutovacuumWorker, VacuumStats};
+
+use tuple::{TUPLE_FLAG_DEAD, TUPLE_FLAG_MOVED};
+
 pub type HeapResult<T> = Result<T, HeapError>;
 
 #[derive(Debug, Clone)]
@@ -36,6 +53,12 @@ impl std::fmt::Display for HeapError {
 
 impl std::error::Error for HeapError {}
 
+impl From<WalError> for HeapError {
+    fn from(e: WalError) -> Self {
+        HeapError::Other(e.to_string())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct RowId {
     pub page_id: PageId,
@@ -48,196 +71,42 @@ impl RowId {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-#[repr(packed)]
-struct SlotEntry {
-    offset: i32,
-    length: u32,
-}
-
-impl SlotEntry {
-    fn new(offset: i32, length: u32) -> Self {
-        Self { offset, length }
-    }
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    Null,
-    Int8(i8),
-    Int16(i16),
-    Int32(i32),
-    Int64(i64),
-    UInt8(u8),
-    UInt16(u16),
-    UInt32(u32),
-    UInt64(u64),
-    Float32(f32),
-    Float64(f64),
-    Boolean(bool),
-    VarChar(String),
-    Blob(Vec<u8>),
-}
-
-impl Value {
-    pub fn serialized_size(&self) -> usize {
-        match self {
-            Value::Null => 0,
-            Value::Int8(_) | Value::UInt8(_) | Value::Boolean(_) => 1,
-            Value::Int16(_) | Value::UInt16(_) => 2,
-            Value::Int32(_) | Value::UInt32(_) | Value::Float32(_) => 4,
-            Value::Int64(_) | Value::UInt64(_) | Value::Float64(_) => 8,
-            Value::VarChar(s) => s.len(),
-            Value::Blob(b) => b.len(),
-        }
-    }
-
-    pub fn serialize(&self) -> Vec<u8> {
-        match self {
-            Value::Null => vec![],
-            Value::Int8(v) => v.to_le_bytes().to_vec(),
-            Value::Int16(v) => v.to_le_bytes().to_vec(),
-            Value::Int32(v) => v.to_le_bytes().to_vec(),
-            Value::Int64(v) => v.to_le_bytes().to_vec(),
-            Value::UInt8(v) => v.to_le_bytes().to_vec(),
-            Value::UInt16(v) => v.to_le_bytes().to_vec(),
-            Value::UInt32(v) => v.to_le_bytes().to_vec(),
-            Value::UInt64(v) => v.to_le_bytes().to_vec(),
-            Value::Float32(v) => v.to_le_bytes().to_vec(),
-            Value::Float64(v) => v.to_le_bytes().to_vec(),
-            Value::Boolean(v) => [*v as u8].to_vec(),
-            Value::VarChar(s) => s.as_bytes().to_vec(),
-            Value::Blob(b) => b.clone(),
-        }
-    }
-
-    pub fn deserialize(data: &[u8], col_type: &crate::types::ColumnType) -> HeapResult<Self> {
-        if data.is_empty() {
-            return Ok(Value::Null);
-        }
-        match col_type {
-            crate::types::ColumnType::Int8 => {
-                Ok(Value::Int8(i8::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::Int16 => {
-                Ok(Value::Int16(i16::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::Int32 => {
-                Ok(Value::Int32(i32::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::Int64 => {
-                Ok(Value::Int64(i64::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::UInt8 => {
-                Ok(Value::UInt8(u8::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::UInt16 => {
-                Ok(Value::UInt16(u16::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::UInt32 => {
-                Ok(Value::UInt32(u32::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::UInt64 => {
-                Ok(Value::UInt64(u64::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::Float32 => {
-                Ok(Value::Float32(f32::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::Float64 => {
-                Ok(Value::Float64(f64::from_le_bytes(data.try_into().unwrap())))
-            }
-            crate::types::ColumnType::Bool => Ok(Value::Boolean(data[0] != 0)),
-            _ => Ok(Value::VarChar(String::from_utf8_lossy(data).to_string())),
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct Tuple {
-    values: Vec<Value>,
-}
-
-impl Tuple {
-    pub fn new(values: Vec<Value>) -> Self {
-        Self { values }
-    }
-    pub fn values(&self) -> &[Value] {
-        &self.values
-    }
-    pub fn get(&self, idx: usize) -> Option<&Value> {
-        self.values.get(idx)
-    }
-
-    pub fn serialize(&self, columns: &[Column]) -> Vec<u8> {
-        let mut result = Vec::new();
-        let mut null_bitmap = vec![0u8; (columns.len() + 7) / 8];
-        for (i, val) in self.values.iter().enumerate() {
-            if matches!(val, Value::Null) {
-                null_bitmap[i / 8] |= 1 << (i % 8);
-            }
-        }
-        result.extend(null_bitmap);
-        for val in &self.values {
-            result.extend(val.serialize());
-        }
-        result
-    }
-
-    pub fn deserialize(data: &[u8], columns: &[Column]) -> HeapResult<Self> {
-        if data.is_empty() {
-            return Ok(Tuple::new(vec![Value::Null; columns.len()]));
-        }
-        let null_bitmap_size = (columns.len() + 7) / 8;
-        if data.len() < null_bitmap_size {
-            return Err(HeapError::SerializationError(
-                "Data too short for null bitmap".to_string(),
-            ));
-        }
-        let null_bitmap = &data[..null_bitmap_size];
-        let mut values = Vec::new();
-        let mut offset = null_bitmap_size;
-
-        for (i, col) in columns.iter().enumerate() {
-            let is_null = (null_bitmap[i / 8] & (1 << (i % 8))) != 0;
-            if is_null {
-                values.push(Value::Null);
-            } else {
-                // For variable length types, read what's available up to column max size
-                let max_size = col.column_type().size();
-                let available = data.len() - offset;
-                let read_size = available.min(max_size);
-
-                if read_size == 0 {
-                    values.push(Value::Null);
-                } else {
-                    values.push(Value::deserialize(
-                        &data[offset..offset + read_size],
-                        &col.column_type(),
-                    )?);
-                }
-                offset += max_size; // Still advance by max size for fixed types
-            }
-        }
-        Ok(Tuple::new(values))
-    }
-}
+/// Heap pages start with the common `PageHeader`: `lower` marks the end of
+/// the slot array, `upper` the start of the tuple area, `glsn` the last
+/// WAL record applied to the page, and the offset in `special` the head of
+/// the free slot list
+const HEAP_PAGE_HEADER_SIZE: usize = std::mem::size_of::<PageHeader>();
 
+/// Slotted heap page
+///
+/// Slot numbers never change while a tuple lives in them, so a RowId stays
+/// valid. A deleted tuple's slot goes on the page's free slot list for the
+/// next insert, and its bytes are reclaimed by `compact`, which runs when
+/// a tuple does not fit in the gap between the slot array and the data.
 #[derive(Clone)]
 pub struct HeapPage {
     page_id: PageId,
-    data: [u8; PAGE_SIZE],
+    data: Page,
     slot_count: usize,
     upper: usize,
+    /// First free slot plus one, zero when there is none
+    free_head: usize,
+    /// Bytes of the live tuples
+    used: usize,
 }
 
 impl HeapPage {
     pub fn new(page_id: PageId) -> Self {
-        Self {
+        let mut page = Self {
             page_id,
-            data: [0u8; PAGE_SIZE],
+            data: Page::new(),
             slot_count: 0,
             upper: PAGE_SIZE,
-        }
+            free_head: 0,
+            used: 0,
+        };
+        page.write_header();
+        page
     }
     pub fn page_id(&self) -> PageId {
         self.page_id
@@ -245,54 +114,231 @@ impl HeapPage {
     pub fn slot_count(&self) -> usize {
         self.slot_count
     }
+
+    /// Free bytes on the page, counting the holes deleted tuples left
     pub fn available_space(&self) -> usize {
-        self.upper - self.slot_count * std::mem::size_of::<SlotEntry>()
+        PAGE_SIZE - HEAP_PAGE_HEADER_SIZE - self.slot_count * SLOT_SIZE - self.used
+    }
+
+    /// Free bytes between the slot array and the tuple data
+    fn contiguous_space(&self) -> usize {
+        self.upper - HEAP_PAGE_HEADER_SIZE - self.slot_count * SLOT_SIZE
     }
+
+    /// LSN of the last WAL record applied to this page
+    pub fn lsn(&self) -> u64 {
+        self.data.header().glsn
+    }
+
+    pub fn set_lsn(&mut self, lsn: u64) {
+        self.data.header_mut().set_lsn(lsn);
+    }
+
+    fn write_header(&mut self) {
+        let lower = (HEAP_PAGE_HEADER_SIZE + self.slot_count * SLOT_SIZE) as u16;
+        let special = Special::new(self.free_head as u16, 0);
+        let header = self.data.header_mut();
+        header.type_ = PageType::Data as u16;
+        header.myself = self.page_id;
+        header.lower = lower;
+        header.upper = self.upper as u16;
+        header.special = special;
+    }
+
     pub fn can_insert(&self, tuple_size: usize) -> bool {
-        tuple_size + std::mem::size_of::<SlotEntry>() <= self.available_space()
+        let slot_size = if self.free_head == 0 { SLOT_SIZE } else { 0 };
+        tuple_size + slot_size <= self.available_space()
     }
 
+    /// Puts `slot_idx` on the free slot list
+    fn push_free_slot(&mut self, slot_idx: usize) {
+        self.data
+            .set_slot(slot_idx, Slot::new(0, self.free_head as u32));
+        self.free_head = slot_idx + 1;
+    }
+
+    /// Takes the first slot off the free slot list
+    fn pop_free_slot(&mut self) -> Option<usize> {
+        let slot_idx = self.free_head.checked_sub(1)?;
+        self.free_head = self.data.slot(slot_idx).length as usize;
+        Some(slot_idx)
+    }
+
+    /// Takes `slot_idx` off the free slot list, wherever it is on it
+    fn unlink_free_slot(&mut self, slot_idx: usize) {
+        let next = self.data.slot(slot_idx).length as usize;
+        if self.free_head == slot_idx + 1 {
+            self.free_head = next;
+            return;
+        }
+        let mut prev = self.free_head;
+        while prev != 0 {
+            let prev_next = self.data.slot(prev - 1).length as usize;
+            if prev_next == slot_idx + 1 {
+                self.data.set_slot(prev - 1, Slot::new(0, next as u32));
+                return;
+            }
+            prev = prev_next;
+        }
+    }
+
+    /// Moves the live tuples back to the end of the page, leaving all free
+    /// space between the slot array and the data
+    ///
+    /// Slot numbers are kept.
+    pub fn compact(&mut self) {
+        let live: Vec<(usize, Vec<u8>)> = self.iter_tuples().collect();
+        self.upper = PAGE_SIZE;
+        for (slot_idx, tuple_data) in &live {
+            self.upper -= tuple_data.len();
+            let slot = Slot::new(
+                self.upper as i32 - PAGE_SIZE as i32,
+                tuple_data.len() as u32,
+            );
+            self.data.as_bytes_mut()[slot.range()].copy_from_slice(tuple_data);
+            self.data.set_slot(*slot_idx, slot);
+        }
+        let lower = HEAP_PAGE_HEADER_SIZE + self.slot_count * SLOT_SIZE;
+        self.data.as_bytes_mut()[lower..self.upper].fill(0);
+        self.write_header();
+    }
+
+    /// Copies `tuple_data` to the top of the free space and points
+    /// `slot_idx` at it, compacting the page first if the free space is
+    /// fragmented; the caller has checked that it fits
+    fn place_tuple(&mut self, slot_idx: usize, tuple_data: &[u8]) {
+        if tuple_data.len() > self.contiguous_space() {
+            self.compact();
+        }
+        self.upper -= tuple_data.len();
+        self.used += tuple_data.len();
+        let slot = Slot::new(
+            self.upper as i32 - PAGE_SIZE as i32,
+            tuple_data.len() as u32,
+        );
+        self.data.as_bytes_mut()[slot.range()].copy_from_slice(tuple_data);
+        self.data.set_slot(slot_idx, slot);
+    }
+
+    /// Stores a tuple in a free slot, or in a new one if there is none
     pub fn insert_tuple(&mut self, tuple_data: &[u8]) -> HeapResult<usize> {
         if !self.can_insert(tuple_data.len()) {
             return Err(HeapError::OutOfSpace);
         }
-        let slot_idx = self.slot_count;
-        self.upper -= tuple_data.len();
-        let offset = self.upper as i32 - PAGE_SIZE as i32;
-        self.data[self.upper..self.upper + tuple_data.len()].copy_from_slice(tuple_data);
-        let slot_offset = slot_idx * std::mem::size_of::<SlotEntry>();
-        let slot = SlotEntry::new(offset, tuple_data.len() as u32);
-        self.data[slot_offset..slot_offset + 4].copy_from_slice(&slot.offset.to_le_bytes());
-        self.data[slot_offset + 4..slot_offset + 8].copy_from_slice(&slot.length.to_le_bytes());
-        self.slot_count += 1;
+        let slot_idx = match self.pop_free_slot() {
+            Some(slot_idx) => slot_idx,
+            None => {
+                if SLOT_SIZE > self.contiguous_space() {
+                    self.compact();
+                }
+                self.slot_count += 1;
+                self.data.set_slot(self.slot_count - 1, Slot::default());
+                self.slot_count - 1
+            }
+        };
+        self.place_tuple(slot_idx, tuple_data);
+        self.write_header();
         Ok(slot_idx)
     }
 
+    /// Places a tuple in a given slot, growing the slot array as needed
+    ///
+    /// Used by WAL redo to repeat an insert at the slot it originally got,
+    /// and by undo to put a deleted tuple back. The slot must be free.
+    pub fn insert_tuple_at(&mut self, slot_idx: usize, tuple_data: &[u8]) -> HeapResult<()> {
+        if slot_idx < self.slot_count && !self.data.slot(slot_idx).is_unused() {
+            return Err(HeapError::InvalidSlot(slot_idx));
+        }
+        let new_slots = (slot_idx + 1).saturating_sub(self.slot_count);
+        if tuple_data.len() + new_slots * SLOT_SIZE > self.available_space() {
+            return Err(HeapError::OutOfSpace);
+        }
+
+        if new_slots == 0 {
+            self.unlink_free_slot(slot_idx);
+        } else {
+            if new_slots * SLOT_SIZE > self.contiguous_space() {
+                self.compact();
+            }
+            let first_new = self.slot_count;
+            self.slot_count += new_slots;
+            for idx in (first_new..slot_idx).rev() {
+                self.push_free_slot(idx);
+            }
+            self.data.set_slot(slot_idx, Slot::default());
+        }
+        self.place_tuple(slot_idx, tuple_data);
+        self.write_header();
+        Ok(())
+    }
+
+    /// Replaces the tuple in a live slot, keeping the slot number
+    ///
+    /// A tuple that is not longer than the old one reuses its space; a
+    /// longer one is copied into free space.
+    pub fn update_tuple(&mut self, slot_idx: usize, tuple_data: &[u8]) -> HeapResult<()> {
+        if slot_idx >= self.slot_count {
+            return Err(HeapError::InvalidSlot(slot_idx));
+        }
+        let slot = self.data.slot(slot_idx);
+        if slot.is_unused() {
+            return Err(HeapError::TupleNotFound(RowId::new(self.page_id, slot_idx)));
+        }
+
+        let old_len = slot.length as usize;
+        if tuple_data.len() <= old_len {
+            let slot = Slot::new(slot.offset, tuple_data.len() as u32);
+            self.data.as_bytes_mut()[slot.range()].copy_from_slice(tuple_data);
+            self.data.set_slot(slot_idx, slot);
+            self.used -= old_len - tuple_data.len();
+        } else if tuple_data.len() - old_len <= self.available_space() {
+            // The old bytes count as free while the page makes room
+            self.data.set_slot(slot_idx, Slot::default());
+            self.used -= old_len;
+            self.place_tuple(slot_idx, tuple_data);
+            self.write_header();
+        } else {
+            return Err(HeapError::OutOfSpace);
+        }
+        Ok(())
+    }
+
     pub fn get_tuple(&self, slot_idx: usize) -> HeapResult<Vec<u8>> {
         if slot_idx >= self.slot_count {
             return Err(HeapError::InvalidSlot(slot_idx));
         }
-        let slot_offset = slot_idx * std::mem::size_of::<SlotEntry>();
-        let offset =
-            i32::from_le_bytes(self.data[slot_offset..slot_offset + 4].try_into().unwrap());
-        let length = u32::from_le_bytes(
-            self.data[slot_offset + 4..slot_offset + 8]
-                .try_into()
-                .unwrap(),
-        ) as usize;
-        if offset == 0 && length == 0 {
+        let slot = self.data.slot(slot_idx);
+        if slot.is_unused() {
             return Err(HeapError::TupleNotFound(RowId::new(self.page_id, slot_idx)));
         }
-        let actual = (PAGE_SIZE as i32 + offset) as usize;
-        Ok(self.data[actual..actual + length].to_vec())
+        Ok(self.data.tuple(slot).to_vec())
     }
 
+    /// Frees a slot; a slot at the end of the array is dropped from it,
+    /// along with the free slots before it
     pub fn delete_tuple(&mut self, slot_idx: usize) -> HeapResult<()> {
         if slot_idx >= self.slot_count {
             return Err(HeapError::InvalidSlot(slot_idx));
         }
-        let slot_offset = slot_idx * std::mem::size_of::<SlotEntry>();
-        self.data[slot_offset..slot_offset + 8].fill(0);
+        let slot = self.data.slot(slot_idx);
+        if slot.is_unused() {
+            return Ok(());
+        }
+        self.used -= slot.length as usize;
+
+        if slot_idx + 1 == self.slot_count {
+            self.slot_count -= 1;
+            while self.slot_count > 0 && self.data.slot(self.slot_count - 1).is_unused() {
+                self.unlink_free_slot(self.slot_count - 1);
+                self.slot_count -= 1;
+            }
+            let lower = HEAP_PAGE_HEADER_SIZE + self.slot_count * SLOT_SIZE;
+            let end = HEAP_PAGE_HEADER_SIZE + (slot_idx + 1) * SLOT_SIZE;
+            self.data.as_bytes_mut()[lower..end].fill(0);
+        } else {
+            self.push_free_slot(slot_idx);
+        }
+        self.write_header();
         Ok(())
     }
 
@@ -301,172 +347,414 @@ impl HeapPage {
     }
 
     pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+
+    pub fn page(&self) -> &Page {
         &self.data
     }
 
     pub fn from_bytes(page_id: PageId, data: &[u8]) -> Self {
-        let mut page = Self::new(page_id);
-        page.data.copy_from_slice(data);
-        page.slot_count = 0;
-        page.upper = PAGE_SIZE;
-        for i in 0..(PAGE_SIZE / std::mem::size_of::<SlotEntry>()) {
-            let slot_offset = i * std::mem::size_of::<SlotEntry>();
-            let length =
-                u32::from_le_bytes(data[slot_offset + 4..slot_offset + 8].try_into().unwrap())
-                    as usize;
-            if length > 0 {
-                page.slot_count = i + 1;
-                let offset =
-                    i32::from_le_bytes(data[slot_offset..slot_offset + 4].try_into().unwrap());
-                let actual = (PAGE_SIZE as i32 + offset) as usize;
-                if actual < page.upper {
-                    page.upper = actual;
-                }
-            }
+        Self::from_page(page_id, Page::from_bytes(data))
+    }
+
+    pub fn from_page(page_id: PageId, data: Page) -> Self {
+        let header = *data.header();
+        let mut page = Self {
+            page_id,
+            data,
+            slot_count: 0,
+            upper: PAGE_SIZE,
+            free_head: 0,
+            used: 0,
+        };
+        // A zeroed page has never held a tuple
+        if (header.lower as usize) < HEAP_PAGE_HEADER_SIZE || header.upper == 0 {
+            page.write_header();
+        } else {
+            page.slot_count = page.data.slot_count();
+            page.upper = header.upper as usize;
+            page.free_head = header.special.m_offset as usize;
+            page.used = (0..page.slot_count)
+                .map(|i| page.data.slot(i))
+                .filter(|slot| !slot.is_unused())
+                .map(|slot| slot.length as usize)
+                .sum();
         }
         page
     }
 }
 
+/// Block 0 of every heap segment holds the heap metapage
+const HEAP_META_BLOCK: u64 = 0;
+const HEAP_META_MAGIC: u32 = 0x48454150;
+const HEAP_META_VERSION: u32 = 3;
+
+/// Builds the page id of `block` inside a heap segment
+#[inline]
+fn make_page_id(segment_id: u64, block: u64) -> PageId {
+    (segment_id << 32) | block
+}
+
+/// Heap table stored in its own segment
+///
+/// Data pages are blocks `1..=num_blocks` of the segment; the metapage at
+/// block 0 records `num_blocks` so the table can be reopened. Pages live
+/// only in the buffer pool: an operation copies the page it works on out
+/// and writes the changed copy straight back.
+///
+/// With a WAL attached, every change is logged and the page is stamped
+/// with the record's LSN. The first change to a page after a checkpoint is
+/// preceded by a full image of the page.
+///
+/// Inserts find a page with room through the free space map, which is
+/// updated whenever a page is written and rebuilt on open if it is missing.
+///
+/// A RowId stays valid for the life of the row: an update that does not
+/// fit on the row's page moves the row and leaves a forwarding pointer in
+/// its slot. A delete only flags the tuple dead; `vacuum` frees it once
+/// the deleting transaction is over.
 pub struct HeapTable {
     table: Arc<Table>,
-    buffer_mgr: Arc<RwLock<BufferMgr>>,
-    first_page_id: PageId,
-    pages: HashMap<PageId, HeapPage>,
+    buffer_mgr: Arc<BufferMgr>,
+    segment_id: u64,
+    num_blocks: u64,
+    wal: Option<Arc<WalManager>>,
+    fsm: FreeSpaceMap,
+    /// Transaction that deleted each dead tuple, for those deleted since
+    /// the heap was opened; older ones are past any rollback
+    deleted_by: HashMap<RowId, TransactionId>,
+    /// Rows in the heap as of the last vacuum or open, plus inserts and
+    /// minus deletes since
+    live_tuples: u64,
+    /// Deleted rows not yet freed by a vacuum, one per row however many
+    /// tuples it has
+    dead_tuples: u64,
+    last_vacuum: Option<VacuumStats>,
 }
 
 impl HeapTable {
-    pub fn new(
+    /// Creates an empty heap in `segment_id`
+    pub fn create(
+        table: Arc<Table>,
+        buffer_mgr: Arc<BufferMgr>,
+        segment_id: u64,
+    ) -> HeapResult<Self> {
+        buffer_mgr
+            .new_page(make_page_id(segment_id, HEAP_META_BLOCK))
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+
+        let heap = Self {
+            fsm: FreeSpaceMap::new(Arc::clone(&buffer_mgr), segment_id),
+            table,
+            buffer_mgr,
+            segment_id,
+            num_blocks: 0,
+            wal: None,
+            deleted_by: HashMap::new(),
+            live_tuples: 0,
+            dead_tuples: 0,
+            last_vacuum: None,
+        };
+        heap.write_meta()?;
+        Ok(heap)
+    }
+
+    /// Reattaches to the heap persisted in `segment_id`
+    ///
+    /// A segment whose metapage was never written (the table was created
+    /// but nothing reached disk) opens as an empty heap. A free space map
+    /// that is missing or damaged is rebuilt from the heap pages. The live
+    /// and dead tuples autovacuum goes by are counted from the pages.
+    pub fn open(
         table: Arc<Table>,
-        buffer_mgr: Arc<RwLock<BufferMgr>>,
-        first_page_id: PageId,
-    ) -> Self {
-        Self {
+        buffer_mgr: Arc<BufferMgr>,
+        segment_id: u64,
+    ) -> HeapResult<Self> {
+        let meta_page_id = make_page_id(segment_id, HEAP_META_BLOCK);
+        let num_blocks = {
+            let data = buffer_mgr
+                .read_page(meta_page_id)
+                .map_err(|e| HeapError::Other(e.to_string()))?;
+            decode_meta(data.payload())
+        };
+
+        let Some(num_blocks) = num_blocks else {
+            return Self::create(table, buffer_mgr, segment_id);
+        };
+        let fsm = FreeSpaceMap::load(Arc::clone(&buffer_mgr), segment_id, num_blocks);
+        let mut heap = Self {
+            fsm: FreeSpaceMap::new(Arc::clone(&buffer_mgr), segment_id),
             table,
             buffer_mgr,
-            first_page_id,
-            pages: HashMap::new(),
+            segment_id,
+            num_blocks,
+            wal: None,
+            deleted_by: HashMap::new(),
+            live_tuples: 0,
+            dead_tuples: 0,
+            last_vacuum: None,
+        };
+        match fsm {
+            Some(fsm) => {
+                heap.fsm = fsm;
+                // The map is not logged; bring the block bulk inserts were
+                // filling up to date. An empty heap has no such block, and
+                // a page that cannot be read is left for the operation that
+                // reads it to report.
+                let last = make_page_id(segment_id, num_blocks);
+                if let Ok(page) = heap.fetch_page(last) {
+                    heap.fsm.record(num_blocks, page.available_space())?;
+                }
+            }
+            None => heap.rebuild_free_space_map()?,
         }
+        heap.count_tuples();
+        Ok(heap)
+    }
+
+    /// Rebuilds the free space map from the heap pages
+    pub fn rebuild_free_space_map(&mut self) -> HeapResult<()> {
+        let mut fsm = FreeSpaceMap::new(Arc::clone(&self.buffer_mgr), self.segment_id);
+        let mut ring = BufferRing::new(SCAN_RING_SIZE);
+        for page_id in self.page_ids() {
+            let free = self.load_page(page_id, Some(&mut ring))?.available_space();
+            fsm.record(page_id as u32 as u64, free)?;
+        }
+        self.fsm = fsm;
+        Ok(())
+    }
+
+    /// Attaches the WAL that changes to this heap are logged to
+    pub fn set_wal(&mut self, wal: Option<Arc<WalManager>>) {
+        self.wal = wal;
     }
 
     pub fn table(&self) -> &Arc<Table> {
         &self.table
     }
+    pub fn segment_id(&self) -> u64 {
+        self.segment_id
+    }
     pub fn first_page_id(&self) -> PageId {
-        self.first_page_id
+        make_page_id(self.segment_id, HEAP_META_BLOCK + 1)
     }
 
-    fn fetch_page(&mut self, page_id: PageId) -> HeapResult<HeapPage> {
-        if let Some(p) = self.pages.get(&page_id) {
-            return Ok(p.clone());
+    /// Copies a page out of the buffer pool to read or change; a changed
+    /// copy goes back through `write_page`
+    fn fetch_page(&self, page_id: PageId) -> HeapResult<HeapPage> {
+        self.load_page(page_id, None)
+    }
+
+    /// Reads a page from the buffer pool, through `ring` for scans
+    fn load_page(&self, page_id: PageId, ring: Option<&mut BufferRing>) -> HeapResult<HeapPage> {
+        if page_id >> 32 != self.segment_id
+            || page_id as u32 as u64 == HEAP_META_BLOCK
+            || page_id as u32 as u64 > self.num_blocks
+        {
+            return Err(HeapError::PageNotFound(page_id));
         }
-        // Try to load from buffer pool
-        let mut buf = self.buffer_mgr.write();
-        if let Ok(page) = buf.get_page(page_id) {
-            let page_data = unsafe {
-                std::slice::from_raw_parts(
-                    page as *const Page as *const u8,
-                    std::mem::size_of::<Page>(),
-                )
-            };
-            let hp = HeapPage::from_bytes(page_id, page_data);
-            return Ok(hp);
+
+        let data = match ring {
+            Some(ring) => self.buffer_mgr.read_page_with_ring(page_id, ring),
+            None => self.buffer_mgr.read_page(page_id),
         }
-        // Page doesn't exist, create new
-        Ok(HeapPage::new(page_id))
+        .map_err(|e| HeapError::Other(e.to_string()))?;
+        Ok(HeapPage::from_page(page_id, data.clone()))
     }
 
     fn write_page(&mut self, page_id: PageId, heap_page: &HeapPage) -> HeapResult<()> {
-        self.pages.insert(page_id, heap_page.clone());
-
-        // Also write to buffer pool if page exists there
-        let mut buf = self.buffer_mgr.write();
-        if let Ok(page) = buf.get_page(page_id) {
-            let page_data = unsafe {
-                std::slice::from_raw_parts_mut(
-                    page as *mut Page as *mut u8,
-                    std::mem::size_of::<Page>(),
-                )
-            };
-            let src = heap_page.as_bytes();
-            if src.len() <= page_data.len() {
-                page_data[..src.len()].copy_from_slice(src);
+        {
+            let mut data = self
+                .buffer_mgr
+                .write_page(page_id)
+                .map_err(|e| HeapError::Other(e.to_string()))?;
+            data.as_bytes_mut().copy_from_slice(heap_page.as_bytes());
+        }
+        self.fsm
+            .record(page_id as u32 as u64, heap_page.available_space())?;
+        Ok(())
+    }
+
+    /// Appends a new block to the segment and records it on the metapage
+    fn extend(&mut self) -> HeapResult<HeapPage> {
+        let page_id = make_page_id(self.segment_id, self.num_blocks + 1);
+        self.buffer_mgr
+            .new_page(page_id)
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        self.num_blocks += 1;
+        self.write_meta()?;
+        Ok(HeapPage::new(page_id))
+    }
+
+    fn write_meta(&self) -> HeapResult<()> {
+        let page_id = make_page_id(self.segment_id, HEAP_META_BLOCK);
+        let mut data = self
+            .buffer_mgr
+            .write_page(page_id)
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        encode_meta(self.num_blocks, data.payload_mut());
+        Ok(())
+    }
+
+    /// Like `write_meta`, logging an image of the metapage
+    ///
+    /// Redo only ever moves the end of the heap forward, so the end
+    /// moving back is logged as the page it leaves.
+    fn write_meta_logged(&self) -> HeapResult<()> {
+        let page_id = make_page_id(self.segment_id, HEAP_META_BLOCK);
+        let mut data = self
+            .buffer_mgr
+            .write_page(page_id)
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        encode_meta(self.num_blocks, data.payload_mut());
+        if let Some(ref wal) = self.wal {
+            let lsn = wal.log_full_page_image(0, page_id, data.as_bytes())?;
+            if lsn.is_valid() {
+                data.header_mut().set_lsn(lsn.raw());
             }
-            buf.mark_dirty(page_id);
         }
         Ok(())
     }
 
     pub fn flush(&mut self) -> HeapResult<()> {
-        let mut buf = self.buffer_mgr.write();
-        for (page_id, heap_page) in self.pages.iter() {
-            if let Ok(page) = buf.get_page(*page_id) {
-                let page_data = unsafe {
-                    std::slice::from_raw_parts_mut(
-                        page as *mut Page as *mut u8,
-                        std::mem::size_of::<Page>(),
-                    )
-                };
-                let src = heap_page.as_bytes();
-                if src.len() <= page_data.len() {
-                    page_data[..src.len()].copy_from_slice(src);
-                }
-                buf.mark_dirty(*page_id);
+        self.buffer_mgr
+            .flush_all()
+            .map_err(|e| HeapError::Other(e.to_string()))
+    }
+
+    /// Logs a full image of `page` if this is its first change since the
+    /// last checkpoint
+    fn log_full_page_image(&self, page: &mut HeapPage) -> HeapResult<()> {
+        match self.wal {
+            Some(ref wal) if wal.needs_full_page_image(LSN::from_raw(page.lsn())) => {
+                let lsn = wal.log_full_page_image(0, page.page_id(), page.as_bytes())?;
+                page.set_lsn(lsn.raw());
             }
+            _ => {}
         }
-        buf.flush_all().map_err(|e| HeapError::Other(e.to_string()))
+        Ok(())
     }
 
-    pub fn insert(&mut self, values: &[Value]) -> HeapResult<RowId> {
-        let columns = self.table.columns();
-        let tuple_data = Tuple::new(values.to_vec()).serialize(columns);
+    /// Logs a change already applied to `page` and stamps its LSN
+    ///
+    /// The change is only made on the caller's copy of the page, so when
+    /// it cannot be logged the error is returned before the page is
+    /// written back.
+    fn log_change(
+        &self,
+        page: &mut HeapPage,
+        log: impl FnOnce(&WalManager) -> WalResult<LSN>,
+    ) -> HeapResult<()> {
+        if let Some(ref wal) = self.wal {
+            let lsn = log(wal)?;
+            // A disabled WAL hands out no LSNs
+            if lsn.is_valid() {
+                page.set_lsn(lsn.raw());
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a row on behalf of `tx_id` (0 outside a transaction)
+    pub fn insert(&mut self, tx_id: TransactionId, values: &[Value]) -> HeapResult<RowId> {
+        let tuple_data = Tuple::new(values.to_vec())
+            .serialize(self.table.columns(), self.table.schema_version())?;
+        let row_id = self.insert_data(tx_id, &tuple_data)?;
+        self.live_tuples += 1;
+        Ok(row_id)
+    }
 
-        // If no pages exist yet, create first page
-        if self.pages.is_empty() {
-            let mut new_page = HeapPage::new(self.first_page_id);
-            let slot_idx = new_page.insert_tuple(&tuple_data)?;
-            self.write_page(self.first_page_id, &new_page)?;
-            return Ok(RowId::new(self.first_page_id, slot_idx));
+    /// Stores an encoded tuple on a page with room for it
+    fn insert_data(&mut self, tx_id: TransactionId, tuple_data: &[u8]) -> HeapResult<RowId> {
+        if tuple_data.len() + SLOT_SIZE + HEAP_PAGE_HEADER_SIZE > PAGE_SIZE {
+            return Err(HeapError::Other(format!(
+                "Tuple too large for page: {} bytes",
+                tuple_data.len()
+            )));
         }
 
-        // Try to find a page with enough space
-        for (&page_id, heap_page) in self.pages.iter() {
+        while let Some(block) = self.fsm.search(tuple_data.len() + SLOT_SIZE)? {
+            // The map is not logged and can be stale after a crash; correct
+            // it when the block does not have the room after all
+            if block == HEAP_META_BLOCK || block > self.num_blocks {
+                self.fsm.record(block, 0)?;
+                continue;
+            }
+            let heap_page = self.fetch_page(make_page_id(self.segment_id, block))?;
             if heap_page.can_insert(tuple_data.len()) {
-                let mut heap_page = self.fetch_page(page_id)?;
-                let slot_idx = heap_page.insert_tuple(&tuple_data)?;
-                self.write_page(page_id, &heap_page)?;
-                return Ok(RowId::new(page_id, slot_idx));
+                return self.insert_into(tx_id, heap_page, tuple_data);
             }
+            self.fsm.record(block, heap_page.available_space())?;
         }
 
-        // Allocate new page
-        let new_page_id = self.first_page_id + self.pages.len() as PageId + 1;
-        let mut new_page = HeapPage::new(new_page_id);
-
-        if new_page.can_insert(tuple_data.len()) {
-            let slot_idx = new_page.insert_tuple(&tuple_data)?;
-            self.write_page(new_page_id, &new_page)?;
-            return Ok(RowId::new(new_page_id, slot_idx));
-        }
+        let new_page = self.extend()?;
+        self.insert_into(tx_id, new_page, tuple_data)
+    }
 
-        Err(HeapError::Other(format!(
-            "Tuple too large for page: {} bytes",
-            tuple_data.len()
-        )))
+    fn insert_into(
+        &mut self,
+        tx_id: TransactionId,
+        mut heap_page: HeapPage,
+        tuple_data: &[u8],
+    ) -> HeapResult<RowId> {
+        let page_id = heap_page.page_id();
+        self.log_full_page_image(&mut heap_page)?;
+        let slot_idx = heap_page.insert_tuple(tuple_data)?;
+        self.log_change(&mut heap_page, |wal| {
+            wal.log_heap_insert(tx_id, page_id, slot_idx, tuple_data)
+        })?;
+        self.write_page(page_id, &heap_page)?;
+        Ok(RowId::new(page_id, slot_idx))
     }
 
     pub fn get(&mut self, row_id: RowId) -> HeapResult<Tuple> {
-        let heap_page = self.fetch_page(row_id.page_id)?;
-        let data = heap_page.get_tuple(row_id.slot_idx)?;
+        let (_, data) = self.resolve(row_id)?;
         Tuple::deserialize(&data, self.table.columns())
     }
 
+    /// Where the row a forwarding pointer leads to is stored
+    fn forward_target(&self, pointer: ForwardPointer) -> RowId {
+        RowId::new(
+            make_page_id(self.segment_id, pointer.block as u64),
+            pointer.slot as usize,
+        )
+    }
+
+    /// Returns where the row of `row_id` is stored and its tuple, following
+    /// the forwarding pointer in its slot if it moved
+    fn resolve(&mut self, row_id: RowId) -> HeapResult<(RowId, Vec<u8>)> {
+        let data = self
+            .fetch_page(row_id.page_id)?
+            .get_tuple(row_id.slot_idx)?;
+        if tuple::is_dead(&data) {
+            return Err(HeapError::TupleNotFound(row_id));
+        }
+        match ForwardPointer::decode(&data) {
+            Some(pointer) => {
+                let stored_at = self.forward_target(pointer);
+                let data = self
+                    .fetch_page(stored_at.page_id)?
+                    .get_tuple(stored_at.slot_idx)?;
+                Ok((stored_at, data))
+            }
+            None => Ok((row_id, data)),
+        }
+    }
+
     /// Scan rows - traverses all pages
+    ///
+    /// Pages are read through a `BufferRing`, so a scan of a large table
+    /// leaves the buffer pool's working set alone.
     pub fn scan_with_filter(&mut self, filter: Option<(usize, &Value)>) -> HeapResult<Vec<Tuple>> {
         let columns: Vec<_> = self.table.columns().to_vec();
         let mut results = Vec::new();
+        let mut ring = BufferRing::new(SCAN_RING_SIZE);
 
-        for (_, heap_page) in self.pages.iter() {
+        for page_id in self.page_ids() {
+            let heap_page = self.load_page(page_id, Some(&mut ring))?;
+            // Forwarding pointers and dead tuples do not decode as rows, so
+            // a moved row is read once, where it is stored
             for (_, data) in heap_page.iter_tuples() {
                 if let Ok(tuple) = Tuple::deserialize(&data, &columns) {
                     let mut matches = true;
@@ -486,17 +774,353 @@ impl HeapTable {
         Ok(results)
     }
 
-    pub fn update(&mut self, row_id: RowId, values: &[Value]) -> HeapResult<()> {
-        let mut heap_page = self.fetch_page(row_id.page_id)?;
-        heap_page.delete_tuple(row_id.slot_idx)?;
-        self.write_page(row_id.page_id, &heap_page)?;
-        self.insert(values)?;
+    /// Ids of the pages holding this table's rows, in ascending order
+    pub fn page_ids(&self) -> Vec<PageId> {
+        (1..=self.num_blocks)
+            .map(|block| make_page_id(self.segment_id, block))
+            .collect()
+    }
+
+    /// Reads every row whose RowId is on one page
+    ///
+    /// A row that moved away is read through its forwarding pointer and
+    /// reported under its own RowId; moved rows stored on this page are
+    /// reported with the page their pointer is on.
+    pub fn scan_page(&mut self, page_id: PageId) -> HeapResult<Vec<(RowId, Tuple)>> {
+        let heap_page = self.fetch_page(page_id)?;
+        let columns = self.table.columns().to_vec();
+        let mut rows = Vec::new();
+        for (slot, data) in heap_page.iter_tuples() {
+            if tuple::is_moved(&data) || tuple::is_dead(&data) {
+                continue;
+            }
+            let row_id = RowId::new(page_id, slot);
+            let data = match ForwardPointer::decode(&data) {
+                Some(_) => self.resolve(row_id)?.1,
+                None => data,
+            };
+            rows.push((row_id, Tuple::deserialize(&data, &columns)?));
+        }
+        Ok(rows)
+    }
+
+    /// Replaces a row and returns its RowId, which does not change
+    ///
+    /// The new tuple overwrites the old one when it fits on the page the
+    /// row is stored on. Otherwise it is stored elsewhere and the row's
+    /// slot gets a forwarding pointer to it; a row that moves again has
+    /// that pointer redirected, so a RowId is never more than one hop
+    /// from its row.
+    pub fn update(
+        &mut self,
+        tx_id: TransactionId,
+        row_id: RowId,
+        values: &[Value],
+    ) -> HeapResult<RowId> {
+        let mut tuple_data = Tuple::new(values.to_vec())
+            .serialize(self.table.columns(), self.table.schema_version())?;
+        let (stored_at, old_data) = self.resolve(row_id)?;
+        if stored_at != row_id {
+            tuple_data[1] |= TUPLE_FLAG_MOVED;
+        }
+
+        let available = self.fetch_page(stored_at.page_id)?.available_space();
+        if tuple_data.len() <= old_data.len() + available {
+            self.update_slot(tx_id, stored_at, &tuple_data)?;
+            return Ok(row_id);
+        }
+
+        tuple_data[1] |= TUPLE_FLAG_MOVED;
+        let moved_to = self.insert_data(tx_id, &tuple_data)?;
+        if stored_at != row_id {
+            self.kill_slot(tx_id, stored_at)?;
+        }
+        // The pointer keeps the slot's length, so rolling back can put the
+        // old tuple back in place
+        let slot_len = self
+            .fetch_page(row_id.page_id)?
+            .get_tuple(row_id.slot_idx)?
+            .len();
+        let pointer = ForwardPointer {
+            block: moved_to.page_id as u32,
+            slot: moved_to.slot_idx as u16,
+        };
+        self.update_slot(tx_id, row_id, &pointer.encode(slot_len))?;
+        Ok(row_id)
+    }
+
+    /// Deletes a row, along with the tuple its forwarding pointer leads to
+    ///
+    /// The tuples are flagged dead and keep their slots until `vacuum`.
+    pub fn delete(&mut self, tx_id: TransactionId, row_id: RowId) -> HeapResult<()> {
+        let (stored_at, _) = self.resolve(row_id)?;
+        if stored_at != row_id {
+            self.kill_slot(tx_id, stored_at)?;
+        }
+        self.kill_slot(tx_id, row_id)?;
+        self.live_tuples = self.live_tuples.saturating_sub(1);
+        self.dead_tuples += 1;
         Ok(())
     }
 
-    pub fn delete(&mut self, row_id: RowId) -> HeapResult<()> {
+    /// Flags the tuple in one slot dead on behalf of `tx_id`
+    fn kill_slot(&mut self, tx_id: TransactionId, row_id: RowId) -> HeapResult<()> {
+        let mut dead = self
+            .fetch_page(row_id.page_id)?
+            .get_tuple(row_id.slot_idx)?;
+        dead[1] |= TUPLE_FLAG_DEAD;
+        self.update_slot(tx_id, row_id, &dead)?;
+        match tx_id {
+            0 => self.deleted_by.remove(&row_id),
+            _ => self.deleted_by.insert(row_id, tx_id),
+        };
+        Ok(())
+    }
+
+    /// Replaces the tuple in one slot and logs the change
+    fn update_slot(
+        &mut self,
+        tx_id: TransactionId,
+        row_id: RowId,
+        tuple_data: &[u8],
+    ) -> HeapResult<()> {
         let mut heap_page = self.fetch_page(row_id.page_id)?;
+        let old_data = heap_page.get_tuple(row_id.slot_idx)?;
+        self.log_full_page_image(&mut heap_page)?;
+        heap_page.update_tuple(row_id.slot_idx, tuple_data)?;
+        self.log_change(&mut heap_page, |wal| {
+            wal.log_heap_update(
+                tx_id,
+                row_id.page_id,
+                row_id.slot_idx,
+                tuple_data,
+                &old_data,
+            )
+        })?;
+        self.write_page(row_id.page_id, &heap_page)
+    }
+
+    /// Empties one slot and logs the change
+    fn delete_slot(&mut self, tx_id: TransactionId, row_id: RowId) -> HeapResult<()> {
+        let mut heap_page = self.fetch_page(row_id.page_id)?;
+        let old_data = heap_page.get_tuple(row_id.slot_idx)?;
+        self.log_full_page_image(&mut heap_page)?;
         heap_page.delete_tuple(row_id.slot_idx)?;
+        self.log_change(&mut heap_page, |wal| {
+            wal.log_heap_delete(tx_id, row_id.page_id, row_id.slot_idx, &old_data)
+        })?;
         self.write_page(row_id.page_id, &heap_page)
     }
+
+    /// Reverses a logged heap change of `tx_id` while rolling it back
+    ///
+    /// The inverse change is logged as a compensation record pointing at
+    /// the undone record's predecessor, so it is never undone twice.
+    pub fn undo(&mut self, tx_id: TransactionId, record: &LogRecord) -> HeapResult<()> {
+        let payload = record.payload().ok_or_else(|| {
+            HeapError::SerializationError(format!(
+                "Undecodable heap record at {}",
+                record.header.lsn
+            ))
+        })?;
+        let Some(page_id) = payload.page_id() else {
+            return Ok(());
+        };
+        // A tuple coming back to life is a delete undone; the row is
+        // counted again if it is the row's home slot
+        let undeleted = match payload {
+            LogPayload::HeapUpdate(ref p)
+                if tuple::is_dead(&p.tuple) && !tuple::is_dead(&p.old_tuple) =>
+            {
+                Some((p.slot as usize, !tuple::is_moved(&p.old_tuple)))
+            }
+            _ => None,
+        };
+        let action = match payload {
+            LogPayload::HeapInsert(p) => CompensationAction::HeapDelete(HeapSlotPayload {
+                page_id: p.page_id,
+                slot: p.slot,
+                old_tuple: p.tuple,
+            }),
+            LogPayload::HeapDelete(p) => CompensationAction::HeapInsert(HeapTuplePayload {
+                page_id: p.page_id,
+                slot: p.slot,
+                tuple: p.old_tuple,
+                old_tuple: Vec::new(),
+            }),
+            LogPayload::HeapUpdate(p) => CompensationAction::HeapUpdate(HeapTuplePayload {
+                page_id: p.page_id,
+                slot: p.slot,
+                tuple: p.old_tuple,
+                old_tuple: p.tuple,
+            }),
+            _ => return Ok(()),
+        };
+
+        let mut heap_page = self.fetch_page(page_id)?;
+        self.log_full_page_image(&mut heap_page)?;
+        match action {
+            CompensationAction::HeapInsert(ref p) => {
+                heap_page.insert_tuple_at(p.slot as usize, &p.tuple)?
+            }
+            CompensationAction::HeapUpdate(ref p) => {
+                heap_page.update_tuple(p.slot as usize, &p.tuple)?
+            }
+            CompensationAction::HeapDelete(ref p) => heap_page.delete_tuple(p.slot as usize)?,
+            _ => {}
+        }
+        let undo_next_lsn = record.header.prev_lsn;
+        self.log_change(&mut heap_page, |wal| {
+            wal.log_compensation(tx_id, undo_next_lsn, action)
+        })?;
+        self.write_page(page_id, &heap_page)?;
+
+        if let Some((slot, home)) = undeleted {
+            self.deleted_by.remove(&RowId::new(page_id, slot));
+            if home {
+                self.dead_tuples = self.dead_tuples.saturating_sub(1);
+                self.live_tuples += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Repeats a logged heap change during recovery
+    ///
+    /// Pages whose LSN shows they already hold the change are left alone.
+    /// Extending a segment is not logged by itself, so a change to a block
+    /// past the end recorded on the metapage moves that end forward.
+    pub fn redo(buffer_mgr: &BufferMgr, lsn: LSN, record: &LogRecord) -> HeapResult<()> {
+        let payload = record.payload().ok_or_else(|| {
+            HeapError::SerializationError(format!("Undecodable heap record at {}", lsn))
+        })?;
+        // A compensation record is redone like the change it carries
+        let payload = match payload {
+            LogPayload::Compensation(clr) => match clr.action {
+                CompensationAction::HeapInsert(p) => LogPayload::HeapInsert(p),
+                CompensationAction::HeapDelete(p) => LogPayload::HeapDelete(p),
+                CompensationAction::HeapUpdate(p) => LogPayload::HeapUpdate(p),
+                _ => return Ok(()),
+            },
+            other => other,
+        };
+        let page_id = match payload {
+            LogPayload::HeapInsert(ref p) | LogPayload::HeapUpdate(ref p) => p.page_id,
+            LogPayload::HeapDelete(ref p) => p.page_id,
+            _ => return Ok(()),
+        };
+
+        let mut data = buffer_mgr
+            .write_page(page_id)
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        let mut page = HeapPage::from_page(page_id, data.clone());
+        let applied = if page.lsn() < lsn.raw() {
+            let result = match payload {
+                LogPayload::HeapInsert(ref p) => page.insert_tuple_at(p.slot as usize, &p.tuple),
+                LogPayload::HeapUpdate(ref p) => page.update_tuple(p.slot as usize, &p.tuple),
+                LogPayload::HeapDelete(ref p) => page.delete_tuple(p.slot as usize),
+                _ => Ok(()),
+            };
+            if result.is_ok() {
+                page.set_lsn(lsn.raw());
+                data.as_bytes_mut().copy_from_slice(page.as_bytes());
+            }
+            result
+        } else {
+            Ok(())
+        };
+        drop(data);
+        applied?;
+
+        let block = page_id as u32 as u64;
+        let meta_page_id = make_page_id(page_id >> 32, HEAP_META_BLOCK);
+        let mut meta = buffer_mgr
+            .write_page(meta_page_id)
+            .map_err(|e| HeapError::Other(e.to_string()))?;
+        if decode_meta(meta.payload()).is_none_or(|num_blocks| num_blocks < block) {
+            encode_meta(block, meta.payload_mut());
+        }
+        Ok(())
+    }
+}
+
+/// Metapage fields live in the page payload, after the page header
+fn encode_meta(num_blocks: u64, data: &mut [u8]) {
+    data[0..4].copy_from_slice(&HEAP_META_MAGIC.to_le_bytes());
+    data[4..8].copy_from_slice(&HEAP_META_VERSION.to_le_bytes());
+    data[8..16].copy_from_slice(&num_blocks.to_le_bytes());
+}
+
+/// Returns the block count, or None for a page that was never initialized
+fn decode_meta(data: &[u8]) -> Option<u64> {
+    let magic = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    if magic != HEAP_META_MAGIC {
+        return None;
+    }
+    Some(u64::from_le_bytes(data[8..16].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuple(byte: u8, len: usize) -> Vec<u8> {
+        vec![byte; len]
+    }
+
+    #[test]
+    fn test_deleted_slots_are_reused_and_trailing_ones_dropped() {
+        let mut page = HeapPage::new(1);
+        for i in 0..4 {
+            assert_eq!(page.insert_tuple(&tuple(i, 16)).unwrap(), i as usize);
+        }
+        page.delete_tuple(1).unwrap();
+        page.delete_tuple(2).unwrap();
+        assert_eq!(page.slot_count(), 4);
+
+        // The free slot list survives a round trip through the page bytes
+        let mut page = HeapPage::from_bytes(1, page.as_bytes());
+        assert_eq!(page.insert_tuple(&tuple(9, 16)).unwrap(), 2);
+        assert_eq!(page.get_tuple(2).unwrap(), tuple(9, 16));
+
+        page.delete_tuple(2).unwrap();
+        page.delete_tuple(3).unwrap();
+        assert_eq!(page.slot_count(), 1);
+        assert_eq!(page.insert_tuple(&tuple(5, 16)).unwrap(), 1);
+        assert_eq!(page.insert_tuple(&tuple(6, 16)).unwrap(), 2);
+
+        // Putting a tuple back past the end frees the slots in between
+        page.insert_tuple_at(5, &tuple(7, 16)).unwrap();
+        assert!(page.insert_tuple_at(5, &tuple(7, 16)).is_err());
+        assert_eq!(page.insert_tuple(&tuple(8, 16)).unwrap(), 3);
+        assert_eq!(page.insert_tuple(&tuple(8, 16)).unwrap(), 4);
+        assert_eq!(page.slot_count(), 6);
+    }
+
+    #[test]
+    fn test_compaction_reclaims_space_and_keeps_slot_numbers() {
+        let mut page = HeapPage::new(1);
+        let mut slots = Vec::new();
+        while page.can_insert(1000) {
+            slots.push(page.insert_tuple(&tuple(slots.len() as u8, 1000)).unwrap());
+        }
+        for slot in [1, 3, 5] {
+            page.delete_tuple(slot).unwrap();
+        }
+        let free = page.available_space();
+        assert!(free >= 3000);
+
+        // Only fits once the holes are put together
+        let slot = page.insert_tuple(&tuple(0xAA, 2500)).unwrap();
+        assert_eq!(page.available_space(), free - 2500);
+        page.update_tuple(0, &tuple(0xBB, 1400)).unwrap();
+        assert_eq!(page.get_tuple(slot).unwrap(), tuple(0xAA, 2500));
+        assert_eq!(page.get_tuple(0).unwrap(), tuple(0xBB, 1400));
+        for &i in &slots[2..] {
+            if i % 2 == 0 {
+                assert_eq!(page.get_tuple(i).unwrap(), tuple(i as u8, 1000));
+            }
+        }
+        assert!(page.update_tuple(2, &tuple(0, PAGE_SIZE)).is_err());
+    }
 }
@@ -0,0 +1,612 @@
+//! Heap tuple format
+//!
+//! A row is stored as a header, a null bitmap, a fixed-width section, an
+//! offset array for the variable-length columns, and their bytes (little
+//! endian):
+//!
+//! ```text
+//! +---------+-------+-------+----------------+-------------+-------------+----------+----------
+//! | version | flags | natts | schema_version | null bitmap | fixed-width | var ends | var data
+//! +---------+-------+-------+----------------+-------------+-------------+----------+----------
+//!      1        1       2            4         (natts+7)/8                 4 per var
+//! ```
+//!
+//! `natts` is the number of columns the row was written with and
+//! `schema_version` the table's schema version at the time. Each of those
+//! columns with a fixed width takes `ColumnType::size()` bytes of the
+//! fixed-width section, zeroed when null, so where it lives depends on the
+//! schema alone. Each variable-length column has an entry in the offset
+//! array with the end of its bytes, counted from the start of the
+//! variable-length data; a null one is empty. Columns the schema gained
+//! after the row was written read as null.
+//!
+//! A row that an update moved off its page leaves a `ForwardPointer` in
+//! its slot, flagged `TUPLE_FLAG_FORWARD`, so its RowId stays valid. The
+//! row itself is stored with `TUPLE_FLAG_MOVED` and is only reached
+//! through the pointer. A deleted row keeps its bytes, flagged
+//! `TUPLE_FLAG_DEAD`, until vacuum frees its slot.
+
+use super::{HeapError, HeapResult};
+use crate::table::Column;
+use crate::types::ColumnType;
+
+/// Version of the row layout, the first byte of every stored row
+pub const TUPLE_FORMAT_VERSION: u8 = 1;
+
+/// Size of `TupleHeader` on disk
+pub const TUPLE_HEADER_SIZE: usize = 8;
+
+/// The slot holds a `ForwardPointer` instead of a row
+pub const TUPLE_FLAG_FORWARD: u8 = 0x01;
+
+/// The row lives away from its RowId, behind a `ForwardPointer`
+pub const TUPLE_FLAG_MOVED: u8 = 0x02;
+
+/// Deleted; the bytes stay so the delete can be rolled back, until vacuum
+/// frees the slot
+pub const TUPLE_FLAG_DEAD: u8 = 0x04;
+
+/// Size of one offset array entry
+const VAR_OFFSET_SIZE: usize = 4;
+
+fn format_error(msg: impl Into<String>) -> HeapError {
+    HeapError::SerializationError(msg.into())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float32(f32),
+    Float64(f64),
+    Boolean(bool),
+    VarChar(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub fn serialized_size(&self) -> usize {
+        match self {
+            Value::Null => 0,
+            Value::Int8(_) | Value::UInt8(_) | Value::Boolean(_) => 1,
+            Value::Int16(_) | Value::UInt16(_) => 2,
+            Value::Int32(_) | Value::UInt32(_) | Value::Float32(_) => 4,
+            Value::Int64(_) | Value::UInt64(_) | Value::Float64(_) => 8,
+            Value::VarChar(s) => s.len(),
+            Value::Blob(b) => b.len(),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Value::Null => vec![],
+            Value::Int8(v) => v.to_le_bytes().to_vec(),
+            Value::Int16(v) => v.to_le_bytes().to_vec(),
+            Value::Int32(v) => v.to_le_bytes().to_vec(),
+            Value::Int64(v) => v.to_le_bytes().to_vec(),
+            Value::UInt8(v) => v.to_le_bytes().to_vec(),
+            Value::UInt16(v) => v.to_le_bytes().to_vec(),
+            Value::UInt32(v) => v.to_le_bytes().to_vec(),
+            Value::UInt64(v) => v.to_le_bytes().to_vec(),
+            Value::Float32(v) => v.to_le_bytes().to_vec(),
+            Value::Float64(v) => v.to_le_bytes().to_vec(),
+            Value::Boolean(v) => [*v as u8].to_vec(),
+            Value::VarChar(s) => s.as_bytes().to_vec(),
+            Value::Blob(b) => b.clone(),
+        }
+    }
+
+    /// Decodes the bytes `serialize` produced for a non-null value of
+    /// `col_type`
+    ///
+    /// Fixed-width types need exactly their width; strings must be UTF-8.
+    pub fn deserialize(data: &[u8], col_type: &ColumnType) -> HeapResult<Self> {
+        if !col_type.is_variable_length() && data.len() != col_type.size() {
+            return Err(format_error(format!(
+                "{:?} value needs {} bytes, got {}",
+                col_type,
+                col_type.size(),
+                data.len()
+            )));
+        }
+        Ok(match col_type {
+            ColumnType::Int8 => Value::Int8(i8::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::Int16 => Value::Int16(i16::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::Int32 => Value::Int32(i32::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::Int64 => Value::Int64(i64::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::UInt8 => Value::UInt8(u8::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::UInt16 => Value::UInt16(u16::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::UInt32 => Value::UInt32(u32::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::UInt64 => Value::UInt64(u64::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::Float32 => Value::Float32(f32::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::Float64 => Value::Float64(f64::from_le_bytes(data.try_into().unwrap())),
+            ColumnType::Bool => Value::Boolean(data[0] != 0),
+            ColumnType::Varchar(_) => Value::VarChar(
+                String::from_utf8(data.to_vec())
+                    .map_err(|_| format_error("VARCHAR value is not valid UTF-8"))?,
+            ),
+            ColumnType::Blob(_) => Value::Blob(data.to_vec()),
+        })
+    }
+
+    /// Checks that the value can be stored in a column of `col_type`
+    ///
+    /// Null fits any column; strings and blobs may not be longer than the
+    /// column's maximum length.
+    fn check_type(&self, col_type: &ColumnType) -> Result<(), String> {
+        let fits = matches!(
+            (col_type, self),
+            (_, Value::Null)
+                | (ColumnType::Int8, Value::Int8(_))
+                | (ColumnType::Int16, Value::Int16(_))
+                | (ColumnType::Int32, Value::Int32(_))
+                | (ColumnType::Int64, Value::Int64(_))
+                | (ColumnType::UInt8, Value::UInt8(_))
+                | (ColumnType::UInt16, Value::UInt16(_))
+                | (ColumnType::UInt32, Value::UInt32(_))
+                | (ColumnType::UInt64, Value::UInt64(_))
+                | (ColumnType::Float32, Value::Float32(_))
+                | (ColumnType::Float64, Value::Float64(_))
+                | (ColumnType::Bool, Value::Boolean(_))
+                | (ColumnType::Varchar(_), Value::VarChar(_))
+                | (ColumnType::Blob(_), Value::Blob(_))
+        );
+        if !fits {
+            return Err(format!("a {:?} column cannot hold {:?}", col_type, self));
+        }
+        match col_type {
+            ColumnType::Varchar(max) | ColumnType::Blob(max)
+                if self.serialized_size() > *max as usize =>
+            {
+                Err(format!(
+                    "{} bytes exceed the maximum length {}",
+                    self.serialized_size(),
+                    max
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Header at the start of every stored row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TupleHeader {
+    /// `TUPLE_FLAG_*` bits
+    pub flags: u8,
+    /// Number of columns the row was written with
+    pub natts: u16,
+    /// Schema version of the table when the row was written
+    pub schema_version: u32,
+}
+
+impl TupleHeader {
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = TUPLE_FORMAT_VERSION;
+        buf[1] = self.flags;
+        buf[2..4].copy_from_slice(&self.natts.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.schema_version.to_le_bytes());
+    }
+
+    /// Reads the header of a stored row
+    pub fn decode(data: &[u8]) -> HeapResult<Self> {
+        if data.len() < TUPLE_HEADER_SIZE {
+            return Err(format_error("Data too short for tuple header"));
+        }
+        if data[0] != TUPLE_FORMAT_VERSION {
+            return Err(format_error(format!(
+                "Unknown tuple format version {}",
+                data[0]
+            )));
+        }
+        Ok(Self {
+            flags: data[1],
+            natts: u16::from_le_bytes(data[2..4].try_into().unwrap()),
+            schema_version: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+        })
+    }
+}
+
+/// Left in the slot of a row that moved to another page of the heap
+///
+/// Shares the tuple header's first two bytes, so it is told apart by its
+/// flags; the slot and block take the place of `natts` and
+/// `schema_version`. Whatever follows the first `TUPLE_HEADER_SIZE` bytes
+/// is padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardPointer {
+    /// Heap block the row moved to
+    pub block: u32,
+    /// Slot the row moved to
+    pub slot: u16,
+}
+
+impl ForwardPointer {
+    /// Encodes the pointer, padded with zeros to `len` bytes so it can
+    /// take over a slot without shrinking it
+    pub fn encode(&self, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len.max(TUPLE_HEADER_SIZE)];
+        buf[0] = TUPLE_FORMAT_VERSION;
+        buf[1] = TUPLE_FLAG_FORWARD;
+        buf[2..4].copy_from_slice(&self.slot.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.block.to_le_bytes());
+        buf
+    }
+
+    /// Reads the pointer stored in a slot, or None if it holds a row
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < TUPLE_HEADER_SIZE
+            || data[0] != TUPLE_FORMAT_VERSION
+            || data[1] & TUPLE_FLAG_FORWARD == 0
+        {
+            return None;
+        }
+        Some(Self {
+            slot: u16::from_le_bytes(data[2..4].try_into().unwrap()),
+            block: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+        })
+    }
+}
+
+/// Returns true if a stored row is only reachable through a forwarding
+/// pointer
+pub fn is_moved(data: &[u8]) -> bool {
+    data.len() >= TUPLE_HEADER_SIZE && data[1] & TUPLE_FLAG_MOVED != 0
+}
+
+/// Returns true if a stored row or forwarding pointer was deleted
+pub fn is_dead(data: &[u8]) -> bool {
+    data.len() >= TUPLE_HEADER_SIZE && data[1] & TUPLE_FLAG_DEAD != 0
+}
+
+/// Where the columns of a schema live in a stored row
+struct TupleLayout {
+    /// Per column, the offset of its fixed-width bytes or of its entry in
+    /// the offset array
+    offsets: Vec<usize>,
+    /// Start of the variable-length data
+    var_start: usize,
+}
+
+impl TupleLayout {
+    fn new(columns: &[Column]) -> Self {
+        let bitmap_end = TUPLE_HEADER_SIZE + columns.len().div_ceil(8);
+        let fixed_size: usize = columns
+            .iter()
+            .map(|c| c.column_type())
+            .filter(|t| !t.is_variable_length())
+            .map(|t| t.size())
+            .sum();
+
+        let mut offsets = Vec::with_capacity(columns.len());
+        let mut fixed = bitmap_end;
+        let mut var_entry = bitmap_end + fixed_size;
+        for column in columns {
+            let col_type = column.column_type();
+            if col_type.is_variable_length() {
+                offsets.push(var_entry);
+                var_entry += VAR_OFFSET_SIZE;
+            } else {
+                offsets.push(fixed);
+                fixed += col_type.size();
+            }
+        }
+        Self {
+            offsets,
+            var_start: var_entry,
+        }
+    }
+}
+
+#[inline]
+fn is_null(data: &[u8], idx: usize) -> bool {
+    data[TUPLE_HEADER_SIZE + idx / 8] & (1 << (idx % 8)) != 0
+}
+
+#[derive(Debug, Clone)]
+pub struct Tuple {
+    values: Vec<Value>,
+}
+
+impl Tuple {
+    pub fn new(values: Vec<Value>) -> Self {
+        Self { values }
+    }
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+    pub fn get(&self, idx: usize) -> Option<&Value> {
+        self.values.get(idx)
+    }
+
+    /// Encodes the row for `columns` at `schema_version`
+    ///
+    /// Fails unless there is one value per column, each of the column's
+    /// type (or null) and within its maximum length.
+    pub fn serialize(&self, columns: &[Column], schema_version: u32) -> HeapResult<Vec<u8>> {
+        if self.values.len() != columns.len() {
+            return Err(format_error(format!(
+                "Tuple has {} values for {} columns",
+                self.values.len(),
+                columns.len()
+            )));
+        }
+        let natts = u16::try_from(columns.len())
+            .map_err(|_| format_error(format!("Too many columns: {}", columns.len())))?;
+
+        let layout = TupleLayout::new(columns);
+        let mut buf = vec![0u8; layout.var_start];
+        TupleHeader {
+            flags: 0,
+            natts,
+            schema_version,
+        }
+        .encode(&mut buf);
+
+        for (i, (value, column)) in self.values.iter().zip(columns).enumerate() {
+            let col_type = column.column_type();
+            value
+                .check_type(&col_type)
+                .map_err(|e| format_error(format!("Column {}: {}", column.name(), e)))?;
+            if matches!(value, Value::Null) {
+                buf[TUPLE_HEADER_SIZE + i / 8] |= 1 << (i % 8);
+            }
+
+            let offset = layout.offsets[i];
+            if col_type.is_variable_length() {
+                buf.extend(value.serialize());
+                let end = (buf.len() - layout.var_start) as u32;
+                buf[offset..offset + VAR_OFFSET_SIZE].copy_from_slice(&end.to_le_bytes());
+            } else if !matches!(value, Value::Null) {
+                buf[offset..offset + col_type.size()].copy_from_slice(&value.serialize());
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Decodes a row stored for `columns` or for an older schema with a
+    /// prefix of them
+    ///
+    /// Columns the row was written without read as null.
+    pub fn deserialize(data: &[u8], columns: &[Column]) -> HeapResult<Self> {
+        let header = TupleHeader::decode(data)?;
+        if header.flags & TUPLE_FLAG_FORWARD != 0 {
+            return Err(format_error("Slot holds a forwarding pointer, not a row"));
+        }
+        if header.flags & TUPLE_FLAG_DEAD != 0 {
+            return Err(format_error("Row was deleted"));
+        }
+        let natts = header.natts as usize;
+        if natts > columns.len() {
+            return Err(format_error(format!(
+                "Row has {} columns, the schema {}",
+                natts,
+                columns.len()
+            )));
+        }
+        let stored = &columns[..natts];
+        let layout = TupleLayout::new(stored);
+        if data.len() < layout.var_start {
+            return Err(format_error(format!(
+                "Data too short for {} columns: {} bytes",
+                natts,
+                data.len()
+            )));
+        }
+
+        let var_data = &data[layout.var_start..];
+        let mut var_begin = 0;
+        let mut values = Vec::with_capacity(columns.len());
+        for (i, column) in stored.iter().enumerate() {
+            let col_type = column.column_type();
+            let offset = layout.offsets[i];
+            let bytes = if col_type.is_variable_length() {
+                let end =
+                    u32::from_le_bytes(data[offset..offset + VAR_OFFSET_SIZE].try_into().unwrap())
+                        as usize;
+                if end < var_begin || end > var_data.len() {
+                    return Err(format_error(format!(
+                        "Column {}: bad end offset {}",
+                        column.name(),
+                        end
+                    )));
+                }
+                let bytes = &var_data[var_begin..end];
+                var_begin = end;
+                bytes
+            } else {
+                &data[offset..offset + col_type.size()]
+            };
+
+            if is_null(data, i) {
+                values.push(Value::Null);
+            } else {
+                values.push(Value::deserialize(bytes, &col_type)?);
+            }
+        }
+        values.resize(columns.len(), Value::Null);
+        Ok(Tuple::new(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn columns_of(types: &[ColumnType]) -> Vec<Column> {
+        types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| Column::new(format!("c{}", i), *t, true, i as u32))
+            .collect()
+    }
+
+    fn column_type() -> impl Strategy<Value = ColumnType> {
+        prop_oneof![
+            Just(ColumnType::Int8),
+            Just(ColumnType::Int16),
+            Just(ColumnType::Int32),
+            Just(ColumnType::Int64),
+            Just(ColumnType::UInt8),
+            Just(ColumnType::UInt16),
+            Just(ColumnType::UInt32),
+            Just(ColumnType::UInt64),
+            Just(ColumnType::Float32),
+            Just(ColumnType::Float64),
+            Just(ColumnType::Bool),
+            (0u32..40).prop_map(ColumnType::Varchar),
+            (0u32..40).prop_map(ColumnType::Blob),
+        ]
+    }
+
+    /// A non-null value of `col_type`; NaN is left out since it never
+    /// equals itself
+    fn value_of(col_type: ColumnType) -> BoxedStrategy<Value> {
+        match col_type {
+            ColumnType::Int8 => any::<i8>().prop_map(Value::Int8).boxed(),
+            ColumnType::Int16 => any::<i16>().prop_map(Value::Int16).boxed(),
+            ColumnType::Int32 => any::<i32>().prop_map(Value::Int32).boxed(),
+            ColumnType::Int64 => any::<i64>().prop_map(Value::Int64).boxed(),
+            ColumnType::UInt8 => any::<u8>().prop_map(Value::UInt8).boxed(),
+            ColumnType::UInt16 => any::<u16>().prop_map(Value::UInt16).boxed(),
+            ColumnType::UInt32 => any::<u32>().prop_map(Value::UInt32).boxed(),
+            ColumnType::UInt64 => any::<u64>().prop_map(Value::UInt64).boxed(),
+            ColumnType::Float32 => any::<f32>()
+                .prop_filter("NaN", |v| !v.is_nan())
+                .prop_map(Value::Float32)
+                .boxed(),
+            ColumnType::Float64 => any::<f64>()
+                .prop_filter("NaN", |v| !v.is_nan())
+                .prop_map(Value::Float64)
+                .boxed(),
+            ColumnType::Bool => any::<bool>().prop_map(Value::Boolean).boxed(),
+            ColumnType::Varchar(max) => prop::collection::vec(any::<char>(), 0..=max as usize)
+                .prop_map(move |chars| {
+                    let mut s = String::new();
+                    for c in chars {
+                        if s.len() + c.len_utf8() > max as usize {
+                            break;
+                        }
+                        s.push(c);
+                    }
+                    Value::VarChar(s)
+                })
+                .boxed(),
+            ColumnType::Blob(max) => prop::collection::vec(any::<u8>(), 0..=max as usize)
+                .prop_map(Value::Blob)
+                .boxed(),
+        }
+    }
+
+    /// A schema of up to 20 columns and a row for it, about a quarter of
+    /// its values null
+    fn row() -> impl Strategy<Value = (Vec<Column>, Vec<Value>)> {
+        prop::collection::vec(column_type(), 0..20).prop_flat_map(|types| {
+            let values: Vec<_> = types
+                .iter()
+                .map(|t| prop_oneof![1 => Just(Value::Null), 3 => value_of(*t)])
+                .collect();
+            (Just(columns_of(&types)), values)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_tuple_round_trip((columns, values) in row(), version in any::<u32>()) {
+            let data = Tuple::new(values.clone()).serialize(&columns, version).unwrap();
+            let header = TupleHeader::decode(&data).unwrap();
+            prop_assert_eq!(header.natts as usize, columns.len());
+            prop_assert_eq!(header.schema_version, version);
+            let tuple = Tuple::deserialize(&data, &columns).unwrap();
+            prop_assert_eq!(tuple.values(), &values[..]);
+        }
+
+        #[test]
+        fn prop_added_columns_read_as_null(
+            (columns, values) in row(),
+            split in any::<prop::sample::Index>(),
+        ) {
+            let kept = split.index(columns.len() + 1);
+            let data = Tuple::new(values[..kept].to_vec())
+                .serialize(&columns[..kept], 1)
+                .unwrap();
+            let tuple = Tuple::deserialize(&data, &columns).unwrap();
+            prop_assert_eq!(&tuple.values()[..kept], &values[..kept]);
+            prop_assert!(tuple.values()[kept..].iter().all(|v| *v == Value::Null));
+        }
+    }
+
+    #[test]
+    fn test_strings_before_other_columns() {
+        let columns = columns_of(&[
+            ColumnType::Varchar(16),
+            ColumnType::Int32,
+            ColumnType::Blob(8),
+            ColumnType::Varchar(16),
+            ColumnType::Bool,
+        ]);
+        let values = vec![
+            Value::VarChar("first".to_string()),
+            Value::Int32(-7),
+            Value::Null,
+            Value::VarChar(String::new()),
+            Value::Boolean(true),
+        ];
+        let data = Tuple::new(values.clone()).serialize(&columns, 3).unwrap();
+        assert_eq!(
+            Tuple::deserialize(&data, &columns).unwrap().values(),
+            values
+        );
+    }
+
+    #[test]
+    fn test_serialize_rejects_rows_that_do_not_fit_the_schema() {
+        let columns = columns_of(&[ColumnType::Int64, ColumnType::Varchar(4)]);
+        let serialize = |values: Vec<Value>| Tuple::new(values).serialize(&columns, 1);
+
+        assert!(serialize(vec![Value::Int64(1)]).is_err());
+        assert!(serialize(vec![Value::Int32(1), Value::Null]).is_err());
+        assert!(serialize(vec![Value::Int64(1), Value::VarChar("hello".into())]).is_err());
+        assert!(serialize(vec![Value::Null, Value::VarChar("four".into())]).is_ok());
+    }
+
+    #[test]
+    fn test_deserialize_rejects_damaged_rows() {
+        let columns = columns_of(&[ColumnType::Int64, ColumnType::Varchar(8)]);
+        let data = Tuple::new(vec![Value::Int64(5), Value::VarChar("abc".into())])
+            .serialize(&columns, 1)
+            .unwrap();
+
+        assert!(Tuple::deserialize(&data[..4], &columns).is_err());
+        assert!(Tuple::deserialize(&data[..data.len() - 1], &columns).is_err());
+        assert!(Tuple::deserialize(&data, &columns[..1]).is_err());
+        let mut bad_version = data.clone();
+        bad_version[0] = TUPLE_FORMAT_VERSION + 1;
+        assert!(Tuple::deserialize(&bad_version, &columns).is_err());
+    }
+
+    #[test]
+    fn test_forward_pointer_is_never_read_as_a_row() {
+        let columns = columns_of(&[ColumnType::Int64]);
+        let data = Tuple::new(vec![Value::Int64(5)])
+            .serialize(&columns, 1)
+            .unwrap();
+        assert_eq!(ForwardPointer::decode(&data), None);
+        assert!(!is_moved(&data));
+
+        let pointer = ForwardPointer { block: 9, slot: 3 };
+        let stub = pointer.encode(data.len());
+        assert_eq!(stub.len(), data.len());
+        assert_eq!(ForwardPointer::decode(&stub), Some(pointer));
+        assert!(Tuple::deserialize(&stub, &columns).is_err());
+        assert_eq!(pointer.encode(0).len(), TUPLE_HEADER_SIZE);
+    }
+}
@@ -0,0 +1,306 @@
+//! Vacuum - reclaiming the space of deleted rows
+//!
+//! A delete only flags its tuples `TUPLE_FLAG_DEAD`, so the deleting
+//! transaction can still roll it back. Vacuum frees the slots of the dead
+//! tuples whose deleter is no longer running, compacts the pages it freed
+//! anything on, records their free space in the free space map and cuts
+//! empty pages off the end of the heap.
+//!
+//! Freeing a slot is logged like any other delete, on behalf of no
+//! transaction, so recovery repeats it and nothing rolls it back. Cutting
+//! the tail off is logged as an image of the metapage with the new end.
+
+use super::tuple::{self, ForwardPointer, TUPLE_FLAG_DEAD};
+use super::{make_page_id, HeapError, HeapResult, HeapTable, RowId, Tuple};
+use crate::buffer::{BufferError, BufferRing, BufferTag, SCAN_RING_SIZE};
+use crate::infrastructure::worker::BackgroundWorker;
+use crate::lock::TransactionId;
+use crate::types::PageId;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// What one vacuum of a heap found and reclaimed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VacuumStats {
+    /// Heap pages read
+    pub pages_scanned: u64,
+    /// Dead tuples whose slots were freed
+    pub tuples_removed: u64,
+    /// Dead tuples left alone because their deleter may still roll back
+    pub tuples_kept: u64,
+    /// Bytes of tuple data freed
+    pub bytes_reclaimed: u64,
+    /// Empty pages cut off the end of the heap
+    pub pages_truncated: u64,
+}
+
+/// When tables are vacuumed without being asked
+///
+/// A table is due once its dead tuples reach `threshold` plus
+/// `scale_factor` times its live rows. A background thread checks the
+/// tables every `naptime`.
+#[derive(Debug, Clone)]
+pub struct AutovacuumConfig {
+    /// Check tables and vacuum the ones that are due; off, only explicit
+    /// vacuums run
+    pub enabled: bool,
+    /// Time between checks
+    pub naptime: Duration,
+    /// Dead tuples a table needs before it is vacuumed, whatever its size
+    pub threshold: u64,
+    /// Fraction of the live rows added to `threshold`
+    pub scale_factor: f64,
+}
+
+impl Default for AutovacuumConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            naptime: Duration::from_secs(60),
+            threshold: 50,
+            scale_factor: 0.2,
+        }
+    }
+}
+
+impl AutovacuumConfig {
+    /// Configuration under which tables are only vacuumed on request
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// Background autovacuum thread
+///
+/// Every `naptime` the thread calls the round it was started with, which
+/// vacuums the tables that are due. A round that fails leaves its error
+/// for `take_error`. A disabled config starts no thread.
+pub struct AutovacuumWorker<E> {
+    config: AutovacuumConfig,
+    worker: BackgroundWorker<E>,
+}
+
+impl<E: Send + 'static> AutovacuumWorker<E> {
+    /// Create a worker that checks tables under `config`
+    pub fn new(config: AutovacuumConfig) -> Self {
+        Self {
+            config,
+            worker: BackgroundWorker::new(),
+        }
+    }
+
+    /// Starts the thread, running `round` every naptime
+    ///
+    /// Restarts the thread if it is running.
+    pub fn start<F>(&mut self, mut round: F)
+    where
+        F: FnMut(&AutovacuumConfig) -> Result<(), E> + Send + 'static,
+    {
+        self.stop();
+        if !self.config.enabled {
+            return;
+        }
+
+        let config = self.config.clone();
+        self.worker
+            .start(self.config.naptime, move || round(&config));
+    }
+
+    /// Returns true while the autovacuum thread is running
+    pub fn is_running(&self) -> bool {
+        self.worker.is_running()
+    }
+
+    /// Returns the error of the last round that failed, if any, and
+    /// clears it
+    pub fn take_error(&self) -> Option<E> {
+        self.worker.take_error()
+    }
+
+    /// Stop the worker
+    pub fn stop(&mut self) {
+        self.worker.stop();
+    }
+}
+
+/// A dead tuple vacuum is going to free
+struct DeadTuple {
+    slot: usize,
+    len: usize,
+    /// The row, for a dead tuple that is a row's home: its RowId is what
+    /// indexes point at
+    row: Option<Tuple>,
+}
+
+impl HeapTable {
+    /// Returns true if the dead tuples not yet freed make the heap due
+    /// under `config`
+    pub fn needs_vacuum(&self, config: &AutovacuumConfig) -> bool {
+        let limit = config.threshold as f64 + config.scale_factor * self.live_tuples as f64;
+        self.dead_tuples > 0 && self.dead_tuples as f64 >= limit
+    }
+
+    /// Counts the live and dead tuples in the heap, for a heap that was
+    /// just opened
+    ///
+    /// A page that cannot be read is left out; the operation that reads it
+    /// reports the error.
+    pub(super) fn count_tuples(&mut self) {
+        let (mut live, mut dead) = (0, 0);
+        let mut ring = BufferRing::new(SCAN_RING_SIZE);
+        for page_id in self.page_ids() {
+            let Ok(heap_page) = self.load_page(page_id, Some(&mut ring)) else {
+                continue;
+            };
+            // A moved row is counted at its home slot only
+            for (_, data) in heap_page.iter_tuples() {
+                if tuple::is_moved(&data) {
+                    continue;
+                }
+                if tuple::is_dead(&data) {
+                    dead += 1;
+                } else {
+                    live += 1;
+                }
+            }
+        }
+        self.live_tuples = live;
+        self.dead_tuples = dead;
+    }
+
+    /// Statistics of the last vacuum since the heap was opened
+    pub fn last_vacuum(&self) -> Option<VacuumStats> {
+        self.last_vacuum
+    }
+
+    /// Frees the dead tuples no running transaction can roll back
+    ///
+    /// `running` tells whether the transaction that deleted a tuple is
+    /// still running. Every row that goes away is passed to `on_remove`
+    /// with its last values before any slot is freed, so the caller can
+    /// drop index entries that still point at it.
+    pub fn vacuum(
+        &mut self,
+        running: impl Fn(TransactionId) -> bool,
+        mut on_remove: impl FnMut(RowId, &Tuple) -> HeapResult<()>,
+    ) -> HeapResult<VacuumStats> {
+        let mut stats = VacuumStats::default();
+        let (mut live, mut kept_rows) = (0, 0);
+        let mut doomed: BTreeMap<PageId, Vec<DeadTuple>> = BTreeMap::new();
+        let mut ring = BufferRing::new(SCAN_RING_SIZE);
+
+        // Find everything first, so a forwarding pointer's row is still
+        // there to be read when the pointer is found
+        for page_id in self.page_ids() {
+            stats.pages_scanned += 1;
+            let heap_page = self.load_page(page_id, Some(&mut ring))?;
+            for (slot, data) in heap_page.iter_tuples() {
+                if !tuple::is_dead(&data) {
+                    if !tuple::is_moved(&data) {
+                        live += 1;
+                    }
+                    continue;
+                }
+                let row_id = RowId::new(page_id, slot);
+                if self.deleted_by.get(&row_id).is_some_and(|&tx| running(tx)) {
+                    stats.tuples_kept += 1;
+                    if !tuple::is_moved(&data) {
+                        kept_rows += 1;
+                    }
+                    continue;
+                }
+                let row = if tuple::is_moved(&data) {
+                    None
+                } else {
+                    self.dead_row(&data)
+                };
+                doomed.entry(page_id).or_default().push(DeadTuple {
+                    slot,
+                    len: data.len(),
+                    row,
+                });
+            }
+        }
+
+        for (&page_id, dead) in &doomed {
+            for tuple in dead {
+                if let Some(ref row) = tuple.row {
+                    on_remove(RowId::new(page_id, tuple.slot), row)?;
+                }
+            }
+        }
+
+        for (page_id, dead) in doomed {
+            for tuple in dead {
+                let row_id = RowId::new(page_id, tuple.slot);
+                self.delete_slot(0, row_id)?;
+                self.deleted_by.remove(&row_id);
+                stats.tuples_removed += 1;
+                stats.bytes_reclaimed += tuple.len as u64;
+            }
+            let mut heap_page = self.fetch_page(page_id)?;
+            heap_page.compact();
+            self.write_page(page_id, &heap_page)?;
+        }
+
+        stats.pages_truncated = self.truncate_empty_tail()?;
+        self.live_tuples = live;
+        self.dead_tuples = kept_rows;
+        self.last_vacuum = Some(stats);
+        Ok(stats)
+    }
+
+    /// Decodes a dead tuple that is a row's home: the row itself, or the
+    /// row its forwarding pointer leads to
+    fn dead_row(&mut self, data: &[u8]) -> Option<Tuple> {
+        let mut data = match ForwardPointer::decode(data) {
+            Some(pointer) => {
+                let stored_at = self.forward_target(pointer);
+                self.fetch_page(stored_at.page_id)
+                    .and_then(|p| p.get_tuple(stored_at.slot_idx))
+                    .ok()?
+            }
+            None => data.to_vec(),
+        };
+        data[1] &= !TUPLE_FLAG_DEAD;
+        Tuple::deserialize(&data, self.table.columns()).ok()
+    }
+
+    /// Drops the empty pages at the end of the heap and returns how many
+    /// were cut off the page file
+    ///
+    /// If one of them is pinned nothing changes and none are counted; a
+    /// later vacuum tries again. Otherwise the blocks leave the buffer pool
+    /// and the page file before the metapage records the new end, so a
+    /// crash in between leaves blocks that read as empty pages.
+    fn truncate_empty_tail(&mut self) -> HeapResult<u64> {
+        let mut keep = self.num_blocks;
+        while keep > 0 {
+            let page_id = make_page_id(self.segment_id, keep);
+            if self.fetch_page(page_id)?.slot_count() > 0 {
+                break;
+            }
+            keep -= 1;
+        }
+        let dropped = self.num_blocks - keep;
+        if dropped == 0 {
+            return Ok(0);
+        }
+
+        let from = BufferTag::from_page_id(make_page_id(self.segment_id, keep + 1));
+        match self.buffer_mgr.truncate_fork(from) {
+            Ok(()) => {}
+            Err(BufferError::PagePinned(_)) => return Ok(0),
+            Err(e) => return Err(HeapError::Other(e.to_string())),
+        }
+        for block in keep + 1..=self.num_blocks {
+            self.fsm.record(block, 0)?;
+        }
+        self.num_blocks = keep;
+        self.write_meta_logged()?;
+        Ok(dropped)
+    }
+}
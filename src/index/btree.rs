@@ -1,16 +1,73 @@
-use crate::buffer::BufferMgr;
-use crate::types::PageId;
-use parking_lot::RwLock;
+//! Paged B+tree
+//!
+//! Each index lives in its own page file (segment) managed through the
+//! buffer pool. Block 0 is the metapage, every other block is either a
+//! node page or a free page chained off the metapage.
+//!
+//! Node page layout:
+//! ```text
+//! Offset  Size  Field
+//!   0     48    PageHeader (type_ = Index, lower = end of used space)
+//!  48     1     kind (INDEX_PAGE_TYPE_INTERNAL / INDEX_PAGE_TYPE_LEAF)
+//!  50     2     level (0 for leaves)
+//!  52     2     num_keys
+//!  56     8     prev sibling (leaves only)
+//!  64     8     next sibling (leaves only)
+//!  72     ...   entries
+//! ```
+//!
+//! Leaf entries are `[key_len u16][key][page_id u64][slot u32]`. Internal
+//! pages start with the leftmost child `u64`, followed by
+//! `[key_len u16][key][page_id u64][slot u32][child u64]`. Entries are
+//! ordered by key and then by row id, so separators stay unique even when
+//! the same key appears many times.
+
+use crate::buffer::{BufferMgr, INVALID_PAGE_ID};
+use crate::index::key::compare_keys;
+use crate::page::page::{PageHeader, PageType};
+use crate::types::{PageId, PAGE_SIZE};
+use crate::wal::lsn::LSN;
+use crate::wal::WalManager;
+use std::cmp::Ordering;
+use std::ops::Bound;
 use std::sync::Arc;
 
 const INDEX_PAGE_TYPE_INTERNAL: u8 = 0;
 const INDEX_PAGE_TYPE_LEAF: u8 = 1;
 
+/// PageHeader flag marking block 0 of an index segment
+const INDEX_META_FLAG: u16 = 0x0001;
+const INDEX_META_MAGIC: u32 = 0x42545245;
+const INDEX_META_VERSION: u16 = 1;
+const META_BLOCK: u64 = 0;
+
+const NODE_KIND_OFFSET: usize = 48;
+const NODE_LEVEL_OFFSET: usize = 50;
+const NODE_NUM_KEYS_OFFSET: usize = 52;
+const NODE_PREV_OFFSET: usize = 56;
+const NODE_NEXT_OFFSET: usize = 64;
+const NODE_HEADER_END: usize = 72;
+
+/// Bytes per entry besides the key: key_len + page_id + slot
+const LEAF_ENTRY_OVERHEAD: usize = 2 + 8 + 4;
+/// Internal entries additionally carry the right child pointer
+const INTERNAL_ENTRY_OVERHEAD: usize = LEAF_ENTRY_OVERHEAD + 8;
+
+/// Usable entry space in a leaf page
+const LEAF_CAPACITY: usize = PAGE_SIZE - NODE_HEADER_END;
+/// Usable entry space in an internal page (leftmost child excluded)
+const INTERNAL_CAPACITY: usize = PAGE_SIZE - NODE_HEADER_END - 8;
+
 #[derive(Debug)]
 pub enum IndexError {
     KeyTooLong,
     DuplicateKey,
     KeyNotFound,
+    /// A unique index already holds `key`; `key` is rendered as `(cols)=(values)`
+    UniqueViolation {
+        index: String,
+        key: String,
+    },
     PageError(String),
     Other(String),
 }
@@ -23,6 +80,9 @@ impl std::fmt::Display for IndexError {
             IndexError::KeyTooLong => write!(f, "Key too long"),
             IndexError::DuplicateKey => write!(f, "Duplicate key"),
             IndexError::KeyNotFound => write!(f, "Key not found"),
+            IndexError::UniqueViolation { index, key } => {
+                write!(f, "Duplicate key in unique index {}: {}", index, key)
+            }
             IndexError::PageError(msg) => write!(f, "Page error: {}", msg),
             IndexError::Other(msg) => write!(f, "Index error: {}", msg),
         }
@@ -31,44 +91,394 @@ impl std::fmt::Display for IndexError {
 
 impl std::error::Error for IndexError {}
 
+impl From<crate::wal::WalError> for IndexError {
+    fn from(err: crate::wal::WalError) -> Self {
+        IndexError::Other(err.to_string())
+    }
+}
+
+impl From<crate::buffer::BufferError> for IndexError {
+    fn from(err: crate::buffer::BufferError) -> Self {
+        IndexError::PageError(err.to_string())
+    }
+}
+
+/// Builds the page id of `block` inside an index segment
+#[inline]
+fn make_page_id(segment_id: u64, block: u64) -> PageId {
+    (segment_id << 32) | block
+}
+
+/// Persistent tree state kept on the metapage
+#[derive(Debug, Clone, Copy)]
+struct MetaData {
+    root: PageId,
+    height: u16,
+    next_block: u64,
+    free_head: PageId,
+}
+
+/// A single (key, row id) pair
+#[derive(Debug, Clone)]
+struct Entry {
+    key: Vec<u8>,
+    rid: (PageId, usize),
+}
+
+impl Entry {
+    fn cmp_to(&self, key: &[u8], rid: (PageId, usize)) -> Ordering {
+        compare_keys(&self.key, key).then(self.rid.cmp(&rid))
+    }
+}
+
+/// Decoded form of a node page
+#[derive(Debug, Clone)]
+struct Node {
+    page_id: PageId,
+    is_leaf: bool,
+    level: u16,
+    prev: PageId,
+    next: PageId,
+    entries: Vec<Entry>,
+    /// Child pointers of an internal node, always `entries.len() + 1` long
+    children: Vec<PageId>,
+}
+
+impl Node {
+    fn new_leaf(page_id: PageId) -> Self {
+        Self {
+            page_id,
+            is_leaf: true,
+            level: 0,
+            prev: INVALID_PAGE_ID,
+            next: INVALID_PAGE_ID,
+            entries: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn new_internal(page_id: PageId, level: u16) -> Self {
+        Self {
+            page_id,
+            is_leaf: false,
+            level,
+            prev: INVALID_PAGE_ID,
+            next: INVALID_PAGE_ID,
+            entries: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn entry_size(&self, entry: &Entry) -> usize {
+        if self.is_leaf {
+            LEAF_ENTRY_OVERHEAD + entry.key.len()
+        } else {
+            INTERNAL_ENTRY_OVERHEAD + entry.key.len()
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        if self.is_leaf {
+            LEAF_CAPACITY
+        } else {
+            INTERNAL_CAPACITY
+        }
+    }
+
+    /// Bytes used by the entries
+    fn used(&self) -> usize {
+        self.entries.iter().map(|e| self.entry_size(e)).sum()
+    }
+
+    /// Index of the child that covers (key, rid): the number of separators
+    /// less than or equal to it
+    fn child_for(&self, key: &[u8], rid: (PageId, usize)) -> usize {
+        self.entries
+            .partition_point(|e| e.cmp_to(key, rid) != Ordering::Greater)
+    }
+
+    /// Index of the leftmost child that may contain `key`
+    fn child_for_key(&self, key: &[u8]) -> usize {
+        self.entries
+            .partition_point(|e| compare_keys(&e.key, key) == Ordering::Less)
+    }
+
+    fn decode(page_id: PageId, data: &[u8]) -> IndexResult<Self> {
+        let header = read_header(data);
+        if header.type_ != PageType::Index as u16 || header.flag & INDEX_META_FLAG != 0 {
+            return Err(IndexError::PageError(format!(
+                "page {} is not an index node",
+                page_id
+            )));
+        }
+
+        let kind = data[NODE_KIND_OFFSET];
+        let is_leaf = match kind {
+            INDEX_PAGE_TYPE_LEAF => true,
+            INDEX_PAGE_TYPE_INTERNAL => false,
+            _ => {
+                return Err(IndexError::PageError(format!(
+                    "page {} has unknown node kind {}",
+                    page_id, kind
+                )));
+            }
+        };
+        let level = read_u16(data, NODE_LEVEL_OFFSET);
+        let num_keys = read_u16(data, NODE_NUM_KEYS_OFFSET) as usize;
+        let prev = read_u64(data, NODE_PREV_OFFSET);
+        let next = read_u64(data, NODE_NEXT_OFFSET);
+
+        let corrupt = || IndexError::PageError(format!("page {} has a corrupt entry", page_id));
+
+        let mut offset = NODE_HEADER_END;
+        let mut children = Vec::new();
+        if !is_leaf {
+            children.reserve(num_keys + 1);
+            children.push(read_u64(data, offset));
+            offset += 8;
+        }
+
+        let mut entries = Vec::with_capacity(num_keys);
+        for _ in 0..num_keys {
+            if offset + 2 > PAGE_SIZE {
+                return Err(corrupt());
+            }
+            let key_len = read_u16(data, offset) as usize;
+            offset += 2;
+            let entry_end = offset + key_len + 12 + if is_leaf { 0 } else { 8 };
+            if entry_end > PAGE_SIZE {
+                return Err(corrupt());
+            }
+            let key = data[offset..offset + key_len].to_vec();
+            offset += key_len;
+            let rid_page = read_u64(data, offset);
+            let rid_slot = read_u32(data, offset + 8) as usize;
+            offset += 12;
+            if !is_leaf {
+                children.push(read_u64(data, offset));
+                offset += 8;
+            }
+            entries.push(Entry {
+                key,
+                rid: (rid_page, rid_slot),
+            });
+        }
+
+        Ok(Self {
+            page_id,
+            is_leaf,
+            level,
+            prev,
+            next,
+            entries,
+            children,
+        })
+    }
+
+    fn encode(&self, data: &mut [u8]) {
+        data[..PAGE_SIZE].fill(0);
+
+        data[NODE_KIND_OFFSET] = if self.is_leaf {
+            INDEX_PAGE_TYPE_LEAF
+        } else {
+            INDEX_PAGE_TYPE_INTERNAL
+        };
+        write_u16(data, NODE_LEVEL_OFFSET, self.level);
+        write_u16(data, NODE_NUM_KEYS_OFFSET, self.entries.len() as u16);
+        write_u64(data, NODE_PREV_OFFSET, self.prev);
+        write_u64(data, NODE_NEXT_OFFSET, self.next);
+
+        let mut offset = NODE_HEADER_END;
+        if !self.is_leaf {
+            write_u64(data, offset, self.children[0]);
+            offset += 8;
+        }
+        for (i, entry) in self.entries.iter().enumerate() {
+            write_u16(data, offset, entry.key.len() as u16);
+            offset += 2;
+            data[offset..offset + entry.key.len()].copy_from_slice(&entry.key);
+            offset += entry.key.len();
+            write_u64(data, offset, entry.rid.0);
+            write_u32(data, offset + 8, entry.rid.1 as u32);
+            offset += 12;
+            if !self.is_leaf {
+                write_u64(data, offset, self.children[i + 1]);
+                offset += 8;
+            }
+        }
+
+        let mut header = PageHeader::new();
+        header.type_ = PageType::Index as u16;
+        header.myself = self.page_id;
+        header.lower = offset as u16;
+        header.upper = PAGE_SIZE as u16;
+        write_header(data, header);
+    }
+}
+
 pub struct BTreeIndex {
-    root_page_id: PageId,
-    buffer_mgr: Arc<RwLock<BufferMgr>>,
+    segment_id: u64,
+    buffer_mgr: Arc<BufferMgr>,
     fill_factor: f32,
     max_key_size: usize,
-    keys: Vec<(Vec<u8>, PageId)>,
+    meta: MetaData,
+    /// Page writes are logged here; see `log_write`
+    wal: Option<Arc<WalManager>>,
+    /// LSN of the logical record describing the change being made, or
+    /// invalid while the tree is created or bulk-loaded
+    redo_lsn: LSN,
 }
 
 impl BTreeIndex {
-    pub fn new(
-        root_page_id: PageId,
-        buffer_mgr: Arc<RwLock<BufferMgr>>,
+    /// Creates an empty tree in `segment_id`: a metapage and an empty root leaf
+    pub fn create(
+        segment_id: u64,
+        buffer_mgr: Arc<BufferMgr>,
         fill_factor: f32,
         max_key_size: usize,
+    ) -> IndexResult<Self> {
+        Self::create_with_wal(segment_id, buffer_mgr, fill_factor, max_key_size, None)
+    }
+
+    /// Like `create`, logging the initial pages to `wal`
+    pub fn create_with_wal(
+        segment_id: u64,
+        buffer_mgr: Arc<BufferMgr>,
+        fill_factor: f32,
+        max_key_size: usize,
+        wal: Option<Arc<WalManager>>,
+    ) -> IndexResult<Self> {
+        let mut index = Self::with_meta(
+            segment_id,
+            buffer_mgr,
+            fill_factor,
+            max_key_size,
+            MetaData {
+                root: INVALID_PAGE_ID,
+                height: 1,
+                next_block: META_BLOCK + 1,
+                free_head: INVALID_PAGE_ID,
+            },
+        );
+        index.wal = wal;
+
+        index
+            .buffer_mgr
+            .new_page(make_page_id(segment_id, META_BLOCK))?;
+
+        let root = index.allocate_page()?;
+        index.write_node(&Node::new_leaf(root))?;
+        index.meta.root = root;
+        index.write_meta()?;
+
+        Ok(index)
+    }
+
+    /// Opens an existing tree by reading the metapage of `segment_id`
+    pub fn open(
+        segment_id: u64,
+        buffer_mgr: Arc<BufferMgr>,
+        fill_factor: f32,
+        max_key_size: usize,
+    ) -> IndexResult<Self> {
+        let meta_page_id = make_page_id(segment_id, META_BLOCK);
+        let meta = decode_meta(buffer_mgr.read_page(meta_page_id)?.as_bytes());
+
+        let meta = meta.ok_or_else(|| {
+            IndexError::PageError(format!("segment {} has no index metapage", segment_id))
+        })?;
+
+        Ok(Self::with_meta(
+            segment_id,
+            buffer_mgr,
+            fill_factor,
+            max_key_size,
+            meta,
+        ))
+    }
+
+    fn with_meta(
+        segment_id: u64,
+        buffer_mgr: Arc<BufferMgr>,
+        fill_factor: f32,
+        max_key_size: usize,
+        meta: MetaData,
     ) -> Self {
+        // Keep at least four entries per page so a split always has room
+        let max_key_size = max_key_size.min(INTERNAL_CAPACITY / 4 - INTERNAL_ENTRY_OVERHEAD);
         Self {
-            root_page_id,
+            segment_id,
             buffer_mgr,
-            fill_factor,
+            fill_factor: fill_factor.clamp(0.1, 1.0),
             max_key_size,
-            keys: Vec::new(),
+            meta,
+            wal: None,
+            redo_lsn: LSN::invalid(),
         }
     }
 
+    /// Attaches the WAL that page writes are logged to
+    pub fn set_wal(&mut self, wal: Option<Arc<WalManager>>) {
+        self.wal = wal;
+    }
+
+    /// Sets the LSN of the logical record that the following `insert` or
+    /// `delete` carries out; the pages it changes are stamped with it
+    pub fn set_redo_lsn(&mut self, lsn: LSN) {
+        self.redo_lsn = lsn;
+    }
+
     pub fn root_page_id(&self) -> PageId {
-        self.root_page_id
+        self.meta.root
+    }
+
+    pub fn segment_id(&self) -> u64 {
+        self.segment_id
     }
 
+    /// Number of levels in the tree, 1 when the root is a leaf
+    pub fn height(&self) -> u16 {
+        self.meta.height
+    }
+
+    /// Returns the row id of the first entry whose key equals `key`
     pub fn search(&self, key: &[u8]) -> IndexResult<Option<(PageId, usize)>> {
-        for (k, page_id) in &self.keys {
-            if k.as_slice() == key {
-                return Ok(Some((*page_id, 0)));
+        let mut node = self.read_node(self.meta.root)?;
+        while !node.is_leaf {
+            let child = node.children[node.child_for_key(key)];
+            node = self.read_node(child)?;
+        }
+
+        loop {
+            let pos = node
+                .entries
+                .partition_point(|e| compare_keys(&e.key, key) == Ordering::Less);
+            if let Some(entry) = node.entries.get(pos) {
+                if compare_keys(&entry.key, key) == Ordering::Equal {
+                    return Ok(Some(entry.rid));
+                }
+                return Ok(None);
             }
-            if k.as_slice() > key {
-                break;
+            if node.next == INVALID_PAGE_ID {
+                return Ok(None);
             }
+            node = self.read_node(node.next)?;
         }
-        Ok(None)
+    }
+
+    /// Returns true if the tree holds the entry matching both `key` and `rid`
+    pub fn contains(&self, key: &[u8], rid: (PageId, usize)) -> IndexResult<bool> {
+        let (leaf, _) = self.descend(key, rid)?;
+        Ok(leaf
+            .entries
+            .iter()
+            .any(|e| e.cmp_to(key, rid) == Ordering::Equal))
+    }
+
+    /// Returns the row ids of every entry whose key equals `key`, in row id order
+    pub fn search_all(&self, key: &[u8]) -> IndexResult<Vec<(PageId, usize)>> {
+        self.range(Bound::Included(key.to_vec()), Bound::Included(key.to_vec()))
+            .collect()
     }
 
     pub fn insert(
@@ -81,44 +491,995 @@ impl BTreeIndex {
             return Err(IndexError::KeyTooLong);
         }
 
-        if check_unique {
-            for (k, _) in &self.keys {
-                if k.as_slice() == key {
-                    return Err(IndexError::DuplicateKey);
+        if check_unique && self.search(key)?.is_some() {
+            return Err(IndexError::DuplicateKey);
+        }
+
+        let (mut leaf, mut path) = self.descend(key, rid)?;
+        let pos = leaf.child_for(key, rid);
+        leaf.entries.insert(
+            pos,
+            Entry {
+                key: key.to_vec(),
+                rid,
+            },
+        );
+
+        if leaf.used() <= leaf.capacity() {
+            return self.write_node(&leaf);
+        }
+
+        let appending = pos == leaf.entries.len() - 1 && leaf.next == INVALID_PAGE_ID;
+        let (mut separator, mut right_page) = self.split_leaf(&mut leaf, appending)?;
+
+        // Propagate separators up the recorded path
+        while let Some((parent_id, child_idx)) = path.pop() {
+            let mut parent = self.read_node(parent_id)?;
+            parent.entries.insert(child_idx, separator);
+            parent.children.insert(child_idx + 1, right_page);
+
+            if parent.used() <= parent.capacity() {
+                return self.write_node(&parent);
+            }
+
+            let appending = child_idx == parent.entries.len() - 1;
+            (separator, right_page) = self.split_internal(&mut parent, appending)?;
+        }
+
+        // The root itself split: grow the tree by one level
+        let old_root = self.meta.root;
+        let new_root_id = self.allocate_page()?;
+        let mut new_root = Node::new_internal(new_root_id, self.meta.height);
+        new_root.entries.push(separator);
+        new_root.children.push(old_root);
+        new_root.children.push(right_page);
+        self.write_node(&new_root)?;
+
+        self.meta.root = new_root_id;
+        self.meta.height += 1;
+        self.write_meta()
+    }
+
+    /// Returns an iterator over the entries with keys between `lower` and `upper`
+    pub fn range(&self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> BTreeRange {
+        BTreeRange {
+            buffer_mgr: Arc::clone(&self.buffer_mgr),
+            root: self.meta.root,
+            lower,
+            upper,
+            front: None,
+            back: None,
+            last_front: None,
+            last_back: None,
+            done: false,
+        }
+    }
+
+    /// Returns true when the tree holds no entries
+    pub fn is_empty(&self) -> IndexResult<bool> {
+        let root = self.read_node(self.meta.root)?;
+        Ok(root.is_leaf && root.entries.is_empty())
+    }
+
+    /// Loads entries into an empty tree bottom-up
+    ///
+    /// `entries` must be sorted by key and then row id. Leaves are packed to
+    /// `fill_factor` and linked left to right, then each internal level is
+    /// built from the first key of every page below it. `progress` is called
+    /// with the number of entries written after each leaf.
+    pub fn bulk_load<I>(&mut self, entries: I, progress: &mut dyn FnMut(u64)) -> IndexResult<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, (PageId, usize))>,
+    {
+        if !self.is_empty()? {
+            return Err(IndexError::Other(
+                "bulk load requires an empty index".to_string(),
+            ));
+        }
+
+        // No record describes a bulk load, so its pages are logged whole
+        self.redo_lsn = LSN::invalid();
+
+        let leaf_limit = (LEAF_CAPACITY as f32 * self.fill_factor) as usize;
+        let mut loaded = 0u64;
+        // (first entry, page id) of every page on the level being built
+        let mut level: Vec<(Entry, PageId)> = Vec::new();
+        let mut leaf = Node::new_leaf(self.meta.root);
+
+        for (key, rid) in entries {
+            if key.len() > self.max_key_size {
+                return Err(IndexError::KeyTooLong);
+            }
+            let entry = Entry { key, rid };
+            if !leaf.entries.is_empty() && leaf.used() + leaf.entry_size(&entry) > leaf_limit {
+                let next_id = self.allocate_page()?;
+                leaf.next = next_id;
+                level.push((leaf.entries[0].clone(), leaf.page_id));
+                loaded += leaf.entries.len() as u64;
+                self.write_node(&leaf)?;
+                progress(loaded);
+
+                let prev = leaf.page_id;
+                leaf = Node::new_leaf(next_id);
+                leaf.prev = prev;
+            }
+            leaf.entries.push(entry);
+        }
+
+        loaded += leaf.entries.len() as u64;
+        self.write_node(&leaf)?;
+        progress(loaded);
+        if leaf.entries.is_empty() {
+            return Ok(());
+        }
+        level.push((leaf.entries[0].clone(), leaf.page_id));
+
+        let internal_limit = (INTERNAL_CAPACITY as f32 * self.fill_factor) as usize;
+        let mut height = 1;
+        while level.len() > 1 {
+            let mut parents = Vec::new();
+            let mut node: Option<Node> = None;
+            for (first, child) in level {
+                let full = node.as_ref().is_some_and(|n| {
+                    !n.entries.is_empty() && n.used() + n.entry_size(&first) > internal_limit
+                });
+                if full {
+                    self.write_node(node.as_ref().unwrap())?;
+                    node = None;
+                }
+                match node.as_mut() {
+                    Some(n) => {
+                        n.entries.push(first);
+                        n.children.push(child);
+                    }
+                    None => {
+                        let mut n = Node::new_internal(self.allocate_page()?, height);
+                        n.children.push(child);
+                        parents.push((first, n.page_id));
+                        node = Some(n);
+                    }
                 }
             }
+            self.write_node(node.as_ref().unwrap())?;
+            level = parents;
+            height += 1;
         }
 
-        let insert_pos = self.keys.len();
-        for (i, (k, _)) in self.keys.iter().enumerate() {
-            if k.as_slice() > key {
-                self.keys.insert(i, (key.to_vec(), rid.0));
+        self.meta.root = level[0].1;
+        self.meta.height = height;
+        self.write_meta()
+    }
+
+    /// Removes the entry matching both `key` and `rid`; missing entries are ignored
+    pub fn delete(&mut self, key: &[u8], rid: (PageId, usize)) -> IndexResult<()> {
+        let (mut leaf, mut path) = self.descend(key, rid)?;
+        let pos = match leaf
+            .entries
+            .iter()
+            .position(|e| e.cmp_to(key, rid) == Ordering::Equal)
+        {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+        leaf.entries.remove(pos);
+        self.write_node(&leaf)?;
+
+        let mut node = leaf;
+        while let Some((parent_id, child_idx)) = path.pop() {
+            if !self.underflows(&node) {
                 return Ok(());
             }
-        }
-        self.keys.insert(insert_pos, (key.to_vec(), rid.0));
 
-        if self.root_page_id == 0 {
-            self.root_page_id = 1;
+            let mut parent = self.read_node(parent_id)?;
+            if !self.merge_child(&mut parent, child_idx)? {
+                return Ok(());
+            }
+
+            if path.is_empty() {
+                return self.collapse_root(parent);
+            }
+            node = parent;
         }
 
         Ok(())
     }
 
-    pub fn delete(&mut self, key: &[u8], _rid: (PageId, usize)) -> IndexResult<()> {
-        for (i, (k, _)) in self.keys.iter().enumerate() {
-            if k.as_slice() == key {
-                self.keys.remove(i);
-                return Ok(());
+    /// Walks from the root to the leaf that owns (key, rid), recording
+    /// `(internal page, child index)` for every level passed
+    fn descend(
+        &self,
+        key: &[u8],
+        rid: (PageId, usize),
+    ) -> IndexResult<(Node, Vec<(PageId, usize)>)> {
+        let mut path = Vec::with_capacity(self.meta.height as usize);
+        let mut node = self.read_node(self.meta.root)?;
+        while !node.is_leaf {
+            let idx = node.child_for(key, rid);
+            path.push((node.page_id, idx));
+            node = self.read_node(node.children[idx])?;
+        }
+        Ok((node, path))
+    }
+
+    /// Chooses how many entries stay on the left side of a split.
+    ///
+    /// Appends to the rightmost page keep `fill_factor` of the page on the
+    /// left so sequential loads leave pages packed to the configured level;
+    /// anything else splits evenly by bytes.
+    fn split_point(&self, node: &Node, appending: bool, min_right: usize) -> usize {
+        let target = if appending {
+            (node.capacity() as f32 * self.fill_factor) as usize
+        } else {
+            node.used() / 2
+        };
+
+        let mut used = 0;
+        let mut split = 0;
+        for entry in &node.entries {
+            let size = node.entry_size(entry);
+            if used + size > target {
+                break;
+            }
+            used += size;
+            split += 1;
+        }
+        split.clamp(1, node.entries.len() - min_right)
+    }
+
+    /// Splits an overflowing leaf, returning the separator and the new right page
+    fn split_leaf(&mut self, left: &mut Node, appending: bool) -> IndexResult<(Entry, PageId)> {
+        let split = self.split_point(left, appending, 1);
+
+        let right_id = self.allocate_page()?;
+        let mut right = Node::new_leaf(right_id);
+        right.entries = left.entries.split_off(split);
+        right.prev = left.page_id;
+        right.next = left.next;
+
+        if left.next != INVALID_PAGE_ID {
+            let mut next = self.read_node(left.next)?;
+            next.prev = right_id;
+            self.write_node(&next)?;
+        }
+        left.next = right_id;
+
+        self.write_node(left)?;
+        self.write_node(&right)?;
+
+        Ok((right.entries[0].clone(), right_id))
+    }
+
+    /// Splits an overflowing internal node; the middle separator moves up
+    fn split_internal(&mut self, left: &mut Node, appending: bool) -> IndexResult<(Entry, PageId)> {
+        let split = self.split_point(left, appending, 2);
+
+        let right_id = self.allocate_page()?;
+        let mut right = Node::new_internal(right_id, left.level);
+        right.entries = left.entries.split_off(split + 1);
+        right.children = left.children.split_off(split + 1);
+        let separator = left
+            .entries
+            .pop()
+            .expect("split keeps the separator on the left");
+
+        self.write_node(left)?;
+        self.write_node(&right)?;
+
+        Ok((separator, right_id))
+    }
+
+    /// A page underflows when it is empty or less than `1 - fill_factor` full
+    fn underflows(&self, node: &Node) -> bool {
+        if node.entries.is_empty() {
+            return true;
+        }
+        let min_used = (node.capacity() as f32 * (1.0 - self.fill_factor)) as usize;
+        node.used() < min_used
+    }
+
+    /// Merges `parent.children[child_idx]` with a sibling if the result fits
+    /// in `fill_factor` of a page. Returns whether a merge happened.
+    fn merge_child(&mut self, parent: &mut Node, child_idx: usize) -> IndexResult<bool> {
+        if parent.children.len() < 2 {
+            return Ok(false);
+        }
+
+        let left_idx = if child_idx > 0 { child_idx - 1 } else { 0 };
+        let mut left = self.read_node(parent.children[left_idx])?;
+        let right = self.read_node(parent.children[left_idx + 1])?;
+        let separator = parent.entries[left_idx].clone();
+
+        let combined = if left.is_leaf {
+            left.used() + right.used()
+        } else {
+            left.used() + right.used() + left.entry_size(&separator)
+        };
+        let limit = (left.capacity() as f32 * self.fill_factor) as usize;
+        let either_empty = left.entries.is_empty() || right.entries.is_empty();
+        if combined > limit && !(left.is_leaf && either_empty) {
+            return Ok(false);
+        }
+
+        if left.is_leaf {
+            left.entries.extend(right.entries);
+            left.next = right.next;
+            if right.next != INVALID_PAGE_ID {
+                let mut next = self.read_node(right.next)?;
+                next.prev = left.page_id;
+                self.write_node(&next)?;
+            }
+        } else {
+            left.entries.push(separator);
+            left.entries.extend(right.entries);
+            left.children.extend(right.children);
+        }
+
+        parent.entries.remove(left_idx);
+        parent.children.remove(left_idx + 1);
+
+        self.write_node(&left)?;
+        self.write_node(parent)?;
+        self.free_page(right.page_id)?;
+
+        Ok(true)
+    }
+
+    /// Replaces an internal root that is left with a single child by that child
+    fn collapse_root(&mut self, mut root: Node) -> IndexResult<()> {
+        while !root.is_leaf && root.entries.is_empty() {
+            let child = root.children[0];
+            self.free_page(root.page_id)?;
+            self.meta.root = child;
+            self.meta.height -= 1;
+            root = self.read_node(child)?;
+        }
+        self.write_meta()
+    }
+
+    #[inline]
+    fn meta_page_id(&self) -> PageId {
+        make_page_id(self.segment_id, META_BLOCK)
+    }
+
+    /// Takes a page from the free list or extends the segment by one block
+    ///
+    /// A page from the free list keeps its contents and LSN until it is
+    /// written, so its first change since the checkpoint still logs it.
+    fn allocate_page(&mut self) -> IndexResult<PageId> {
+        let page_id = if self.meta.free_head != INVALID_PAGE_ID {
+            let page_id = self.meta.free_head;
+            let page = self.buffer_mgr.read_page(page_id)?;
+            self.meta.free_head = read_u64(page.as_bytes(), NODE_NEXT_OFFSET);
+            page_id
+        } else {
+            let page_id = make_page_id(self.segment_id, self.meta.next_block);
+            self.meta.next_block += 1;
+            let mut page = self.buffer_mgr.new_page(page_id)?;
+            // A block past the end the checkpoint saw has nothing to restore
+            if self.redo_lsn.is_valid() {
+                let data = page.as_bytes_mut();
+                let mut header = read_header(data);
+                header.set_lsn(self.redo_lsn.raw());
+                write_header(data, header);
             }
+            page_id
+        };
+
+        self.write_meta()?;
+        Ok(page_id)
+    }
+
+    /// Returns a page to the metapage free list
+    fn free_page(&mut self, page_id: PageId) -> IndexResult<()> {
+        {
+            let mut page = self.buffer_mgr.write_page(page_id)?;
+            let free_head = self.meta.free_head;
+            self.log_write(page_id, page.as_bytes_mut(), |data| {
+                data[..PAGE_SIZE].fill(0);
+                let mut header = PageHeader::new();
+                header.type_ = PageType::Free as u16;
+                header.myself = page_id;
+                write_header(data, header);
+                write_u64(data, NODE_NEXT_OFFSET, free_head);
+            })?;
+        }
+        self.meta.free_head = page_id;
+        self.write_meta()
+    }
+
+    fn read_node(&self, page_id: PageId) -> IndexResult<Node> {
+        read_node(&self.buffer_mgr, page_id)
+    }
+
+    fn write_node(&self, node: &Node) -> IndexResult<()> {
+        let mut page = self.buffer_mgr.write_page(node.page_id)?;
+        self.log_write(node.page_id, page.as_bytes_mut(), |data| node.encode(data))
+    }
+
+    fn write_meta(&self) -> IndexResult<()> {
+        let page_id = self.meta_page_id();
+        let mut page = self.buffer_mgr.write_page(page_id)?;
+        self.log_write(page_id, page.as_bytes_mut(), |data| {
+            encode_meta(page_id, &self.meta, data)
+        })
+    }
+
+    /// Rewrites a page with `encode`, logging it and stamping its LSN
+    ///
+    /// A change described by a logical record (`redo_lsn`) logs the page's
+    /// image from before the change, and only on its first change since
+    /// the last checkpoint: recovery puts the tree back the way the
+    /// checkpoint left it and then repeats the records. Creating and
+    /// bulk-loading a tree have no such record, so they log every page as
+    /// it is written. Encoding rebuilds the header, so the page's previous
+    /// LSN is carried over into `plsn`.
+    fn log_write(
+        &self,
+        page_id: PageId,
+        data: &mut [u8],
+        encode: impl FnOnce(&mut [u8]),
+    ) -> IndexResult<()> {
+        let old_lsn = read_header(data).glsn;
+        let mut lsn = self.redo_lsn;
+        if let Some(ref wal) = self.wal
+            && lsn.is_valid()
+            && wal.needs_full_page_image(LSN::from_raw(old_lsn))
+        {
+            lsn = wal.log_full_page_image(0, page_id, data)?;
         }
+
+        encode(data);
+        if let Some(ref wal) = self.wal
+            && !self.redo_lsn.is_valid()
+        {
+            lsn = wal.log_full_page_image(0, page_id, data)?;
+        }
+
+        let mut header = read_header(data);
+        header.glsn = old_lsn;
+        // A disabled WAL hands out no LSNs
+        if lsn.is_valid() {
+            header.set_lsn(lsn.raw());
+        }
+        write_header(data, header);
         Ok(())
     }
 }
 
-pub fn create_root_page(
-    _buffer_mgr: &Arc<RwLock<BufferMgr>>,
-    _is_leaf: bool,
-) -> IndexResult<PageId> {
-    Ok(0)
+fn read_node(buffer_mgr: &BufferMgr, page_id: PageId) -> IndexResult<Node> {
+    Node::decode(page_id, buffer_mgr.read_page(page_id)?.as_bytes())
+}
+
+/// Position inside a decoded leaf
+struct LeafCursor {
+    node: Node,
+    /// Next entry to return going forward, or one past it going backward
+    pos: usize,
+}
+
+/// Iterator over the row ids whose keys fall between two bounds
+///
+/// Leaves are read one at a time through the buffer pool and followed via
+/// their sibling links, so the scan never holds a page pinned between calls.
+/// Iterating from the back walks the range in descending key order.
+pub struct BTreeRange {
+    buffer_mgr: Arc<BufferMgr>,
+    root: PageId,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    front: Option<LeafCursor>,
+    back: Option<LeafCursor>,
+    /// Last entries handed out from each end, so the two ends never cross
+    last_front: Option<Entry>,
+    last_back: Option<Entry>,
+    done: bool,
+}
+
+impl BTreeRange {
+    fn above_lower(&self, key: &[u8]) -> bool {
+        match &self.lower {
+            Bound::Included(lower) => compare_keys(key, lower) != Ordering::Less,
+            Bound::Excluded(lower) => compare_keys(key, lower) == Ordering::Greater,
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn below_upper(&self, key: &[u8]) -> bool {
+        match &self.upper {
+            Bound::Included(upper) => compare_keys(key, upper) != Ordering::Greater,
+            Bound::Excluded(upper) => compare_keys(key, upper) == Ordering::Less,
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Descends to the leftmost leaf that can hold an entry above `lower`
+    fn seek_front(&self) -> IndexResult<LeafCursor> {
+        let mut node = read_node(&self.buffer_mgr, self.root)?;
+        while !node.is_leaf {
+            let idx = match &self.lower {
+                Bound::Included(key) => node.child_for_key(key),
+                Bound::Excluded(key) => node
+                    .entries
+                    .partition_point(|e| compare_keys(&e.key, key) != Ordering::Greater),
+                Bound::Unbounded => 0,
+            };
+            node = read_node(&self.buffer_mgr, node.children[idx])?;
+        }
+        let pos = node.entries.partition_point(|e| !self.above_lower(&e.key));
+        Ok(LeafCursor { node, pos })
+    }
+
+    /// Descends to the rightmost leaf that can hold an entry below `upper`
+    fn seek_back(&self) -> IndexResult<LeafCursor> {
+        let mut node = read_node(&self.buffer_mgr, self.root)?;
+        while !node.is_leaf {
+            let idx = match &self.upper {
+                Bound::Included(key) => node
+                    .entries
+                    .partition_point(|e| compare_keys(&e.key, key) != Ordering::Greater),
+                Bound::Excluded(key) => node.child_for_key(key),
+                Bound::Unbounded => node.entries.len(),
+            };
+            node = read_node(&self.buffer_mgr, node.children[idx])?;
+        }
+        let pos = node.entries.partition_point(|e| self.below_upper(&e.key));
+        Ok(LeafCursor { node, pos })
+    }
+
+    fn step_front(&mut self) -> IndexResult<Option<Entry>> {
+        if self.front.is_none() {
+            self.front = Some(self.seek_front()?);
+        }
+
+        loop {
+            let cursor = self.front.as_mut().unwrap();
+            if cursor.pos < cursor.node.entries.len() {
+                let entry = cursor.node.entries[cursor.pos].clone();
+                cursor.pos += 1;
+                if !self.above_lower(&entry.key) {
+                    continue;
+                }
+                return Ok(Some(entry));
+            }
+
+            if cursor.node.next == INVALID_PAGE_ID {
+                return Ok(None);
+            }
+            let next = read_node(&self.buffer_mgr, cursor.node.next)?;
+            *cursor = LeafCursor { node: next, pos: 0 };
+        }
+    }
+
+    fn step_back(&mut self) -> IndexResult<Option<Entry>> {
+        if self.back.is_none() {
+            self.back = Some(self.seek_back()?);
+        }
+
+        loop {
+            let cursor = self.back.as_mut().unwrap();
+            if cursor.pos > 0 {
+                cursor.pos -= 1;
+                let entry = cursor.node.entries[cursor.pos].clone();
+                if !self.below_upper(&entry.key) {
+                    continue;
+                }
+                return Ok(Some(entry));
+            }
+
+            if cursor.node.prev == INVALID_PAGE_ID {
+                return Ok(None);
+            }
+            let prev = read_node(&self.buffer_mgr, cursor.node.prev)?;
+            let pos = prev.entries.len();
+            *cursor = LeafCursor { node: prev, pos };
+        }
+    }
+}
+
+impl Iterator for BTreeRange {
+    type Item = IndexResult<(PageId, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let entry = match self.step_front() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+
+        let crossed = self
+            .last_back
+            .as_ref()
+            .is_some_and(|last| entry.cmp_to(&last.key, last.rid) != Ordering::Less);
+        if !self.below_upper(&entry.key) || crossed {
+            self.done = true;
+            return None;
+        }
+
+        let rid = entry.rid;
+        self.last_front = Some(entry);
+        Some(Ok(rid))
+    }
+}
+
+impl DoubleEndedIterator for BTreeRange {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let entry = match self.step_back() {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+
+        let crossed = self
+            .last_front
+            .as_ref()
+            .is_some_and(|last| entry.cmp_to(&last.key, last.rid) != Ordering::Greater);
+        if !self.above_lower(&entry.key) || crossed {
+            self.done = true;
+            return None;
+        }
+
+        let rid = entry.rid;
+        self.last_back = Some(entry);
+        Some(Ok(rid))
+    }
+}
+
+fn encode_meta(page_id: PageId, meta: &MetaData, data: &mut [u8]) {
+    data[..PAGE_SIZE].fill(0);
+
+    let mut header = PageHeader::new();
+    header.type_ = PageType::Index as u16;
+    header.flag = INDEX_META_FLAG;
+    header.myself = page_id;
+    header.lower = 80;
+    header.upper = PAGE_SIZE as u16;
+    write_header(data, header);
+
+    write_u32(data, 48, INDEX_META_MAGIC);
+    write_u16(data, 52, INDEX_META_VERSION);
+    write_u16(data, 54, meta.height);
+    write_u64(data, 56, meta.root);
+    write_u64(data, 64, meta.next_block);
+    write_u64(data, 72, meta.free_head);
+}
+
+fn decode_meta(data: &[u8]) -> Option<MetaData> {
+    let header = read_header(data);
+    if header.type_ != PageType::Index as u16
+        || header.flag & INDEX_META_FLAG == 0
+        || read_u32(data, 48) != INDEX_META_MAGIC
+        || read_u16(data, 52) != INDEX_META_VERSION
+    {
+        return None;
+    }
+
+    Some(MetaData {
+        height: read_u16(data, 54),
+        root: read_u64(data, 56),
+        next_block: read_u64(data, 64),
+        free_head: read_u64(data, 72),
+    })
+}
+
+#[inline]
+fn read_header(data: &[u8]) -> PageHeader {
+    PageHeader::read_from(data)
+}
+
+#[inline]
+fn write_header(data: &mut [u8], header: PageHeader) {
+    header.write_to(data)
+}
+
+#[inline]
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+#[inline]
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[inline]
+fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[inline]
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[inline]
+fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::LocalFs;
+    use tempfile::TempDir;
+
+    const SEGMENT: u64 = 0x1_0001;
+
+    fn buffer_mgr(dir: &TempDir, frames: usize) -> Arc<BufferMgr> {
+        Arc::new(BufferMgr::init(
+            frames,
+            Arc::new(LocalFs::new()),
+            dir.path().to_path_buf(),
+        ))
+    }
+
+    fn key(i: u64) -> Vec<u8> {
+        format!("key-{:08}", i).into_bytes()
+    }
+
+    /// A fixed permutation of 0..n so tests are repeatable
+    fn shuffled(n: u64) -> Vec<u64> {
+        (0..n).map(|i| (i * 7919) % n).collect()
+    }
+
+    #[test]
+    fn test_insert_and_search_random_order() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+
+        for i in shuffled(5000) {
+            tree.insert(&key(i), (i + 1, i as usize % 64), true)
+                .unwrap();
+        }
+        assert!(tree.height() > 1);
+
+        for i in 0..5000 {
+            assert_eq!(
+                tree.search(&key(i)).unwrap(),
+                Some((i + 1, i as usize % 64))
+            );
+        }
+        assert_eq!(tree.search(b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_unique_violation() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+
+        tree.insert(b"a", (1, 0), true).unwrap();
+        assert!(matches!(
+            tree.insert(b"a", (2, 0), true),
+            Err(IndexError::DuplicateKey)
+        ));
+        assert!(matches!(
+            tree.insert(&vec![0u8; 2048], (3, 0), false),
+            Err(IndexError::KeyTooLong)
+        ));
+    }
+
+    #[test]
+    fn test_delete_merges_pages() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+
+        for i in shuffled(4000) {
+            tree.insert(&key(i), (i, 0), true).unwrap();
+        }
+        let height = tree.height();
+        assert!(height > 1);
+
+        for i in (0..4000).filter(|i| i % 2 == 0) {
+            tree.delete(&key(i), (i, 0)).unwrap();
+        }
+        for i in 0..4000 {
+            let expected = if i % 2 == 0 { None } else { Some((i, 0)) };
+            assert_eq!(tree.search(&key(i)).unwrap(), expected);
+        }
+
+        // Deleting with the wrong row id leaves the entry alone
+        tree.delete(&key(1), (999_999, 0)).unwrap();
+        assert_eq!(tree.search(&key(1)).unwrap(), Some((1, 0)));
+
+        for i in shuffled(4000).into_iter().filter(|i| i % 2 == 1) {
+            tree.delete(&key(i), (i, 0)).unwrap();
+        }
+        assert_eq!(tree.height(), 1);
+        assert_eq!(tree.search(&key(1)).unwrap(), None);
+
+        // Freed pages are reused before the segment grows again
+        let next_block = tree.meta.next_block;
+        for i in 0..4000 {
+            tree.insert(&key(i), (i, 0), true).unwrap();
+        }
+        assert!(tree.meta.next_block <= next_block);
+    }
+
+    #[test]
+    fn test_reopen_after_flush() {
+        let dir = TempDir::new().unwrap();
+        let root = {
+            let mgr = buffer_mgr(&dir, 16);
+            let mut tree = BTreeIndex::create(SEGMENT, Arc::clone(&mgr), 0.8, 1024).unwrap();
+            for i in shuffled(3000) {
+                tree.insert(&key(i), (i, 7), true).unwrap();
+            }
+            mgr.flush_all().unwrap();
+            tree.root_page_id()
+        };
+
+        let tree = BTreeIndex::open(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+        assert_eq!(tree.root_page_id(), root);
+        for i in 0..3000 {
+            assert_eq!(tree.search(&key(i)).unwrap(), Some((i, 7)));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys_keep_every_row() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+
+        // Enough duplicates of one key to span several leaves
+        let dup = vec![b'd'; 200];
+        for i in shuffled(600) {
+            tree.insert(&dup, (i / 8, (i % 8) as usize), false).unwrap();
+            tree.insert(&key(i), (i, 0), false).unwrap();
+        }
+        assert!(tree.height() > 1);
+
+        let expected: Vec<(PageId, usize)> = (0..600).map(|i| (i / 8, (i % 8) as usize)).collect();
+        assert_eq!(tree.search_all(&dup).unwrap(), expected);
+        assert_eq!(tree.search(&dup).unwrap(), Some((0, 0)));
+
+        // Delete removes only the exact (page_id, slot) entry
+        tree.delete(&dup, (10, 3)).unwrap();
+        tree.delete(&dup, (10, 9)).unwrap();
+        let remaining = tree.search_all(&dup).unwrap();
+        assert_eq!(remaining.len(), 599);
+        assert!(!remaining.contains(&(10, 3)));
+        assert!(remaining.contains(&(10, 4)));
+
+        for rid in expected.iter().filter(|rid| **rid != (10, 3)) {
+            tree.delete(&dup, *rid).unwrap();
+        }
+        assert!(tree.search_all(&dup).unwrap().is_empty());
+        assert_eq!(tree.search(&key(599)).unwrap(), Some((599, 0)));
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+
+        let mut reports = Vec::new();
+        tree.bulk_load((0..20000).map(|i| (key(i), (i, 1))), &mut |n| {
+            reports.push(n)
+        })
+        .unwrap();
+        assert!(tree.height() >= 2);
+        assert_eq!(*reports.last().unwrap(), 20000);
+        assert!(reports.windows(2).all(|w| w[0] < w[1]));
+
+        assert_eq!(
+            collect(tree.range(Bound::Unbounded, Bound::Unbounded)),
+            (0..20000).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            collect(tree.range(Bound::Unbounded, Bound::Unbounded).rev()),
+            (0..20000).rev().collect::<Vec<_>>()
+        );
+        for i in (0..20000).step_by(37) {
+            assert_eq!(tree.search(&key(i)).unwrap(), Some((i, 1)));
+            assert_eq!(tree.search_all(&key(i)).unwrap(), vec![(i, 1)]);
+        }
+
+        // The loaded tree accepts regular inserts and deletes afterwards
+        tree.insert(b"key-00000100a", (1, 1), true).unwrap();
+        tree.delete(&key(100), (100, 1)).unwrap();
+        assert_eq!(tree.search(b"key-00000100a").unwrap(), Some((1, 1)));
+        assert_eq!(tree.search(&key(100)).unwrap(), None);
+
+        assert!(tree.bulk_load(vec![(key(1), (1, 1))], &mut |_| {}).is_err());
+    }
+
+    fn collect(range: impl Iterator<Item = IndexResult<(PageId, usize)>>) -> Vec<u64> {
+        range.map(|r| r.unwrap().0).collect()
+    }
+
+    #[test]
+    fn test_range_scan_bounds_and_direction() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+        for i in shuffled(3000) {
+            tree.insert(&key(i), (i, 0), true).unwrap();
+        }
+
+        let all = collect(tree.range(Bound::Unbounded, Bound::Unbounded));
+        assert_eq!(all, (0..3000).collect::<Vec<_>>());
+
+        let inclusive = collect(tree.range(Bound::Included(key(100)), Bound::Included(key(1500))));
+        assert_eq!(inclusive, (100..=1500).collect::<Vec<_>>());
+
+        let exclusive = collect(tree.range(Bound::Excluded(key(100)), Bound::Excluded(key(1500))));
+        assert_eq!(exclusive, (101..1500).collect::<Vec<_>>());
+
+        let reverse = collect(
+            tree.range(Bound::Included(key(2990)), Bound::Unbounded)
+                .rev(),
+        );
+        assert_eq!(reverse, (2990..3000).rev().collect::<Vec<_>>());
+
+        // Bounds that fall between keys, and empty ranges
+        let between = collect(tree.range(
+            Bound::Included(b"key-00000010x".to_vec()),
+            Bound::Included(b"key-00000013x".to_vec()),
+        ));
+        assert_eq!(between, vec![11, 12, 13]);
+        assert!(collect(tree.range(Bound::Included(key(50)), Bound::Excluded(key(50)))).is_empty());
+    }
+
+    #[test]
+    fn test_range_scan_from_both_ends() {
+        let dir = TempDir::new().unwrap();
+        let mut tree = BTreeIndex::create(SEGMENT, buffer_mgr(&dir, 16), 0.8, 1024).unwrap();
+        for i in 0..1000 {
+            tree.insert(&key(i), (i, 0), true).unwrap();
+        }
+
+        let mut range = tree.range(Bound::Included(key(10)), Bound::Excluded(key(990)));
+        let mut seen = Vec::new();
+        loop {
+            match (range.next(), range.next_back()) {
+                (None, None) => break,
+                (front, back) => {
+                    seen.extend(front.map(|r| r.unwrap().0));
+                    seen.extend(back.map(|r| r.unwrap().0));
+                }
+            }
+        }
+        seen.sort();
+        assert_eq!(seen, (10..990).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sequential_load_honors_fill_factor() {
+        let dir = TempDir::new().unwrap();
+        let mgr = buffer_mgr(&dir, 64);
+        let mut dense = BTreeIndex::create(SEGMENT, Arc::clone(&mgr), 1.0, 1024).unwrap();
+        let mut sparse = BTreeIndex::create(SEGMENT + 1, Arc::clone(&mgr), 0.5, 1024).unwrap();
+
+        for i in 0..5000 {
+            dense.insert(&key(i), (i, 0), true).unwrap();
+            sparse.insert(&key(i), (i, 0), true).unwrap();
+        }
+
+        let dense_pages = dense.meta.next_block;
+        let sparse_pages = sparse.meta.next_block;
+        assert!(sparse_pages > dense_pages * 3 / 2);
+    }
 }
@@ -0,0 +1,63 @@
+//! Index builds over existing table data
+//!
+//! A build collects `(key, RowId)` pairs from a heap scan, sorts them and
+//! bulk-loads the tree bottom-up. While a build is registered, DML against
+//! the index is captured in a delta log instead of touching the tree; the
+//! log is folded into the scanned keys before the load. This lets an online
+//! build scan the heap in steps while inserts, updates and deletes keep
+//! running.
+
+use crate::heap::RowId;
+use crate::types::PageId;
+
+/// Stage an index build is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexBuildPhase {
+    /// Reading rows from the heap; counts pages
+    Scan,
+    /// Folding in changes captured during an online build; counts log records
+    CatchUp,
+    /// Sorting the collected keys; counts entries
+    Sort,
+    /// Writing leaf and internal pages; counts entries
+    Load,
+}
+
+/// Progress report passed to the build callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexBuildProgress {
+    pub phase: IndexBuildPhase,
+    pub done: u64,
+    pub total: u64,
+}
+
+impl IndexBuildProgress {
+    pub fn new(phase: IndexBuildPhase, done: u64, total: u64) -> Self {
+        Self { phase, done, total }
+    }
+}
+
+/// Change made to the indexed table while a build was running
+#[derive(Debug, Clone)]
+pub(crate) enum DeltaOp {
+    Insert { key: Vec<u8>, rid: RowId },
+    Delete { key: Vec<u8>, rid: RowId },
+}
+
+/// In-progress state of one index build
+#[derive(Debug, Default)]
+pub(crate) struct IndexBuild {
+    /// Keys collected from the heap scan so far
+    pub(crate) entries: Vec<(Vec<u8>, RowId)>,
+    /// DML captured since the build started, in arrival order
+    pub(crate) delta: Vec<DeltaOp>,
+    /// Next heap page an online build will scan; pages are visited in
+    /// ascending id order
+    pub(crate) next_page: PageId,
+}
+
+impl IndexBuild {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+}
@@ -1,11 +1,120 @@
+//! Order-preserving (memcomparable) key encoding
+//!
+//! Every column is encoded as a one byte marker followed by a payload so that
+//! comparing two encoded keys byte-wise gives the same order as comparing the
+//! values column by column:
+//!
+//! - the marker is `NULL_FIRST` / `NULL_LAST` for NULL, otherwise a type tag
+//! - signed integers are big-endian with the sign bit flipped
+//! - unsigned integers and booleans are big-endian
+//! - floats use the IEEE 754 total order (negative values have every bit
+//!   flipped, positive values only the sign bit)
+//! - strings and blobs escape `0x00` as `0x00 0xFF` and end with `0x00 0x01`
+//!
+//! Each column is self-delimiting, so the encoding of a leading subset of the
+//! columns is a byte prefix of the full key. Descending columns store the
+//! bitwise complement of the ascending encoding; the NULL marker is never
+//! complemented so `NullOrder` holds in either direction.
+
 use crate::heap::Value;
-use crate::types::ColumnType;
 use std::cmp::Ordering;
 
 pub const MAX_KEY_SIZE: usize = 1024;
 
+/// Marker for NULL when NULLs sort before every value
+const NULL_FIRST: u8 = 0x00;
+/// Marker for NULL when NULLs sort after every value
+const NULL_LAST: u8 = 0xFF;
+
+const TAG_INT8: u8 = 1;
+const TAG_INT16: u8 = 2;
+const TAG_INT32: u8 = 3;
+const TAG_INT64: u8 = 4;
+const TAG_UINT8: u8 = 5;
+const TAG_UINT16: u8 = 6;
+const TAG_UINT32: u8 = 7;
+const TAG_UINT64: u8 = 8;
+const TAG_FLOAT32: u8 = 9;
+const TAG_FLOAT64: u8 = 10;
+const TAG_BOOL: u8 = 11;
+const TAG_VARCHAR: u8 = 12;
+const TAG_BLOB: u8 = 13;
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+/// Sort direction of an index column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Placement of NULLs relative to non-NULL values of an index column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NullOrder {
+    #[default]
+    First,
+    Last,
+}
+
+/// Ordering rules for one index column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ColumnOrder {
+    pub sort: SortOrder,
+    pub nulls: NullOrder,
+}
+
+impl ColumnOrder {
+    pub fn new(sort: SortOrder, nulls: NullOrder) -> Self {
+        Self { sort, nulls }
+    }
+
+    pub fn ascending() -> Self {
+        Self::default()
+    }
+
+    pub fn descending() -> Self {
+        Self::new(SortOrder::Descending, NullOrder::First)
+    }
+
+    #[inline]
+    fn is_descending(&self) -> bool {
+        self.sort == SortOrder::Descending
+    }
+
+    /// Packs the order into one byte for the index metadata file
+    pub fn to_flags(self) -> u8 {
+        let mut flags = 0;
+        if self.sort == SortOrder::Descending {
+            flags |= 0x01;
+        }
+        if self.nulls == NullOrder::Last {
+            flags |= 0x02;
+        }
+        flags
+    }
+
+    pub fn from_flags(flags: u8) -> Self {
+        Self {
+            sort: if flags & 0x01 != 0 {
+                SortOrder::Descending
+            } else {
+                SortOrder::Ascending
+            },
+            nulls: if flags & 0x02 != 0 {
+                NullOrder::Last
+            } else {
+                NullOrder::First
+            },
+        }
+    }
+}
+
 pub fn serialize_int64(v: i64) -> Vec<u8> {
-    (v.wrapping_add(i64::MAX / 2)).to_be_bytes().to_vec()
+    ((v as u64) ^ (1 << 63)).to_be_bytes().to_vec()
 }
 
 pub fn deserialize_int64(data: &[u8]) -> Option<i64> {
@@ -14,8 +123,7 @@ pub fn deserialize_int64(data: &[u8]) -> Option<i64> {
     }
     let mut bytes = [0u8; 8];
     bytes.copy_from_slice(&data[..8]);
-    let unsigned = i64::from_be_bytes(bytes);
-    Some(unsigned.wrapping_sub(i64::MAX / 2))
+    Some((u64::from_be_bytes(bytes) ^ (1 << 63)) as i64)
 }
 
 pub fn compare_int64(a: &[u8], b: &[u8]) -> Ordering {
@@ -24,81 +132,231 @@ pub fn compare_int64(a: &[u8], b: &[u8]) -> Ordering {
     a_val.cmp(&b_val)
 }
 
-pub fn serialize_value(value: &Value) -> Option<Vec<u8>> {
+#[inline]
+fn encode_f32(v: f32) -> u32 {
+    let bits = v.to_bits();
+    if bits & (1 << 31) != 0 {
+        !bits
+    } else {
+        bits | (1 << 31)
+    }
+}
+
+#[inline]
+fn decode_f32(bits: u32) -> f32 {
+    if bits & (1 << 31) != 0 {
+        f32::from_bits(bits & !(1 << 31))
+    } else {
+        f32::from_bits(!bits)
+    }
+}
+
+#[inline]
+fn encode_f64(v: f64) -> u64 {
+    let bits = v.to_bits();
+    if bits & (1 << 63) != 0 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    }
+}
+
+#[inline]
+fn decode_f64(bits: u64) -> f64 {
+    if bits & (1 << 63) != 0 {
+        f64::from_bits(bits & !(1 << 63))
+    } else {
+        f64::from_bits(!bits)
+    }
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        out.push(b);
+        if b == ESCAPE {
+            out.push(ESCAPED_ZERO);
+        }
+    }
+    out.push(ESCAPE);
+    out.push(TERMINATOR);
+}
+
+/// Appends the ordered encoding of one value to `out`
+pub fn encode_value(value: &Value, order: ColumnOrder, out: &mut Vec<u8>) {
+    if let Value::Null = value {
+        out.push(match order.nulls {
+            NullOrder::First => NULL_FIRST,
+            NullOrder::Last => NULL_LAST,
+        });
+        return;
+    }
+
+    let start = out.len();
     match value {
-        Value::Null => Some(vec![0]),
+        Value::Null => unreachable!(),
         Value::Int8(v) => {
-            let mut bytes = vec![1];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_INT8);
+            out.extend_from_slice(&((*v as u8) ^ 0x80).to_be_bytes());
         }
         Value::Int16(v) => {
-            let mut bytes = vec![2];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_INT16);
+            out.extend_from_slice(&((*v as u16) ^ (1 << 15)).to_be_bytes());
         }
         Value::Int32(v) => {
-            let mut bytes = vec![3];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_INT32);
+            out.extend_from_slice(&((*v as u32) ^ (1 << 31)).to_be_bytes());
         }
         Value::Int64(v) => {
-            let mut bytes = vec![4];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_INT64);
+            out.extend_from_slice(&serialize_int64(*v));
         }
         Value::UInt8(v) => {
-            let mut bytes = vec![5];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_UINT8);
+            out.push(*v);
         }
         Value::UInt16(v) => {
-            let mut bytes = vec![6];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_UINT16);
+            out.extend_from_slice(&v.to_be_bytes());
         }
         Value::UInt32(v) => {
-            let mut bytes = vec![7];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_UINT32);
+            out.extend_from_slice(&v.to_be_bytes());
         }
         Value::UInt64(v) => {
-            let mut bytes = vec![8];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_UINT64);
+            out.extend_from_slice(&v.to_be_bytes());
         }
         Value::Float32(v) => {
-            let mut bytes = vec![9];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_FLOAT32);
+            out.extend_from_slice(&encode_f32(*v).to_be_bytes());
         }
         Value::Float64(v) => {
-            let mut bytes = vec![10];
-            bytes.extend_from_slice(&v.to_le_bytes());
-            Some(bytes)
+            out.push(TAG_FLOAT64);
+            out.extend_from_slice(&encode_f64(*v).to_be_bytes());
         }
         Value::Boolean(b) => {
-            let mut bytes = vec![11];
-            bytes.push(*b as u8);
-            Some(bytes)
+            out.push(TAG_BOOL);
+            out.push(*b as u8);
         }
         Value::VarChar(s) => {
-            let mut bytes = vec![12];
-            let len = (s.len() as u32).to_le_bytes();
-            bytes.extend_from_slice(&len);
-            bytes.extend_from_slice(s.as_bytes());
-            Some(bytes)
+            out.push(TAG_VARCHAR);
+            encode_bytes(s.as_bytes(), out);
         }
         Value::Blob(b) => {
-            let mut bytes = vec![13];
-            let len = (b.len() as u32).to_le_bytes();
-            bytes.extend_from_slice(&len);
-            bytes.extend_from_slice(b);
-            Some(bytes)
+            out.push(TAG_BLOB);
+            encode_bytes(b, out);
+        }
+    }
+
+    if order.is_descending() {
+        for b in &mut out[start..] {
+            *b = !*b;
+        }
+    }
+}
+
+/// Encodes `values` as one key; `orders` gives the ordering of each column
+/// and defaults to ascending for columns it does not cover
+pub fn encode_key(values: &[Value], orders: &[ColumnOrder]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        let order = orders.get(i).copied().unwrap_or_default();
+        encode_value(value, order, &mut out);
+    }
+    out
+}
+
+/// Cursor that undoes the descending complement while reading
+struct KeyReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    invert: bool,
+}
+
+impl KeyReader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(if self.invert { !b } else { b })
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let mut bytes = [0u8; N];
+        for b in &mut bytes {
+            *b = self.byte()?;
+        }
+        Some(bytes)
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        loop {
+            let b = self.byte()?;
+            if b != ESCAPE {
+                out.push(b);
+                continue;
+            }
+            match self.byte()? {
+                ESCAPED_ZERO => out.push(ESCAPE),
+                TERMINATOR => return Some(out),
+                _ => return None,
+            }
         }
     }
 }
 
+/// Decodes one value from the front of `data`, returning it together with
+/// the number of bytes consumed
+pub fn decode_value(data: &[u8], order: ColumnOrder) -> Option<(Value, usize)> {
+    let marker = *data.first()?;
+    if marker == NULL_FIRST || marker == NULL_LAST {
+        return Some((Value::Null, 1));
+    }
+
+    let mut reader = KeyReader {
+        data,
+        pos: 0,
+        invert: order.is_descending(),
+    };
+    let value = match reader.byte()? {
+        TAG_INT8 => Value::Int8((u8::from_be_bytes(reader.array()?) ^ 0x80) as i8),
+        TAG_INT16 => Value::Int16((u16::from_be_bytes(reader.array()?) ^ (1 << 15)) as i16),
+        TAG_INT32 => Value::Int32((u32::from_be_bytes(reader.array()?) ^ (1 << 31)) as i32),
+        TAG_INT64 => Value::Int64(deserialize_int64(&reader.array::<8>()?)?),
+        TAG_UINT8 => Value::UInt8(reader.byte()?),
+        TAG_UINT16 => Value::UInt16(u16::from_be_bytes(reader.array()?)),
+        TAG_UINT32 => Value::UInt32(u32::from_be_bytes(reader.array()?)),
+        TAG_UINT64 => Value::UInt64(u64::from_be_bytes(reader.array()?)),
+        TAG_FLOAT32 => Value::Float32(decode_f32(u32::from_be_bytes(reader.array()?))),
+        TAG_FLOAT64 => Value::Float64(decode_f64(u64::from_be_bytes(reader.array()?))),
+        TAG_BOOL => Value::Boolean(reader.byte()? != 0),
+        TAG_VARCHAR => Value::VarChar(String::from_utf8(reader.bytes()?).ok()?),
+        TAG_BLOB => Value::Blob(reader.bytes()?),
+        _ => return None,
+    };
+    Some((value, reader.pos))
+}
+
+/// Decodes a full key produced by `encode_key`
+pub fn decode_key(data: &[u8], orders: &[ColumnOrder]) -> Option<Vec<Value>> {
+    let mut values = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let order = orders.get(values.len()).copied().unwrap_or_default();
+        let (value, used) = decode_value(&data[offset..], order)?;
+        values.push(value);
+        offset += used;
+    }
+    Some(values)
+}
+
+/// Ascending, NULLs-first encoding of a single value
+pub fn serialize_value(value: &Value) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    encode_value(value, ColumnOrder::ascending(), &mut out);
+    Some(out)
+}
+
 pub fn compare_keys(a: &[u8], b: &[u8]) -> Ordering {
     let min_len = a.len().min(b.len());
     let cmp = a[..min_len].cmp(&b[..min_len]);
@@ -123,12 +381,16 @@ mod tests {
 
     #[test]
     fn test_int64_ordering() {
+        let min = serialize_int64(i64::MIN);
         let neg = serialize_int64(-100);
         let zero = serialize_int64(0);
         let pos = serialize_int64(100);
+        let max = serialize_int64(i64::MAX);
 
+        assert!(min < neg);
         assert!(neg < zero);
         assert!(zero < pos);
+        assert!(pos < max);
     }
 
     #[test]
@@ -141,4 +403,179 @@ mod tests {
         assert!(compare_keys(&b, &a) == Ordering::Greater);
         assert!(compare_keys(&a, &c) == Ordering::Less);
     }
+
+    /// Asserts that `values` (given in ascending order) encode to strictly
+    /// increasing keys, and to strictly decreasing keys when descending
+    fn assert_ordered(values: &[Value]) {
+        for order in [ColumnOrder::ascending(), ColumnOrder::descending()] {
+            let keys: Vec<Vec<u8>> = values
+                .iter()
+                .map(|v| encode_key(std::slice::from_ref(v), &[order]))
+                .collect();
+            for pair in keys.windows(2) {
+                let expected = if order.is_descending() {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                };
+                assert_eq!(compare_keys(&pair[0], &pair[1]), expected, "{:?}", values);
+            }
+            for (value, key) in values.iter().zip(&keys) {
+                assert_eq!(decode_key(key, &[order]).unwrap(), vec![value.clone()]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_order_and_round_trip() {
+        assert_ordered(&[
+            Value::Int8(i8::MIN),
+            Value::Int8(-1),
+            Value::Int8(0),
+            Value::Int8(i8::MAX),
+        ]);
+        assert_ordered(&[
+            Value::Int16(i16::MIN),
+            Value::Int16(-300),
+            Value::Int16(0),
+            Value::Int16(300),
+        ]);
+        assert_ordered(&[
+            Value::Int32(i32::MIN),
+            Value::Int32(-70000),
+            Value::Int32(-1),
+            Value::Int32(70000),
+        ]);
+        assert_ordered(&[
+            Value::Int64(i64::MIN),
+            Value::Int64(-1),
+            Value::Int64(0),
+            Value::Int64(i64::MAX),
+        ]);
+        assert_ordered(&[Value::UInt8(0), Value::UInt8(1), Value::UInt8(255)]);
+        assert_ordered(&[
+            Value::UInt16(1),
+            Value::UInt16(256),
+            Value::UInt16(u16::MAX),
+        ]);
+        assert_ordered(&[
+            Value::UInt32(1),
+            Value::UInt32(1 << 20),
+            Value::UInt32(u32::MAX),
+        ]);
+        assert_ordered(&[
+            Value::UInt64(0),
+            Value::UInt64(1 << 40),
+            Value::UInt64(u64::MAX),
+        ]);
+        assert_ordered(&[Value::Boolean(false), Value::Boolean(true)]);
+    }
+
+    #[test]
+    fn test_float_total_order() {
+        assert_ordered(&[
+            Value::Float64(f64::NEG_INFINITY),
+            Value::Float64(-1e300),
+            Value::Float64(-1.5),
+            Value::Float64(-0.0),
+            Value::Float64(0.0),
+            Value::Float64(f64::MIN_POSITIVE),
+            Value::Float64(2.5),
+            Value::Float64(f64::INFINITY),
+        ]);
+        assert_ordered(&[
+            Value::Float32(f32::NEG_INFINITY),
+            Value::Float32(-3.25),
+            Value::Float32(-0.0),
+            Value::Float32(0.0),
+            Value::Float32(1.0e-30),
+            Value::Float32(7.0),
+        ]);
+
+        let nan = encode_key(&[Value::Float64(f64::NAN)], &[]);
+        let inf = encode_key(&[Value::Float64(f64::INFINITY)], &[]);
+        assert!(nan > inf);
+    }
+
+    #[test]
+    fn test_string_and_blob_order() {
+        assert_ordered(&[
+            Value::VarChar(String::new()),
+            Value::VarChar("a".to_string()),
+            Value::VarChar("a\0".to_string()),
+            Value::VarChar("a\0b".to_string()),
+            Value::VarChar("a\u{1}".to_string()),
+            Value::VarChar("ab".to_string()),
+            Value::VarChar("b".to_string()),
+        ]);
+        assert_ordered(&[
+            Value::Blob(vec![]),
+            Value::Blob(vec![0]),
+            Value::Blob(vec![0, 0]),
+            Value::Blob(vec![0, 1]),
+            Value::Blob(vec![0xFF]),
+            Value::Blob(vec![0xFF, 0xFF]),
+        ]);
+    }
+
+    #[test]
+    fn test_null_ordering() {
+        let one = Value::Int32(1);
+        for sort in [SortOrder::Ascending, SortOrder::Descending] {
+            let first = ColumnOrder::new(sort, NullOrder::First);
+            let last = ColumnOrder::new(sort, NullOrder::Last);
+
+            assert!(
+                encode_key(&[Value::Null], &[first])
+                    < encode_key(std::slice::from_ref(&one), &[first])
+            );
+            assert!(
+                encode_key(&[Value::Null], &[last])
+                    > encode_key(std::slice::from_ref(&one), &[last])
+            );
+            assert_eq!(
+                decode_key(&encode_key(&[Value::Null], &[last]), &[last]).unwrap(),
+                vec![Value::Null]
+            );
+        }
+    }
+
+    #[test]
+    fn test_composite_keys() {
+        let orders = [ColumnOrder::ascending(), ColumnOrder::descending()];
+        let key = |name: &str, n: i64| {
+            encode_key(
+                &[Value::VarChar(name.to_string()), Value::Int64(n)],
+                &orders,
+            )
+        };
+
+        // First column ascending, second descending
+        assert!(key("a", 5) < key("a", 1));
+        assert!(key("a", 1) < key("ab", 9));
+        assert!(key("ab", -1) < key("b", 100));
+
+        // A leading column is a byte prefix of the whole key
+        let prefix = encode_key(&[Value::VarChar("a".to_string())], &orders);
+        assert!(key("a", 5).starts_with(&prefix));
+        assert!(!key("ab", 5).starts_with(&prefix));
+
+        let values = vec![Value::VarChar("x\0y".to_string()), Value::Int64(-42)];
+        assert_eq!(
+            decode_key(&encode_key(&values, &orders), &orders).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn test_column_order_flags() {
+        for order in [
+            ColumnOrder::ascending(),
+            ColumnOrder::descending(),
+            ColumnOrder::new(SortOrder::Ascending, NullOrder::Last),
+            ColumnOrder::new(SortOrder::Descending, NullOrder::Last),
+        ] {
+            assert_eq!(ColumnOrder::from_flags(order.to_flags()), order);
+        }
+    }
 }
@@ -1,7 +1,10 @@
+use crate::index::key::ColumnOrder;
 use crate::types::PageId;
 
 pub const MAX_KEY_SIZE: usize = 1024;
 pub const DEFAULT_FILL_FACTOR: f32 = 0.8;
+/// Index segments are numbered from here so they never share a page file with heap data
+pub const INDEX_SEGMENT_BASE: u64 = 1 << 16;
 
 #[derive(Debug, Clone)]
 pub struct IndexMeta {
@@ -9,6 +12,8 @@ pub struct IndexMeta {
     pub name: String,
     pub table_id: u64,
     pub columns: Vec<String>,
+    /// Sort direction and NULL placement of each column, parallel to `columns`
+    pub orders: Vec<ColumnOrder>,
     pub is_unique: bool,
     pub root_page_id: PageId,
     pub segment_id: u64,
@@ -24,11 +29,13 @@ impl IndexMeta {
         columns: Vec<String>,
         is_unique: bool,
     ) -> Self {
+        let orders = vec![ColumnOrder::default(); columns.len()];
         Self {
             id,
             name,
             table_id,
             columns,
+            orders,
             is_unique,
             root_page_id: 0,
             segment_id: 0,
@@ -37,6 +44,13 @@ impl IndexMeta {
         }
     }
 
+    pub fn with_orders(mut self, orders: Vec<ColumnOrder>) -> Self {
+        self.orders = orders;
+        self.orders
+            .resize(self.columns.len(), ColumnOrder::default());
+        self
+    }
+
     pub fn with_root_page_id(mut self, page_id: PageId) -> Self {
         self.root_page_id = page_id;
         self
@@ -73,5 +87,6 @@ mod tests {
         assert!(meta.is_unique);
         assert_eq!(meta.fill_factor, DEFAULT_FILL_FACTOR);
         assert_eq!(meta.max_key_size, MAX_KEY_SIZE);
+        assert_eq!(meta.orders, vec![ColumnOrder::ascending()]);
     }
 }
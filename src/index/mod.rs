@@ -1,33 +1,80 @@
 pub mod btree;
+pub mod build;
 pub mod key;
 pub mod meta;
 
 use crate::buffer::BufferMgr;
 use crate::heap::{RowId, Value};
+use crate::lock::TransactionId;
 use crate::table::Column;
 use crate::types::PageId;
-use crate::vfs::{FileHandle, VfsInterface};
-use btree::{create_root_page, BTreeIndex, IndexError, IndexResult};
-use meta::IndexMeta;
-use parking_lot::RwLock;
+use crate::vfs::{VfsError, VfsInterface};
+use crate::wal::log_record::{CompensationAction, LogPayload, LogRecord};
+use crate::wal::lsn::LSN;
+use crate::wal::WalManager;
+use btree::{BTreeIndex, BTreeRange, IndexError, IndexResult};
+use build::{DeltaOp, IndexBuild, IndexBuildPhase, IndexBuildProgress};
+use key::ColumnOrder;
+use meta::{IndexMeta, INDEX_SEGMENT_BASE};
 use std::collections::HashMap;
+use std::ops::Bound;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+/// Name of the index metadata file in the data directory
+const INDEX_FILE_NAME: &str = "index.dat";
 const INDEX_FILE_MAGIC: u32 = 0x494e4458;
-const INDEX_FILE_VERSION: u32 = 1;
+const INDEX_FILE_VERSION: u32 = 4;
+/// magic + version + index count + padding + next index id
+const INDEX_FILE_HEADER_SIZE: usize = 24;
+
+/// Row ids produced by an index range scan, in key order
+///
+/// Iterating from the back (e.g. with `.rev()`) yields descending key order.
+pub struct IndexScan {
+    range: BTreeRange,
+}
+
+impl IndexScan {
+    fn empty(btree: &BTreeIndex) -> Self {
+        Self {
+            range: btree.range(Bound::Excluded(Vec::new()), Bound::Excluded(Vec::new())),
+        }
+    }
+}
+
+impl Iterator for IndexScan {
+    type Item = IndexResult<RowId>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range
+            .next()
+            .map(|r| r.map(|(page_id, slot_idx)| RowId::new(page_id, slot_idx)))
+    }
+}
+
+impl DoubleEndedIterator for IndexScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range
+            .next_back()
+            .map(|r| r.map(|(page_id, slot_idx)| RowId::new(page_id, slot_idx)))
+    }
+}
 
 pub struct IndexManager {
-    buffer_mgr: Arc<RwLock<BufferMgr>>,
+    buffer_mgr: Arc<BufferMgr>,
     vfs: Arc<dyn VfsInterface>,
     data_dir: PathBuf,
     indexes: HashMap<u64, IndexMeta>,
     btrees: HashMap<u64, BTreeIndex>,
+    /// Indexes whose initial build has not finished yet
+    builds: HashMap<u64, IndexBuild>,
     next_index_id: u64,
+    wal: Option<Arc<WalManager>>,
 }
 
 impl IndexManager {
-    pub fn new(buffer_mgr: Arc<RwLock<BufferMgr>>, data_dir: PathBuf) -> Self {
+    pub fn new(buffer_mgr: Arc<BufferMgr>, data_dir: PathBuf) -> Self {
         let vfs: Arc<dyn VfsInterface> = Arc::new(crate::vfs::LocalFs::new());
         Self {
             buffer_mgr,
@@ -35,161 +82,135 @@ impl IndexManager {
             data_dir,
             indexes: HashMap::new(),
             btrees: HashMap::new(),
+            builds: HashMap::new(),
             next_index_id: 1,
+            wal: None,
+        }
+    }
+
+    /// Attaches the WAL that index pages and entry changes are logged to
+    pub fn set_wal(&mut self, wal: Option<Arc<WalManager>>) {
+        for btree in self.btrees.values_mut() {
+            btree.set_wal(wal.clone());
         }
+        self.wal = wal;
     }
 
+    /// Reads the index metadata file and opens every index in it
+    ///
+    /// A missing file means there are no indexes yet; a file that cannot
+    /// be read or is not an index metadata file is an error, so indexes
+    /// are never dropped silently.
     pub fn load(&mut self) -> IndexResult<()> {
-        let index_file = self.data_dir.join("index.dat");
+        let index_file = self.data_dir.join(INDEX_FILE_NAME);
+        let path = index_file.to_str().unwrap();
 
-        let mut data = vec![0u8; 65536];
-        let n = match self.vfs.pread(index_file.to_str().unwrap(), &mut data, 0) {
-            Ok(n) => n,
-            Err(_) => return Ok(()),
-        };
-        data.truncate(n);
+        let mut data = Vec::new();
+        let mut chunk = vec![0u8; 65536];
+        loop {
+            let n = match self.vfs.pread(path, &mut chunk, data.len() as u64) {
+                Ok(n) => n,
+                Err(VfsError::SystemError(errno, _)) if errno == libc::ENOENT => return Ok(()),
+                Err(e) => return Err(IndexError::Other(e.to_string())),
+            };
+            data.extend_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                break;
+            }
+        }
 
-        if data.len() < 16 {
-            return Ok(());
+        if data.len() < INDEX_FILE_HEADER_SIZE {
+            return Err(IndexError::Other(format!(
+                "Truncated index file header: {} bytes",
+                data.len()
+            )));
         }
 
-        let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let mut offset = 0;
+        let magic = read_u32(&data, &mut offset).unwrap_or(0);
         if magic != INDEX_FILE_MAGIC {
-            return Ok(());
+            return Err(IndexError::Other(format!(
+                "Bad index file magic {:#010x}",
+                magic
+            )));
         }
 
-        let version = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        let version = read_u32(&data, &mut offset).unwrap_or(0);
         if version != INDEX_FILE_VERSION {
-            return Ok(());
+            return Err(IndexError::Other(format!(
+                "Unsupported index file version {}",
+                version
+            )));
         }
 
-        let mut offset = 16;
-        let num_indexes = if offset + 4 <= data.len() {
-            u32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]) as usize
-        } else {
-            0
-        };
-        offset += 4;
+        let num_indexes = read_u32(&data, &mut offset).unwrap_or(0) as usize;
+        offset = 16;
+        self.next_index_id = read_u64(&data, &mut offset).unwrap_or(1);
+        offset = INDEX_FILE_HEADER_SIZE;
 
         for _ in 0..num_indexes {
-            if offset + 8 > data.len() {
-                break;
-            }
-            let id = u64::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-                data[offset + 4],
-                data[offset + 5],
-                data[offset + 6],
-                data[offset + 7],
-            ]);
-            offset += 8;
-
-            if offset + 4 > data.len() {
-                break;
-            }
-            let name_len = u32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]) as usize;
-            offset += 4;
-
-            if offset + name_len > data.len() {
-                break;
-            }
-            let name = String::from_utf8_lossy(&data[offset..offset + name_len]).to_string();
-            offset += name_len;
-
-            if offset + 17 > data.len() {
-                break;
-            }
-            let table_id = u64::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-                data[offset + 4],
-                data[offset + 5],
-                data[offset + 6],
-                data[offset + 7],
-            ]);
-            let is_unique = data[offset + 8] != 0;
-            let root_page_id = u64::from_le_bytes([
-                data[offset + 9],
-                data[offset + 10],
-                data[offset + 11],
-                data[offset + 12],
-                data[offset + 13],
-                data[offset + 14],
-                data[offset + 15],
-                data[offset + 16],
-            ]);
-            offset += 17;
-
-            let num_cols = if offset + 4 <= data.len() {
-                u32::from_le_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]) as usize
-            } else {
-                0
-            };
-            offset += 4;
-
-            let mut columns = Vec::new();
-            for _ in 0..num_cols {
-                if offset + 4 > data.len() {
-                    break;
+            let meta = match read_index_meta(&data, &mut offset) {
+                Some(meta) => meta,
+                None => {
+                    return Err(IndexError::Other(
+                        "Truncated index metadata file".to_string(),
+                    ));
                 }
-                let col_len = u32::from_le_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]) as usize;
-                offset += 4;
-                if offset + col_len > data.len() {
-                    break;
-                }
-                columns.push(String::from_utf8_lossy(&data[offset..offset + col_len]).to_string());
-                offset += col_len;
-            }
-
-            let meta = IndexMeta::new(id, name, table_id, columns, is_unique)
-                .with_root_page_id(root_page_id);
-            self.indexes.insert(id, meta);
+            };
 
-            self.next_index_id = self.next_index_id.max(id + 1);
+            let mut btree = BTreeIndex::open(
+                meta.segment_id,
+                Arc::clone(&self.buffer_mgr),
+                meta.fill_factor,
+                meta.max_key_size,
+            )?;
+            btree.set_wal(self.wal.clone());
 
-            self.btrees.insert(
-                id,
-                BTreeIndex::new(root_page_id, Arc::clone(&self.buffer_mgr), 0.8, 1024),
-            );
+            self.next_index_id = self.next_index_id.max(meta.id + 1);
+            self.btrees.insert(meta.id, btree);
+            self.indexes.insert(meta.id, meta);
         }
 
         Ok(())
     }
 
+    /// Writes all index pages and the index metadata file to disk
     pub fn flush(&self) -> IndexResult<()> {
-        let index_file = self.data_dir.join("index.dat");
+        self.buffer_mgr.flush_all()?;
+        self.save()
+    }
+
+    /// Writes the index metadata file
+    ///
+    /// Index pages are recovered from the WAL, but which indexes exist is
+    /// only recorded here, so the file is rewritten whenever the set of
+    /// ready indexes changes.
+    ///
+    /// The file is written to a temporary file that is synced and renamed
+    /// over the old one, so a crash leaves either version whole. The trees
+    /// it names have to survive the crash as well, so the WAL they are
+    /// logged to is flushed first, or without one the pages themselves.
+    fn save(&self) -> IndexResult<()> {
+        match self.wal {
+            Some(ref wal) => wal.flush()?,
+            None => self.buffer_mgr.flush_all()?,
+        }
+        let index_file = self.data_dir.join(INDEX_FILE_NAME);
 
         let mut data = Vec::new();
         data.extend_from_slice(&INDEX_FILE_MAGIC.to_le_bytes());
         data.extend_from_slice(&INDEX_FILE_VERSION.to_le_bytes());
-        data.extend_from_slice(&(self.indexes.len() as u32).to_le_bytes());
+        // Indexes that are still being built are not persisted
+        let ready: Vec<(&u64, &IndexMeta)> = self
+            .indexes
+            .iter()
+            .filter(|(id, _)| !self.builds.contains_key(id))
+            .collect();
+        data.extend_from_slice(&(ready.len() as u32).to_le_bytes());
+        data.resize(16, 0);
+        data.extend_from_slice(&self.next_index_id.to_le_bytes());
 
-        for (id, meta) in &self.indexes {
+        for (id, meta) in ready {
             data.extend_from_slice(&id.to_le_bytes());
 
             let name_bytes = meta.name.as_bytes();
@@ -199,20 +220,37 @@ impl IndexManager {
             data.extend_from_slice(&meta.table_id.to_le_bytes());
             data.push(if meta.is_unique { 1 } else { 0 });
             data.extend_from_slice(&meta.root_page_id.to_le_bytes());
+            data.extend_from_slice(&meta.segment_id.to_le_bytes());
+            data.extend_from_slice(&meta.fill_factor.to_le_bytes());
+            data.extend_from_slice(&(meta.max_key_size as u32).to_le_bytes());
 
             data.extend_from_slice(&(meta.columns.len() as u32).to_le_bytes());
-            for col in &meta.columns {
+            for (col, order) in meta.columns.iter().zip(&meta.orders) {
                 let col_bytes = col.as_bytes();
                 data.extend_from_slice(&(col_bytes.len() as u32).to_le_bytes());
                 data.extend_from_slice(col_bytes);
+                data.push(order.to_flags());
             }
         }
 
-        let handle = self.vfs.create_file(index_file.to_str().unwrap()).ok();
-        if let Some(mut h) = handle {
-            h.pwrite(&data, 0)
-                .map_err(|e| IndexError::Other(e.to_string()))?;
+        let io_err = |e: VfsError| IndexError::Other(e.to_string());
+        let tmp_path = index_file.with_extension("dat.tmp");
+        let tmp = tmp_path.to_str().unwrap();
+        let handle = self.vfs.create_file(tmp).map_err(io_err)?;
+        let written = handle.pwrite(&data, 0);
+        handle.close().map_err(io_err)?;
+        let n = written.map_err(io_err)?;
+        if n != data.len() {
+            return Err(IndexError::Other(format!(
+                "short write to index file: {} of {} bytes",
+                n,
+                data.len()
+            )));
         }
+        self.vfs.fsync(tmp).map_err(io_err)?;
+        self.vfs
+            .rename(tmp, index_file.to_str().unwrap())
+            .map_err(io_err)?;
 
         Ok(())
     }
@@ -223,23 +261,43 @@ impl IndexManager {
         name: String,
         columns: Vec<String>,
         is_unique: bool,
+    ) -> IndexResult<u64> {
+        self.create_index_with_orders(table_id, name, columns, Vec::new(), is_unique)
+    }
+
+    /// Creates an index whose columns use the given sort and NULL orders;
+    /// columns without an entry in `orders` are ascending with NULLs first
+    pub fn create_index_with_orders(
+        &mut self,
+        table_id: u64,
+        name: String,
+        columns: Vec<String>,
+        orders: Vec<ColumnOrder>,
+        is_unique: bool,
     ) -> IndexResult<u64> {
         let index_id = self.next_index_id;
         self.next_index_id += 1;
-
-        let root_page_id = create_root_page(&self.buffer_mgr, true)?;
+        // Persisted before anything is logged for the index, so recovery
+        // can tell an id that was handed out from one that never was
+        if let Err(e) = self.save() {
+            self.next_index_id -= 1;
+            return Err(e);
+        }
 
         let meta = IndexMeta::new(index_id, name, table_id, columns, is_unique)
-            .with_root_page_id(root_page_id);
+            .with_orders(orders)
+            .with_segment_id(INDEX_SEGMENT_BASE + index_id);
 
-        self.indexes.insert(index_id, meta.clone());
-
-        let btree = BTreeIndex::new(
-            root_page_id,
+        let mut btree = BTreeIndex::create_with_wal(
+            meta.segment_id,
             Arc::clone(&self.buffer_mgr),
             meta.fill_factor,
             meta.max_key_size,
-        );
+            self.wal.clone(),
+        )?;
+
+        self.indexes
+            .insert(index_id, meta.with_root_page_id(btree.root_page_id()));
         self.btrees.insert(index_id, btree);
 
         Ok(index_id)
@@ -250,7 +308,8 @@ impl IndexManager {
             .remove(&index_id)
             .ok_or(IndexError::KeyNotFound)?;
         self.btrees.remove(&index_id);
-        Ok(())
+        self.builds.remove(&index_id);
+        self.save()
     }
 
     pub fn get_index(&self, index_id: u64) -> Option<&IndexMeta> {
@@ -268,60 +327,497 @@ impl IndexManager {
             .collect()
     }
 
+    /// Returns true if a row's entry in `index_id` differs between its
+    /// `old` and `new` values
+    pub fn key_changed(
+        &self,
+        index_id: u64,
+        old: &[Value],
+        new: &[Value],
+        columns: &[Column],
+    ) -> IndexResult<bool> {
+        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        Ok(build_key(old, columns, meta)? != build_key(new, columns, meta)?)
+    }
+
+    /// Checks that `insert` can add the entry for `values` without
+    /// breaking `index_id`'s unique constraint, apart from an entry of
+    /// `rid` itself
+    ///
+    /// Lets a caller find a violation before it changes anything else. An
+    /// index still being built checks uniqueness when the build finishes.
+    pub fn check_insert(
+        &self,
+        index_id: u64,
+        values: &[Value],
+        columns: &[Column],
+        rid: RowId,
+    ) -> IndexResult<()> {
+        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        let key = build_key(values, columns, meta)?;
+        if key.len() > meta.max_key_size {
+            return Err(IndexError::KeyTooLong);
+        }
+        if !meta.is_unique || self.builds.contains_key(&index_id) {
+            return Ok(());
+        }
+        let btree = self.btrees.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        let own = (rid.page_id, rid.slot_idx);
+        if btree.search_all(&key)?.iter().any(|&other| other != own) {
+            return Err(unique_violation(meta, &key));
+        }
+        Ok(())
+    }
+
+    /// Adds the entry for a row on behalf of `tx_id` (0 outside a
+    /// transaction)
     pub fn insert(
         &mut self,
+        tx_id: TransactionId,
         index_id: u64,
         values: &[Value],
         columns: &[Column],
         rid: RowId,
     ) -> IndexResult<()> {
-        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        let meta = self
+            .indexes
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
 
-        let key = build_key(values, columns, &meta.columns)?;
+        let key = build_key(values, columns, meta)?;
+
+        if let Some(build) = self.builds.get_mut(&index_id) {
+            log_entry(&self.wal, tx_id, true, index_id, &key, rid)?;
+            build.delta.push(DeltaOp::Insert { key, rid });
+            return Ok(());
+        }
 
         let btree = self
             .btrees
             .get_mut(&index_id)
             .ok_or(IndexError::KeyNotFound)?;
 
-        btree.insert(&key, (rid.page_id, rid.slot_idx), meta.is_unique)
+        // Checked before the entry is logged, so that only a change that
+        // is going to be made gets a record
+        if key.len() > meta.max_key_size {
+            return Err(IndexError::KeyTooLong);
+        }
+        if meta.is_unique && btree.search(&key)?.is_some() {
+            return Err(unique_violation(meta, &key));
+        }
+        btree.set_redo_lsn(log_entry(&self.wal, tx_id, true, index_id, &key, rid)?);
+        btree.insert(&key, (rid.page_id, rid.slot_idx), false)?;
+        meta.root_page_id = btree.root_page_id();
+        Ok(())
     }
 
+    /// Removes the entry for a row on behalf of `tx_id`
     pub fn delete(
         &mut self,
+        tx_id: TransactionId,
         index_id: u64,
         values: &[Value],
         columns: &[Column],
         rid: RowId,
     ) -> IndexResult<()> {
-        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        let meta = self
+            .indexes
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+
+        let key = build_key(values, columns, meta)?;
 
-        let key = build_key(values, columns, &meta.columns)?;
+        if let Some(build) = self.builds.get_mut(&index_id) {
+            log_entry(&self.wal, tx_id, false, index_id, &key, rid)?;
+            build.delta.push(DeltaOp::Delete { key, rid });
+            return Ok(());
+        }
 
         let btree = self
             .btrees
             .get_mut(&index_id)
             .ok_or(IndexError::KeyNotFound)?;
 
-        btree.delete(&key, (rid.page_id, rid.slot_idx))
+        // A missing entry is ignored, and not logged either: rolling the
+        // delete back would add an entry that was never there
+        let entry = (rid.page_id, rid.slot_idx);
+        if !btree.contains(&key, entry)? {
+            return Ok(());
+        }
+        btree.set_redo_lsn(log_entry(&self.wal, tx_id, false, index_id, &key, rid)?);
+        btree.delete(&key, entry)?;
+        meta.root_page_id = btree.root_page_id();
+        Ok(())
     }
 
-    pub fn lookup(
-        &self,
+    /// Drops the entry of a row vacuum is freeing, if there still is one
+    ///
+    /// The removal is logged outside any transaction, so recovery repeats
+    /// it but never rolls it back.
+    pub fn purge(
+        &mut self,
         index_id: u64,
         values: &[Value],
         columns: &[Column],
-    ) -> IndexResult<Vec<RowId>> {
-        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        rid: RowId,
+    ) -> IndexResult<()> {
+        let meta = self
+            .indexes
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+
+        let key = build_key(values, columns, meta)?;
 
-        let key = build_key(values, columns, &meta.columns)?;
+        if let Some(build) = self.builds.get_mut(&index_id) {
+            build.delta.push(DeltaOp::Delete { key, rid });
+            return Ok(());
+        }
+
+        let btree = self
+            .btrees
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+
+        let entry = (rid.page_id, rid.slot_idx);
+        if !btree.contains(&key, entry)? {
+            return Ok(());
+        }
+        btree.set_redo_lsn(log_entry(&self.wal, 0, false, index_id, &key, rid)?);
+        btree.delete(&key, entry)?;
+        meta.root_page_id = btree.root_page_id();
+        Ok(())
+    }
+
+    /// Reverses a logged index entry change of `tx_id` while rolling it back
+    ///
+    /// The compensation record is written first and describes the inverse
+    /// change, so recovery can repeat it like any other entry change; it
+    /// also moves the rollback past the undone record.
+    pub fn undo(&mut self, tx_id: TransactionId, record: &LogRecord) -> IndexResult<()> {
+        let (reinsert, entry) = match record.payload() {
+            Some(LogPayload::IndexInsert(entry)) => (false, entry),
+            Some(LogPayload::IndexDelete(entry)) => (true, entry),
+            _ => return Ok(()),
+        };
+        let index_id = entry.index_id;
+        let rid = RowId::new(entry.page_id, entry.slot as usize);
+        let Some(meta) = self.indexes.get_mut(&index_id) else {
+            return self.check_gone(index_id);
+        };
 
+        let key = entry.key.clone();
+        let lsn = match self.wal {
+            Some(ref wal) => {
+                let action = if reinsert {
+                    CompensationAction::IndexInsert(entry)
+                } else {
+                    CompensationAction::IndexDelete(entry)
+                };
+                wal.log_compensation(tx_id, record.header.prev_lsn, action)?
+            }
+            None => LSN::invalid(),
+        };
+
+        if let Some(build) = self.builds.get_mut(&index_id) {
+            build.delta.push(if reinsert {
+                DeltaOp::Insert { key, rid }
+            } else {
+                DeltaOp::Delete { key, rid }
+            });
+        } else if let Some(btree) = self.btrees.get_mut(&index_id) {
+            // Putting back our own deleted key cannot legitimately conflict,
+            // so uniqueness is not checked again
+            btree.set_redo_lsn(lsn);
+            apply_entry(btree, reinsert, &key, (rid.page_id, rid.slot_idx))?;
+            meta.root_page_id = btree.root_page_id();
+        }
+        Ok(())
+    }
+
+    /// Repeats a logged index entry change during recovery
+    ///
+    /// The tree pages have been put back the way the last checkpoint left
+    /// them, so the changes logged since are applied again in log order,
+    /// compensation records included. Applying one is idempotent, since
+    /// changes captured during a build are in both the log and the bulk
+    /// load. A change to an index that was dropped or never finished
+    /// building is skipped. Must run before `set_wal`, as nothing here is
+    /// logged again.
+    pub fn redo(&mut self, record: &LogRecord) -> IndexResult<()> {
+        let (insert, entry) = match record.payload() {
+            Some(LogPayload::IndexInsert(entry)) => (true, entry),
+            Some(LogPayload::IndexDelete(entry)) => (false, entry),
+            Some(LogPayload::Compensation(clr)) => match clr.action {
+                CompensationAction::IndexInsert(entry) => (true, entry),
+                CompensationAction::IndexDelete(entry) => (false, entry),
+                _ => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        let (Some(meta), Some(btree)) = (
+            self.indexes.get_mut(&entry.index_id),
+            self.btrees.get_mut(&entry.index_id),
+        ) else {
+            return self.check_gone(entry.index_id);
+        };
+        apply_entry(
+            btree,
+            insert,
+            &entry.key,
+            (entry.page_id, entry.slot as usize),
+        )?;
+        meta.root_page_id = btree.root_page_id();
+        Ok(())
+    }
+
+    /// Looks up every row with the given key; `values` are given in index
+    /// column order
+    pub fn lookup(&self, index_id: u64, values: &[Value]) -> IndexResult<Vec<RowId>> {
+        let meta = self.ready_index(index_id)?;
+
+        if values.len() != meta.columns.len() {
+            return Err(IndexError::Other(format!(
+                "Index {} expects {} key values, got {}",
+                meta.name,
+                meta.columns.len(),
+                values.len()
+            )));
+        }
+        let key = key::encode_key(values, &meta.orders);
+
+        let btree = self.btrees.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+
+        let rids = btree.search_all(&key)?;
+        Ok(rids
+            .into_iter()
+            .map(|(page_id, slot_idx)| RowId::new(page_id, slot_idx))
+            .collect())
+    }
+
+    /// Scans the rows whose keys fall between `lower` and `upper`
+    ///
+    /// Bounds are given in index column order and may name only a leading
+    /// subset of the columns, in which case they compare as prefixes: an
+    /// inclusive upper bound of `[a]` covers every key starting with `a`.
+    /// `None` leaves that side of the range open. The returned scan yields
+    /// row ids in ascending key order; use `.rev()` for descending order.
+    pub fn lookup_range(
+        &self,
+        index_id: u64,
+        lower: Option<&[Value]>,
+        upper: Option<&[Value]>,
+        lower_inclusive: bool,
+        upper_inclusive: bool,
+    ) -> IndexResult<IndexScan> {
+        let meta = self.ready_index(index_id)?;
         let btree = self.btrees.get(&index_id).ok_or(IndexError::KeyNotFound)?;
 
-        match btree.search(&key)? {
-            Some((page_id, slot_idx)) => Ok(vec![RowId::new(page_id, slot_idx)]),
-            None => Ok(vec![]),
+        let lower = match lower {
+            Some(values) => {
+                let key = encode_bound(meta, values)?;
+                if lower_inclusive {
+                    Bound::Included(key)
+                } else {
+                    // Skip every key that starts with the bound
+                    match prefix_successor(&key) {
+                        Some(next) => Bound::Included(next),
+                        None => return Ok(IndexScan::empty(btree)),
+                    }
+                }
+            }
+            None => Bound::Unbounded,
+        };
+
+        let upper = match upper {
+            Some(values) => {
+                let key = encode_bound(meta, values)?;
+                if upper_inclusive {
+                    match prefix_successor(&key) {
+                        Some(next) => Bound::Excluded(next),
+                        None => Bound::Unbounded,
+                    }
+                } else {
+                    Bound::Excluded(key)
+                }
+            }
+            None => Bound::Unbounded,
+        };
+
+        Ok(IndexScan {
+            range: btree.range(lower, upper),
+        })
+    }
+
+    /// Scans the rows whose leading index columns equal `prefix`
+    pub fn lookup_prefix(&self, index_id: u64, prefix: &[Value]) -> IndexResult<IndexScan> {
+        self.lookup_range(index_id, Some(prefix), Some(prefix), true, true)
+    }
+
+    /// Starts building `index_id` from existing rows
+    ///
+    /// Until `finish_build` runs, `insert` and `delete` on the index are
+    /// recorded instead of applied, and lookups on it fail. Rows are fed in
+    /// with `add_build_rows`.
+    pub fn begin_build(&mut self, index_id: u64) -> IndexResult<()> {
+        let btree = self.btrees.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        if self.builds.contains_key(&index_id) {
+            return Err(IndexError::Other(format!(
+                "Index {} is already being built",
+                index_id
+            )));
         }
+        if !btree.is_empty()? {
+            return Err(IndexError::Other(format!(
+                "Index {} already has entries",
+                index_id
+            )));
+        }
+        self.builds.insert(index_id, IndexBuild::new());
+        Ok(())
+    }
+
+    /// Returns true while `index_id` has a build in progress
+    pub fn is_building(&self, index_id: u64) -> bool {
+        self.builds.contains_key(&index_id)
+    }
+
+    /// Next heap page an online build of `index_id` should scan
+    pub fn build_cursor(&self, index_id: u64) -> Option<PageId> {
+        self.builds.get(&index_id).map(|b| b.next_page)
+    }
+
+    /// Records that every heap page before `next_page` has been scanned
+    pub fn set_build_cursor(&mut self, index_id: u64, next_page: PageId) -> IndexResult<()> {
+        let build = self
+            .builds
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+        build.next_page = next_page;
+        Ok(())
+    }
+
+    /// Feeds scanned heap rows into a build
+    pub fn add_build_rows(
+        &mut self,
+        index_id: u64,
+        rows: &[(RowId, Vec<Value>)],
+        columns: &[Column],
+    ) -> IndexResult<()> {
+        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        let build = self
+            .builds
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+
+        for (rid, values) in rows {
+            let key = build_key(values, columns, meta)?;
+            build.entries.push((key, *rid));
+        }
+        Ok(())
+    }
+
+    /// Folds the changes captured while the build ran into the scanned
+    /// keys, sorts them and bulk-loads the tree
+    ///
+    /// The last captured change to a `(key, RowId)` pair decides whether it
+    /// ends up in the index, whether or not the heap scan saw it. A unique
+    /// index fails with `IndexError::UniqueViolation` naming the first
+    /// duplicated key. The build state is dropped either way; the caller
+    /// decides whether to drop the index after a failure.
+    pub fn finish_build(
+        &mut self,
+        index_id: u64,
+        progress: &mut dyn FnMut(&IndexBuildProgress),
+    ) -> IndexResult<()> {
+        let build = self
+            .builds
+            .remove(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+        let meta = self
+            .indexes
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+        let btree = self
+            .btrees
+            .get_mut(&index_id)
+            .ok_or(IndexError::KeyNotFound)?;
+
+        let mut entries = build.entries;
+        let total = build.delta.len() as u64;
+        let mut last_op: HashMap<(Vec<u8>, RowId), bool> = HashMap::new();
+        for (i, op) in build.delta.into_iter().enumerate() {
+            match op {
+                DeltaOp::Insert { key, rid } => last_op.insert((key, rid), true),
+                DeltaOp::Delete { key, rid } => last_op.insert((key, rid), false),
+            };
+            progress(&IndexBuildProgress::new(
+                IndexBuildPhase::CatchUp,
+                i as u64 + 1,
+                total,
+            ));
+        }
+        if !last_op.is_empty() {
+            entries.retain(|e| !last_op.contains_key(e));
+            entries.extend(
+                last_op
+                    .into_iter()
+                    .filter_map(|(entry, present)| present.then_some(entry)),
+            );
+        }
+
+        let total = entries.len() as u64;
+        progress(&IndexBuildProgress::new(IndexBuildPhase::Sort, 0, total));
+        entries.sort_unstable_by(|a, b| {
+            key::compare_keys(&a.0, &b.0)
+                .then((a.1.page_id, a.1.slot_idx).cmp(&(b.1.page_id, b.1.slot_idx)))
+        });
+        entries.dedup();
+        progress(&IndexBuildProgress::new(
+            IndexBuildPhase::Sort,
+            total,
+            total,
+        ));
+
+        if meta.is_unique
+            && let Some(pair) = entries.windows(2).find(|w| w[0].0 == w[1].0)
+        {
+            return Err(unique_violation(meta, &pair[0].0));
+        }
+
+        let total = entries.len() as u64;
+        btree.bulk_load(
+            entries
+                .into_iter()
+                .map(|(key, rid)| (key, (rid.page_id, rid.slot_idx))),
+            &mut |done| progress(&IndexBuildProgress::new(IndexBuildPhase::Load, done, total)),
+        )?;
+
+        meta.root_page_id = btree.root_page_id();
+        self.save()
+    }
+
+    /// Accepts a logged change to an index that is gone because it was
+    /// dropped or its build never finished, and fails for an id that was
+    /// never handed out
+    fn check_gone(&self, index_id: u64) -> IndexResult<()> {
+        if index_id < self.next_index_id {
+            return Ok(());
+        }
+        Err(IndexError::Other(format!(
+            "Log record for unknown index {}",
+            index_id
+        )))
+    }
+
+    fn ready_index(&self, index_id: u64) -> IndexResult<&IndexMeta> {
+        let meta = self.indexes.get(&index_id).ok_or(IndexError::KeyNotFound)?;
+        if self.builds.contains_key(&index_id) {
+            return Err(IndexError::Other(format!(
+                "Index {} is still being built",
+                meta.name
+            )));
+        }
+        Ok(meta)
     }
 
     pub fn all_indexes(&self) -> Vec<&IndexMeta> {
@@ -329,14 +825,10 @@ impl IndexManager {
     }
 }
 
-fn build_key(
-    values: &[Value],
-    columns: &[Column],
-    index_columns: &[String],
-) -> IndexResult<Vec<u8>> {
-    let mut key = Vec::new();
+fn build_key(values: &[Value], columns: &[Column], meta: &IndexMeta) -> IndexResult<Vec<u8>> {
+    let mut key_values = Vec::with_capacity(meta.columns.len());
 
-    for col_name in index_columns {
+    for col_name in &meta.columns {
         let col_idx = columns
             .iter()
             .position(|c| c.name() == col_name)
@@ -346,13 +838,140 @@ fn build_key(
             .get(col_idx)
             .ok_or_else(|| IndexError::Other(format!("Value for column {} not found", col_name)))?;
 
-        let serialized = key::serialize_value(value)
-            .ok_or_else(|| IndexError::Other("Failed to serialize value".to_string()))?;
+        key_values.push(value.clone());
+    }
+
+    Ok(key::encode_key(&key_values, &meta.orders))
+}
 
-        key.extend_from_slice(&serialized);
+/// Adds or removes an entry unless the tree already reflects the change
+fn apply_entry(
+    btree: &mut BTreeIndex,
+    insert: bool,
+    key: &[u8],
+    rid: (PageId, usize),
+) -> IndexResult<()> {
+    if btree.contains(key, rid)? == insert {
+        return Ok(());
     }
+    if insert {
+        btree.insert(key, rid, false)
+    } else {
+        btree.delete(key, rid)
+    }
+}
 
-    Ok(key)
+/// Logs an index entry change, returning the LSN the tree pages it
+/// changes are stamped with
+fn log_entry(
+    wal: &Option<Arc<WalManager>>,
+    tx_id: TransactionId,
+    insert: bool,
+    index_id: u64,
+    key: &[u8],
+    rid: RowId,
+) -> IndexResult<LSN> {
+    match wal {
+        Some(wal) => {
+            Ok(wal.log_index_entry(tx_id, insert, index_id, key, (rid.page_id, rid.slot_idx))?)
+        }
+        None => Ok(LSN::invalid()),
+    }
+}
+
+/// Builds the error for a key that already exists in a unique index
+fn unique_violation(meta: &IndexMeta, key: &[u8]) -> IndexError {
+    let key = match key::decode_key(key, &meta.orders) {
+        Some(values) => format!(
+            "({})=({})",
+            meta.columns.join(", "),
+            values
+                .iter()
+                .map(|v| format!("{:?}", v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None => format!("{:02x?}", key),
+    };
+    IndexError::UniqueViolation {
+        index: meta.name.clone(),
+        key,
+    }
+}
+
+/// Encodes a (possibly partial) range bound in index column order
+fn encode_bound(meta: &IndexMeta, values: &[Value]) -> IndexResult<Vec<u8>> {
+    if values.len() > meta.columns.len() {
+        return Err(IndexError::Other(format!(
+            "Index {} has {} columns, bound has {} values",
+            meta.name,
+            meta.columns.len(),
+            values.len()
+        )));
+    }
+    Ok(key::encode_key(values, &meta.orders))
+}
+
+/// Returns the smallest byte string greater than every string starting with
+/// `prefix`, or None when no such string exists (all bytes are 0xFF)
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut next = prefix.to_vec();
+    while let Some(last) = next.pop() {
+        if last != u8::MAX {
+            next.push(last + 1);
+            return Some(next);
+        }
+    }
+    None
+}
+
+fn read_u32(data: &[u8], offset: &mut usize) -> Option<u32> {
+    let bytes = data.get(*offset..*offset + 4)?;
+    *offset += 4;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let bytes = data.get(*offset..*offset + 8)?;
+    *offset += 8;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_string(data: &[u8], offset: &mut usize) -> Option<String> {
+    let len = read_u32(data, offset)? as usize;
+    let bytes = data.get(*offset..*offset + len)?;
+    *offset += len;
+    Some(String::from_utf8_lossy(bytes).to_string())
+}
+
+fn read_index_meta(data: &[u8], offset: &mut usize) -> Option<IndexMeta> {
+    let id = read_u64(data, offset)?;
+    let name = read_string(data, offset)?;
+    let table_id = read_u64(data, offset)?;
+    let is_unique = *data.get(*offset)? != 0;
+    *offset += 1;
+    let root_page_id = read_u64(data, offset)?;
+    let segment_id = read_u64(data, offset)?;
+    let fill_factor = f32::from_bits(read_u32(data, offset)?);
+    let max_key_size = read_u32(data, offset)? as usize;
+
+    let num_cols = read_u32(data, offset)? as usize;
+    let mut columns = Vec::with_capacity(num_cols);
+    let mut orders = Vec::with_capacity(num_cols);
+    for _ in 0..num_cols {
+        columns.push(read_string(data, offset)?);
+        orders.push(ColumnOrder::from_flags(*data.get(*offset)?));
+        *offset += 1;
+    }
+
+    Some(
+        IndexMeta::new(id, name, table_id, columns, is_unique)
+            .with_orders(orders)
+            .with_root_page_id(root_page_id)
+            .with_segment_id(segment_id)
+            .with_fill_factor(fill_factor)
+            .with_max_key_size(max_key_size),
+    )
 }
 
 #[cfg(test)]
@@ -360,6 +979,7 @@ mod tests {
     use super::*;
     use crate::table::Column;
     use crate::types::ColumnType;
+    use tempfile::TempDir;
 
     fn create_test_columns() -> Vec<Column> {
         vec![
@@ -368,15 +988,20 @@ mod tests {
         ]
     }
 
-    #[test]
-    fn test_index_manager_creation() {
-        let buffer_mgr = Arc::new(RwLock::new(BufferMgr::init(
+    fn create_test_mgr(dir: &TempDir) -> IndexManager {
+        let buffer_mgr = Arc::new(BufferMgr::init(
             100,
             Arc::new(crate::vfs::LocalFs::new()),
-            PathBuf::from("./test_data"),
-        )));
+            dir.path().to_path_buf(),
+        ));
 
-        let mut mgr = IndexManager::new(buffer_mgr, PathBuf::from("./test_data"));
+        IndexManager::new(buffer_mgr, dir.path().to_path_buf())
+    }
+
+    #[test]
+    fn test_index_manager_creation() {
+        let dir = TempDir::new().unwrap();
+        let mut mgr = create_test_mgr(&dir);
         let index_id = mgr
             .create_index(1, "idx_id".to_string(), vec!["id".to_string()], true)
             .unwrap();
@@ -385,5 +1010,373 @@ mod tests {
 
         let meta = mgr.get_index(1).unwrap();
         assert_eq!(meta.name, "idx_id");
+        assert_eq!(meta.segment_id, INDEX_SEGMENT_BASE + 1);
+    }
+
+    #[test]
+    fn test_index_manager_flush_and_load() {
+        let dir = TempDir::new().unwrap();
+        let columns = create_test_columns();
+
+        {
+            let mut mgr = create_test_mgr(&dir);
+            let index_id = mgr
+                .create_index(1, "idx_id".to_string(), vec!["id".to_string()], true)
+                .unwrap();
+            for i in 0..2000 {
+                let values = vec![Value::Int64(i), Value::VarChar(format!("name{}", i))];
+                mgr.insert(0, index_id, &values, &columns, RowId::new(i as u64 + 1, 3))
+                    .unwrap();
+            }
+            mgr.flush().unwrap();
+        }
+
+        let mut mgr = create_test_mgr(&dir);
+        mgr.load().unwrap();
+
+        let meta = mgr.get_index_by_name("idx_id").unwrap();
+        assert!(meta.is_unique);
+        assert_eq!(meta.columns, vec!["id"]);
+        for i in 0..2000 {
+            let rows = mgr.lookup(meta.id, &[Value::Int64(i)]).unwrap();
+            assert_eq!(rows, vec![RowId::new(i as u64 + 1, 3)]);
+        }
+
+        let next_id = mgr
+            .create_index(1, "idx_name".to_string(), vec!["name".to_string()], false)
+            .unwrap();
+        assert_eq!(next_id, 2);
+    }
+
+    #[test]
+    fn test_undo_skips_dropped_indexes_and_rejects_unknown_ids() {
+        let dir = TempDir::new().unwrap();
+        let entry = |index_id| {
+            LogRecord::index_entry(
+                7,
+                LSN::invalid(),
+                true,
+                crate::wal::log_record::IndexEntryPayload {
+                    index_id,
+                    key: vec![1],
+                    page_id: 1,
+                    slot: 0,
+                },
+            )
+        };
+        {
+            let mut mgr = create_test_mgr(&dir);
+            let kept = mgr
+                .create_index(1, "idx_id".to_string(), vec!["id".to_string()], true)
+                .unwrap();
+            let dropped = mgr
+                .create_index(1, "idx_name".to_string(), vec!["name".to_string()], false)
+                .unwrap();
+            mgr.flush().unwrap();
+            mgr.drop_index(dropped).unwrap();
+            assert_eq!((kept, dropped), (1, 2));
+        }
+
+        // The dropped id is not handed out again after a restart
+        let mut mgr = create_test_mgr(&dir);
+        mgr.load().unwrap();
+        mgr.undo(7, &entry(2)).unwrap();
+        assert!(mgr.undo(7, &entry(3)).is_err());
+        assert!(mgr.redo(&entry(3)).is_err());
+        let next_id = mgr
+            .create_index(1, "idx_other".to_string(), vec!["name".to_string()], false)
+            .unwrap();
+        assert_eq!(next_id, 3);
+        mgr.undo(7, &entry(3)).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_a_damaged_index_file() {
+        let dir = TempDir::new().unwrap();
+        // No file yet: no indexes
+        create_test_mgr(&dir).load().unwrap();
+
+        {
+            let mut mgr = create_test_mgr(&dir);
+            mgr.create_index(1, "idx_id".to_string(), vec!["id".to_string()], true)
+                .unwrap();
+            mgr.flush().unwrap();
+        }
+        let path = dir.path().join(INDEX_FILE_NAME);
+        assert!(!path.with_extension("dat.tmp").exists());
+        let data = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &data[..INDEX_FILE_HEADER_SIZE - 1]).unwrap();
+        assert!(create_test_mgr(&dir).load().is_err());
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] ^= 0xff;
+        std::fs::write(&path, &bad_magic).unwrap();
+        assert!(create_test_mgr(&dir).load().is_err());
+
+        std::fs::write(&path, &data).unwrap();
+        let mut mgr = create_test_mgr(&dir);
+        mgr.load().unwrap();
+        assert!(mgr.get_index_by_name("idx_id").is_some());
+    }
+
+    #[test]
+    fn test_index_range_and_prefix_scan() {
+        let dir = TempDir::new().unwrap();
+        let mut mgr = create_test_mgr(&dir);
+        let columns = vec![
+            Column::new("region".to_string(), ColumnType::Varchar(16), false, 0),
+            Column::new("day".to_string(), ColumnType::Varchar(16), false, 1),
+        ];
+        let index_id = mgr
+            .create_index(
+                1,
+                "idx_region_day".to_string(),
+                vec!["region".to_string(), "day".to_string()],
+                true,
+            )
+            .unwrap();
+
+        let mut row = 0;
+        for region in ["east", "west"] {
+            for day in 0..100 {
+                let values = vec![
+                    Value::VarChar(region.to_string()),
+                    Value::VarChar(format!("{:03}", day)),
+                ];
+                mgr.insert(0, index_id, &values, &columns, RowId::new(row, 0))
+                    .unwrap();
+                row += 1;
+            }
+        }
+
+        let slots = |scan: IndexScan| -> Vec<u64> { scan.map(|r| r.unwrap().page_id).collect() };
+
+        let east = mgr
+            .lookup_prefix(index_id, &[Value::VarChar("east".to_string())])
+            .unwrap();
+        assert_eq!(slots(east), (0..100).collect::<Vec<_>>());
+
+        let lower = [Value::VarChar("west".into()), Value::VarChar("010".into())];
+        let upper = [Value::VarChar("west".into()), Value::VarChar("020".into())];
+        let range = mgr
+            .lookup_range(index_id, Some(&lower), Some(&upper), true, false)
+            .unwrap();
+        assert_eq!(slots(range), (110..120).collect::<Vec<_>>());
+
+        let reverse = mgr
+            .lookup_range(index_id, Some(&lower), Some(&upper), false, true)
+            .unwrap()
+            .rev();
+        assert_eq!(
+            reverse.map(|r| r.unwrap().page_id).collect::<Vec<_>>(),
+            (111..=120).rev().collect::<Vec<_>>()
+        );
+
+        // A partial exclusive lower bound skips the whole prefix
+        let after_east = mgr
+            .lookup_range(
+                index_id,
+                Some(&[Value::VarChar("east".to_string())]),
+                None,
+                false,
+                true,
+            )
+            .unwrap();
+        assert_eq!(slots(after_east), (100..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_numeric_range_with_descending_column() {
+        let dir = TempDir::new().unwrap();
+        let mut mgr = create_test_mgr(&dir);
+        let columns = vec![
+            Column::new("amount".to_string(), ColumnType::Int64, true, 0),
+            Column::new("price".to_string(), ColumnType::Float64, false, 1),
+        ];
+        let asc = mgr
+            .create_index(
+                1,
+                "idx_amount".to_string(),
+                vec!["amount".to_string()],
+                false,
+            )
+            .unwrap();
+        let desc = mgr
+            .create_index_with_orders(
+                1,
+                "idx_price_desc".to_string(),
+                vec!["price".to_string()],
+                vec![ColumnOrder::descending()],
+                false,
+            )
+            .unwrap();
+
+        for i in -50i64..50 {
+            let values = vec![Value::Int64(i * 1000), Value::Float64(i as f64 / 4.0)];
+            let rid = RowId::new((i + 50) as u64, 0);
+            mgr.insert(0, asc, &values, &columns, rid).unwrap();
+            mgr.insert(0, desc, &values, &columns, rid).unwrap();
+        }
+        let null_row = vec![Value::Null, Value::Float64(0.0)];
+        mgr.insert(0, asc, &null_row, &columns, RowId::new(500, 0))
+            .unwrap();
+
+        let rows = |scan: IndexScan| -> Vec<u64> { scan.map(|r| r.unwrap().page_id).collect() };
+
+        let lower = [Value::Int64(-3000)];
+        let upper = [Value::Int64(2000)];
+        let scan = mgr
+            .lookup_range(asc, Some(&lower), Some(&upper), true, true)
+            .unwrap();
+        assert_eq!(rows(scan), (47..=52).collect::<Vec<_>>());
+
+        // NULLs sort first in an ascending index
+        let first = mgr
+            .lookup_range(asc, None, None, true, true)
+            .unwrap()
+            .next();
+        assert_eq!(first.unwrap().unwrap(), RowId::new(500, 0));
+
+        // Descending index: bounds follow key order, so the larger value comes first
+        let lower = [Value::Float64(1.0)];
+        let upper = [Value::Float64(-0.5)];
+        let scan = mgr
+            .lookup_range(desc, Some(&lower), Some(&upper), true, true)
+            .unwrap();
+        assert_eq!(rows(scan), (48..=54).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_non_unique_index_lookup_and_delete() {
+        let dir = TempDir::new().unwrap();
+        let mut mgr = create_test_mgr(&dir);
+        let columns = create_test_columns();
+        let index_id = mgr
+            .create_index(1, "idx_name".to_string(), vec!["name".to_string()], false)
+            .unwrap();
+
+        for i in 0..300u64 {
+            let values = vec![
+                Value::Int64(i as i64),
+                Value::VarChar(format!("group{}", i % 3)),
+            ];
+            mgr.insert(
+                0,
+                index_id,
+                &values,
+                &columns,
+                RowId::new(i / 10, (i % 10) as usize),
+            )
+            .unwrap();
+        }
+
+        let key = [Value::VarChar("group1".to_string())];
+        let rows = mgr.lookup(index_id, &key).unwrap();
+        assert_eq!(rows.len(), 100);
+        assert!(rows.contains(&RowId::new(0, 1)));
+        assert!(rows.contains(&RowId::new(29, 8)));
+
+        let values = vec![Value::Int64(7), Value::VarChar("group1".to_string())];
+        mgr.delete(0, index_id, &values, &columns, RowId::new(0, 7))
+            .unwrap();
+        let rows = mgr.lookup(index_id, &key).unwrap();
+        assert_eq!(rows.len(), 99);
+        assert!(!rows.contains(&RowId::new(0, 7)));
+        assert!(rows.contains(&RowId::new(0, 4)));
+    }
+
+    #[test]
+    fn test_index_build_with_captured_changes() {
+        let dir = TempDir::new().unwrap();
+        let mut mgr = create_test_mgr(&dir);
+        let columns = create_test_columns();
+        let row = |i: i64| vec![Value::Int64(i), Value::VarChar(format!("name{}", i))];
+        let index_id = mgr
+            .create_index(1, "idx_id".to_string(), vec!["id".to_string()], true)
+            .unwrap();
+
+        mgr.begin_build(index_id).unwrap();
+        let scanned: Vec<(RowId, Vec<Value>)> = (0..3000)
+            .map(|i| (RowId::new(i as u64 / 50 + 1, i as usize % 50), row(i)))
+            .collect();
+        mgr.add_build_rows(index_id, &scanned[..1500], &columns)
+            .unwrap();
+
+        // DML while the build runs is captured, not applied
+        mgr.insert(0, index_id, &row(5000), &columns, RowId::new(100, 0))
+            .unwrap();
+        mgr.delete(0, index_id, &row(10), &columns, RowId::new(1, 10))
+            .unwrap();
+        // Key 20 moves to a new row id: delete plus re-insert under the same key
+        mgr.delete(0, index_id, &row(20), &columns, RowId::new(1, 20))
+            .unwrap();
+        mgr.insert(0, index_id, &row(20), &columns, RowId::new(100, 1))
+            .unwrap();
+        // A row on a page the scan has not reached yet is seen twice
+        mgr.insert(0, index_id, &row(6000), &columns, RowId::new(60, 0))
+            .unwrap();
+        assert!(mgr.lookup(index_id, &[Value::Int64(0)]).is_err());
+
+        let mut rest = scanned[1500..].to_vec();
+        rest.push((RowId::new(60, 0), row(6000)));
+        mgr.add_build_rows(index_id, &rest, &columns).unwrap();
+
+        let mut phases = Vec::new();
+        mgr.finish_build(index_id, &mut |p| phases.push(*p))
+            .unwrap();
+        assert!(!mgr.is_building(index_id));
+        assert_eq!(phases.first().unwrap().phase, IndexBuildPhase::CatchUp);
+        let last = phases.last().unwrap();
+        assert_eq!(last.phase, IndexBuildPhase::Load);
+        assert_eq!((last.done, last.total), (3001, 3001));
+
+        let lookup = |mgr: &IndexManager, i: i64| mgr.lookup(index_id, &[Value::Int64(i)]).unwrap();
+        assert_eq!(lookup(&mgr, 0), vec![RowId::new(1, 0)]);
+        assert_eq!(lookup(&mgr, 2999), vec![RowId::new(60, 49)]);
+        assert_eq!(lookup(&mgr, 5000), vec![RowId::new(100, 0)]);
+        assert_eq!(lookup(&mgr, 6000), vec![RowId::new(60, 0)]);
+        assert_eq!(lookup(&mgr, 20), vec![RowId::new(100, 1)]);
+        assert!(lookup(&mgr, 10).is_empty());
+
+        // Regular maintenance resumes once the build is done
+        mgr.insert(0, index_id, &row(7000), &columns, RowId::new(101, 0))
+            .unwrap();
+        assert_eq!(lookup(&mgr, 7000), vec![RowId::new(101, 0)]);
+    }
+
+    #[test]
+    fn test_index_build_unique_violation() {
+        let dir = TempDir::new().unwrap();
+        let mut mgr = create_test_mgr(&dir);
+        let columns = create_test_columns();
+        let index_id = mgr
+            .create_index(1, "idx_id".to_string(), vec!["id".to_string()], true)
+            .unwrap();
+
+        mgr.begin_build(index_id).unwrap();
+        let rows: Vec<(RowId, Vec<Value>)> = (0..100)
+            .map(|i| {
+                let id = if i == 70 { 42 } else { i };
+                (
+                    RowId::new(1, i as usize),
+                    vec![Value::Int64(id), Value::Null],
+                )
+            })
+            .collect();
+        mgr.add_build_rows(index_id, &rows, &columns).unwrap();
+
+        let err = mgr.finish_build(index_id, &mut |_| {}).unwrap_err();
+        match &err {
+            IndexError::UniqueViolation { index, key } => {
+                assert_eq!(index, "idx_id");
+                assert_eq!(key, "(id)=(Int64(42))");
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(
+            err.to_string(),
+            "Duplicate key in unique index idx_id: (id)=(Int64(42))"
+        );
     }
 }
@@ -33,6 +33,19 @@ pub fn crc32_hash(s: &str) -> u64 {
     crc32fast::hash(s.as_bytes()) as u64
 }
 
+/// Hash a 64-bit integer
+/// Uses the MurmurHash3 finalizer, so it needs no allocation or byte buffer
+#[inline]
+pub fn hash_u64(value: u64) -> u64 {
+    let mut h = value;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+    h
+}
+
 /// Hash a string and return an integer
 /// Uses FNV-1a as the default hash algorithm
 pub fn hash_string(s: &str) -> u64 {
@@ -144,3 +144,13 @@ fn test_murmur3_hash_long_string() {
     // Should be consistent
     assert_eq!(hash, murmur3_hash(s));
 }
+
+#[test]
+fn test_hash_u64_spreads_consecutive_values() {
+    assert_eq!(hash_u64(42), hash_u64(42));
+    assert_eq!(hash_u64(0), 0);
+
+    // Consecutive page ids spread over the buckets like random values
+    let buckets: std::collections::HashSet<u64> = (1..=64u64).map(|v| hash_u64(v) % 64).collect();
+    assert!(buckets.len() > 32);
+}
@@ -1,16 +1,49 @@
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 
 // Import the hash map wrappers and interface from the crate
+use aistore::infrastructure::hash::fnv1a_hash;
 use aistore::infrastructure::hash_table::{
-    HashMapInterface, LinkedHashMapWrapper, StdHashMapWrapper,
+    HashMapInterface, LinkedHashMapWrapper, MappingTable, StdHashMapWrapper,
 };
 
 // Test configuration
 const THREAD_COUNT: usize = 8;
 const OPERATIONS_PER_THREAD: usize = 10_000;
 
+// Buffer mapping configuration: pool size and partitions of the buffer pool
+const MAPPING_SLOTS: usize = 4096;
+const MAPPING_STRIPES: usize = 16;
+
+// Counts heap allocations, so the mapping benchmark can prove it makes none
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// Number of heap allocations `f` makes
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
 // Benchmark concurrent insertions for StdHashMapWrapper
 pub fn bench_concurrent_insertions_std_hash_map(c: &mut Criterion) {
     let mut group = c.benchmark_group("ConcurrentInsertions");
@@ -187,6 +220,103 @@ pub fn bench_read_heavy_linked_hash_map(c: &mut Criterion) {
     group.finish();
 }
 
+// Page ids spread over a few segments, like the buffer pool sees them
+fn page_id(i: usize) -> u64 {
+    ((i as u64 % 8) << 32) | (i as u64 / 8)
+}
+
+// The buffer pool's old mapping path: format the page id and hash the string
+fn string_bucket(page_id: u64) -> usize {
+    (fnv1a_hash(&page_id.to_string()) as usize) % MAPPING_SLOTS
+}
+
+// Benchmark the buffer mapping hot path: lookup, and remap of a slot to
+// another page as eviction does
+pub fn bench_buffer_mapping(c: &mut Criterion) {
+    let table = MappingTable::new(MAPPING_SLOTS, MAPPING_STRIPES, u64::MAX);
+    for slot in 0..MAPPING_SLOTS {
+        table.insert(page_id(slot), slot).unwrap();
+    }
+
+    // Lookups and remaps must not touch the heap
+    let allocations = allocations_during(|| {
+        for i in 0..OPERATIONS_PER_THREAD {
+            let slot = i % MAPPING_SLOTS;
+            black_box(table.get(&page_id(slot)));
+            black_box(table.get_optimistic(&page_id(slot)));
+            table.remove(&page_id(slot)).unwrap();
+            table.insert(page_id(slot), slot).unwrap();
+        }
+    });
+    assert_eq!(allocations, 0, "mapping table allocated on the hot path");
+
+    let string_allocations = allocations_during(|| {
+        for i in 0..OPERATIONS_PER_THREAD {
+            black_box(string_bucket(page_id(i)));
+        }
+    });
+    println!(
+        "allocations per {} lookups: mapping table {}, string hash {}",
+        OPERATIONS_PER_THREAD, allocations, string_allocations
+    );
+
+    let mut group = c.benchmark_group("BufferMapping");
+
+    group.bench_function("StringHashBucket", |b| {
+        b.iter(|| {
+            for i in 0..OPERATIONS_PER_THREAD {
+                black_box(string_bucket(page_id(i)));
+            }
+        })
+    });
+    group.bench_function("MappingTableGet", |b| {
+        b.iter(|| {
+            for i in 0..OPERATIONS_PER_THREAD {
+                black_box(table.get(&page_id(i % MAPPING_SLOTS)));
+            }
+        })
+    });
+    group.bench_function("MappingTableGetOptimistic", |b| {
+        b.iter(|| {
+            for i in 0..OPERATIONS_PER_THREAD {
+                black_box(table.get_optimistic(&page_id(i % MAPPING_SLOTS)));
+            }
+        })
+    });
+    group.bench_function("MappingTableRemap", |b| {
+        b.iter(|| {
+            for i in 0..OPERATIONS_PER_THREAD {
+                let slot = i % MAPPING_SLOTS;
+                table.remove(&page_id(slot)).unwrap();
+                table.insert(page_id(slot), slot).unwrap();
+            }
+        })
+    });
+
+    // Readers on every thread, each over its own pages
+    let table = Arc::new(table);
+    group.bench_function("MappingTableConcurrentGet", |b| {
+        b.iter(|| {
+            let handles: Vec<_> = (0..THREAD_COUNT)
+                .map(|thread_id| {
+                    let table = Arc::clone(&table);
+                    thread::spawn(move || {
+                        for i in 0..OPERATIONS_PER_THREAD {
+                            let slot = (thread_id * OPERATIONS_PER_THREAD + i) % MAPPING_SLOTS;
+                            black_box(table.get_optimistic(&page_id(slot)));
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
 // Export the benchmark group for criterion
 criterion_group!(
     benches,
@@ -195,7 +325,8 @@ criterion_group!(
     bench_single_threaded_std_hash_map,
     bench_single_threaded_linked_hash_map,
     bench_read_heavy_std_hash_map,
-    bench_read_heavy_linked_hash_map
+    bench_read_heavy_linked_hash_map,
+    bench_buffer_mapping
 );
 
 // Only run the benchmark group when this file is executed directly
@@ -0,0 +1,263 @@
+// Lock-striped mapping table from keys to slot numbers, without allocation
+
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::cell::UnsafeCell;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+/// End of a bucket chain
+const NIL: u32 = u32::MAX;
+
+/// Optimistic reads retried before `get_optimistic` takes the stripe lock
+const OPTIMISTIC_RETRIES: usize = 4;
+
+/// Key of a `MappingTable`
+pub trait MappingKey: Copy + Eq {
+    /// Integer hash of the key; must not allocate
+    fn hash_key(&self) -> u64;
+}
+
+impl MappingKey for u64 {
+    #[inline]
+    fn hash_key(&self) -> u64 {
+        crate::infrastructure::hash::hash_u64(*self)
+    }
+}
+
+/// Chain link of one slot
+struct Entry<K> {
+    key: UnsafeCell<K>,
+    next: AtomicU32,
+}
+
+/// Hash table mapping keys to slot numbers `0..capacity`
+///
+/// Each slot is mapped by at most one key at a time, so the table keeps
+/// one chain entry per slot and inserting or removing a mapping only
+/// relinks entries that were allocated up front. Buckets are split into
+/// stripes, each with its own lock and a version counter for
+/// `get_optimistic`.
+///
+/// ```text
+/// heads:   [b0][b1][b2][b3] ...      bucket -> first slot in chain
+/// entries: [s0][s1][s2][s3] ...      slot -> (key, next slot)
+/// stripes: bucket % num_stripes     lock + version per stripe
+/// ```
+pub struct MappingTable<K: MappingKey> {
+    heads: Box<[AtomicU32]>,
+    entries: Box<[Entry<K>]>,
+    stripes: Box<[Stripe]>,
+    mask: u64,
+}
+
+struct Stripe {
+    lock: RwLock<()>,
+    /// Odd while a writer holds the stripe
+    version: AtomicU64,
+}
+
+// Keys are only written under a stripe's exclusive lock; readers either
+// hold the lock shared or validate what they read against the version
+unsafe impl<K: MappingKey + Send> Send for MappingTable<K> {}
+unsafe impl<K: MappingKey + Send + Sync> Sync for MappingTable<K> {}
+
+impl<K: MappingKey> MappingTable<K> {
+    /// Creates a table for `capacity` slots split into `num_stripes` stripes
+    ///
+    /// `empty_key` fills the entries of unmapped slots; it is never
+    /// returned by a lookup.
+    pub fn new(capacity: usize, num_stripes: usize, empty_key: K) -> Self {
+        assert!(capacity < NIL as usize, "too many slots");
+        assert!(num_stripes > 0, "need at least one stripe");
+        let num_buckets = capacity.max(num_stripes).next_power_of_two();
+        Self {
+            heads: (0..num_buckets).map(|_| AtomicU32::new(NIL)).collect(),
+            entries: (0..capacity)
+                .map(|_| Entry {
+                    key: UnsafeCell::new(empty_key),
+                    next: AtomicU32::new(NIL),
+                })
+                .collect(),
+            stripes: (0..num_stripes)
+                .map(|_| Stripe {
+                    lock: RwLock::new(()),
+                    version: AtomicU64::new(0),
+                })
+                .collect(),
+            mask: num_buckets as u64 - 1,
+        }
+    }
+
+    /// Number of slots the table maps to
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    fn bucket(&self, key: &K) -> usize {
+        (key.hash_key() & self.mask) as usize
+    }
+
+    #[inline]
+    fn stripe(&self, bucket: usize) -> &Stripe {
+        &self.stripes[bucket % self.stripes.len()]
+    }
+
+    /// Walks a chain; the caller keeps writers out or validates afterwards
+    #[inline]
+    fn find(&self, bucket: usize, key: &K) -> Option<usize> {
+        let mut slot = self.heads[bucket].load(Ordering::Acquire);
+        // Bounded, so a chain changed under an optimistic reader still ends
+        for _ in 0..=self.entries.len() {
+            if slot == NIL {
+                return None;
+            }
+            let entry = &self.entries[slot as usize];
+            if unsafe { std::ptr::read_volatile(entry.key.get()) } == *key {
+                return Some(slot as usize);
+            }
+            slot = entry.next.load(Ordering::Acquire);
+        }
+        None
+    }
+
+    /// Locks the stripe of `key` for lookups
+    pub fn read_stripe(&self, key: &K) -> MappingReadGuard<'_, K> {
+        let bucket = self.bucket(key);
+        MappingReadGuard {
+            table: self,
+            bucket,
+            _lock: self.stripe(bucket).lock.read(),
+        }
+    }
+
+    /// Locks the stripe of `key` for changes
+    pub fn write_stripe(&self, key: &K) -> MappingWriteGuard<'_, K> {
+        let bucket = self.bucket(key);
+        let stripe = self.stripe(bucket);
+        let lock = stripe.lock.write();
+        stripe.version.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        MappingWriteGuard {
+            table: self,
+            bucket,
+            _lock: lock,
+        }
+    }
+
+    /// Returns the slot `key` maps to
+    pub fn get(&self, key: &K) -> Option<usize> {
+        self.read_stripe(key).get(key)
+    }
+
+    /// Returns the slot `key` maps to without taking the stripe lock
+    ///
+    /// Retries while a writer changes the stripe and falls back to the
+    /// lock if that keeps happening. The answer may be stale as soon as it
+    /// is returned; callers that act on it must check the slot again.
+    pub fn get_optimistic(&self, key: &K) -> Option<usize> {
+        let bucket = self.bucket(key);
+        let version = &self.stripe(bucket).version;
+        for _ in 0..OPTIMISTIC_RETRIES {
+            let before = version.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let slot = self.find(bucket, key);
+            fence(Ordering::Acquire);
+            if version.load(Ordering::Relaxed) == before {
+                return slot;
+            }
+        }
+        self.get(key)
+    }
+
+    /// Maps `key` to `slot`, returning the existing slot if `key` is mapped
+    pub fn insert(&self, key: K, slot: usize) -> Result<(), usize> {
+        self.write_stripe(&key).insert(key, slot)
+    }
+
+    /// Removes the mapping of `key`, returning its slot
+    pub fn remove(&self, key: &K) -> Option<usize> {
+        self.write_stripe(key).remove(key)
+    }
+}
+
+/// Shared lock on the stripe of one key
+pub struct MappingReadGuard<'a, K: MappingKey> {
+    table: &'a MappingTable<K>,
+    bucket: usize,
+    _lock: RwLockReadGuard<'a, ()>,
+}
+
+impl<K: MappingKey> MappingReadGuard<'_, K> {
+    /// Returns the slot `key` maps to; `key` must be the one the stripe was
+    /// locked for
+    pub fn get(&self, key: &K) -> Option<usize> {
+        debug_assert_eq!(self.table.bucket(key), self.bucket);
+        self.table.find(self.bucket, key)
+    }
+}
+
+/// Exclusive lock on the stripe of one key
+pub struct MappingWriteGuard<'a, K: MappingKey> {
+    table: &'a MappingTable<K>,
+    bucket: usize,
+    _lock: RwLockWriteGuard<'a, ()>,
+}
+
+impl<K: MappingKey> MappingWriteGuard<'_, K> {
+    /// Returns the slot `key` maps to; `key` must be the one the stripe was
+    /// locked for
+    pub fn get(&self, key: &K) -> Option<usize> {
+        debug_assert_eq!(self.table.bucket(key), self.bucket);
+        self.table.find(self.bucket, key)
+    }
+
+    /// Maps `key` to `slot`, returning the existing slot if `key` is mapped
+    ///
+    /// `slot` must not be mapped by another key.
+    pub fn insert(&mut self, key: K, slot: usize) -> Result<(), usize> {
+        debug_assert_eq!(self.table.bucket(&key), self.bucket);
+        if let Some(existing) = self.get(&key) {
+            return Err(existing);
+        }
+        let entry = &self.table.entries[slot];
+        let head = &self.table.heads[self.bucket];
+        unsafe { std::ptr::write_volatile(entry.key.get(), key) };
+        entry
+            .next
+            .store(head.load(Ordering::Relaxed), Ordering::Release);
+        head.store(slot as u32, Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the mapping of `key`, returning its slot
+    pub fn remove(&mut self, key: &K) -> Option<usize> {
+        debug_assert_eq!(self.table.bucket(key), self.bucket);
+        let mut link = &self.table.heads[self.bucket];
+        loop {
+            let slot = link.load(Ordering::Relaxed);
+            if slot == NIL {
+                return None;
+            }
+            let entry = &self.table.entries[slot as usize];
+            if unsafe { *entry.key.get() } == *key {
+                link.store(entry.next.load(Ordering::Relaxed), Ordering::Release);
+                return Some(slot as usize);
+            }
+            link = &entry.next;
+        }
+    }
+}
+
+impl<K: MappingKey> Drop for MappingWriteGuard<'_, K> {
+    fn drop(&mut self) {
+        // Even again before the lock is released
+        self.table
+            .stripe(self.bucket)
+            .version
+            .fetch_add(1, Ordering::Release);
+    }
+}
@@ -1,4 +1,9 @@
-// Hash table wrappers for std::collections::HashMap and linked_hash_map::LinkedHashMap
+// Hash table wrappers for std::collections::HashMap and linked_hash_map::LinkedHashMap,
+// and the lock-striped mapping table used by the buffer pool
+
+mod mapping_table;
+
+pub use mapping_table::{MappingKey, MappingReadGuard, MappingTable, MappingWriteGuard};
 
 use std::hash::Hash;
 // Don't import HashMap directly to avoid name conflict with the re-export
@@ -111,3 +111,76 @@ fn test_linked_hash_map_empty() {
     assert!(!hash_table.is_empty());
     assert_eq!(hash_table.size(), 1);
 }
+
+// Tests for MappingTable
+
+#[test]
+fn test_mapping_table_basic() {
+    let table = MappingTable::new(8, 2, u64::MAX);
+    assert_eq!(table.capacity(), 8);
+
+    // Insert mappings
+    assert_eq!(table.insert(100, 0), Ok(()));
+    assert_eq!(table.insert(200, 1), Ok(()));
+    assert_eq!(table.insert(300, 2), Ok(()));
+
+    // A mapped key keeps its slot
+    assert_eq!(table.insert(200, 5), Err(1));
+
+    // Get values, with and without the stripe lock
+    assert_eq!(table.get(&100), Some(0));
+    assert_eq!(table.get_optimistic(&300), Some(2));
+    assert_eq!(table.get(&400), None);
+
+    // Remove a mapping and reuse its slot
+    assert_eq!(table.remove(&200), Some(1));
+    assert_eq!(table.remove(&200), None);
+    assert_eq!(table.get(&200), None);
+    assert_eq!(table.insert(400, 1), Ok(()));
+    assert_eq!(table.get_optimistic(&400), Some(1));
+}
+
+#[test]
+fn test_mapping_table_chains_share_buckets() {
+    // As many keys as buckets, so some chains hold several entries
+    let table = MappingTable::new(16, 1, u64::MAX);
+    for key in 0..16u64 {
+        table.insert(key * 1000, key as usize).unwrap();
+    }
+    for key in (0..16u64).step_by(2) {
+        assert_eq!(table.remove(&(key * 1000)), Some(key as usize));
+    }
+    for key in 0..16u64 {
+        let expected = (key % 2 == 1).then_some(key as usize);
+        assert_eq!(table.get(&(key * 1000)), expected);
+    }
+}
+
+#[test]
+fn test_mapping_table_concurrent_remap() {
+    use std::sync::Arc;
+    use std::thread;
+
+    // Each thread owns a few slots and keeps moving them between keys
+    // while readers look up keys optimistically
+    let table = Arc::new(MappingTable::new(64, 4, u64::MAX));
+    let writers: Vec<_> = (0..4usize)
+        .map(|t| {
+            let table = Arc::clone(&table);
+            thread::spawn(move || {
+                for round in 0..2_000u64 {
+                    for slot in (t * 16)..(t * 16 + 16) {
+                        let key = round * 64 + slot as u64;
+                        table.insert(key, slot).unwrap();
+                        assert_eq!(table.get_optimistic(&key), Some(slot));
+                        assert_eq!(table.remove(&key), Some(slot));
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(table.get(&0), None);
+}
@@ -3,3 +3,4 @@
 pub mod hash;
 pub mod hash_table;
 pub mod lwlock;
+pub mod worker;
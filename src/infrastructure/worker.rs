@@ -0,0 +1,123 @@
+//! Background worker - a thread that runs a round at a fixed interval
+//!
+//! `BackgroundWorker` owns the thread behind the page flusher, autovacuum
+//! and the periodic checkpoint. A round that fails leaves its error for the
+//! owner to pick up with `take_error`; the thread keeps going.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Background thread running a round every interval until stopped
+pub struct BackgroundWorker<E> {
+    /// Error of the last round that failed, until it is taken
+    last_error: Arc<Mutex<Option<E>>>,
+    /// Dropped to wake the thread up and make it exit
+    stop_tx: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<E> BackgroundWorker<E> {
+    /// Create a worker with no thread running
+    pub fn new() -> Self {
+        Self {
+            last_error: Arc::new(Mutex::new(None)),
+            stop_tx: None,
+            handle: None,
+        }
+    }
+
+    /// Starts the thread, running `round` every `interval`
+    ///
+    /// Restarts the thread if it is running.
+    pub fn start<F>(&mut self, interval: Duration, mut round: F)
+    where
+        F: FnMut() -> Result<(), E> + Send + 'static,
+        E: Send + 'static,
+    {
+        self.stop();
+
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let last_error = Arc::clone(&self.last_error);
+
+        self.stop_tx = Some(stop_tx);
+        self.handle = Some(thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                if let Err(e) = round() {
+                    *last_error.lock().unwrap() = Some(e);
+                }
+            }
+        }));
+    }
+
+    /// Returns true while the thread is running
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Returns the error of the last round that failed, if any, and
+    /// clears it
+    pub fn take_error(&self) -> Option<E> {
+        self.last_error.lock().unwrap().take()
+    }
+
+    /// Stop the thread
+    ///
+    /// Wakes the thread up and waits for it to finish the round it is in.
+    pub fn stop(&mut self) {
+        self.stop_tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<E> Default for BackgroundWorker<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Drop for BackgroundWorker<E> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    #[test]
+    fn test_rounds_run_until_stopped_and_keep_errors() {
+        let rounds = Arc::new(AtomicU64::new(0));
+        let mut worker = BackgroundWorker::new();
+        assert!(!worker.is_running());
+
+        let counter = Arc::clone(&rounds);
+        worker.start(Duration::from_millis(5), move || {
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                1 => Err("second round failed"),
+                _ => Ok(()),
+            }
+        });
+        assert!(worker.is_running());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while rounds.load(Ordering::SeqCst) < 3 {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+        worker.stop();
+        assert!(!worker.is_running());
+        let after_stop = rounds.load(Ordering::SeqCst);
+
+        assert_eq!(worker.take_error(), Some("second round failed"));
+        assert_eq!(worker.take_error(), None);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(rounds.load(Ordering::SeqCst), after_stop);
+    }
+}
@@ -6,6 +6,7 @@ pub mod types;
 // Import various modules
 pub mod buffer;
 pub mod catalog;
+pub mod config;
 pub mod controlfile;
 pub mod heap;
 pub mod index;
@@ -33,6 +34,9 @@ pub use heap::{HeapTable, RowId, Tuple, Value};
 // Re-export lock items for easier access
 pub use lock::{LockManager, LockMode, TransactionId};
 
+// Re-export configuration items
+pub use config::{AistoreConfig, ConfigError};
+
 // Re-export storage engine API
 pub use storage::{Filter, StorageEngine, StorageError, StorageResult, TableId};
 
@@ -41,4 +45,4 @@ pub use catalog::Catalog;
 pub use table::Column;
 
 // Re-export buffer items
-pub use buffer::BufferMgr;
+pub use buffer::{BadPagePolicy, BufferError, BufferMgr};
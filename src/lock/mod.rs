@@ -34,6 +34,15 @@ impl LockManager {
         }
     }
 
+    pub fn with_timeout(timeout_secs: u64) -> Self {
+        Self {
+            tx_manager: TransactionManager::with_timeout(timeout_secs),
+            row_locks: RowLockManager::with_timeout(timeout_secs),
+            table_locks: TableLockManager::with_timeout(timeout_secs),
+            deadlock_detector: DeadlockDetector::new(),
+        }
+    }
+
     pub fn begin(&self) -> TransactionId {
         self.tx_manager.begin()
     }
@@ -50,6 +59,13 @@ impl LockManager {
         self.tx_manager.abort(tx_id)
     }
 
+    /// Returns true if `tx_id` has begun and not yet committed or aborted
+    pub fn is_active(&self, tx_id: TransactionId) -> bool {
+        self.tx_manager
+            .get(tx_id)
+            .is_some_and(|tx| tx.status == TxStatus::Active)
+    }
+
     pub fn lock_row(
         &self,
         tx_id: TransactionId,
@@ -7,6 +7,7 @@ static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 mod buffer;
 mod catalog;
+mod config;
 mod controlfile;
 mod heap;
 mod index;
@@ -6,7 +6,9 @@
 //! IMPORTANT: PageHeader uses #[repr(packed)] for persistent storage.
 //! See PAGEDESIGN.md for design guidelines.
 
-use crate::types::PageId;
+use crate::types::{PageId, PAGE_SIZE};
+use std::fmt;
+use std::ops::Range;
 
 /// Special page metadata containing offset and reserve bits
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -143,6 +145,30 @@ impl PageHeader {
     pub fn slot_space(&self) -> usize {
         self.lower as usize
     }
+
+    /// Reads the header stored at the start of a page buffer
+    #[inline]
+    pub fn read_from(data: &[u8]) -> Self {
+        assert!(data.len() >= Self::size());
+        // PageHeader is packed, so an unaligned read is always valid
+        unsafe { std::ptr::read_unaligned(data.as_ptr() as *const PageHeader) }
+    }
+
+    /// Writes this header to the start of a page buffer
+    #[inline]
+    pub fn write_to(&self, data: &mut [u8]) {
+        assert!(data.len() >= Self::size());
+        unsafe { std::ptr::write_unaligned(data.as_mut_ptr() as *mut PageHeader, *self) }
+    }
+
+    /// Records `lsn` as the latest WAL record applied to the page
+    ///
+    /// The LSN it replaces moves to `plsn`.
+    #[inline]
+    pub fn set_lsn(&mut self, lsn: u64) {
+        self.plsn = self.glsn;
+        self.glsn = lsn;
+    }
 }
 
 impl Default for PageHeader {
@@ -152,24 +178,98 @@ impl Default for PageHeader {
     }
 }
 
+/// Size of one entry in the slot array
+pub const SLOT_SIZE: usize = 8;
+
+/// Slot array entry pointing at a tuple in the page's data area
+///
+/// Stored little-endian right after the page header, one per slot: the
+/// tuple's start counted back from the end of the page (so never positive)
+/// followed by its length. A slot with a zero offset holds no tuple; pages
+/// that keep a list of their free slots chain them through its length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Slot {
+    /// Tuple start relative to the end of the page
+    pub offset: i32,
+    /// Tuple length in bytes
+    pub length: u32,
+}
+
+impl Slot {
+    /// Creates a new Slot
+    #[inline]
+    pub fn new(offset: i32, length: u32) -> Self {
+        Self { offset, length }
+    }
+
+    /// Returns true if the slot does not point at a tuple
+    #[inline]
+    pub fn is_unused(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Returns the byte range of the tuple within the page
+    #[inline]
+    pub fn range(&self) -> Range<usize> {
+        let start = (PAGE_SIZE as i32 + self.offset) as usize;
+        start..start + self.length as usize
+    }
+}
+
 /// Core Page structure for storage engine
 ///
-/// Contains a packed PageHeader for persistent storage compatibility.
-#[derive(Debug, Clone, Copy)]
+/// Owns a full `PAGE_SIZE` block, aligned for direct I/O, that starts with
+/// the packed PageHeader. Pages using the slotted layout keep their slot
+/// array right after the header (up to `lower`) and tuple data at the end
+/// of the block (from `upper`).
+#[derive(Clone)]
+#[repr(C, align(64))]
 pub struct Page {
-    /// Packed page header for persistent storage
-    pub header: PageHeader,
+    data: [u8; PAGE_SIZE],
 }
 
 impl Page {
-    /// Creates a new uninitialized Page
+    /// Creates a new zeroed Page
     #[inline]
     pub fn new() -> Self {
         Self {
-            header: PageHeader::new(),
+            data: [0u8; PAGE_SIZE],
         }
     }
 
+    /// Creates a Page holding a copy of `data`, which must be PAGE_SIZE bytes
+    #[inline]
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut page = Self::new();
+        page.data.copy_from_slice(data);
+        page
+    }
+
+    /// Returns the raw bytes of the page
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the raw bytes of the page for modification
+    #[inline]
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Returns the page header
+    #[inline]
+    pub fn header(&self) -> &PageHeader {
+        // PageHeader is packed (alignment 1) and fits inside the block
+        unsafe { &*(self.data.as_ptr() as *const PageHeader) }
+    }
+
+    /// Returns the page header for modification
+    #[inline]
+    pub fn header_mut(&mut self) -> &mut PageHeader {
+        unsafe { &mut *(self.data.as_mut_ptr() as *mut PageHeader) }
+    }
+
     /// Returns the size of the page header in bytes
     #[inline]
     pub fn header_size() -> usize {
@@ -179,13 +279,86 @@ impl Page {
     /// Returns the available data space in the page
     #[inline]
     pub fn available_space(&self) -> usize {
-        self.header.available_space()
+        self.header().available_space()
     }
 
     /// Returns the slot area size
     #[inline]
     pub fn slot_space(&self) -> usize {
-        self.header.slot_space()
+        self.header().slot_space()
+    }
+
+    /// Returns the number of slots, as recorded by `lower`
+    #[inline]
+    pub fn slot_count(&self) -> usize {
+        (self.header().lower as usize).saturating_sub(PageHeader::size()) / SLOT_SIZE
+    }
+
+    /// Byte offset of a slot array entry
+    #[inline]
+    fn slot_offset(slot_idx: usize) -> usize {
+        PageHeader::size() + slot_idx * SLOT_SIZE
+    }
+
+    /// Reads a slot array entry
+    #[inline]
+    pub fn slot(&self, slot_idx: usize) -> Slot {
+        let at = Self::slot_offset(slot_idx);
+        Slot::new(
+            i32::from_le_bytes(self.data[at..at + 4].try_into().unwrap()),
+            u32::from_le_bytes(self.data[at + 4..at + 8].try_into().unwrap()),
+        )
+    }
+
+    /// Writes a slot array entry
+    #[inline]
+    pub fn set_slot(&mut self, slot_idx: usize, slot: Slot) {
+        let at = Self::slot_offset(slot_idx);
+        self.data[at..at + 4].copy_from_slice(&slot.offset.to_le_bytes());
+        self.data[at + 4..at + 8].copy_from_slice(&slot.length.to_le_bytes());
+    }
+
+    /// Returns the tuple a slot points at
+    #[inline]
+    pub fn tuple(&self, slot: Slot) -> &[u8] {
+        &self.data[slot.range()]
+    }
+
+    /// Computes the checksum of the page as stored at `page_id`
+    ///
+    /// CRC32C over the page id and the whole block, with the header's
+    /// checksum field read as zero. Mixing in the page id also catches a
+    /// block that was written to the wrong place.
+    pub fn compute_checksum(&self, page_id: PageId) -> u32 {
+        // The checksum field is the first 4 bytes of the header
+        let crc = crc32c::crc32c(&page_id.to_le_bytes());
+        let crc = crc32c::crc32c_append(crc, &[0u8; 4]);
+        crc32c::crc32c_append(crc, &self.data[4..])
+    }
+
+    /// Stores the checksum for `page_id` in the header
+    #[inline]
+    pub fn update_checksum(&mut self, page_id: PageId) {
+        let checksum = self.compute_checksum(page_id);
+        self.header_mut().checksum = checksum;
+    }
+
+    /// Returns true for a block that was never written
+    #[inline]
+    pub fn is_zeroed(&self) -> bool {
+        self.data.iter().all(|&b| b == 0)
+    }
+
+    /// Returns everything after the header
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.data[PageHeader::size()..]
+    }
+
+    /// Returns everything after the header for modification
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.data[PageHeader::size()..]
     }
 }
 
@@ -196,6 +369,14 @@ impl Default for Page {
     }
 }
 
+impl fmt::Debug for Page {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Page")
+            .field("header", self.header())
+            .finish_non_exhaustive()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     include!("tests.rs");
@@ -2,21 +2,39 @@
 //!
 //! Provides a simple table-oriented storage API for benchmarks and applications.
 
-use crate::buffer::BufferMgr;
+use crate::buffer::{
+    BadPagePolicy, BufferMgr, BufferWarmup, DoubleWriteBuffer, PageFlusher, WarmupConfig,
+    WarmupFile, WARMUP_FILE_NAME,
+};
 use crate::catalog::Catalog;
-use crate::heap::{HeapTable, RowId, Tuple, Value};
+use crate::config::{AistoreConfig, ConfigError};
+use crate::heap::{
+    AutovacuumConfig, AutovacuumWorker, HeapError, HeapTable, RowId, Tuple, VacuumStats, Value,
+};
+use crate::index::btree::IndexResult;
+use crate::index::build::{IndexBuildPhase, IndexBuildProgress};
+use crate::index::key::ColumnOrder;
 use crate::index::IndexManager;
+use crate::infrastructure::worker::BackgroundWorker;
 use crate::lock::{LockManager, LockMode, TransactionId};
 use crate::table::Column;
-use crate::types::PAGE_SIZE;
+use crate::types::{PageId, PAGE_SIZE};
+use crate::wal::log_record::{CompensationAction, LogPayload, LogRecord, LogType};
+use crate::wal::lsn::LSN;
 use crate::wal::WalManager;
-use parking_lot::RwLock;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 /// Table ID type
 pub type TableId = u64;
 
+/// Pages written through the double-write file per batch
+const DOUBLE_WRITE_PAGES: usize = 64;
+
+/// How often the background flusher checks its policy
+const FLUSHER_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Filter condition for scan operations
 #[derive(Debug, Clone)]
 pub struct Filter {
@@ -35,6 +53,7 @@ pub enum StorageError {
     TransactionNotActive,
     LockTimeout,
     Deadlock,
+    Config(ConfigError),
     Other(String),
 }
 
@@ -47,6 +66,7 @@ impl std::fmt::Display for StorageError {
             StorageError::TransactionNotActive => write!(f, "Transaction not active"),
             StorageError::LockTimeout => write!(f, "Lock timeout"),
             StorageError::Deadlock => write!(f, "Deadlock detected"),
+            StorageError::Config(e) => write!(f, "{}", e),
             StorageError::Other(msg) => write!(f, "Storage error: {}", msg),
         }
     }
@@ -62,68 +82,214 @@ pub type StorageResult<T> = Result<T, StorageError>;
 /// Provides table-oriented operations:
 /// - create_table / drop_table: DDL
 /// - insert / scan / update / delete: DML
+/// - vacuum: reclaiming the space of deleted rows
 pub struct StorageEngine {
     catalog: Arc<Catalog>,
-    buffer_mgr: Arc<RwLock<BufferMgr>>,
+    buffer_mgr: Arc<BufferMgr>,
+    relations: Arc<Mutex<Relations>>,
+    lock_mgr: Arc<LockManager>,
+    wal: Option<Arc<WalManager>>,
+    /// Writes dirty pages in the background once their WAL is durable
+    flusher: PageFlusher,
+    /// Saves the resident page list and reloads it on the next open
+    warmup: BufferWarmup,
+    /// Vacuums the tables that are due in the background
+    autovacuum: AutovacuumWorker<StorageError>,
+    /// Takes a checkpoint every `checkpoint_interval_sec`
+    checkpointer: BackgroundWorker<StorageError>,
+}
+
+/// The heaps and the indexes on them, shared with the autovacuum thread
+struct Relations {
     tables: HashMap<String, HeapTable>,
-    lock_mgr: LockManager,
-    wal: Option<WalManager>,
     index_mgr: IndexManager,
 }
 
 impl StorageEngine {
-    /// Create a new storage engine
+    /// Create a storage engine in `data_dir`, or reopen the one already there
+    ///
+    /// Settings come from `aistore.toml` or `aistore.json` in `data_dir`
+    /// when there is one; see [`AistoreConfig::load`].
     pub fn new(data_dir: impl Into<std::path::PathBuf>) -> StorageResult<Self> {
-        let data_dir = data_dir.into();
+        Self::open(AistoreConfig::load(data_dir).map_err(StorageError::Config)?)
+    }
+
+    /// Like `new`, choosing what happens when a page read from disk fails
+    /// its checksum
+    ///
+    /// With `BadPagePolicy::Fail` the operation that read the page returns
+    /// an error; with `BadPagePolicy::RepairFromDoubleWrite` the page is
+    /// restored from the double-write area when it holds an intact copy.
+    pub fn new_with_bad_page_policy(
+        data_dir: impl Into<std::path::PathBuf>,
+        policy: BadPagePolicy,
+    ) -> StorageResult<Self> {
+        let config = AistoreConfig::load(data_dir).map_err(StorageError::Config)?;
+        Self::open(config.with_bad_page_policy(policy))
+    }
+
+    /// Like `new`, choosing whether the buffer pool's resident pages are
+    /// saved and read back in on the next open
+    ///
+    /// With warm-up enabled the list of resident pages goes to
+    /// `buffer_warmup.dat` every `dump_interval` and on close; on open the
+    /// listed pages are prefetched in the background.
+    pub fn new_with_warmup(
+        data_dir: impl Into<std::path::PathBuf>,
+        warmup: WarmupConfig,
+    ) -> StorageResult<Self> {
+        let config = AistoreConfig::load(data_dir).map_err(StorageError::Config)?;
+        Self::open(config.with_warmup(warmup))
+    }
+
+    /// Open the storage engine in `config.data_dir` with `config`
+    ///
+    /// Opening replays the WAL into the buffer pool, then loads the catalog,
+    /// reattaches every table to its heap segment and restores the indexes,
+    /// repeating the entry changes logged since the last checkpoint.
+    /// Transactions the log shows neither committed nor aborted are rolled
+    /// back last. A config that fails validation is returned as
+    /// `StorageError::Config` before anything is touched.
+    pub fn open(config: AistoreConfig) -> StorageResult<Self> {
+        config.validate().map_err(StorageError::Config)?;
+        let data_dir = config.data_dir.clone();
         std::fs::create_dir_all(&data_dir).map_err(|e| StorageError::Other(e.to_string()))?;
 
-        let catalog = Catalog::new(&data_dir).map_err(|e| StorageError::Other(e.to_string()))?;
+        let catalog = Catalog::load(&data_dir).map_err(|e| StorageError::Other(e.to_string()))?;
 
         let vfs: Arc<dyn crate::vfs::VfsInterface> = Arc::new(crate::vfs::LocalFs::new());
 
-        let buffer_mgr = Arc::new(RwLock::new(BufferMgr::init(
-            10000,
-            Arc::clone(&vfs),
-            data_dir.clone(),
-        )));
+        let buffer_mgr = Arc::new(
+            BufferMgr::init(config.buffer_pool_size, Arc::clone(&vfs), data_dir.clone())
+                .with_bad_page_policy(config.bad_page_policy)
+                .with_double_write(DoubleWriteBuffer::new(
+                    DOUBLE_WRITE_PAGES,
+                    Arc::clone(&vfs),
+                    data_dir.join("doublewrite.dat"),
+                )),
+        );
+        // Torn pages have to be whole again before redo reads them
+        buffer_mgr
+            .restore_torn_pages()
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+
+        let lock_mgr = LockManager::with_timeout(config.lock_timeout_secs);
 
-        let lock_mgr = LockManager::new();
+        let wal = WalManager::with_config(data_dir.clone(), vfs.clone(), config.wal_config())
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+        let mut index_changes = Vec::new();
+        let losers = wal
+            .recover(|lsn, record| redo_record(&buffer_mgr, lsn, record, &mut index_changes))
+            .map_err(|e| StorageError::Other(e.to_string()))?
+            .active_transactions;
+        let wal = Some(Arc::new(wal));
+        // Neither eviction nor the background flusher writes a page ahead
+        // of its log
+        let durable_lsn = {
+            let wal = wal.clone();
+            move || wal.as_ref().map_or(u64::MAX, |wal| wal.flushed_lsn().raw())
+        };
+        buffer_mgr.set_durable_lsn(durable_lsn.clone());
 
-        let wal = WalManager::new(data_dir.clone(), vfs.clone()).ok();
+        let mut tables = HashMap::new();
+        for table in catalog.list_tables() {
+            let name = table.table_name().to_string();
+            let segment_id = table.segment_id();
+            let mut heap_table = HeapTable::open(table, Arc::clone(&buffer_mgr), segment_id)
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+            heap_table.set_wal(wal.clone());
+            tables.insert(name, heap_table);
+        }
 
-        let index_mgr = IndexManager::new(Arc::clone(&buffer_mgr), data_dir);
+        let mut index_mgr = IndexManager::new(Arc::clone(&buffer_mgr), data_dir.clone());
+        index_mgr
+            .load()
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+        for record in &index_changes {
+            index_mgr
+                .redo(record)
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+        }
+        index_mgr.set_wal(wal.clone());
 
-        Ok(Self {
+        let mut engine = Self {
             catalog: Arc::new(catalog),
             buffer_mgr,
-            tables: HashMap::new(),
-            lock_mgr,
+            relations: Arc::new(Mutex::new(Relations { tables, index_mgr })),
+            lock_mgr: Arc::new(lock_mgr),
             wal,
-            index_mgr,
-        })
+            flusher: PageFlusher::new(config.flush_policy, FLUSHER_INTERVAL),
+            warmup: BufferWarmup::new(
+                config.warmup,
+                WarmupFile::new(Arc::clone(&vfs), data_dir.join(WARMUP_FILE_NAME)),
+            ),
+            autovacuum: AutovacuumWorker::new(config.autovacuum),
+            checkpointer: BackgroundWorker::new(),
+        };
+        for (tx_id, last_lsn) in losers {
+            engine.rollback(tx_id, last_lsn)?;
+        }
+        // Write out what recovery rebuilt so the next restart replays from here
+        engine.flush()?;
+
+        engine
+            .flusher
+            .start(Arc::clone(&engine.buffer_mgr), durable_lsn);
+        engine.warmup.start(Arc::clone(&engine.buffer_mgr));
+        engine.start_autovacuum();
+        let interval = config.wal.checkpoint_interval_sec;
+        if interval > 0 {
+            let relations = Arc::clone(&engine.relations);
+            let wal = engine.wal.clone();
+            engine
+                .checkpointer
+                .start(Duration::from_secs(interval), move || {
+                    checkpoint(&relations, wal.as_deref())
+                });
+        }
+        Ok(engine)
+    }
+
+    /// Starts the autovacuum thread on the engine's heaps and indexes
+    fn start_autovacuum(&mut self) {
+        let relations = Arc::clone(&self.relations);
+        let lock_mgr = Arc::clone(&self.lock_mgr);
+        self.autovacuum
+            .start(move |config| autovacuum_round(&relations, &lock_mgr, config));
+    }
+
+    /// Locks the heaps and indexes against the autovacuum thread
+    fn relations(&self) -> MutexGuard<'_, Relations> {
+        self.relations.lock().unwrap()
     }
 
     /// Create a new table
+    ///
+    /// Each table gets its own heap segment, numbered after its table id.
     pub fn create_table(&mut self, name: &str, columns: Vec<Column>) -> StorageResult<TableId> {
-        if self.tables.contains_key(name) {
+        let mut relations = self.relations();
+        if relations.tables.contains_key(name) {
             return Err(StorageError::TableAlreadyExists(name.to_string()));
         }
 
+        let segment_id = self.catalog.peek_next_table_id();
         let table = self
             .catalog
-            .create_table(name, 1, columns)
+            .create_table(name, segment_id, columns)
             .map_err(|e| StorageError::Other(e.to_string()))?;
 
         let table_id = table.table_id();
-        let heap_table = HeapTable::new(table, Arc::clone(&self.buffer_mgr), 1);
-        self.tables.insert(name.to_string(), heap_table);
+        let mut heap_table = HeapTable::create(table, Arc::clone(&self.buffer_mgr), segment_id)
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+        heap_table.set_wal(self.wal.clone());
+        relations.tables.insert(name.to_string(), heap_table);
 
         Ok(table_id)
     }
 
     /// Drop a table
     pub fn drop_table(&mut self, name: &str) -> StorageResult<()> {
-        self.tables.remove(name);
+        self.relations().tables.remove(name);
         self.catalog
             .drop_table(name)
             .map_err(|e| StorageError::Other(e.to_string()))?;
@@ -132,97 +298,12 @@ impl StorageEngine {
 
     /// Check if table exists
     pub fn table_exists(&self, name: &str) -> bool {
-        self.tables.contains_key(name)
+        self.relations().tables.contains_key(name)
     }
 
     /// Insert a row (without transaction)
     pub fn insert(&mut self, table: &str, values: Vec<Value>) -> StorageResult<RowId> {
-        let heap_table = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-        let row_id = heap_table
-            .insert(&values)
-            .map_err(|e| StorageError::Other(e.to_string()))?;
-
-        self.maintain_index_insert(table, &values, row_id)?;
-
-        Ok(row_id)
-    }
-
-    fn maintain_index_insert(
-        &mut self,
-        table: &str,
-        values: &[Value],
-        row_id: RowId,
-    ) -> StorageResult<()> {
-        let (columns, index_ids) = {
-            let heap_table = self
-                .tables
-                .get(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let table_arc = heap_table.table();
-            let table_id = table_arc.table_id();
-            let columns: Vec<crate::table::Column> =
-                table_arc.columns().iter().map(|c| c.clone()).collect();
-
-            let indexes = self.index_mgr.get_table_indexes(table_id);
-            let index_ids: Vec<u64> = indexes.iter().map(|m| m.id).collect();
-
-            (columns, index_ids)
-        };
-
-        for id in index_ids {
-            if let Err(e) = self.index_mgr.insert(id, values, &columns, row_id) {
-                return Err(StorageError::Other(format!("Index insert failed: {}", e)));
-            }
-        }
-
-        Ok(())
-    }
-
-    fn maintain_index_delete(&mut self, table: &str, row_id: RowId) -> StorageResult<()> {
-        let (old_values, columns) = {
-            let mut heap_table = self
-                .tables
-                .get_mut(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let table_arc = heap_table.table();
-            let columns: Vec<crate::table::Column> =
-                table_arc.columns().iter().map(|c| c.clone()).collect();
-
-            let old_tuple = heap_table
-                .get(row_id)
-                .map_err(|e| StorageError::Other(e.to_string()))?;
-
-            let old_values: Vec<Value> = old_tuple.values().to_vec();
-
-            (old_values, columns)
-        };
-
-        let index_ids: Vec<u64> = {
-            let heap_table = self
-                .tables
-                .get(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let table_arc = heap_table.table();
-            let table_id = table_arc.table_id();
-
-            let indexes = self.index_mgr.get_table_indexes(table_id);
-            indexes.iter().map(|m| m.id).collect()
-        };
-
-        for id in index_ids {
-            if let Err(e) = self.index_mgr.delete(id, &old_values, &columns, row_id) {
-                return Err(StorageError::Other(format!("Index delete failed: {}", e)));
-            }
-        }
-
-        Ok(())
+        self.relations().insert_row(0, table, values)
     }
 
     /// Insert a row with transaction (acquires X lock on row)
@@ -232,21 +313,21 @@ impl StorageEngine {
         table: &str,
         values: Vec<Value>,
     ) -> StorageResult<RowId> {
-        // Get heap table first to perform insert and get RowId
-        let heap_table = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
         // Insert to get the row_id
-        let row_id = heap_table
-            .insert(&values)
-            .map_err(|e| StorageError::Other(e.to_string()))?;
-
-        // Maintain indexes
-        self.maintain_index_insert(table, &values, row_id)?;
+        let row_id = self.relations().insert_row(tx_id, table, values)?;
 
         // Acquire row lock (X mode for insert)
+        self.lock_row_exclusive(tx_id, table, row_id)?;
+
+        Ok(row_id)
+    }
+
+    fn lock_row_exclusive(
+        &mut self,
+        tx_id: TransactionId,
+        table: &str,
+        row_id: RowId,
+    ) -> StorageResult<()> {
         self.lock_mgr
             .lock_row(
                 tx_id,
@@ -259,14 +340,13 @@ impl StorageEngine {
                 crate::lock::LockError::Timeout => StorageError::LockTimeout,
                 crate::lock::LockError::Deadlock => StorageError::Deadlock,
                 _ => StorageError::Other(e.to_string()),
-            })?;
-
-        Ok(row_id)
+            })
     }
 
     /// Get a row by RowId directly (used with index lookup)
     pub fn get_row(&mut self, table: &str, row_id: RowId) -> StorageResult<Tuple> {
-        let heap_table = self
+        let mut relations = self.relations();
+        let heap_table = relations
             .tables
             .get_mut(table)
             .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
@@ -283,7 +363,8 @@ impl StorageEngine {
         table: &str,
         filter: Option<Filter>,
     ) -> StorageResult<Vec<Tuple>> {
-        let heap_table = self
+        let mut relations = self.relations();
+        let heap_table = relations
             .tables
             .get_mut(table)
             .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
@@ -324,7 +405,8 @@ impl StorageEngine {
 
     /// Scan rows from a table with optional filter (without transaction)
     pub fn scan(&mut self, table: &str, filter: Option<Filter>) -> StorageResult<Vec<Tuple>> {
-        let heap_table = self
+        let mut relations = self.relations();
+        let heap_table = relations
             .tables
             .get_mut(table)
             .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
@@ -352,56 +434,13 @@ impl StorageEngine {
     }
 
     /// Update a row (without transaction)
-    pub fn update(&mut self, table: &str, row_id: RowId, values: Vec<Value>) -> StorageResult<()> {
-        let (old_values, columns_clone, index_ids) = {
-            let mut heap_table = self
-                .tables
-                .get_mut(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let old_tuple = heap_table
-                .get(row_id)
-                .map_err(|e| StorageError::Other(e.to_string()))?;
-
-            let old_values: Vec<Value> = old_tuple.values().to_vec();
-
-            let table_arc = heap_table.table();
-            let table_id = table_arc.table_id();
-            let columns = table_arc.columns();
-            let columns_clone: Vec<crate::table::Column> =
-                columns.iter().map(|c| c.clone()).collect();
-
-            let indexes = self.index_mgr.get_table_indexes(table_id);
-            let index_ids: Vec<u64> = indexes.iter().map(|m| m.id).collect();
-
-            for id in &index_ids {
-                if let Err(e) = self
-                    .index_mgr
-                    .delete(*id, &old_values, &columns_clone, row_id)
-                {
-                    return Err(StorageError::Other(format!("Index delete failed: {}", e)));
-                }
-            }
-
-            (old_values, columns_clone, index_ids)
-        };
-
-        let heap_table = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-        heap_table
-            .update(row_id, &values)
-            .map_err(|e| StorageError::Other(e.to_string()))?;
-
-        for id in index_ids {
-            if let Err(e) = self.index_mgr.insert(id, &values, &columns_clone, row_id) {
-                return Err(StorageError::Other(format!("Index insert failed: {}", e)));
-            }
-        }
-
-        Ok(())
+    pub fn update(
+        &mut self,
+        table: &str,
+        row_id: RowId,
+        values: Vec<Value>,
+    ) -> StorageResult<RowId> {
+        self.relations().update_row(0, table, row_id, values)
     }
 
     /// Update a row with transaction (acquires X lock)
@@ -411,85 +450,17 @@ impl StorageEngine {
         table: &str,
         row_id: RowId,
         values: Vec<Value>,
-    ) -> StorageResult<()> {
+    ) -> StorageResult<RowId> {
         // Acquire X lock on row
-        self.lock_mgr
-            .lock_row(
-                tx_id,
-                table,
-                row_id.page_id,
-                row_id.slot_idx,
-                LockMode::Exclusive,
-            )
-            .map_err(|e| match e {
-                crate::lock::LockError::Timeout => StorageError::LockTimeout,
-                crate::lock::LockError::Deadlock => StorageError::Deadlock,
-                _ => StorageError::Other(e.to_string()),
-            })?;
+        self.lock_row_exclusive(tx_id, table, row_id)?;
 
         // Perform update
-        let heap_table = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-        heap_table
-            .update(row_id, &values)
-            .map_err(|e| StorageError::Other(e.to_string()))
+        self.relations().update_row(tx_id, table, row_id, values)
     }
 
     /// Delete a row (without transaction)
     pub fn delete(&mut self, table: &str, row_id: RowId) -> StorageResult<()> {
-        let old_values: Vec<Value> = {
-            let mut heap_table = self
-                .tables
-                .get_mut(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let old_tuple = heap_table
-                .get(row_id)
-                .map_err(|e| StorageError::Other(e.to_string()))?;
-
-            old_tuple.values().to_vec()
-        };
-
-        let columns: Vec<crate::table::Column> = {
-            let heap_table = self
-                .tables
-                .get(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let table_arc = heap_table.table();
-            table_arc.columns().iter().map(|c| c.clone()).collect()
-        };
-
-        let index_ids: Vec<u64> = {
-            let heap_table = self
-                .tables
-                .get(table)
-                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-            let table_arc = heap_table.table();
-            let table_id = table_arc.table_id();
-
-            let indexes = self.index_mgr.get_table_indexes(table_id);
-            indexes.iter().map(|m| m.id).collect()
-        };
-
-        for id in index_ids {
-            if let Err(e) = self.index_mgr.delete(id, &old_values, &columns, row_id) {
-                return Err(StorageError::Other(format!("Index delete failed: {}", e)));
-            }
-        }
-
-        let heap_table = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-        heap_table
-            .delete(row_id)
-            .map_err(|e| StorageError::Other(e.to_string()))
+        self.relations().delete_row(0, table, row_id)
     }
 
     /// Delete a row with transaction (acquires X lock)
@@ -500,29 +471,10 @@ impl StorageEngine {
         row_id: RowId,
     ) -> StorageResult<()> {
         // Acquire X lock on row
-        self.lock_mgr
-            .lock_row(
-                tx_id,
-                table,
-                row_id.page_id,
-                row_id.slot_idx,
-                LockMode::Exclusive,
-            )
-            .map_err(|e| match e {
-                crate::lock::LockError::Timeout => StorageError::LockTimeout,
-                crate::lock::LockError::Deadlock => StorageError::Deadlock,
-                _ => StorageError::Other(e.to_string()),
-            })?;
+        self.lock_row_exclusive(tx_id, table, row_id)?;
 
         // Perform delete
-        let heap_table = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
-
-        heap_table
-            .delete(row_id)
-            .map_err(|e| StorageError::Other(e.to_string()))
+        self.relations().delete_row(tx_id, table, row_id)
     }
 
     /// Get table info
@@ -534,16 +486,22 @@ impl StorageEngine {
 
     /// List all tables
     pub fn list_tables(&self) -> Vec<String> {
-        self.tables.keys().cloned().collect()
+        self.relations().tables.keys().cloned().collect()
     }
 
     /// Begin a new transaction
-    pub fn begin_transaction(&mut self) -> TransactionId {
+    ///
+    /// Fails, leaving no transaction behind, if its begin record cannot be
+    /// logged.
+    pub fn begin_transaction(&mut self) -> StorageResult<TransactionId> {
         let tx_id = self.lock_mgr.begin();
-        if let Some(ref wal) = self.wal {
-            wal.tx_begin(tx_id);
+        if let Some(ref wal) = self.wal
+            && let Err(e) = wal.tx_begin(tx_id)
+        {
+            let _ = self.lock_mgr.abort(tx_id);
+            return Err(StorageError::Other(e.to_string()));
         }
-        tx_id
+        Ok(tx_id)
     }
 
     /// Commit a transaction
@@ -559,11 +517,12 @@ impl StorageEngine {
         })
     }
 
-    /// Abort a transaction
+    /// Abort a transaction, undoing its changes before its locks are
+    /// released
     pub fn abort(&mut self, tx_id: TransactionId) -> StorageResult<()> {
         if let Some(ref wal) = self.wal {
-            wal.abort(tx_id)
-                .map_err(|e| StorageError::Other(e.to_string()))?;
+            let last_lsn = wal.last_lsn(tx_id);
+            self.rollback(tx_id, last_lsn)?;
         }
         self.lock_mgr.abort(tx_id).map_err(|e| match e {
             crate::lock::LockError::Timeout => StorageError::LockTimeout,
@@ -572,23 +531,134 @@ impl StorageEngine {
         })
     }
 
-    /// Flush all dirty pages to disk
+    /// Undoes the logged changes of `tx_id`, newest first from `last_lsn`,
+    /// and writes its abort record
+    fn rollback(&mut self, tx_id: TransactionId, last_lsn: LSN) -> StorageResult<()> {
+        let Some(wal) = self.wal.clone() else {
+            return Ok(());
+        };
+        let mut relations = self.relations();
+        let Relations { tables, index_mgr } = &mut *relations;
+        wal.rollback(tx_id, last_lsn, |_, record| {
+            undo_record(tables, index_mgr, tx_id, record)
+        })
+        .map_err(|e| StorageError::Other(e.to_string()))?;
+        wal.abort(tx_id)
+            .map_err(|e| StorageError::Other(e.to_string()))
+    }
+
+    /// Reclaim the space of a table's deleted rows
+    ///
+    /// Rows deleted by a transaction that is still running are left for a
+    /// later vacuum. Index entries still pointing at a reclaimed row are
+    /// dropped with it.
+    pub fn vacuum(&mut self, table: &str) -> StorageResult<VacuumStats> {
+        self.relations().vacuum(table, &self.lock_mgr)
+    }
+
+    /// Statistics of the last vacuum of a table since the engine was opened
+    pub fn last_vacuum(&self, table: &str) -> StorageResult<Option<VacuumStats>> {
+        self.relations()
+            .tables
+            .get(table)
+            .map(|t| t.last_vacuum())
+            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))
+    }
+
+    /// Returns the error of the last background flush, autovacuum or
+    /// checkpoint that failed, if any, and clears it
+    ///
+    /// Background threads keep running after a failed round; this is where
+    /// their errors surface.
+    pub fn take_background_error(&self) -> Option<StorageError> {
+        self.flusher
+            .take_error()
+            .map(|e| StorageError::Other(e.to_string()))
+            .or_else(|| self.autovacuum.take_error())
+            .or_else(|| self.checkpointer.take_error())
+    }
+
+    /// Flush all dirty pages to disk and take a checkpoint
+    ///
+    /// The WAL is forced first so no page reaches disk ahead of its log
+    /// records; once every page is written, recovery can start from here.
+    /// The checkpoint thread does the same every `checkpoint_interval_sec`.
     pub fn flush(&mut self) -> StorageResult<()> {
-        for heap_table in self.tables.values_mut() {
-            heap_table
-                .flush()
-                .map_err(|e| StorageError::Other(e.to_string()))?;
-        }
-        Ok(())
+        checkpoint(&self.relations, self.wal.as_deref())
     }
 
-    /// Create an index on a table
+    /// Create an index on a table, indexing the rows it already holds
+    ///
+    /// Fails without leaving the index behind if a unique index finds a
+    /// duplicate key.
     pub fn create_index(
         &mut self,
         table: &str,
         name: &str,
         columns: Vec<String>,
         unique: bool,
+    ) -> StorageResult<u64> {
+        self.build_index(table, name, columns, Vec::new(), unique, &mut |_| {})
+    }
+
+    /// Create an index with per-column sort direction and NULL placement
+    pub fn create_index_with_orders(
+        &mut self,
+        table: &str,
+        name: &str,
+        columns: Vec<String>,
+        orders: Vec<ColumnOrder>,
+        unique: bool,
+    ) -> StorageResult<u64> {
+        self.build_index(table, name, columns, orders, unique, &mut |_| {})
+    }
+
+    /// Create an index, reporting build progress through `progress`
+    pub fn create_index_with_progress(
+        &mut self,
+        table: &str,
+        name: &str,
+        columns: Vec<String>,
+        unique: bool,
+        progress: &mut dyn FnMut(&IndexBuildProgress),
+    ) -> StorageResult<u64> {
+        self.build_index(table, name, columns, Vec::new(), unique, progress)
+    }
+
+    fn build_index(
+        &mut self,
+        table: &str,
+        name: &str,
+        columns: Vec<String>,
+        orders: Vec<ColumnOrder>,
+        unique: bool,
+        progress: &mut dyn FnMut(&IndexBuildProgress),
+    ) -> StorageResult<u64> {
+        let index_id = self.begin_online_index_build(table, name, columns, orders, unique)?;
+        let scanned = self
+            .relations()
+            .scan_for_index_build(index_id, usize::MAX, progress);
+        if let Err(e) = scanned {
+            let _ = self.relations().index_mgr.drop_index(index_id);
+            return Err(e);
+        }
+        self.finish_online_index_build(index_id, progress)?;
+        Ok(index_id)
+    }
+
+    /// Start an online index build
+    ///
+    /// The index is registered right away so that inserts, updates and
+    /// deletes on the table are captured, but it cannot be queried until
+    /// `finish_online_index_build` returns. Existing rows are read with
+    /// `online_index_build_step`, which may be interleaved with DML.
+    pub fn begin_online_index_build(
+        &mut self,
+        table: &str,
+        name: &str,
+        columns: Vec<String>,
+        orders: Vec<ColumnOrder>,
+        unique: bool,
     ) -> StorageResult<u64> {
         let table_arc = self
             .catalog
@@ -598,25 +668,1770 @@ impl StorageEngine {
         let table = table_arc.as_ref();
         let table_id = table.table_id();
 
-        let index_id = self
+        let mut relations = self.relations();
+        let index_id = relations
             .index_mgr
-            .create_index(table_id, name.to_string(), columns, unique)
+            .create_index_with_orders(table_id, name.to_string(), columns, orders, unique)
             .map_err(|e| StorageError::Other(e.to_string()))?;
 
+        if let Err(e) = relations.index_mgr.begin_build(index_id) {
+            let _ = relations.index_mgr.drop_index(index_id);
+            return Err(StorageError::Other(e.to_string()));
+        }
+
         Ok(index_id)
     }
 
+    /// Scan up to `max_pages` more heap pages into an online build
+    ///
+    /// Returns true once every page of the table has been scanned.
+    pub fn online_index_build_step(
+        &mut self,
+        index_id: u64,
+        max_pages: usize,
+    ) -> StorageResult<bool> {
+        self.relations()
+            .scan_for_index_build(index_id, max_pages, &mut |_| {})
+    }
+
+    /// Load the scanned rows and the changes captured since the build began
+    ///
+    /// Pages not yet visited by `online_index_build_step` are scanned first.
+    /// On failure, such as a duplicate key in a unique index, the index is
+    /// dropped.
+    pub fn finish_online_index_build(
+        &mut self,
+        index_id: u64,
+        progress: &mut dyn FnMut(&IndexBuildProgress),
+    ) -> StorageResult<()> {
+        let mut relations = self.relations();
+        let result = relations
+            .scan_for_index_build(index_id, usize::MAX, progress)
+            .and_then(|_| {
+                relations
+                    .index_mgr
+                    .finish_build(index_id, progress)
+                    .map_err(|e| StorageError::Other(e.to_string()))
+            });
+        if result.is_err() {
+            let _ = relations.index_mgr.drop_index(index_id);
+        }
+        result
+    }
+
     /// Drop an index
     pub fn drop_index(&mut self, index_id: u64) -> StorageResult<()> {
-        self.index_mgr
+        self.relations()
+            .index_mgr
             .drop_index(index_id)
             .map_err(|e| StorageError::Other(e.to_string()))
     }
 
     /// Lookup by index
     pub fn lookup_index(&self, index_id: u64, values: &[Value]) -> StorageResult<Vec<RowId>> {
-        self.index_mgr
-            .lookup(index_id, values, &[])
+        self.relations()
+            .index_mgr
+            .lookup(index_id, values)
+            .map_err(|e| StorageError::Other(e.to_string()))
+    }
+
+    /// Range scan on an index, returning matching row ids in key order
+    ///
+    /// `lower` / `upper` hold values for a leading subset of the index
+    /// columns; `None` leaves that side open. Call `.rev()` on the result
+    /// to walk the range in descending order. The row ids are collected
+    /// while the index is locked, so changes made after the call, by the
+    /// caller or by autovacuum, do not show up in the iterator.
+    pub fn lookup_range(
+        &self,
+        index_id: u64,
+        lower: Option<&[Value]>,
+        upper: Option<&[Value]>,
+        lower_inclusive: bool,
+        upper_inclusive: bool,
+    ) -> StorageResult<impl DoubleEndedIterator<Item = RowId> + use<>> {
+        let relations = self.relations();
+        let row_ids = relations
+            .index_mgr
+            .lookup_range(index_id, lower, upper, lower_inclusive, upper_inclusive)
+            .and_then(|scan| scan.collect::<IndexResult<Vec<RowId>>>())
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+        Ok(row_ids.into_iter())
+    }
+
+    /// Prefix scan on a composite index: every row whose leading index
+    /// columns equal `prefix`
+    pub fn lookup_prefix(
+        &self,
+        index_id: u64,
+        prefix: &[Value],
+    ) -> StorageResult<impl DoubleEndedIterator<Item = RowId> + use<>> {
+        self.lookup_range(index_id, Some(prefix), Some(prefix), true, true)
+    }
+}
+
+impl Relations {
+    /// Inserts a row into the heap and its indexes, logging on behalf of
+    /// `tx_id` (0 outside a transaction)
+    fn insert_row(
+        &mut self,
+        tx_id: TransactionId,
+        table: &str,
+        values: Vec<Value>,
+    ) -> StorageResult<RowId> {
+        let heap_table = self
+            .tables
+            .get_mut(table)
+            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+        let row_id = heap_table
+            .insert(tx_id, &values)
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+
+        self.maintain_index_insert(tx_id, table, &values, row_id)?;
+
+        Ok(row_id)
+    }
+
+    fn maintain_index_insert(
+        &mut self,
+        tx_id: TransactionId,
+        table: &str,
+        values: &[Value],
+        row_id: RowId,
+    ) -> StorageResult<()> {
+        let (columns, index_ids) = {
+            let heap_table = self
+                .tables
+                .get(table)
+                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+            let table_arc = heap_table.table();
+            let table_id = table_arc.table_id();
+            let columns: Vec<crate::table::Column> =
+                table_arc.columns().iter().map(|c| c.clone()).collect();
+
+            let indexes = self.index_mgr.get_table_indexes(table_id);
+            let index_ids: Vec<u64> = indexes.iter().map(|m| m.id).collect();
+
+            (columns, index_ids)
+        };
+
+        for id in index_ids {
+            if let Err(e) = self.index_mgr.insert(tx_id, id, values, &columns, row_id) {
+                return Err(StorageError::Other(format!("Index insert failed: {}", e)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the index entries of a row and returns its old values
+    fn maintain_index_delete(
+        &mut self,
+        tx_id: TransactionId,
+        table: &str,
+        row_id: RowId,
+    ) -> StorageResult<Vec<Value>> {
+        let (old_values, columns) = {
+            let heap_table = self
+                .tables
+                .get_mut(table)
+                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+            let table_arc = heap_table.table();
+            let columns: Vec<crate::table::Column> =
+                table_arc.columns().iter().map(|c| c.clone()).collect();
+
+            let old_tuple = heap_table
+                .get(row_id)
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+
+            let old_values: Vec<Value> = old_tuple.values().to_vec();
+
+            (old_values, columns)
+        };
+
+        let index_ids: Vec<u64> = {
+            let heap_table = self
+                .tables
+                .get(table)
+                .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+            let table_arc = heap_table.table();
+            let table_id = table_arc.table_id();
+
+            let indexes = self.index_mgr.get_table_indexes(table_id);
+            indexes.iter().map(|m| m.id).collect()
+        };
+
+        for id in index_ids {
+            if let Err(e) = self
+                .index_mgr
+                .delete(tx_id, id, &old_values, &columns, row_id)
+            {
+                return Err(StorageError::Other(format!("Index delete failed: {}", e)));
+            }
+        }
+
+        Ok(old_values)
+    }
+
+    /// Replaces a row in the heap and its indexes, returning where the row
+    /// now lives
+    ///
+    /// Only the indexes whose key changed are touched; the heap keeps the
+    /// row's RowId, so the others still point at it. Unique indexes are
+    /// checked before the heap is changed, so a violation leaves the row
+    /// and its index entries as they were.
+    fn update_row(
+        &mut self,
+        tx_id: TransactionId,
+        table: &str,
+        row_id: RowId,
+        values: Vec<Value>,
+    ) -> StorageResult<RowId> {
+        let heap_table = self
+            .tables
+            .get_mut(table)
+            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+        let old_values = heap_table
+            .get(row_id)
+            .map_err(|e| StorageError::Other(e.to_string()))?
+            .values()
+            .to_vec();
+
+        let table_arc = Arc::clone(heap_table.table());
+        let columns = table_arc.columns();
+        let mut changed = Vec::new();
+        for meta in self.index_mgr.get_table_indexes(table_arc.table_id()) {
+            let key_changed = self
+                .index_mgr
+                .key_changed(meta.id, &old_values, &values, columns)
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+            if key_changed {
+                changed.push(meta.id);
+            }
+        }
+        for &id in &changed {
+            self.index_mgr
+                .check_insert(id, &values, columns, row_id)
+                .map_err(|e| StorageError::Other(format!("Index insert failed: {}", e)))?;
+        }
+
+        let new_row_id = heap_table
+            .update(tx_id, row_id, &values)
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+
+        for id in changed {
+            self.index_mgr
+                .delete(tx_id, id, &old_values, columns, row_id)
+                .map_err(|e| StorageError::Other(format!("Index delete failed: {}", e)))?;
+            self.index_mgr
+                .insert(tx_id, id, &values, columns, new_row_id)
+                .map_err(|e| StorageError::Other(format!("Index insert failed: {}", e)))?;
+        }
+
+        Ok(new_row_id)
+    }
+
+    /// Removes a row from its indexes and the heap
+    fn delete_row(
+        &mut self,
+        tx_id: TransactionId,
+        table: &str,
+        row_id: RowId,
+    ) -> StorageResult<()> {
+        self.maintain_index_delete(tx_id, table, row_id)?;
+
+        let heap_table = self
+            .tables
+            .get_mut(table)
+            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+        heap_table
+            .delete(tx_id, row_id)
+            .map_err(|e| StorageError::Other(e.to_string()))
+    }
+
+    fn scan_for_index_build(
+        &mut self,
+        index_id: u64,
+        max_pages: usize,
+        progress: &mut dyn FnMut(&IndexBuildProgress),
+    ) -> StorageResult<bool> {
+        let table_id = self
+            .index_mgr
+            .get_index(index_id)
+            .map(|m| m.table_id)
+            .ok_or_else(|| StorageError::Other(format!("Index not found: {}", index_id)))?;
+        let next_page = self.index_mgr.build_cursor(index_id).ok_or_else(|| {
+            StorageError::Other(format!("Index {} has no build in progress", index_id))
+        })?;
+        let heap_table = self
+            .tables
+            .values_mut()
+            .find(|h| h.table().table_id() == table_id)
+            .ok_or_else(|| StorageError::TableNotFound(table_id.to_string()))?;
+        let columns: Vec<Column> = heap_table.table().columns().to_vec();
+
+        let page_ids = heap_table.page_ids();
+        let total = page_ids.len() as u64;
+        let mut done = page_ids.iter().filter(|&&id| id < next_page).count();
+        let pending: Vec<PageId> = page_ids
+            .into_iter()
+            .filter(|&id| id >= next_page)
+            .take(max_pages)
+            .collect();
+
+        for page_id in pending {
+            let rows: Vec<(RowId, Vec<Value>)> = heap_table
+                .scan_page(page_id)
+                .map_err(|e| StorageError::Other(e.to_string()))?
+                .into_iter()
+                .map(|(rid, tuple)| (rid, tuple.values().to_vec()))
+                .collect();
+            self.index_mgr
+                .add_build_rows(index_id, &rows, &columns)
+                .and_then(|_| self.index_mgr.set_build_cursor(index_id, page_id + 1))
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+            done += 1;
+            progress(&IndexBuildProgress::new(
+                IndexBuildPhase::Scan,
+                done as u64,
+                total,
+            ));
+        }
+
+        Ok(done as u64 == total)
+    }
+
+    /// Vacuums one table; see `StorageEngine::vacuum`
+    fn vacuum(&mut self, table: &str, lock_mgr: &LockManager) -> StorageResult<VacuumStats> {
+        let heap_table = self
+            .tables
+            .get_mut(table)
+            .ok_or_else(|| StorageError::TableNotFound(table.to_string()))?;
+
+        let table_arc = Arc::clone(heap_table.table());
+        let columns = table_arc.columns();
+        let index_ids: Vec<u64> = self
+            .index_mgr
+            .get_table_indexes(table_arc.table_id())
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        let index_mgr = &mut self.index_mgr;
+
+        heap_table
+            .vacuum(
+                |tx_id| lock_mgr.is_active(tx_id),
+                |row_id, tuple| {
+                    for &id in &index_ids {
+                        index_mgr
+                            .purge(id, tuple.values(), columns, row_id)
+                            .map_err(|e| HeapError::Other(e.to_string()))?;
+                    }
+                    Ok(())
+                },
+            )
             .map_err(|e| StorageError::Other(e.to_string()))
     }
 }
+
+/// Writes every dirty heap and index page and takes a checkpoint
+///
+/// The heaps and indexes stay locked from the WAL flush to the checkpoint,
+/// so no change slips in between.
+fn checkpoint(relations: &Mutex<Relations>, wal: Option<&WalManager>) -> StorageResult<()> {
+    let mut relations = relations.lock().unwrap();
+    if let Some(wal) = wal {
+        wal.flush()
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+    }
+    for heap_table in relations.tables.values_mut() {
+        heap_table
+            .flush()
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+    }
+    relations
+        .index_mgr
+        .flush()
+        .map_err(|e| StorageError::Other(e.to_string()))?;
+    if let Some(wal) = wal {
+        wal.checkpoint(Vec::new())
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Vacuums the tables that are due under `config`, on the autovacuum
+/// thread
+///
+/// The heaps and indexes are locked one table at a time. A vacuum that
+/// fails is left for the next round; the other tables are still vacuumed
+/// and the first error is returned.
+fn autovacuum_round(
+    relations: &Mutex<Relations>,
+    lock_mgr: &LockManager,
+    config: &AutovacuumConfig,
+) -> StorageResult<()> {
+    let due: Vec<String> = relations
+        .lock()
+        .unwrap()
+        .tables
+        .iter()
+        .filter(|(_, t)| t.needs_vacuum(config))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut result = Ok(());
+    for table in due {
+        let mut relations = relations.lock().unwrap();
+        // Dropped, or vacuumed on request, since the list was made
+        if !relations
+            .tables
+            .get(&table)
+            .is_some_and(|t| t.needs_vacuum(config))
+        {
+            continue;
+        }
+        if let Err(e) = relations.vacuum(&table, lock_mgr)
+            && result.is_ok()
+        {
+            result = Err(StorageError::Other(format!(
+                "autovacuum of {} failed: {}",
+                table, e
+            )));
+        }
+    }
+    result
+}
+
+/// Reverses one logged change of `tx_id` through the heap or index that
+/// made it
+fn undo_record(
+    tables: &mut HashMap<String, HeapTable>,
+    index_mgr: &mut IndexManager,
+    tx_id: TransactionId,
+    record: &LogRecord,
+) -> Result<(), String> {
+    match record.header.log_type {
+        LogType::IndexInsert | LogType::IndexDelete => {
+            index_mgr.undo(tx_id, record).map_err(|e| e.to_string())
+        }
+        _ => {
+            let Some(page_id) = record.payload().and_then(|p| p.page_id()) else {
+                return Ok(());
+            };
+            // Nothing to undo in a table that has been dropped since
+            match tables
+                .values_mut()
+                .find(|t| t.segment_id() == page_id >> 32)
+            {
+                Some(heap_table) => heap_table.undo(tx_id, record).map_err(|e| e.to_string()),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Applies one WAL record to the buffer pool during recovery
+///
+/// Heap records and their compensation records are repeated logically;
+/// page images and page redo records are copied in. Either way a page is
+/// only touched when its LSN is older than the record's, except for a
+/// full-page image: it replaces the page without reading it, since the
+/// copy on disk may be torn and fail its checksum. Index entry changes
+/// are collected in `index_changes`, to be repeated by
+/// `IndexManager::redo` once the page images have put the trees back.
+fn redo_record(
+    buffer_mgr: &BufferMgr,
+    lsn: LSN,
+    record: &LogRecord,
+    index_changes: &mut Vec<LogRecord>,
+) -> Result<(), String> {
+    match record.payload() {
+        Some(LogPayload::IndexInsert(_)) | Some(LogPayload::IndexDelete(_)) => {
+            index_changes.push(record.clone());
+            Ok(())
+        }
+        Some(LogPayload::Compensation(clr))
+            if matches!(
+                clr.action,
+                CompensationAction::IndexInsert(_) | CompensationAction::IndexDelete(_)
+            ) =>
+        {
+            index_changes.push(record.clone());
+            Ok(())
+        }
+        Some(LogPayload::HeapInsert(_))
+        | Some(LogPayload::HeapUpdate(_))
+        | Some(LogPayload::HeapDelete(_))
+        | Some(LogPayload::Compensation(_)) => {
+            HeapTable::redo(buffer_mgr, lsn, record).map_err(|e| e.to_string())
+        }
+        Some(LogPayload::FullPageImage(image)) => {
+            let mut page = buffer_mgr
+                .new_page(image.page_id)
+                .map_err(|e| e.to_string())?;
+            image.restore(page.as_bytes_mut());
+            page.header_mut().set_lsn(lsn.raw());
+            Ok(())
+        }
+        Some(LogPayload::PageRedo(redo)) => redo_page(buffer_mgr, lsn, redo.page_id, |page| {
+            let offset = redo.offset as usize;
+            let len = redo.data.len().min(page.len().saturating_sub(offset));
+            page[offset..offset + len].copy_from_slice(&redo.data[..len]);
+        }),
+        Some(_) => Ok(()),
+        None => Err(format!("undecodable {:?} record", record.header.log_type)),
+    }
+}
+
+fn redo_page(
+    buffer_mgr: &BufferMgr,
+    lsn: LSN,
+    page_id: PageId,
+    apply: impl FnOnce(&mut [u8]),
+) -> Result<(), String> {
+    let mut page = buffer_mgr.write_page(page_id).map_err(|e| e.to_string())?;
+    if page.header().glsn < lsn.raw() {
+        apply(page.as_bytes_mut());
+        page.header_mut().set_lsn(lsn.raw());
+    }
+    Ok(())
+}
+
+impl Drop for StorageEngine {
+    fn drop(&mut self) {
+        self.checkpointer.stop();
+        self.autovacuum.stop();
+        self.flusher.stop();
+        let _ = self.flush();
+        self.warmup.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::FlushPolicy;
+    use crate::types::ColumnType;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    fn create_test_engine(dir: &TempDir) -> StorageEngine {
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        engine
+            .create_table(
+                "orders",
+                vec![
+                    Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                    Column::new("customer".to_string(), ColumnType::Int64, false, 1),
+                ],
+            )
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_create_index_backfills_existing_rows() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = create_test_engine(&dir);
+        let mut row_ids = Vec::new();
+        for i in 0..2000 {
+            let rid = engine
+                .insert("orders", vec![Value::Int64(i), Value::Int64(i % 10)])
+                .unwrap();
+            row_ids.push(rid);
+        }
+
+        let mut reports = Vec::new();
+        let index_id = engine
+            .create_index_with_progress(
+                "orders",
+                "idx_id",
+                vec!["id".to_string()],
+                true,
+                &mut |p| reports.push(*p),
+            )
+            .unwrap();
+        let phases: Vec<IndexBuildPhase> = reports.iter().map(|p| p.phase).collect();
+        assert_eq!(phases.first(), Some(&IndexBuildPhase::Scan));
+        assert_eq!(phases.last(), Some(&IndexBuildPhase::Load));
+        let scan_end = reports
+            .iter()
+            .rfind(|p| p.phase == IndexBuildPhase::Scan)
+            .unwrap();
+        assert_eq!(scan_end.done, scan_end.total);
+
+        for i in (0..2000).step_by(7) {
+            let rows = engine.lookup_index(index_id, &[Value::Int64(i)]).unwrap();
+            assert_eq!(rows, vec![row_ids[i as usize]]);
+        }
+
+        let by_customer = engine
+            .create_index(
+                "orders",
+                "idx_customer",
+                vec!["customer".to_string()],
+                false,
+            )
+            .unwrap();
+        let rows = engine
+            .lookup_index(by_customer, &[Value::Int64(3)])
+            .unwrap();
+        assert_eq!(rows.len(), 200);
+    }
+
+    #[test]
+    fn test_create_unique_index_rejects_duplicates() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = create_test_engine(&dir);
+        for i in 0..50 {
+            engine
+                .insert("orders", vec![Value::Int64(i), Value::Int64(i % 10)])
+                .unwrap();
+        }
+
+        let err = engine
+            .create_index("orders", "idx_customer", vec!["customer".to_string()], true)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Duplicate key in unique index idx_customer: (customer)=(Int64(0))"));
+        assert!(engine
+            .relations()
+            .index_mgr
+            .get_index_by_name("idx_customer")
+            .is_none());
+    }
+
+    #[test]
+    fn test_online_index_build_with_concurrent_dml() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = create_test_engine(&dir);
+        let mut row_ids = Vec::new();
+        for i in 0..3000 {
+            let rid = engine
+                .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                .unwrap();
+            row_ids.push(rid);
+        }
+
+        let index_id = engine
+            .begin_online_index_build("orders", "idx_id", vec!["id".to_string()], Vec::new(), true)
+            .unwrap();
+        assert!(!engine.online_index_build_step(index_id, 1).unwrap());
+
+        let new_row = engine
+            .insert("orders", vec![Value::Int64(10_000), Value::Int64(0)])
+            .unwrap();
+        engine.delete("orders", row_ids[5]).unwrap();
+        engine.delete("orders", row_ids[2999]).unwrap();
+        assert!(engine.lookup_index(index_id, &[Value::Int64(0)]).is_err());
+
+        while !engine.online_index_build_step(index_id, 1).unwrap() {}
+        engine
+            .finish_online_index_build(index_id, &mut |_| {})
+            .unwrap();
+
+        let lookup = |i: i64| engine.lookup_index(index_id, &[Value::Int64(i)]).unwrap();
+        assert_eq!(lookup(0), vec![row_ids[0]]);
+        assert_eq!(lookup(10_000), vec![new_row]);
+        assert!(lookup(5).is_empty());
+        assert!(lookup(2999).is_empty());
+        assert_eq!(lookup(2998), vec![row_ids[2998]]);
+    }
+
+    #[test]
+    fn test_reopen_restores_tables_and_indexes() {
+        let dir = TempDir::new().unwrap();
+        let mut row_ids = Vec::new();
+        let index_id;
+        {
+            let mut engine = create_test_engine(&dir);
+            engine
+                .create_table(
+                    "customers",
+                    vec![Column::new("id".to_string(), ColumnType::Int64, false, 0)],
+                )
+                .unwrap();
+            for i in 0..3000 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(i % 10)])
+                    .unwrap();
+                row_ids.push(rid);
+            }
+            engine.insert("customers", vec![Value::Int64(7)]).unwrap();
+            index_id = engine
+                .create_index("orders", "idx_id", vec!["id".to_string()], true)
+                .unwrap();
+            engine.delete("orders", row_ids[10]).unwrap();
+        }
+
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        assert!(engine.table_exists("orders"));
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 2999);
+        let customers = engine.scan_all("customers").unwrap();
+        assert_eq!(customers.len(), 1);
+        assert_eq!(customers[0].values(), &[Value::Int64(7)]);
+
+        let rows = engine
+            .lookup_index(index_id, &[Value::Int64(1234)])
+            .unwrap();
+        assert_eq!(rows, vec![row_ids[1234]]);
+        let tuple = engine.get_row("orders", rows[0]).unwrap();
+        assert_eq!(tuple.values(), &[Value::Int64(1234), Value::Int64(4)]);
+        assert!(engine
+            .lookup_index(index_id, &[Value::Int64(10)])
+            .unwrap()
+            .is_empty());
+
+        // New rows keep going after the reopen, into the same segment
+        let rid = engine
+            .insert("orders", vec![Value::Int64(5000), Value::Int64(0)])
+            .unwrap();
+        assert_eq!(rid.page_id >> 32, row_ids[0].page_id >> 32);
+        // Only the deleted row's slot may be reused
+        assert!(!row_ids[..10].contains(&rid) && !row_ids[11..].contains(&rid));
+        assert_eq!(
+            engine
+                .lookup_index(index_id, &[Value::Int64(5000)])
+                .unwrap(),
+            vec![rid]
+        );
+        assert!(engine.create_table("orders", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_buffer_warmup_saves_resident_pages() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(WARMUP_FILE_NAME);
+        {
+            let mut engine = create_test_engine(&dir);
+            for i in 0..100 {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+            }
+        }
+        let vfs = Arc::new(crate::vfs::LocalFs::new());
+        let saved = WarmupFile::new(vfs, path.clone()).read().unwrap();
+        assert!(!saved.is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        let mut engine =
+            StorageEngine::new_with_warmup(dir.path(), WarmupConfig::disabled()).unwrap();
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 100);
+        drop(engine);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_inserts_reuse_free_space_after_the_map_is_rebuilt() {
+        let dir = TempDir::new().unwrap();
+        let row = |i: i64, len: usize| vec![Value::Int64(i), Value::VarChar("x".repeat(len))];
+        let (first_page, segment_id) = {
+            let mut engine = StorageEngine::new(dir.path()).unwrap();
+            engine
+                .create_table(
+                    "docs",
+                    vec![
+                        Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                        Column::new("body".to_string(), ColumnType::Varchar(2000), true, 1),
+                    ],
+                )
+                .unwrap();
+            // Seven rows fill most of the first page, the eighth starts a
+            // second one and leaves a gap behind
+            for i in 0..8 {
+                engine.insert("docs", row(i, 1000)).unwrap();
+            }
+            let relations = engine.relations();
+            let heap = &relations.tables["docs"];
+            assert_eq!(heap.page_ids().len(), 2);
+            (heap.first_page_id(), heap.segment_id())
+        };
+
+        std::fs::remove_file(dir.path().join(format!("page_{}_fsm.dat", segment_id))).unwrap();
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        let rid = engine.insert("docs", row(8, 500)).unwrap();
+        assert_eq!(rid.page_id, first_page);
+        assert_eq!(engine.relations().tables["docs"].page_ids().len(), 2);
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_update_that_outgrows_its_page_keeps_the_row_id() {
+        let dir = TempDir::new().unwrap();
+        let row = |i: i64, len: usize| vec![Value::Int64(i), Value::VarChar("x".repeat(len))];
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        engine
+            .create_table(
+                "docs",
+                vec![
+                    Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                    Column::new("body".to_string(), ColumnType::Varchar(2000), true, 1),
+                ],
+            )
+            .unwrap();
+        let index_id = engine
+            .create_index("docs", "idx_id", vec!["id".to_string()], true)
+            .unwrap();
+        let row_ids: Vec<RowId> = (0..7)
+            .map(|i| engine.insert("docs", row(i, 1000)).unwrap())
+            .collect();
+
+        // No longer fits on the full first page, moves and stays reachable
+        // under the same RowId, twice over
+        for len in [1900, 1950, 100] {
+            let rid = engine.update("docs", row_ids[2], row(2, len)).unwrap();
+            assert_eq!(rid, row_ids[2]);
+            let tuple = engine.get_row("docs", rid).unwrap();
+            assert_eq!(tuple.values(), &row(2, len)[..]);
+            assert_eq!(
+                engine.lookup_index(index_id, &[Value::Int64(2)]).unwrap(),
+                vec![rid]
+            );
+            assert_eq!(engine.scan_all("docs").unwrap().len(), 7);
+        }
+
+        // Changing the key re-points the index at the same RowId
+        engine.update("docs", row_ids[2], row(20, 1900)).unwrap();
+        assert!(engine
+            .lookup_index(index_id, &[Value::Int64(2)])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            engine.lookup_index(index_id, &[Value::Int64(20)]).unwrap(),
+            vec![row_ids[2]]
+        );
+        // A build reads the moved row through its pointer
+        let rebuilt = engine
+            .create_index("docs", "idx_id_again", vec!["id".to_string()], true)
+            .unwrap();
+        assert_eq!(
+            engine.lookup_index(rebuilt, &[Value::Int64(20)]).unwrap(),
+            vec![row_ids[2]]
+        );
+
+        // Rolling back a move puts the row back in its slot
+        let tx = engine.begin_transaction().unwrap();
+        engine
+            .update_with_tx(tx, "docs", row_ids[4], row(4, 1990))
+            .unwrap();
+        engine.abort(tx).unwrap();
+        assert_eq!(
+            engine.get_row("docs", row_ids[4]).unwrap().values(),
+            &row(4, 1000)[..]
+        );
+
+        engine.delete("docs", row_ids[2]).unwrap();
+        assert!(engine.get_row("docs", row_ids[2]).is_err());
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_unique_violation_leaves_the_updated_row_alone() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        engine
+            .create_table(
+                "users",
+                vec![
+                    Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                    Column::new("email".to_string(), ColumnType::Varchar(100), false, 1),
+                ],
+            )
+            .unwrap();
+        let id_index = engine
+            .create_index("users", "idx_id", vec!["id".to_string()], true)
+            .unwrap();
+        let email_index = engine
+            .create_index("users", "idx_email", vec!["email".to_string()], true)
+            .unwrap();
+        let user = |i: i64, email: &str| vec![Value::Int64(i), Value::VarChar(email.to_string())];
+        let first = engine.insert("users", user(1, "a@x")).unwrap();
+        let second = engine.insert("users", user(2, "b@x")).unwrap();
+
+        // The new id is free but the email is taken
+        let err = engine.update("users", second, user(3, "a@x")).unwrap_err();
+        assert!(err.to_string().contains("idx_email"));
+        assert_eq!(
+            engine.get_row("users", second).unwrap().values(),
+            &user(2, "b@x")[..]
+        );
+        assert_eq!(
+            engine.lookup_index(id_index, &[Value::Int64(2)]).unwrap(),
+            vec![second]
+        );
+        assert!(engine
+            .lookup_index(id_index, &[Value::Int64(3)])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            engine
+                .lookup_index(email_index, &[Value::VarChar("a@x".to_string())])
+                .unwrap(),
+            vec![first]
+        );
+
+        // Keeping its own key is not a violation
+        engine.update("users", second, user(3, "b@x")).unwrap();
+        assert_eq!(
+            engine.lookup_index(id_index, &[Value::Int64(3)]).unwrap(),
+            vec![second]
+        );
+    }
+
+    #[test]
+    fn test_vacuum_reclaims_deleted_rows() {
+        let dir = TempDir::new().unwrap();
+        let row = |i: i64, len: usize| vec![Value::Int64(i), Value::VarChar("x".repeat(len))];
+        let config = AistoreConfig::new(dir.path()).with_autovacuum(AutovacuumConfig::disabled());
+        let mut engine = StorageEngine::open(config).unwrap();
+        create_docs_table(&mut engine);
+        let index_id = engine
+            .create_index("docs", "idx_id", vec!["id".to_string()], true)
+            .unwrap();
+        let row_ids: Vec<RowId> = (0..70)
+            .map(|i| engine.insert("docs", row(i, 1000)).unwrap())
+            .collect();
+        let last_page = row_ids.iter().map(|rid| rid.page_id).max().unwrap();
+
+        // A moved row leaves a pointer and the row to reclaim
+        engine.update("docs", row_ids[3], row(3, 1990)).unwrap();
+        engine.delete("docs", row_ids[3]).unwrap();
+        for &rid in &row_ids[35..] {
+            engine.delete("docs", rid).unwrap();
+        }
+        // Deleted by a transaction that may still roll back
+        let tx = engine.begin_transaction().unwrap();
+        engine.delete_with_tx(tx, "docs", row_ids[5]).unwrap();
+
+        let stats = engine.vacuum("docs").unwrap();
+        assert_eq!(stats.tuples_removed, 37);
+        assert_eq!(stats.tuples_kept, 1);
+        assert!(stats.bytes_reclaimed > 36 * 1000);
+        assert!(stats.pages_truncated > 0);
+        assert_eq!(engine.last_vacuum("docs").unwrap(), Some(stats));
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 33);
+
+        engine.abort(tx).unwrap();
+        assert_eq!(
+            engine.lookup_index(index_id, &[Value::Int64(5)]).unwrap(),
+            vec![row_ids[5]]
+        );
+        let stats = engine.vacuum("docs").unwrap();
+        assert_eq!((stats.tuples_removed, stats.tuples_kept), (0, 0));
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 34);
+
+        // Freed slots and truncated pages are used again
+        let rid = engine.insert("docs", row(100, 1000)).unwrap();
+        assert!(rid.page_id < last_page);
+        assert!(engine.get_row("docs", row_ids[40]).is_err());
+        assert_eq!(
+            engine.lookup_index(index_id, &[Value::Int64(100)]).unwrap(),
+            vec![rid]
+        );
+        assert!(engine
+            .lookup_index(index_id, &[Value::Int64(40)])
+            .unwrap()
+            .is_empty());
+        for i in 200..240 {
+            engine.insert("docs", row(i, 1000)).unwrap();
+        }
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 75);
+    }
+
+    fn create_docs_table(engine: &mut StorageEngine) {
+        engine
+            .create_table(
+                "docs",
+                vec![
+                    Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                    Column::new("body".to_string(), ColumnType::Varchar(2000), true, 1),
+                ],
+            )
+            .unwrap();
+    }
+
+    fn heap_blocks(engine: &StorageEngine, table: &str) -> usize {
+        engine.relations().tables[table].page_ids().len()
+    }
+
+    #[test]
+    fn test_dead_rows_are_counted_once_and_given_back_by_rollback() {
+        let dir = TempDir::new().unwrap();
+        let row = |i: i64, len: usize| vec![Value::Int64(i), Value::VarChar("x".repeat(len))];
+        let config =
+            || AistoreConfig::new(dir.path()).with_autovacuum(AutovacuumConfig::disabled());
+        // Due once the table has `threshold` dead rows
+        let due = |engine: &StorageEngine, threshold: u64| {
+            engine.relations().tables["docs"].needs_vacuum(&AutovacuumConfig {
+                threshold,
+                scale_factor: 0.0,
+                ..AutovacuumConfig::default()
+            })
+        };
+        {
+            let mut engine = StorageEngine::open(config()).unwrap();
+            create_docs_table(&mut engine);
+            let row_ids: Vec<RowId> = (0..7)
+                .map(|i| engine.insert("docs", row(i, 1000)).unwrap())
+                .collect();
+
+            // A moved row is one dead row, not a pointer and a row
+            engine.update("docs", row_ids[0], row(0, 1990)).unwrap();
+            engine.delete("docs", row_ids[0]).unwrap();
+            assert!(due(&engine, 1));
+            assert!(!due(&engine, 2));
+
+            let tx = engine.begin_transaction().unwrap();
+            for &rid in &row_ids[1..4] {
+                engine.delete_with_tx(tx, "docs", rid).unwrap();
+            }
+            assert!(due(&engine, 4));
+            engine.abort(tx).unwrap();
+            assert!(!due(&engine, 2));
+        }
+
+        let engine = StorageEngine::open(config()).unwrap();
+        assert!(due(&engine, 1));
+        assert!(!due(&engine, 2));
+    }
+
+    #[test]
+    fn test_vacuum_leaves_the_heap_alone_while_a_tail_page_is_pinned() {
+        let dir = TempDir::new().unwrap();
+        let row = |i: i64| vec![Value::Int64(i), Value::VarChar("x".repeat(1000))];
+        let config = AistoreConfig::new(dir.path()).with_autovacuum(AutovacuumConfig::disabled());
+        let mut engine = StorageEngine::open(config).unwrap();
+        create_docs_table(&mut engine);
+        for i in 0..5 {
+            engine.insert("docs", row(i)).unwrap();
+        }
+        // Rolled back inserts leave the pages they extended the heap by empty
+        let tx = engine.begin_transaction().unwrap();
+        let last_page = (5..30)
+            .map(|i| engine.insert_with_tx(tx, "docs", row(i)).unwrap().page_id)
+            .max()
+            .unwrap();
+        engine.abort(tx).unwrap();
+        let blocks = heap_blocks(&engine, "docs");
+        assert!(blocks > 1);
+
+        {
+            let buffer_mgr = Arc::clone(&engine.buffer_mgr);
+            let _pinned = buffer_mgr.read_page(last_page).unwrap();
+            let stats = engine.vacuum("docs").unwrap();
+            assert_eq!(stats.pages_truncated, 0);
+            assert_eq!(heap_blocks(&engine, "docs"), blocks);
+        }
+        let stats = engine.vacuum("docs").unwrap();
+        assert_eq!(stats.pages_truncated as usize, blocks - 1);
+        assert_eq!(heap_blocks(&engine, "docs"), 1);
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_crash_after_vacuum_keeps_the_heap_truncated() {
+        let dir = TempDir::new().unwrap();
+        let row = |i: i64| vec![Value::Int64(i), Value::VarChar("x".repeat(1000))];
+        let config = || {
+            AistoreConfig::new(dir.path())
+                .with_flush_policy(FlushPolicy::Manual)
+                .with_autovacuum(AutovacuumConfig::disabled())
+        };
+        {
+            let mut engine = StorageEngine::open(config()).unwrap();
+            create_docs_table(&mut engine);
+            let row_ids: Vec<RowId> = (0..30)
+                .map(|i| engine.insert("docs", row(i)).unwrap())
+                .collect();
+            engine.flush().unwrap();
+
+            for &rid in &row_ids[5..] {
+                engine.delete("docs", rid).unwrap();
+            }
+            let stats = engine.vacuum("docs").unwrap();
+            assert!(stats.pages_truncated > 0);
+            assert_eq!(heap_blocks(&engine, "docs"), 1);
+            // The log is durable, the metapage is not
+            engine.wal.as_ref().unwrap().flush().unwrap();
+            std::mem::forget(engine);
+        }
+
+        let mut engine = StorageEngine::open(config()).unwrap();
+        assert_eq!(heap_blocks(&engine, "docs"), 1);
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 5);
+        for i in 30..40 {
+            engine.insert("docs", row(i)).unwrap();
+        }
+        assert_eq!(engine.scan_all("docs").unwrap().len(), 15);
+    }
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn autovacuum_every_10ms(dir: &TempDir) -> AistoreConfig {
+        AistoreConfig::new(dir.path()).with_autovacuum(AutovacuumConfig {
+            enabled: true,
+            naptime: Duration::from_millis(10),
+            threshold: 10,
+            scale_factor: 0.0,
+        })
+    }
+
+    #[test]
+    fn test_autovacuum_runs_once_enough_rows_are_dead() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = StorageEngine::open(autovacuum_every_10ms(&dir)).unwrap();
+        assert!(engine.autovacuum.is_running());
+        engine
+            .create_table(
+                "orders",
+                vec![
+                    Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                    Column::new("customer".to_string(), ColumnType::Int64, false, 1),
+                ],
+            )
+            .unwrap();
+        let row_ids: Vec<RowId> = (0..20)
+            .map(|i| {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(i)])
+                    .unwrap()
+            })
+            .collect();
+
+        for &rid in &row_ids[..9] {
+            engine.delete("orders", rid).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(engine.last_vacuum("orders").unwrap(), None);
+
+        // The tenth dead row makes the table due; the worker vacuums it
+        // without another call into the engine. It is paused meanwhile so
+        // no round sees the row before its delete commits.
+        engine.autovacuum.stop();
+        let tx = engine.begin_transaction().unwrap();
+        engine.delete_with_tx(tx, "orders", row_ids[9]).unwrap();
+        engine.commit(tx).unwrap();
+        engine.start_autovacuum();
+        wait_until(|| engine.last_vacuum("orders").unwrap().is_some());
+        let stats = engine.last_vacuum("orders").unwrap().unwrap();
+        assert_eq!(stats.tuples_removed, 10);
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_autovacuum_counts_dead_rows_left_before_a_restart() {
+        let dir = TempDir::new().unwrap();
+        {
+            let config =
+                AistoreConfig::new(dir.path()).with_autovacuum(AutovacuumConfig::disabled());
+            let mut engine = StorageEngine::open(config).unwrap();
+            engine
+                .create_table(
+                    "orders",
+                    vec![
+                        Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                        Column::new("customer".to_string(), ColumnType::Int64, false, 1),
+                    ],
+                )
+                .unwrap();
+            for i in 0..20 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(i)])
+                    .unwrap();
+                if i % 2 == 0 {
+                    engine.delete("orders", rid).unwrap();
+                }
+            }
+        }
+
+        // Nothing is deleted after the restart: the dead rows found on open
+        // are enough
+        let engine = StorageEngine::open(autovacuum_every_10ms(&dir)).unwrap();
+        wait_until(|| engine.last_vacuum("orders").unwrap().is_some());
+        let stats = engine.last_vacuum("orders").unwrap().unwrap();
+        assert_eq!(stats.tuples_removed, 10);
+        assert_eq!(stats.tuples_kept, 0);
+    }
+
+    #[test]
+    fn test_checkpoint_thread_writes_pages_and_checkpoints() {
+        let dir = TempDir::new().unwrap();
+        let config = AistoreConfig::new(dir.path())
+            .with_checkpoint_interval(1)
+            .with_flush_policy(FlushPolicy::Manual)
+            .with_autovacuum(AutovacuumConfig::disabled());
+        let mut engine = StorageEngine::open(config).unwrap();
+        engine
+            .create_table(
+                "orders",
+                vec![
+                    Column::new("id".to_string(), ColumnType::Int64, false, 0),
+                    Column::new("customer".to_string(), ColumnType::Int64, false, 1),
+                ],
+            )
+            .unwrap();
+        let wal = Arc::clone(engine.wal.as_ref().unwrap());
+        let opened_at = wal.last_checkpoint_lsn();
+        engine
+            .insert("orders", vec![Value::Int64(1), Value::Int64(2)])
+            .unwrap();
+        assert!(!engine.buffer_mgr.get_dirty_pages().is_empty());
+
+        wait_until(|| wal.last_checkpoint_lsn() > opened_at);
+        assert!(engine.buffer_mgr.get_dirty_pages().is_empty());
+        assert!(engine.take_background_error().is_none());
+    }
+
+    #[test]
+    fn test_open_reads_config_from_data_dir() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join(crate::config::CONFIG_FILE_TOML),
+            "buffer_pool_size = 256\n\n[wal]\nsync_mode = \"normal\"\n",
+        )
+        .unwrap();
+        {
+            let mut engine = create_test_engine(&dir);
+            assert_eq!(engine.buffer_mgr.buffer_size(), 256);
+            engine
+                .insert("orders", vec![Value::Int64(1), Value::Int64(2)])
+                .unwrap();
+        }
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 1);
+        drop(engine);
+
+        std::fs::write(
+            dir.path().join(crate::config::CONFIG_FILE_TOML),
+            "lock_timeout_secs = 0\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            StorageEngine::new(dir.path()),
+            Err(StorageError::Config(ConfigError::Invalid { key, .. })) if key == "lock_timeout_secs"
+        ));
+        let config = AistoreConfig::new(dir.path()).with_buffer_pool_size(0);
+        assert!(matches!(
+            StorageEngine::open(config),
+            Err(StorageError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_crash_recovery_replays_heap_and_index_changes() {
+        let dir = TempDir::new().unwrap();
+        let mut row_ids = Vec::new();
+        let index_id;
+        {
+            let mut engine = create_test_engine(&dir);
+            index_id = engine
+                .create_index("orders", "idx_id", vec!["id".to_string()], true)
+                .unwrap();
+            for i in 0..1000 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(i % 10)])
+                    .unwrap();
+                row_ids.push(rid);
+            }
+            engine
+                .update(
+                    "orders",
+                    row_ids[5],
+                    vec![Value::Int64(5), Value::Int64(99)],
+                )
+                .unwrap();
+            engine.delete("orders", row_ids[6]).unwrap();
+
+            let tx = engine.begin_transaction().unwrap();
+            engine
+                .insert_with_tx(tx, "orders", vec![Value::Int64(2000), Value::Int64(1)])
+                .unwrap();
+            engine.commit(tx).unwrap();
+
+            // Crash: nothing but the WAL reaches disk
+            std::mem::forget(engine);
+        }
+
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 1000);
+        let tuple = engine.get_row("orders", row_ids[5]).unwrap();
+        assert_eq!(tuple.values(), &[Value::Int64(5), Value::Int64(99)]);
+        assert!(engine.get_row("orders", row_ids[6]).is_err());
+
+        assert_eq!(
+            engine.lookup_index(index_id, &[Value::Int64(777)]).unwrap(),
+            vec![row_ids[777]]
+        );
+        assert!(engine
+            .lookup_index(index_id, &[Value::Int64(6)])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            engine
+                .lookup_index(index_id, &[Value::Int64(2000)])
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_range_scan_is_unaffected_by_deletes_during_iteration() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = create_test_engine(&dir);
+        let index_id = engine
+            .create_index("orders", "idx_id", vec!["id".to_string()], true)
+            .unwrap();
+        let row_ids: Vec<RowId> = (0..2000)
+            .map(|i| {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap()
+            })
+            .collect();
+
+        let lower = [Value::Int64(100)];
+        let upper = [Value::Int64(1900)];
+        let mut scan = engine
+            .lookup_range(index_id, Some(&lower), Some(&upper), true, false)
+            .unwrap()
+            .rev();
+        let mut found = scan.by_ref().take(10).collect::<Vec<_>>();
+
+        // Emptying the tree pages the scan has yet to reach changes nothing
+        for &rid in &row_ids {
+            engine.delete("orders", rid).unwrap();
+        }
+        engine.vacuum("orders").unwrap();
+        found.extend(scan);
+        found.reverse();
+        assert_eq!(found, row_ids[100..1900]);
+        assert!(engine
+            .lookup_range(index_id, None, None, true, true)
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_crash_right_after_create_index_keeps_the_index() {
+        let dir = TempDir::new().unwrap();
+        let mut row_ids = Vec::new();
+        let index_id;
+        {
+            let mut engine = create_test_engine(&dir);
+            for i in 0..1000 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(i % 10)])
+                    .unwrap();
+                row_ids.push(rid);
+            }
+            engine.flush().unwrap();
+            index_id = engine
+                .create_index("orders", "idx_id", vec!["id".to_string()], true)
+                .unwrap();
+            // The index file names the new index, so the log of its pages
+            // has to be durable already
+            let wal = engine.wal.as_ref().unwrap();
+            assert!(wal.flushed_lsn() >= wal.current_lsn());
+            std::mem::forget(engine);
+        }
+
+        let engine = StorageEngine::new(dir.path()).unwrap();
+        for i in [0, 500, 999] {
+            assert_eq!(
+                engine
+                    .lookup_index(index_id, &[Value::Int64(i as i64)])
+                    .unwrap(),
+                vec![row_ids[i]]
+            );
+        }
+    }
+
+    #[test]
+    fn test_crash_recovery_repeats_index_changes_since_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let mut row_ids = Vec::new();
+        let index_id;
+        {
+            let mut engine = create_test_engine(&dir);
+            index_id = engine
+                .create_index("orders", "idx_id", vec!["id".to_string()], true)
+                .unwrap();
+            for i in 0..500 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+                row_ids.push(rid);
+            }
+            engine.flush().unwrap();
+
+            // Splits and merges after the checkpoint, with some of the
+            // pages they touch written out half way through
+            for i in 500..2000 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+                row_ids.push(rid);
+            }
+            engine.buffer_mgr.flush_all().unwrap();
+            for &rid in &row_ids[..400] {
+                engine.delete("orders", rid).unwrap();
+            }
+            let tx = engine.begin_transaction().unwrap();
+            engine.delete_with_tx(tx, "orders", row_ids[1000]).unwrap();
+            engine
+                .insert_with_tx(tx, "orders", vec![Value::Int64(5000), Value::Int64(0)])
+                .unwrap();
+            engine.abort(tx).unwrap();
+            std::mem::forget(engine);
+        }
+
+        let engine = StorageEngine::new(dir.path()).unwrap();
+        let found: Vec<RowId> = engine
+            .lookup_range(index_id, None, None, true, true)
+            .unwrap()
+            .collect();
+        assert_eq!(found, row_ids[400..]);
+        assert!(engine
+            .lookup_index(index_id, &[Value::Int64(5000)])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_full_page_image_repairs_torn_page() {
+        let dir = TempDir::new().unwrap();
+        let first_page;
+        let segment_id;
+        {
+            let mut engine = create_test_engine(&dir);
+            for i in 0..50 {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+            }
+            engine.flush().unwrap();
+
+            // The first change after the checkpoint logs the whole page
+            let rid = engine
+                .insert("orders", vec![Value::Int64(50), Value::Int64(0)])
+                .unwrap();
+            first_page = rid.page_id;
+            segment_id = engine.get_table("orders").unwrap().segment_id();
+            std::mem::forget(engine);
+        }
+
+        // Tear the page on disk: its second half never made it
+        let path = dir.path().join(format!("page_{}.dat", segment_id));
+        let offset = (first_page & 0xFFFF_FFFF) * PAGE_SIZE as u64;
+        let vfs = crate::vfs::LocalFs::new();
+        crate::vfs::VfsInterface::pwrite(
+            &vfs,
+            path.to_str().unwrap(),
+            &[0xEE; PAGE_SIZE / 2],
+            offset + PAGE_SIZE as u64 / 2,
+        )
+        .unwrap();
+
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        let mut ids: Vec<i64> = engine
+            .scan_all("orders")
+            .unwrap()
+            .iter()
+            .map(|t| match t.get(0) {
+                Some(Value::Int64(id)) => *id,
+                other => panic!("unexpected value {:?}", other),
+            })
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..=50).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn test_corrupted_page_is_restored_or_reported() {
+        let dir = TempDir::new().unwrap();
+        let (page_id, segment_id) = {
+            let mut engine = create_test_engine(&dir);
+            let rid = engine
+                .insert("orders", vec![Value::Int64(1), Value::Int64(0)])
+                .unwrap();
+            engine.flush().unwrap();
+            (
+                rid.page_id,
+                engine.get_table("orders").unwrap().segment_id(),
+            )
+        };
+
+        // Flip a byte in the middle of the data page; the WAL no longer
+        // covers it after the checkpoint, so only the checksum can tell
+        let path = dir.path().join(format!("page_{}.dat", segment_id));
+        let offset = (page_id & 0xFFFF_FFFF) as usize * PAGE_SIZE + PAGE_SIZE / 2;
+        let corrupt = || {
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes[offset] ^= 0xFF;
+            std::fs::write(&path, bytes).unwrap();
+        };
+
+        // The page was in the last double-write batch, so opening restores it
+        corrupt();
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 1);
+        std::mem::forget(engine);
+
+        // Without an intact copy both policies report the page
+        corrupt();
+        std::fs::remove_file(dir.path().join("doublewrite.dat")).unwrap();
+        for policy in [BadPagePolicy::Fail, BadPagePolicy::RepairFromDoubleWrite] {
+            let mut engine = StorageEngine::new_with_bad_page_policy(dir.path(), policy).unwrap();
+            let err = engine.scan_all("orders").err().unwrap();
+            assert!(err.to_string().contains("corrupted"), "{}", err);
+            std::mem::forget(engine);
+        }
+    }
+
+    #[test]
+    fn test_abort_reverts_heap_and_index_changes() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = create_test_engine(&dir);
+        let index_id = engine
+            .create_index("orders", "idx_id", vec!["id".to_string()], true)
+            .unwrap();
+        let row_ids: Vec<RowId> = (0..10)
+            .map(|i| {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(i)])
+                    .unwrap()
+            })
+            .collect();
+
+        let tx = engine.begin_transaction().unwrap();
+        engine
+            .insert_with_tx(tx, "orders", vec![Value::Int64(100), Value::Int64(0)])
+            .unwrap();
+        engine
+            .update_with_tx(
+                tx,
+                "orders",
+                row_ids[3],
+                vec![Value::Int64(3), Value::Int64(77)],
+            )
+            .unwrap();
+        engine
+            .update_with_tx(
+                tx,
+                "orders",
+                row_ids[4],
+                vec![Value::Int64(400), Value::Int64(4)],
+            )
+            .unwrap();
+        engine.delete_with_tx(tx, "orders", row_ids[5]).unwrap();
+        engine.abort(tx).unwrap();
+
+        assert_eq!(engine.scan_all("orders").unwrap().len(), 10);
+        for i in [3, 4, 5] {
+            let tuple = engine.get_row("orders", row_ids[i]).unwrap();
+            assert_eq!(
+                tuple.values(),
+                &[Value::Int64(i as i64), Value::Int64(i as i64)]
+            );
+            assert_eq!(
+                engine
+                    .lookup_index(index_id, &[Value::Int64(i as i64)])
+                    .unwrap(),
+                vec![row_ids[i]]
+            );
+        }
+        for id in [100, 400] {
+            assert!(engine
+                .lookup_index(index_id, &[Value::Int64(id)])
+                .unwrap()
+                .is_empty());
+        }
+
+        // The aborted key is free again
+        engine
+            .insert("orders", vec![Value::Int64(100), Value::Int64(0)])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_crash_recovery_rolls_back_uncommitted_transaction() {
+        let dir = TempDir::new().unwrap();
+        let mut row_ids = Vec::new();
+        let index_id;
+        {
+            let mut engine = create_test_engine(&dir);
+            index_id = engine
+                .create_index("orders", "idx_id", vec!["id".to_string()], true)
+                .unwrap();
+            for i in 0..100 {
+                let rid = engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+                row_ids.push(rid);
+            }
+
+            let committed = engine.begin_transaction().unwrap();
+            engine
+                .insert_with_tx(
+                    committed,
+                    "orders",
+                    vec![Value::Int64(2000), Value::Int64(0)],
+                )
+                .unwrap();
+            engine.commit(committed).unwrap();
+
+            let loser = engine.begin_transaction().unwrap();
+            engine
+                .insert_with_tx(loser, "orders", vec![Value::Int64(3000), Value::Int64(0)])
+                .unwrap();
+            engine.delete_with_tx(loser, "orders", row_ids[1]).unwrap();
+            engine
+                .update_with_tx(
+                    loser,
+                    "orders",
+                    row_ids[2],
+                    vec![Value::Int64(2002), Value::Int64(9)],
+                )
+                .unwrap();
+
+            // Crash with `loser` still running
+            std::mem::forget(engine);
+        }
+
+        // Reopening twice checks the rollback itself is not undone again
+        for _ in 0..2 {
+            let mut engine = StorageEngine::new(dir.path()).unwrap();
+            assert_eq!(engine.scan_all("orders").unwrap().len(), 101);
+            for i in [1, 2] {
+                let tuple = engine.get_row("orders", row_ids[i]).unwrap();
+                assert_eq!(tuple.values(), &[Value::Int64(i as i64), Value::Int64(0)]);
+                assert_eq!(
+                    engine
+                        .lookup_index(index_id, &[Value::Int64(i as i64)])
+                        .unwrap(),
+                    vec![row_ids[i]]
+                );
+            }
+            for id in [3000, 2002] {
+                assert!(engine
+                    .lookup_index(index_id, &[Value::Int64(id)])
+                    .unwrap()
+                    .is_empty());
+            }
+            assert_eq!(
+                engine
+                    .lookup_index(index_id, &[Value::Int64(2000)])
+                    .unwrap()
+                    .len(),
+                1
+            );
+        }
+    }
+
+    fn wal_file(dir: &TempDir) -> std::path::PathBuf {
+        dir.path().join("wal").join(format!("{:016x}.wal", 0))
+    }
+
+    fn count_rows(engine: &mut StorageEngine) -> usize {
+        engine.scan_all("orders").unwrap().len()
+    }
+
+    #[test]
+    fn test_torn_wal_tail_is_treated_as_end_of_log() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = create_test_engine(&dir);
+            for i in 0..100 {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+            }
+            std::mem::forget(engine);
+        }
+
+        // Tear the last record, the insert of row 99
+        let path = wal_file(&dir);
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 5).unwrap();
+        drop(file);
+
+        {
+            let mut engine = StorageEngine::new(dir.path()).unwrap();
+            assert_eq!(count_rows(&mut engine), 99);
+            engine
+                .insert("orders", vec![Value::Int64(100), Value::Int64(0)])
+                .unwrap();
+            std::mem::forget(engine);
+        }
+
+        // The new record replaced the torn one
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        assert_eq!(count_rows(&mut engine), 100);
+    }
+
+    #[test]
+    fn test_damaged_wal_record_fails_recovery() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = create_test_engine(&dir);
+            for i in 0..100 {
+                engine
+                    .insert("orders", vec![Value::Int64(i), Value::Int64(0)])
+                    .unwrap();
+            }
+            std::mem::forget(engine);
+        }
+
+        let path = wal_file(&dir);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        match StorageEngine::new(dir.path()) {
+            Err(e) => assert!(e.to_string().contains("WAL corrupted"), "{}", e),
+            Ok(_) => panic!("recovery accepted a damaged log"),
+        }
+    }
+
+    #[test]
+    fn test_failed_redo_stops_recovery() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut engine = create_test_engine(&dir);
+            engine
+                .insert("orders", vec![Value::Int64(1), Value::Int64(0)])
+                .unwrap();
+            std::mem::forget(engine);
+        }
+
+        let wal = WalManager::new(
+            dir.path().to_path_buf(),
+            Arc::new(crate::vfs::LocalFs::new()),
+        )
+        .unwrap();
+        match wal.recover(|_, _| Err("page unreadable".to_string())) {
+            Err(crate::wal::WalError::Corrupted { reason, .. }) => {
+                assert!(reason.contains("page unreadable"), "{}", reason)
+            }
+            other => panic!("recovery carried on past a failed redo: {:?}", other.err()),
+        }
+    }
+}
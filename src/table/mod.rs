@@ -15,6 +15,7 @@ pub use column::Column;
 pub use syscache::SysCache;
 pub use table::Table;
 pub use table::TableType;
+pub use table::INITIAL_SCHEMA_VERSION;
 
 #[cfg(test)]
 mod tests {
@@ -33,6 +33,7 @@ impl fmt::Display for TableType {
 /// - segment_id: Associated storage segment
 /// - table_type: Type of table (user/system/temporary)
 /// - columns: Column definitions for the table schema
+/// - schema_version: Version of the column list, recorded in every row
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Table {
     /// Unique table identifier
@@ -51,8 +52,14 @@ pub struct Table {
     pub created_at: u64,
     /// Column definitions
     pub columns: Vec<Column>,
+    /// Schema version, starting at 1; rows record the version they were
+    /// written with
+    pub schema_version: u32,
 }
 
+/// Schema version of a newly created table
+pub const INITIAL_SCHEMA_VERSION: u32 = 1;
+
 impl Table {
     /// Create a new table with basic fields
     pub fn new(table_id: u64, table_name: String, segment_id: SegmentId) -> Self {
@@ -65,6 +72,7 @@ impl Table {
             column_count: 0,
             created_at: current_timestamp(),
             columns: Vec::new(),
+            schema_version: INITIAL_SCHEMA_VERSION,
         }
     }
 
@@ -84,6 +92,7 @@ impl Table {
             column_count: 0,
             created_at: current_timestamp(),
             columns: Vec::new(),
+            schema_version: INITIAL_SCHEMA_VERSION,
         }
     }
 
@@ -104,6 +113,7 @@ impl Table {
             column_count,
             created_at: current_timestamp(),
             columns,
+            schema_version: INITIAL_SCHEMA_VERSION,
         }
     }
 
@@ -162,6 +172,11 @@ impl Table {
     pub fn column_count(&self) -> u32 {
         self.column_count
     }
+
+    /// Get schema version
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
 }
 
 /// Get current timestamp (simple counter for now)
@@ -181,6 +196,7 @@ mod tests {
     fn test_table_new() {
         let table = Table::new(1, "test_table".to_string(), 100);
         assert_eq!(table.table_id(), 1);
+        assert_eq!(table.schema_version(), INITIAL_SCHEMA_VERSION);
         assert_eq!(table.table_name(), "test_table");
         assert_eq!(table.segment_id(), 100);
         assert!(!table.is_system());
@@ -2,7 +2,7 @@
 
 pub mod segment;
 
-use crate::types::Timestamp;
+use crate::types::{ForkNumber, Timestamp, DEFAULT_TABLESPACE_ID};
 use crc32fast;
 use std::fmt;
 use std::fs::{File, OpenOptions};
@@ -574,6 +574,29 @@ impl TablespaceManager {
         let name_to_id = self.name_to_id.read().unwrap();
         name_to_id.keys().cloned().collect()
     }
+
+    /// Directory holding the relation files of a tablespace
+    ///
+    /// The default tablespace is the data directory itself; any other
+    /// tablespace gets a subdirectory named after its id.
+    pub fn relation_dir(&self, tablespace_id: u64) -> PathBuf {
+        if tablespace_id == DEFAULT_TABLESPACE_ID as u64 {
+            self.data_dir.clone()
+        } else {
+            self.data_dir.join(format!("tablespace_{}", tablespace_id))
+        }
+    }
+
+    /// File holding one fork of a relation
+    ///
+    /// Block `n` of the fork lives at offset `n * PAGE_SIZE`.
+    pub fn relation_path(&self, tablespace_id: u64, relation_id: u64, fork: ForkNumber) -> PathBuf {
+        self.relation_dir(tablespace_id).join(format!(
+            "page_{}{}.dat",
+            relation_id,
+            fork.file_suffix()
+        ))
+    }
 }
 
 // Tests
@@ -636,6 +659,23 @@ mod tests {
         assert!(header.verify_checksum());
     }
 
+    #[test]
+    fn test_relation_paths() {
+        let mgr = TablespaceManager::new("/data");
+        assert_eq!(
+            mgr.relation_path(0, 7, ForkNumber::Main),
+            PathBuf::from("/data/page_7.dat")
+        );
+        assert_eq!(
+            mgr.relation_path(0, 7, ForkNumber::Fsm),
+            PathBuf::from("/data/page_7_fsm.dat")
+        );
+        assert_eq!(
+            mgr.relation_path(3, 7, ForkNumber::Main),
+            PathBuf::from("/data/tablespace_3/page_7.dat")
+        );
+    }
+
     #[test]
     fn test_tablespace_creation() {
         let temp_dir = get_test_temp_dir();
@@ -91,6 +91,42 @@ pub type SegmentId = u64;
 /// Tablespace ID type
 pub type TablespaceId = u32;
 
+/// Tablespace whose relation files live directly in the data directory
+pub const DEFAULT_TABLESPACE_ID: TablespaceId = 0;
+
+/// Relation fork
+///
+/// Each fork of a relation is stored in a file of its own and has its own
+/// block numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(u8)]
+pub enum ForkNumber {
+    /// Table rows or index entries
+    #[default]
+    Main = 0,
+    /// Free space map
+    Fsm = 1,
+}
+
+impl ForkNumber {
+    /// Converts a stored fork number back to a `ForkNumber`
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ForkNumber::Main),
+            1 => Some(ForkNumber::Fsm),
+            _ => None,
+        }
+    }
+
+    /// Suffix of the fork's file name
+    pub fn file_suffix(&self) -> &'static str {
+        match self {
+            ForkNumber::Main => "",
+            ForkNumber::Fsm => "_fsm",
+        }
+    }
+}
+
 /// Index ID type
 pub type IndexId = u32;
 
@@ -159,43 +195,6 @@ pub enum PageType {
     Free,
 }
 
-/// Storage engine configuration
-#[derive(Debug, Clone)]
-pub struct AistoreConfig {
-    /// Data directory path
-    pub data_dir: String,
-    /// Buffer size (in blocks)
-    pub buffer_size: usize,
-    /// Whether to enable logging
-    pub enable_log: bool,
-    /// Log level
-    pub log_level: LogLevel,
-}
-
-impl Default for AistoreConfig {
-    fn default() -> Self {
-        Self {
-            data_dir: String::from("./data"),
-            buffer_size: 1024, // 1024 blocks, approximately 4MB
-            enable_log: true,
-            log_level: LogLevel::Info,
-        }
-    }
-}
-
-/// Log level
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum LogLevel {
-    /// Debug level
-    Debug,
-    /// Info level
-    Info,
-    /// Warn level
-    Warn,
-    /// Error level
-    Error,
-}
-
 /// Key-value pair
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KeyValue {
@@ -111,4 +111,36 @@ pub trait VfsInterface: Send + Sync {
     /// * `Ok(usize)` - The number of bytes written
     /// * `Err(VfsError)` if an error occurred
     fn pwrite(&self, path: &str, buf: &[u8], offset: u64) -> VfsResult<usize>;
+
+    /// List the entries of a directory
+    ///
+    /// # Arguments
+    /// * `path` - The path to the directory to list
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` - The entry names, without `.` and `..`, in no
+    ///   particular order
+    /// * `Err(VfsError)` if an error occurred
+    fn list_dir(&self, path: &str) -> VfsResult<Vec<String>>;
+
+    /// Rename a file, replacing `to` if it exists
+    ///
+    /// # Arguments
+    /// * `from` - The current path of the file
+    /// * `to` - The new path of the file
+    ///
+    /// # Returns
+    /// * `Ok(())` if the file was renamed successfully
+    /// * `Err(VfsError)` if an error occurred
+    fn rename(&self, from: &str, to: &str) -> VfsResult<()>;
+
+    /// Flush a file's data to stable storage
+    ///
+    /// # Arguments
+    /// * `path` - The path to the file to sync
+    ///
+    /// # Returns
+    /// * `Ok(())` once everything written to the file so far is durable
+    /// * `Err(VfsError)` if an error occurred
+    fn fsync(&self, path: &str) -> VfsResult<()>;
 }
@@ -282,4 +282,68 @@ impl VfsInterface for LocalFs {
             Ok(result as usize)
         }
     }
+
+    fn list_dir(&self, path: &str) -> VfsResult<Vec<String>> {
+        let c_path = std::ffi::CString::new(path)?;
+
+        let dir = unsafe { libc::opendir(c_path.as_ptr()) };
+        if dir.is_null() {
+            let errno = unsafe { *libc::__errno_location() };
+            return match errno {
+                libc::ENOENT => Err(VfsError::NotFound(path.to_string())),
+                libc::EACCES | libc::EPERM => Err(VfsError::PermissionDenied(path.to_string())),
+                _ => Err(VfsError::SystemError(errno, "opendir failed".to_string())),
+            };
+        }
+
+        let mut names = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                break;
+            }
+            let name = unsafe { std::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) };
+            let name = name.to_string_lossy();
+            if name != "." && name != ".." {
+                names.push(name.into_owned());
+            }
+        }
+
+        let _ = unsafe { libc::closedir(dir) };
+        Ok(names)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> VfsResult<()> {
+        let c_from = std::ffi::CString::new(from)?;
+        let c_to = std::ffi::CString::new(to)?;
+
+        let result = unsafe { libc::rename(c_from.as_ptr(), c_to.as_ptr()) };
+
+        if result < 0 {
+            let errno = unsafe { *libc::__errno_location() };
+            match errno {
+                libc::ENOENT => Err(VfsError::NotFound(from.to_string())),
+                libc::EACCES | libc::EPERM => Err(VfsError::PermissionDenied(from.to_string())),
+                _ => Err(VfsError::SystemError(errno, "rename failed".to_string())),
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    fn fsync(&self, path: &str) -> VfsResult<()> {
+        let fd = self.open_file_internal(path, libc::O_RDONLY, 0)?;
+
+        let result = unsafe { libc::fsync(fd) };
+        let errno = unsafe { *libc::__errno_location() };
+
+        // Close the file descriptor regardless of result
+        let _ = unsafe { libc::close(fd) };
+
+        if result < 0 {
+            Err(VfsError::SystemError(errno, "fsync failed".to_string()))
+        } else {
+            Ok(())
+        }
+    }
 }
@@ -154,6 +154,44 @@ fn test_vfs_pread_pwrite() {
     assert!(fs.remove_dir(&test_dir).is_ok());
 }
 
+#[test]
+fn test_vfs_list_dir_rename_and_fsync() {
+    let fs = LocalFs::new();
+    let test_dir = get_unique_test_dir();
+    let a = format!("{}/a.dat", test_dir);
+    let b = format!("{}/b.dat", test_dir);
+
+    let _ = fs.remove_file(&a);
+    let _ = fs.remove_file(&b);
+    let _ = fs.remove_dir(&test_dir);
+
+    assert!(fs.create_dir(&test_dir).is_ok());
+    assert!(fs.list_dir(&test_dir).unwrap().is_empty());
+
+    fs.create_file(&a).unwrap().close().unwrap();
+    fs.pwrite(&a, b"moved", 0).unwrap();
+    assert_eq!(fs.list_dir(&test_dir).unwrap(), vec!["a.dat".to_string()]);
+
+    // Rename to a new name keeps the contents
+    assert!(fs.rename(&a, &b).is_ok());
+    assert_eq!(fs.list_dir(&test_dir).unwrap(), vec!["b.dat".to_string()]);
+    let mut buf = [0u8; 5];
+    assert_eq!(fs.pread(&b, &mut buf, 0).unwrap(), 5);
+    assert_eq!(&buf, b"moved");
+
+    assert!(matches!(fs.rename(&a, &b), Err(VfsError::NotFound(_))));
+    assert!(matches!(
+        fs.list_dir(&format!("{}/missing", test_dir)),
+        Err(VfsError::NotFound(_))
+    ));
+
+    assert!(fs.fsync(&b).is_ok());
+    assert!(fs.fsync(&a).is_err());
+
+    assert!(fs.remove_file(&b).is_ok());
+    assert!(fs.remove_dir(&test_dir).is_ok());
+}
+
 #[test]
 fn test_vfs_error_handling() {
     let fs = LocalFs::new();
@@ -1,13 +1,15 @@
 //! WAL Checkpoint
 
 use crate::types::PageId;
-use crate::vfs::{VfsInterface, VfsResult};
+use crate::vfs::{VfsError, VfsInterface, VfsResult};
 use crate::wal::lsn::LSN;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 const CHECKPOINT_MAGIC: u32 = 0x434B5054; // "CKPT"
-const CHECKPOINT_VERSION: u32 = 0x00000001;
+/// Version 2 stores each active transaction with its last LSN; version 1
+/// stored bare transaction ids
+const CHECKPOINT_VERSION: u32 = 0x00000002;
 
 /// Checkpoint record
 #[derive(Debug, Clone)]
@@ -16,7 +18,9 @@ pub struct CheckpointRecord {
     pub begin_lsn: LSN,
     pub end_lsn: LSN,
     pub dirty_pages: Vec<PageId>,
-    pub active_transactions: Vec<u64>,
+    /// Transactions running at the checkpoint with the LSN of their last
+    /// record, the starting point of their undo chain
+    pub active_transactions: Vec<(u64, LSN)>,
 }
 
 #[derive(Clone)]
@@ -37,12 +41,22 @@ impl CheckpointManager {
         }
     }
 
+    /// Creates a manager that continues from the checkpoint already on disk
+    pub fn open(checkpoint_dir: PathBuf, vfs: Arc<dyn VfsInterface>) -> Self {
+        let mut mgr = Self::new(checkpoint_dir, vfs);
+        if let Some(cp) = mgr.load_latest() {
+            mgr.last_checkpoint_lsn = cp.begin_lsn;
+            mgr.checkpoint_id = cp.checkpoint_id;
+        }
+        mgr
+    }
+
     /// Create a checkpoint
     pub fn checkpoint(
         &mut self,
         begin_lsn: LSN,
         dirty_pages: Vec<PageId>,
-        active_transactions: Vec<u64>,
+        active_transactions: Vec<(u64, LSN)>,
     ) -> VfsResult<CheckpointRecord> {
         self.checkpoint_id += 1;
 
@@ -62,7 +76,10 @@ impl CheckpointManager {
 
     /// Write checkpoint to disk
     fn write_checkpoint(&self, record: &CheckpointRecord) -> VfsResult<()> {
-        self.vfs.create_dir(self.checkpoint_dir.to_str().unwrap())?;
+        match self.vfs.create_dir(self.checkpoint_dir.to_str().unwrap()) {
+            Ok(()) | Err(VfsError::AlreadyExists(_)) => {}
+            Err(e) => return Err(e),
+        }
 
         let path = self.checkpoint_dir.join("checkpoint.bin");
 
@@ -81,11 +98,15 @@ impl CheckpointManager {
 
         let tx_count = record.active_transactions.len() as u32;
         data.extend_from_slice(&tx_count.to_le_bytes());
-        for tx_id in &record.active_transactions {
+        for (tx_id, last_lsn) in &record.active_transactions {
             data.extend_from_slice(&tx_id.to_le_bytes());
+            data.extend_from_slice(&last_lsn.raw().to_le_bytes());
         }
 
-        self.vfs.pwrite(path.to_str().unwrap(), &data, 0)?;
+        let handle = self.vfs.create_file(path.to_str().unwrap())?;
+        let written = handle.pwrite(&data, 0);
+        handle.close()?;
+        written?;
 
         Ok(())
     }
@@ -100,7 +121,7 @@ impl CheckpointManager {
             Err(_) => return None,
         };
 
-        if n < 24 {
+        if n < 40 {
             return None;
         }
 
@@ -108,6 +129,8 @@ impl CheckpointManager {
         if magic != CHECKPOINT_MAGIC {
             return None;
         }
+        let version = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        let tx_entry_size = if version >= 2 { 16 } else { 8 };
 
         let checkpoint_id = u64::from_le_bytes([
             data[8], data[9], data[10], data[11], data[12], data[13], data[14], data[15],
@@ -116,9 +139,10 @@ impl CheckpointManager {
             data[16], data[17], data[18], data[19], data[20], data[21], data[22], data[23],
         ]));
 
-        let dirty_count = u32::from_le_bytes([data[24], data[25], data[26], data[27]]) as usize;
+        // end_lsn sits at 24..32
+        let dirty_count = u32::from_le_bytes([data[32], data[33], data[34], data[35]]) as usize;
         let mut dirty_pages = Vec::new();
-        let mut offset = 28;
+        let mut offset = 36;
         for _ in 0..dirty_count {
             if offset + 8 > n {
                 break;
@@ -146,21 +170,19 @@ impl CheckpointManager {
         let mut active_transactions = Vec::new();
         offset += 4;
         for _ in 0..tx_count {
-            if offset + 8 > n {
+            if offset + tx_entry_size > n {
                 break;
             }
-            let tx_id = u64::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-                data[offset + 4],
-                data[offset + 5],
-                data[offset + 6],
-                data[offset + 7],
-            ]);
-            active_transactions.push(tx_id);
-            offset += 8;
+            let tx_id = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+            let last_lsn = if tx_entry_size == 16 {
+                LSN::from_raw(u64::from_le_bytes(
+                    data[offset + 8..offset + 16].try_into().unwrap(),
+                ))
+            } else {
+                LSN::invalid()
+            };
+            active_transactions.push((tx_id, last_lsn));
+            offset += tx_entry_size;
         }
 
         Some(CheckpointRecord {
@@ -2,11 +2,26 @@
 
 use std::path::PathBuf;
 
+/// When a commit waits for its log records to reach disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Commit and abort return once the log is synced
+    #[default]
+    Full,
+    /// Commit and abort return right away and the group commit thread
+    /// syncs within `group_commit_timeout_ms`; a crash can lose the
+    /// transactions committed in that window, never corrupt the log
+    Normal,
+}
+
 /// WAL configuration
 #[derive(Debug, Clone)]
 pub struct WalConfig {
     /// WAL directory
     pub log_dir: PathBuf,
+    /// Where log files no longer needed for recovery are moved; defaults
+    /// to `archive` under `log_dir`
+    pub archive_dir: Option<PathBuf>,
     /// Maximum size of a single log file (default 1GB)
     pub max_file_size: u64,
     /// Memory buffer size (default 8MB)
@@ -15,8 +30,11 @@ pub struct WalConfig {
     pub group_commit_batch: usize,
     /// Group commit maximum wait time in milliseconds (default 10ms)
     pub group_commit_timeout_ms: u64,
-    /// Checkpoint interval in seconds (default 60s)
+    /// Seconds between the checkpoints `StorageEngine` takes in the
+    /// background; 0 only checkpoints on flush (default 60s)
     pub checkpoint_interval_sec: u64,
+    /// Whether commits wait for the log to be synced (default `Full`)
+    pub sync_mode: SyncMode,
     /// Whether WAL is enabled
     pub enabled: bool,
 }
@@ -25,11 +43,13 @@ impl Default for WalConfig {
     fn default() -> Self {
         Self {
             log_dir: PathBuf::from("./wal"),
+            archive_dir: None,
             max_file_size: 1 << 30, // 1GB
             buffer_size: 8 << 20,   // 8MB
             group_commit_batch: 4,
             group_commit_timeout_ms: 10,
             checkpoint_interval_sec: 60,
+            sync_mode: SyncMode::Full,
             enabled: true,
         }
     }
@@ -47,6 +67,19 @@ impl WalConfig {
         self
     }
 
+    /// Create a config with custom archive directory
+    pub fn with_archive_dir(mut self, archive_dir: PathBuf) -> Self {
+        self.archive_dir = Some(archive_dir);
+        self
+    }
+
+    /// Directory recycled log files are archived to
+    pub fn archive_dir(&self) -> PathBuf {
+        self.archive_dir
+            .clone()
+            .unwrap_or_else(|| self.log_dir.join("archive"))
+    }
+
     /// Create a config with custom max file size
     pub fn with_max_file_size(mut self, size: u64) -> Self {
         self.max_file_size = size;
@@ -77,6 +110,12 @@ impl WalConfig {
         self
     }
 
+    /// Create a config with custom commit sync mode
+    pub fn with_sync_mode(mut self, mode: SyncMode) -> Self {
+        self.sync_mode = mode;
+        self
+    }
+
     /// Enable or disable WAL
     pub fn with_enabled(mut self, enabled: bool) -> Self {
         self.enabled = enabled;
@@ -96,6 +135,7 @@ mod tests {
         assert_eq!(config.group_commit_batch, 4);
         assert_eq!(config.group_commit_timeout_ms, 10);
         assert_eq!(config.checkpoint_interval_sec, 60);
+        assert_eq!(config.sync_mode, SyncMode::Full);
         assert!(config.enabled);
     }
 
@@ -108,6 +148,7 @@ mod tests {
             .with_group_commit_batch(8)
             .with_group_commit_timeout(20)
             .with_checkpoint_interval(30)
+            .with_sync_mode(SyncMode::Normal)
             .with_enabled(false);
 
         assert_eq!(config.log_dir, PathBuf::from("/tmp/wal"));
@@ -116,6 +157,7 @@ mod tests {
         assert_eq!(config.group_commit_batch, 8);
         assert_eq!(config.group_commit_timeout_ms, 20);
         assert_eq!(config.checkpoint_interval_sec, 30);
+        assert_eq!(config.sync_mode, SyncMode::Normal);
         assert!(!config.enabled);
     }
 }
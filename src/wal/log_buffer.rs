@@ -28,10 +28,14 @@ pub struct LogBuffer {
     last_flush_time: Mutex<Instant>,
     running: AtomicBool,
     flush_tx: Mutex<Option<mpsc::Sender<()>>>,
+    /// End of the log known to be on stable storage
+    flushed_lsn: Mutex<LSN>,
 }
 
 impl LogBuffer {
     pub fn new(config: WalConfig, file_mgr: Arc<LogFileManager>) -> Arc<Self> {
+        // Whatever was in the log files when they were opened is durable
+        let flushed_lsn = file_mgr.current_lsn();
         let buffer = Arc::new(Self {
             config: config.clone(),
             file_mgr,
@@ -40,6 +44,7 @@ impl LogBuffer {
             last_flush_time: Mutex::new(Instant::now()),
             running: AtomicBool::new(true),
             flush_tx: Mutex::new(None),
+            flushed_lsn: Mutex::new(flushed_lsn),
         });
 
         let buffer_clone = Arc::clone(&buffer);
@@ -54,10 +59,10 @@ impl LogBuffer {
     pub fn append(
         &self,
         tx_id: TransactionId,
-        data: Vec<u8>,
+        mut data: Vec<u8>,
         waiter: Option<std::sync::mpsc::Sender<LSN>>,
     ) -> Result<LSN, String> {
-        let lsn = self.file_mgr.append(&data).map_err(|e| e.to_string())?;
+        let lsn = self.file_mgr.append(&mut data).map_err(|e| e.to_string())?;
 
         let record = PendingRecord {
             tx_id,
@@ -125,7 +130,13 @@ impl LogBuffer {
     }
 
     /// Force flush all pending records
+    ///
+    /// `flushed_lsn` only moves once the log files are synced; if the sync
+    /// fails the records stay pending for the next flush.
     pub fn flush(&self) -> Result<(), String> {
+        // Records are written to the file before they are queued, so the
+        // sync below covers everything up to here
+        let end_lsn = self.file_mgr.current_lsn();
         let records = {
             let mut pending = self.pending.lock();
             if pending.is_empty() {
@@ -134,7 +145,16 @@ impl LogBuffer {
             std::mem::take(&mut *pending)
         };
 
-        self.file_mgr.flush().map_err(|e| e.to_string())?;
+        if let Err(e) = self.file_mgr.flush() {
+            let mut pending = self.pending.lock();
+            let newer = std::mem::replace(&mut *pending, records);
+            pending.extend(newer);
+            return Err(e.to_string());
+        }
+        {
+            let mut flushed_lsn = self.flushed_lsn.lock();
+            *flushed_lsn = (*flushed_lsn).max(end_lsn);
+        }
         self.batch_count.store(0, Ordering::Relaxed);
         *self.last_flush_time.lock() = Instant::now();
 
@@ -147,6 +167,12 @@ impl LogBuffer {
         Ok(())
     }
 
+    /// End of the durable part of the log: every record that starts before
+    /// it has been flushed
+    pub fn flushed_lsn(&self) -> LSN {
+        *self.flushed_lsn.lock()
+    }
+
     /// Wait for a specific LSN to be flushed
     pub fn wait_for_flush(&self, tx_id: TransactionId) -> LSN {
         let (sender, receiver) = mpsc::channel();
@@ -168,3 +194,81 @@ impl LogBuffer {
         self.trigger_flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{FileHandle, LocalFs, VfsError, VfsInterface, VfsResult};
+    use crate::wal::log_record::LogRecord;
+    use tempfile::TempDir;
+
+    /// Local file system whose fsync fails while `failing` is set
+    struct FailingFsyncVfs {
+        inner: LocalFs,
+        failing: AtomicBool,
+    }
+
+    impl VfsInterface for FailingFsyncVfs {
+        fn create_dir(&self, path: &str) -> VfsResult<()> {
+            self.inner.create_dir(path)
+        }
+        fn remove_dir(&self, path: &str) -> VfsResult<()> {
+            self.inner.remove_dir(path)
+        }
+        fn create_file(&self, path: &str) -> VfsResult<Box<dyn FileHandle>> {
+            self.inner.create_file(path)
+        }
+        fn open_file(&self, path: &str) -> VfsResult<Box<dyn FileHandle>> {
+            self.inner.open_file(path)
+        }
+        fn remove_file(&self, path: &str) -> VfsResult<()> {
+            self.inner.remove_file(path)
+        }
+        fn truncate(&self, path: &str, length: u64) -> VfsResult<()> {
+            self.inner.truncate(path, length)
+        }
+        fn pread(&self, path: &str, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+            self.inner.pread(path, buf, offset)
+        }
+        fn pwrite(&self, path: &str, buf: &[u8], offset: u64) -> VfsResult<usize> {
+            self.inner.pwrite(path, buf, offset)
+        }
+        fn list_dir(&self, path: &str) -> VfsResult<Vec<String>> {
+            self.inner.list_dir(path)
+        }
+        fn rename(&self, from: &str, to: &str) -> VfsResult<()> {
+            self.inner.rename(from, to)
+        }
+        fn fsync(&self, path: &str) -> VfsResult<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(VfsError::SystemError(libc::EIO, "fsync failed".to_string()));
+            }
+            self.inner.fsync(path)
+        }
+    }
+
+    #[test]
+    fn test_flushed_lsn_waits_for_fsync() {
+        let dir = TempDir::new().unwrap();
+        let vfs = Arc::new(FailingFsyncVfs {
+            inner: LocalFs::new(),
+            failing: AtomicBool::new(true),
+        });
+        let config = WalConfig::new().with_log_dir(dir.path().join("wal"));
+        let file_mgr =
+            Arc::new(LogFileManager::new(config.clone(), Arc::clone(&vfs) as _).unwrap());
+        let buffer = LogBuffer::new(config, file_mgr);
+        let durable = buffer.flushed_lsn();
+
+        let record = LogRecord::heap_insert(1, LSN::invalid(), 1, 0, vec![7; 50]);
+        buffer.append(1, record.serialize(), None).unwrap();
+        assert!(buffer.flush().is_err());
+        assert_eq!(buffer.flushed_lsn(), durable);
+
+        // The record is still pending and made durable by the next flush
+        vfs.failing.store(false, Ordering::SeqCst);
+        buffer.flush().unwrap();
+        assert!(buffer.flushed_lsn() > durable);
+        buffer.stop();
+    }
+}
@@ -2,13 +2,44 @@
 
 use crate::vfs::{VfsError, VfsInterface, VfsResult};
 use crate::wal::config::WalConfig;
+use crate::wal::log_record::{self, LogRecord, LOG_RECORD_HEADER_SIZE};
 use crate::wal::lsn::LSN;
+use crate::wal::{WalError, WalResult};
 use parking_lot::RwLock;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 const WAL_MAGIC: u32 = 0x57414C31;
-const WAL_VERSION: u32 = 0x00000001;
+/// Version 2: binary payloads and a CRC32C in every record header
+const WAL_VERSION: u32 = 0x00000002;
+/// Size of the file header; the first record starts right after it
+pub const WAL_FILE_HEADER_SIZE: u64 = 16;
+
+/// Chunk size used when scanning past a damaged record
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// What was found at a position in a log file
+enum Probe {
+    Valid(LogRecord),
+    /// Nothing at all: the file ends exactly here
+    End,
+    /// No intact record here, and why
+    Invalid(&'static str),
+}
+
+/// Name of log file `file_id`
+fn file_name(file_id: u16) -> String {
+    format!("{:016x}.wal", file_id)
+}
+
+/// File id of a log file name, or None for anything else in the directory
+fn parse_file_name(name: &str) -> Option<u16> {
+    let hex = name.strip_suffix(".wal")?;
+    if hex.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()?.try_into().ok()
+}
 
 pub struct LogFile {
     file_id: u16,
@@ -18,35 +49,72 @@ pub struct LogFile {
 }
 
 impl LogFile {
-    pub fn create(vfs: Arc<dyn VfsInterface>, dir: &PathBuf, file_id: u16) -> VfsResult<Self> {
-        let path = dir.join(format!("{:016x}.wal", file_id));
+    /// Creates log file `file_id` in `dir` with an empty log, creating the
+    /// directory if needed
+    pub fn create(vfs: Arc<dyn VfsInterface>, dir: &Path, file_id: u16) -> VfsResult<Self> {
+        match vfs.create_dir(dir.to_str().unwrap()) {
+            Ok(()) | Err(VfsError::AlreadyExists(_)) => {}
+            Err(e) => return Err(e),
+        }
 
-        vfs.create_dir(dir.to_str().unwrap())?;
+        let mut log_file = Self {
+            file_id,
+            path: dir.join(file_name(file_id)),
+            size: WAL_FILE_HEADER_SIZE,
+            vfs,
+        };
+        log_file
+            .vfs
+            .create_file(log_file.path.to_str().unwrap())?
+            .close()?;
+        log_file.write_header()?;
 
-        let handle = vfs.open_file(path.to_str().unwrap())?;
-        let size = 0;
+        Ok(log_file)
+    }
 
-        let mut log_file = Self {
+    /// Opens the existing log file `file_id` in `dir`, checking its header
+    ///
+    /// Its size is only known once the end of its records has been found.
+    pub fn open(vfs: Arc<dyn VfsInterface>, dir: &Path, file_id: u16) -> WalResult<Self> {
+        let log_file = Self {
             file_id,
-            path,
-            size,
+            path: dir.join(file_name(file_id)),
+            size: WAL_FILE_HEADER_SIZE,
             vfs,
         };
 
-        if size == 0 {
-            log_file.write_header()?;
+        let (_, version) = log_file
+            .read_header()
+            .map_err(|e| WalError::InvalidState(format!("{}: {}", log_file.path.display(), e)))?;
+        if version != WAL_VERSION {
+            return Err(WalError::InvalidState(format!(
+                "unsupported WAL version {} in {}",
+                version,
+                log_file.path.display()
+            )));
         }
 
         Ok(log_file)
     }
 
+    /// Whether log file `file_id` in `dir` is shorter than a file header
+    fn blank(vfs: &Arc<dyn VfsInterface>, dir: &Path, file_id: u16) -> VfsResult<bool> {
+        let mut header = [0u8; WAL_FILE_HEADER_SIZE as usize];
+        let n = vfs.pread(
+            dir.join(file_name(file_id)).to_str().unwrap(),
+            &mut header,
+            0,
+        )?;
+        Ok(n < header.len())
+    }
+
     fn write_header(&mut self) -> VfsResult<()> {
         let mut header = [0u8; 16];
         header[0..4].copy_from_slice(&WAL_MAGIC.to_le_bytes());
         header[4..8].copy_from_slice(&WAL_VERSION.to_le_bytes());
 
         self.vfs.pwrite(self.path.to_str().unwrap(), &header, 0)?;
-        self.size = 16;
+        self.size = WAL_FILE_HEADER_SIZE;
 
         Ok(())
     }
@@ -72,22 +140,108 @@ impl LogFile {
     }
 
     pub fn append(&mut self, data: &[u8], offset: u64) -> VfsResult<usize> {
-        let handle = self.vfs.open_file(self.path.to_str().unwrap())?;
-        let written = handle.pwrite(data, offset)?;
+        let written = self.vfs.pwrite(self.path.to_str().unwrap(), data, offset)?;
         self.size = offset + written as u64;
 
         Ok(written)
     }
 
+    /// Reads up to `len` bytes at `offset`; short at the end of the file
     pub fn read(&self, offset: u64, len: usize) -> VfsResult<Vec<u8>> {
         let mut buf = vec![0u8; len];
-        let handle = self.vfs.open_file(self.path.to_str().unwrap())?;
-        let n = handle.pread(&mut buf, offset)?;
+        let n = self
+            .vfs
+            .pread(self.path.to_str().unwrap(), &mut buf, offset)?;
         buf.truncate(n);
 
         Ok(buf)
     }
 
+    /// Reads and checks the record at `offset`
+    fn probe(&self, offset: u64) -> VfsResult<Probe> {
+        let header = self.read(offset, LOG_RECORD_HEADER_SIZE)?;
+        if header.is_empty() {
+            return Ok(Probe::End);
+        }
+        if header.len() < LOG_RECORD_HEADER_SIZE {
+            return Ok(Probe::Invalid("record header cut short"));
+        }
+        if log_record::stamped_lsn(&header) != LSN::new(self.file_id, offset) {
+            return Ok(Probe::Invalid("header does not carry its own LSN"));
+        }
+
+        // Check the file is long enough before trusting the length enough
+        // to allocate for it
+        let len = LOG_RECORD_HEADER_SIZE + log_record::payload_len(&header);
+        if self.read(offset + len as u64 - 1, 1)?.is_empty() {
+            return Ok(Probe::Invalid("record cut short"));
+        }
+        let data = self.read(offset, len)?;
+        Ok(match LogRecord::deserialize(&data) {
+            Some(record) => Probe::Valid(record),
+            None => Probe::Invalid("checksum mismatch"),
+        })
+    }
+
+    /// Offset of the first intact record after `offset`, if any
+    ///
+    /// Every record names its own position in its header, so candidates are
+    /// found by looking for that and confirmed with the checksum.
+    fn next_valid_record(&self, offset: u64) -> VfsResult<Option<u64>> {
+        let mut start = offset + 1;
+        loop {
+            let chunk = self.read(start, SCAN_CHUNK_SIZE)?;
+            for i in 0..chunk.len().saturating_sub(7) {
+                let pos = start + i as u64;
+                if log_record::stamped_lsn(&chunk[i..]) == LSN::new(self.file_id, pos)
+                    && matches!(self.probe(pos)?, Probe::Valid(_))
+                {
+                    return Ok(Some(pos));
+                }
+            }
+            if chunk.len() < SCAN_CHUNK_SIZE {
+                return Ok(None);
+            }
+            // Overlap so an LSN straddling two chunks is still seen
+            start += (SCAN_CHUNK_SIZE - 7) as u64;
+        }
+    }
+
+    /// Accepts the damaged or missing record at `offset` as the end of the
+    /// log, unless intact records follow it
+    ///
+    /// A record torn by a crash can only be the last one written; anything
+    /// valid after it means the log is damaged in the middle, and dropping
+    /// the rest silently would lose committed work.
+    fn check_end(&self, offset: u64, reason: &str) -> WalResult<()> {
+        match self.next_valid_record(offset)? {
+            Some(next) => Err(WalError::Corrupted {
+                lsn: LSN::new(self.file_id, offset),
+                reason: format!("{}; an intact record follows at offset {}", reason, next),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Finds the end of the records in this file
+    ///
+    /// The scan stops at the first position without an intact record:
+    /// unwritten space, or a record torn at the tail, which the next append
+    /// overwrites.
+    fn find_end(&self) -> WalResult<u64> {
+        let mut offset = WAL_FILE_HEADER_SIZE;
+        loop {
+            match self.probe(offset)? {
+                Probe::Valid(record) => offset += record.serialized_size() as u64,
+                Probe::End => return Ok(offset),
+                Probe::Invalid(reason) => {
+                    self.check_end(offset, reason)?;
+                    return Ok(offset);
+                }
+            }
+        }
+    }
+
     pub fn file_id(&self) -> u16 {
         self.file_id
     }
@@ -100,8 +254,9 @@ impl LogFile {
         &self.path
     }
 
+    /// Forces everything written to this file to stable storage
     pub fn sync(&self) -> VfsResult<()> {
-        Ok(())
+        self.vfs.fsync(self.path.to_str().unwrap())
     }
 }
 
@@ -111,16 +266,19 @@ pub struct LogFileManager {
     files: RwLock<Vec<LogFile>>,
     current_file_id: RwLock<u16>,
     current_offset: RwLock<u64>,
+    /// Files before this one were synced after their last write
+    synced_file_id: RwLock<u16>,
 }
 
 impl LogFileManager {
-    pub fn new(config: WalConfig, vfs: Arc<dyn VfsInterface>) -> VfsResult<Self> {
+    pub fn new(config: WalConfig, vfs: Arc<dyn VfsInterface>) -> WalResult<Self> {
         let mut manager = Self {
             config,
             vfs,
             files: RwLock::new(Vec::new()),
             current_file_id: RwLock::new(0),
             current_offset: RwLock::new(16),
+            synced_file_id: RwLock::new(0),
         };
 
         manager.init()?;
@@ -128,61 +286,124 @@ impl LogFileManager {
         Ok(manager)
     }
 
-    fn init(&mut self) -> VfsResult<()> {
-        let file = LogFile::create(Arc::clone(&self.vfs), &self.config.log_dir, 0)?;
+    /// Opens the log: finds every log file in `log_dir`, checks their
+    /// headers and positions the write offset after the last complete
+    /// record of the newest one, so a restarted engine appends instead of
+    /// overwriting. An empty directory starts a new log in file 0.
+    fn init(&mut self) -> WalResult<()> {
+        let dir = self.config.log_dir.clone();
+        match self.vfs.create_dir(dir.to_str().unwrap()) {
+            Ok(()) | Err(VfsError::AlreadyExists(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
 
-        *self.current_file_id.write() = 0;
-        *self.current_offset.write() = file.size();
-        self.files.write().push(file);
+        let mut file_ids: Vec<u16> = self
+            .vfs
+            .list_dir(dir.to_str().unwrap())?
+            .iter()
+            .filter_map(|name| parse_file_name(name))
+            .collect();
+        file_ids.sort_unstable();
+
+        let mut files: Vec<LogFile> = Vec::with_capacity(file_ids.len());
+        for (i, &file_id) in file_ids.iter().enumerate() {
+            if let Some(expected) = files.last().map(|f| f.file_id() + 1)
+                && file_id != expected
+            {
+                return Err(WalError::InvalidState(format!(
+                    "WAL file {} is missing from {}",
+                    file_name(expected),
+                    dir.display()
+                )));
+            }
+
+            let file = match LogFile::open(Arc::clone(&self.vfs), &dir, file_id) {
+                Ok(file) => file,
+                // A crash between creating the newest file and writing its
+                // header leaves it blank; no record can be in it yet
+                Err(_) if i + 1 == file_ids.len() && LogFile::blank(&self.vfs, &dir, file_id)? => {
+                    LogFile::create(Arc::clone(&self.vfs), &dir, file_id)?
+                }
+                Err(e) => return Err(e),
+            };
+            files.push(file);
+        }
+
+        if files.is_empty() {
+            files.push(LogFile::create(Arc::clone(&self.vfs), &dir, 0)?);
+        }
+
+        let last = files.last_mut().unwrap();
+        let end = last.find_end()?;
+        last.size = end;
+
+        *self.current_file_id.write() = last.file_id();
+        *self.current_offset.write() = end;
+        *self.files.write() = files;
 
         Ok(())
     }
 
-    pub fn append(&self, data: &[u8]) -> VfsResult<LSN> {
+    /// Appends one serialized record and returns its LSN
+    ///
+    /// The LSN is written into the header of `data` and the checksum
+    /// computed before the write.
+    pub fn append(&self, data: &mut [u8]) -> VfsResult<LSN> {
+        let mut offset_guard = self.current_offset.write();
         let file_id = *self.current_file_id.read();
-        let offset = *self.current_offset.read();
+        let offset = *offset_guard;
 
         if offset + data.len() as u64 > self.config.max_file_size {
+            drop(offset_guard);
             return self.rotate_and_append(data);
         }
 
+        let lsn = LSN::new(file_id, offset);
+        log_record::seal(data, lsn);
+
         let mut files = self.files.write();
-        if let Some(file) = files.get_mut(file_id as usize) {
+        if let Some(file) = files.last_mut() {
             file.append(data, offset)?;
         }
 
-        let lsn = LSN::new(file_id, offset);
-        *self.current_offset.write() = offset + data.len() as u64;
+        *offset_guard = offset + data.len() as u64;
 
         Ok(lsn)
     }
 
-    fn rotate_and_append(&self, data: &[u8]) -> VfsResult<LSN> {
+    fn rotate_and_append(&self, data: &mut [u8]) -> VfsResult<LSN> {
+        let mut offset_guard = self.current_offset.write();
         let new_file_id = *self.current_file_id.read() + 1;
 
         let file = LogFile::create(Arc::clone(&self.vfs), &self.config.log_dir, new_file_id)?;
 
         *self.current_file_id.write() = new_file_id;
-        *self.current_offset.write() = 16;
 
-        let lsn = LSN::new(new_file_id, 16);
+        let lsn = LSN::new(new_file_id, WAL_FILE_HEADER_SIZE);
+        log_record::seal(data, lsn);
         let mut files = self.files.write();
         files.push(file);
 
         if let Some(file) = files.last_mut() {
-            file.append(data, 16)?;
+            file.append(data, WAL_FILE_HEADER_SIZE)?;
         }
 
-        *self.current_offset.write() = 16 + data.len() as u64;
+        *offset_guard = WAL_FILE_HEADER_SIZE + data.len() as u64;
 
         Ok(lsn)
     }
 
+    /// Syncs the log files written since the last flush
+    ///
+    /// On error nothing past the previous flush may be taken as durable.
     pub fn flush(&self) -> VfsResult<()> {
+        let mut synced_file_id = self.synced_file_id.write();
+        let current_file_id = *self.current_file_id.read();
         let files = self.files.read();
-        for file in files.iter() {
+        for file in files.iter().filter(|f| f.file_id() >= *synced_file_id) {
             file.sync()?;
         }
+        *synced_file_id = current_file_id;
         Ok(())
     }
 
@@ -194,43 +415,213 @@ impl LogFileManager {
         self.current_lsn()
     }
 
-    pub fn read_from(&self, lsn: LSN) -> VfsResult<Vec<u8>> {
+    /// Reads the record at `lsn`
+    ///
+    /// `Ok(None)` marks the end of a log file. In the newest file that is
+    /// the end of the log, and includes a record torn at the tail by a
+    /// crash; a damaged record with intact ones after it, or anywhere in an
+    /// older file, is reported as `WalError::Corrupted`.
+    pub fn read_record(&self, lsn: LSN) -> WalResult<Option<LogRecord>> {
         let files = self.files.read();
-
-        if let Some(file) = files.get(lsn.file_id() as usize) {
-            file.read(lsn.offset(), 1024 * 1024)
-        } else {
-            Err(VfsError::NotFound("Log file not found".to_string()))
+        let idx = match files.binary_search_by_key(&lsn.file_id(), |f| f.file_id()) {
+            Ok(idx) => idx,
+            Err(0) => {
+                return Err(WalError::NotFound(format!(
+                    "log file for {} has been archived",
+                    lsn
+                )));
+            }
+            Err(_) => return Ok(None),
+        };
+        let file = &files[idx];
+
+        match file.probe(lsn.offset())? {
+            Probe::Valid(record) => Ok(Some(record)),
+            Probe::End => Ok(None),
+            Probe::Invalid(reason) if idx + 1 == files.len() => {
+                file.check_end(lsn.offset(), reason)?;
+                Ok(None)
+            }
+            Probe::Invalid(reason) => Err(WalError::Corrupted {
+                lsn,
+                reason: format!("{}; the log continues in a later file", reason),
+            }),
         }
     }
 
+    /// LSN of the first record in the file after `file_id`, if there is one
+    pub fn next_file_lsn(&self, file_id: u16) -> Option<LSN> {
+        self.files
+            .read()
+            .iter()
+            .find(|f| f.file_id() > file_id)
+            .map(|f| LSN::new(f.file_id(), WAL_FILE_HEADER_SIZE))
+    }
+
+    /// LSN of the first record in the oldest log file still kept
+    pub fn first_lsn(&self) -> LSN {
+        let file_id = self.files.read().first().map_or(0, |f| f.file_id());
+        LSN::new(file_id, WAL_FILE_HEADER_SIZE)
+    }
+
     pub fn list_files(&self) -> Vec<PathBuf> {
         let files = self.files.read();
         files.iter().map(|f| f.path().clone()).collect()
     }
 
-    /// Clean up old log files before checkpoint_lsn
-    pub fn cleanup_old_logs(&self, checkpoint_lsn: LSN) -> VfsResult<usize> {
-        let mut cleaned = 0;
-        let checkpoint_file_id = checkpoint_lsn.file_id();
+    /// Moves log files wholly before `keep_lsn` to the archive directory
+    ///
+    /// The file being written is never archived. Returns the number of
+    /// files moved.
+    pub fn archive_old_logs(&self, keep_lsn: LSN) -> WalResult<usize> {
         let current_file_id = *self.current_file_id.read();
+        let archive_dir = self.config.archive_dir();
 
         let mut files = self.files.write();
-        let mut to_remove = Vec::new();
-
-        for (idx, file) in files.iter().enumerate() {
+        let mut archived = 0;
+        while let Some(file) = files.first() {
             let file_id = file.file_id();
-            if file_id < checkpoint_file_id && file_id < current_file_id {
-                to_remove.push(idx);
+            if file_id >= keep_lsn.file_id() || file_id >= current_file_id {
+                break;
+            }
+            if archived == 0 {
+                match self.vfs.create_dir(archive_dir.to_str().unwrap()) {
+                    Ok(()) | Err(VfsError::AlreadyExists(_)) => {}
+                    Err(e) => return Err(e.into()),
+                }
             }
+
+            let to = archive_dir.join(file_name(file_id));
+            self.vfs
+                .rename(file.path().to_str().unwrap(), to.to_str().unwrap())?;
+            files.remove(0);
+            archived += 1;
         }
 
-        for idx in to_remove.iter().rev() {
-            let file = files.remove(*idx);
-            let _ = self.vfs.remove_file(file.path().to_str().unwrap());
-            cleaned += 1;
+        Ok(archived)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::LocalFs;
+    use crate::wal::log_reader::LogReader;
+    use tempfile::TempDir;
+
+    fn open(dir: &TempDir, max_file_size: u64) -> LogFileManager {
+        let config = WalConfig::new()
+            .with_log_dir(dir.path().join("wal"))
+            .with_max_file_size(max_file_size);
+        LogFileManager::new(config, Arc::new(LocalFs::new())).unwrap()
+    }
+
+    fn append(mgr: &LogFileManager, tx_id: u64) -> LSN {
+        let record = LogRecord::heap_insert(tx_id, LSN::invalid(), 1, 0, vec![tx_id as u8; 50]);
+        mgr.append(&mut record.serialize()).unwrap()
+    }
+
+    fn read_all(mgr: &LogFileManager) -> Vec<(LSN, u64)> {
+        LogReader::new(mgr, mgr.first_lsn())
+            .map(|item| item.map(|(lsn, record)| (lsn, record.header.tx_id)))
+            .collect::<WalResult<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reopen_resumes_at_end_of_log() {
+        let dir = TempDir::new().unwrap();
+        let (lsns, end) = {
+            let mgr = open(&dir, 1 << 20);
+            let lsns: Vec<LSN> = (1..=3).map(|tx| append(&mgr, tx)).collect();
+            (lsns, mgr.current_lsn())
+        };
+
+        let mgr = open(&dir, 1 << 20);
+        assert_eq!(mgr.current_lsn(), end);
+        assert_eq!(append(&mgr, 4), end);
+        let read: Vec<LSN> = read_all(&mgr).into_iter().map(|(lsn, _)| lsn).collect();
+        assert_eq!(&read[..3], &lsns[..]);
+        assert_eq!(read[3], end);
+    }
+
+    #[test]
+    fn test_reader_crosses_file_boundaries() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mgr = open(&dir, 400);
+            for tx in 1..=20 {
+                append(&mgr, tx);
+            }
+            assert!(mgr.list_files().len() > 1);
+        }
+
+        let mgr = open(&dir, 400);
+        let files = mgr.list_files().len();
+        assert!(files > 1);
+        let read = read_all(&mgr);
+        assert_eq!(
+            read.iter().map(|(_, tx)| *tx).collect::<Vec<_>>(),
+            (1..=20).collect::<Vec<u64>>()
+        );
+        assert_eq!(read.last().unwrap().0.file_id() as usize, files - 1);
+
+        // New records go to the newest file
+        let lsn = append(&mgr, 21);
+        assert_eq!(lsn.file_id() as usize, files - 1);
+    }
+
+    #[test]
+    fn test_archive_moves_old_files() {
+        let dir = TempDir::new().unwrap();
+        let mgr = open(&dir, 400);
+        let lsns: Vec<LSN> = (1..=20).map(|tx| append(&mgr, tx)).collect();
+        let keep = lsns[10];
+
+        let archived = mgr.archive_old_logs(keep).unwrap();
+        assert_eq!(archived, keep.file_id() as usize);
+        let archive_dir = dir.path().join("wal").join("archive");
+        for file_id in 0..keep.file_id() {
+            assert!(archive_dir.join(file_name(file_id)).exists());
+            assert!(!dir.path().join("wal").join(file_name(file_id)).exists());
         }
 
-        Ok(cleaned)
+        // Everything from the kept file on is still readable, and the
+        // archived part reports itself as such
+        assert_eq!(mgr.first_lsn().file_id(), keep.file_id());
+        let read = read_all(&mgr);
+        assert_eq!(read.last().unwrap().1, 20);
+        assert!(read.iter().any(|(lsn, _)| *lsn == keep));
+        assert!(matches!(
+            mgr.read_record(lsns[0]),
+            Err(WalError::NotFound(_))
+        ));
+
+        // Reopening finds the remaining files only
+        drop(mgr);
+        let mgr = open(&dir, 400);
+        assert_eq!(mgr.first_lsn().file_id(), keep.file_id());
+        assert_eq!(read_all(&mgr).last().unwrap().1, 20);
+    }
+
+    #[test]
+    fn test_missing_file_is_reported() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mgr = open(&dir, 400);
+            for tx in 1..=20 {
+                append(&mgr, tx);
+            }
+        }
+        std::fs::remove_file(dir.path().join("wal").join(file_name(1))).unwrap();
+
+        let config = WalConfig::new()
+            .with_log_dir(dir.path().join("wal"))
+            .with_max_file_size(400);
+        match LogFileManager::new(config, Arc::new(LocalFs::new())) {
+            Err(WalError::InvalidState(msg)) => assert!(msg.contains(&file_name(1)), "{}", msg),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("opened a log with a missing file"),
+        }
     }
 }
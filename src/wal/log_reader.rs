@@ -0,0 +1,59 @@
+//! Sequential WAL reader
+
+use crate::wal::log_file::LogFileManager;
+use crate::wal::log_record::LogRecord;
+use crate::wal::lsn::LSN;
+use crate::wal::WalResult;
+
+/// Iterator over log records in LSN order
+///
+/// Records are read one at a time straight from the log files; at the end
+/// of a file the reader carries on with the first record of the next one.
+/// A damaged record ends the iteration with its error.
+pub struct LogReader<'a> {
+    file_mgr: &'a LogFileManager,
+    next_lsn: LSN,
+    done: bool,
+}
+
+impl<'a> LogReader<'a> {
+    /// Starts reading at `lsn`, which must be the start of a record
+    pub fn new(file_mgr: &'a LogFileManager, lsn: LSN) -> Self {
+        Self {
+            file_mgr,
+            next_lsn: lsn,
+            done: false,
+        }
+    }
+
+    /// LSN the next record will be read from; once the reader is
+    /// exhausted, the end of the log
+    pub fn next_lsn(&self) -> LSN {
+        self.next_lsn
+    }
+}
+
+impl Iterator for LogReader<'_> {
+    type Item = WalResult<(LSN, LogRecord)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.file_mgr.read_record(self.next_lsn) {
+                Ok(Some(record)) => {
+                    let lsn = self.next_lsn;
+                    self.next_lsn = lsn + record.serialized_size() as u64;
+                    return Some(Ok((lsn, record)));
+                }
+                Ok(None) => match self.file_mgr.next_file_lsn(self.next_lsn.file_id()) {
+                    Some(lsn) => self.next_lsn = lsn,
+                    None => self.done = true,
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
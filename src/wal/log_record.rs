@@ -4,6 +4,17 @@ use crate::lock::TransactionId;
 use crate::types::PageId;
 use crate::wal::lsn::LSN;
 
+/// Size of the serialized record header; the payload follows it
+///
+/// Layout: lsn u64 @0, tx_id u64 @8, prev_lsn u64 @16, payload_len u32 @24,
+/// CRC32C u32 @28, log_type u8 @32, reserved up to 40.
+pub const LOG_RECORD_HEADER_SIZE: usize = 40;
+
+const LSN_OFFSET: usize = 0;
+const PAYLOAD_LEN_OFFSET: usize = 24;
+const CHECKSUM_OFFSET: usize = 28;
+const LOG_TYPE_OFFSET: usize = 32;
+
 /// Log type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogType {
@@ -11,9 +22,59 @@ pub enum LogType {
     TxCommit,
     TxAbort,
     PageRedo,
+    /// Tuple placed in a heap slot
+    HeapInsert,
+    /// Heap slot cleared
+    HeapDelete,
+    /// Heap slot rewritten in place with a new tuple
+    HeapUpdate,
+    /// Entry added to an index; index pages are redone from page images
+    IndexInsert,
+    /// Entry removed from an index
+    IndexDelete,
+    /// Whole page image, minus its longest run of zero bytes
+    FullPageImage,
+    /// Compensation log record (CLR): the redo-only change that undid an
+    /// earlier record of the same transaction
+    Compensation,
+}
+
+impl LogType {
+    fn to_u8(self) -> u8 {
+        match self {
+            LogType::TxBegin => 0,
+            LogType::TxCommit => 1,
+            LogType::TxAbort => 2,
+            LogType::PageRedo => 3,
+            LogType::HeapInsert => 4,
+            LogType::HeapDelete => 5,
+            LogType::HeapUpdate => 6,
+            LogType::IndexInsert => 7,
+            LogType::IndexDelete => 8,
+            LogType::FullPageImage => 9,
+            LogType::Compensation => 10,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => LogType::TxBegin,
+            1 => LogType::TxCommit,
+            2 => LogType::TxAbort,
+            3 => LogType::PageRedo,
+            4 => LogType::HeapInsert,
+            5 => LogType::HeapDelete,
+            6 => LogType::HeapUpdate,
+            7 => LogType::IndexInsert,
+            8 => LogType::IndexDelete,
+            9 => LogType::FullPageImage,
+            10 => LogType::Compensation,
+            _ => return None,
+        })
+    }
 }
 
-/// Log record header (fixed 32 bytes)
+/// Log record header (fixed 40 bytes)
 #[derive(Debug, Clone)]
 pub struct LogRecordHeader {
     pub lsn: LSN,
@@ -32,13 +93,351 @@ pub struct LogRecord {
 }
 
 /// Page redo payload
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone)]
 pub struct PageRedoPayload {
     pub page_id: PageId,
     pub offset: u32,
     pub data: Vec<u8>,
 }
 
+/// Payload of `HeapInsert` and `HeapUpdate`: the tuple now in the slot
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapTuplePayload {
+    pub page_id: PageId,
+    pub slot: u32,
+    pub tuple: Vec<u8>,
+    /// Before-image of an update; empty for inserts
+    pub old_tuple: Vec<u8>,
+}
+
+/// Payload of `HeapDelete`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapSlotPayload {
+    pub page_id: PageId,
+    pub slot: u32,
+    /// The tuple the slot held, so the delete can be undone
+    pub old_tuple: Vec<u8>,
+}
+
+/// Payload of `IndexInsert` and `IndexDelete`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntryPayload {
+    pub index_id: u64,
+    pub key: Vec<u8>,
+    pub page_id: PageId,
+    pub slot: u32,
+}
+
+/// Payload of `FullPageImage`
+///
+/// The longest run of zero bytes in the page (usually the free space
+/// between slot array and tuples, or the tail of an index node) is left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FullPageImagePayload {
+    pub page_id: PageId,
+    pub hole_offset: u32,
+    pub hole_len: u32,
+    pub data: Vec<u8>,
+}
+
+impl FullPageImagePayload {
+    pub fn new(page_id: PageId, page: &[u8]) -> Self {
+        let (mut hole_offset, mut hole_len) = (0, 0);
+        let mut run_start = 0;
+        for (i, &byte) in page.iter().enumerate() {
+            if byte != 0 {
+                run_start = i + 1;
+            } else if i + 1 - run_start > hole_len {
+                hole_offset = run_start;
+                hole_len = i + 1 - run_start;
+            }
+        }
+
+        let mut data = Vec::with_capacity(page.len() - hole_len);
+        data.extend_from_slice(&page[..hole_offset]);
+        data.extend_from_slice(&page[hole_offset + hole_len..]);
+        Self {
+            page_id,
+            hole_offset: hole_offset as u32,
+            hole_len: hole_len as u32,
+            data,
+        }
+    }
+
+    /// Rebuilds the page image into `page`
+    pub fn restore(&self, page: &mut [u8]) {
+        let hole_offset = self.hole_offset as usize;
+        let hole_end = hole_offset + self.hole_len as usize;
+        page[..hole_offset].copy_from_slice(&self.data[..hole_offset]);
+        page[hole_offset..hole_end].fill(0);
+        page[hole_end..hole_end + self.data.len() - hole_offset]
+            .copy_from_slice(&self.data[hole_offset..]);
+    }
+}
+
+/// Change carried by a compensation record
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompensationAction {
+    HeapInsert(HeapTuplePayload),
+    HeapDelete(HeapSlotPayload),
+    HeapUpdate(HeapTuplePayload),
+    IndexInsert(IndexEntryPayload),
+    IndexDelete(IndexEntryPayload),
+}
+
+/// Payload of `Compensation`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompensationPayload {
+    /// Next record of the transaction still to undo (the `prev_lsn` of the
+    /// record this one compensates); rollback resumes here after a crash
+    pub undo_next_lsn: u64,
+    pub action: CompensationAction,
+}
+
+impl CompensationPayload {
+    pub fn undo_next_lsn(&self) -> LSN {
+        LSN::from_raw(self.undo_next_lsn)
+    }
+}
+
+/// Little-endian writer for record payloads
+///
+/// Byte strings are written with a u32 length prefix.
+struct PayloadWriter(Vec<u8>);
+
+impl PayloadWriter {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.0.extend_from_slice(v);
+    }
+}
+
+/// Reader matching `PayloadWriter`; every getter returns None once the
+/// payload runs out
+struct PayloadReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        self.take(len).map(|b| b.to_vec())
+    }
+}
+
+/// Binary encoding of a record payload
+trait PayloadCodec: Sized {
+    fn encode(&self, w: &mut PayloadWriter);
+    fn decode(r: &mut PayloadReader) -> Option<Self>;
+}
+
+impl PayloadCodec for PageRedoPayload {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.u64(self.page_id);
+        w.u32(self.offset);
+        w.bytes(&self.data);
+    }
+
+    fn decode(r: &mut PayloadReader) -> Option<Self> {
+        Some(Self {
+            page_id: r.u64()?,
+            offset: r.u32()?,
+            data: r.bytes()?,
+        })
+    }
+}
+
+impl PayloadCodec for HeapTuplePayload {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.u64(self.page_id);
+        w.u32(self.slot);
+        w.bytes(&self.tuple);
+        w.bytes(&self.old_tuple);
+    }
+
+    fn decode(r: &mut PayloadReader) -> Option<Self> {
+        Some(Self {
+            page_id: r.u64()?,
+            slot: r.u32()?,
+            tuple: r.bytes()?,
+            old_tuple: r.bytes()?,
+        })
+    }
+}
+
+impl PayloadCodec for HeapSlotPayload {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.u64(self.page_id);
+        w.u32(self.slot);
+        w.bytes(&self.old_tuple);
+    }
+
+    fn decode(r: &mut PayloadReader) -> Option<Self> {
+        Some(Self {
+            page_id: r.u64()?,
+            slot: r.u32()?,
+            old_tuple: r.bytes()?,
+        })
+    }
+}
+
+impl PayloadCodec for IndexEntryPayload {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.u64(self.index_id);
+        w.bytes(&self.key);
+        w.u64(self.page_id);
+        w.u32(self.slot);
+    }
+
+    fn decode(r: &mut PayloadReader) -> Option<Self> {
+        Some(Self {
+            index_id: r.u64()?,
+            key: r.bytes()?,
+            page_id: r.u64()?,
+            slot: r.u32()?,
+        })
+    }
+}
+
+impl PayloadCodec for FullPageImagePayload {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.u64(self.page_id);
+        w.u32(self.hole_offset);
+        w.u32(self.hole_len);
+        w.bytes(&self.data);
+    }
+
+    fn decode(r: &mut PayloadReader) -> Option<Self> {
+        Some(Self {
+            page_id: r.u64()?,
+            hole_offset: r.u32()?,
+            hole_len: r.u32()?,
+            data: r.bytes()?,
+        })
+    }
+}
+
+impl PayloadCodec for CompensationPayload {
+    /// The action is tagged with the log type of the change it performs
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.u64(self.undo_next_lsn);
+        match &self.action {
+            CompensationAction::HeapInsert(p) => {
+                w.u8(LogType::HeapInsert.to_u8());
+                p.encode(w);
+            }
+            CompensationAction::HeapDelete(p) => {
+                w.u8(LogType::HeapDelete.to_u8());
+                p.encode(w);
+            }
+            CompensationAction::HeapUpdate(p) => {
+                w.u8(LogType::HeapUpdate.to_u8());
+                p.encode(w);
+            }
+            CompensationAction::IndexInsert(p) => {
+                w.u8(LogType::IndexInsert.to_u8());
+                p.encode(w);
+            }
+            CompensationAction::IndexDelete(p) => {
+                w.u8(LogType::IndexDelete.to_u8());
+                p.encode(w);
+            }
+        }
+    }
+
+    fn decode(r: &mut PayloadReader) -> Option<Self> {
+        let undo_next_lsn = r.u64()?;
+        let action = match LogType::from_u8(r.u8()?)? {
+            LogType::HeapInsert => CompensationAction::HeapInsert(PayloadCodec::decode(r)?),
+            LogType::HeapDelete => CompensationAction::HeapDelete(PayloadCodec::decode(r)?),
+            LogType::HeapUpdate => CompensationAction::HeapUpdate(PayloadCodec::decode(r)?),
+            LogType::IndexInsert => CompensationAction::IndexInsert(PayloadCodec::decode(r)?),
+            LogType::IndexDelete => CompensationAction::IndexDelete(PayloadCodec::decode(r)?),
+            _ => return None,
+        };
+        Some(Self {
+            undo_next_lsn,
+            action,
+        })
+    }
+}
+
+/// Decodes a whole payload; trailing bytes make it invalid
+fn decode_payload<T: PayloadCodec>(data: &[u8]) -> Option<T> {
+    let mut r = PayloadReader { data, pos: 0 };
+    let payload = T::decode(&mut r)?;
+    (r.pos == data.len()).then_some(payload)
+}
+
+/// Decoded record payload
+#[derive(Debug, Clone)]
+pub enum LogPayload {
+    None,
+    PageRedo(PageRedoPayload),
+    HeapInsert(HeapTuplePayload),
+    HeapDelete(HeapSlotPayload),
+    HeapUpdate(HeapTuplePayload),
+    IndexInsert(IndexEntryPayload),
+    IndexDelete(IndexEntryPayload),
+    FullPageImage(FullPageImagePayload),
+    Compensation(CompensationPayload),
+}
+
+impl LogPayload {
+    /// Page whose contents the record changes; None for transaction control
+    /// and logical index records
+    pub fn page_id(&self) -> Option<PageId> {
+        match self {
+            LogPayload::PageRedo(p) => Some(p.page_id),
+            LogPayload::HeapInsert(p) | LogPayload::HeapUpdate(p) => Some(p.page_id),
+            LogPayload::HeapDelete(p) => Some(p.page_id),
+            LogPayload::FullPageImage(p) => Some(p.page_id),
+            LogPayload::Compensation(clr) => match &clr.action {
+                CompensationAction::HeapInsert(p) | CompensationAction::HeapUpdate(p) => {
+                    Some(p.page_id)
+                }
+                CompensationAction::HeapDelete(p) => Some(p.page_id),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
 impl LogRecord {
     /// Create a transaction begin log
     pub fn tx_begin(tx_id: TransactionId, prev_lsn: LSN) -> Self {
@@ -98,14 +497,116 @@ impl LogRecord {
             offset,
             data,
         };
-        let payload_bytes = serde_json::to_vec(&payload).unwrap_or_default();
+        Self::with_payload(tx_id, prev_lsn, LogType::PageRedo, &payload)
+    }
+
+    /// Create a heap insert log
+    pub fn heap_insert(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        page_id: PageId,
+        slot: u32,
+        tuple: Vec<u8>,
+    ) -> Self {
+        let payload = HeapTuplePayload {
+            page_id,
+            slot,
+            tuple,
+            old_tuple: Vec::new(),
+        };
+        Self::with_payload(tx_id, prev_lsn, LogType::HeapInsert, &payload)
+    }
+
+    /// Create a heap delete log carrying the deleted tuple
+    pub fn heap_delete(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        page_id: PageId,
+        slot: u32,
+        old_tuple: Vec<u8>,
+    ) -> Self {
+        let payload = HeapSlotPayload {
+            page_id,
+            slot,
+            old_tuple,
+        };
+        Self::with_payload(tx_id, prev_lsn, LogType::HeapDelete, &payload)
+    }
+
+    /// Create a heap in-place update log with before- and after-image
+    pub fn heap_update(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        page_id: PageId,
+        slot: u32,
+        tuple: Vec<u8>,
+        old_tuple: Vec<u8>,
+    ) -> Self {
+        let payload = HeapTuplePayload {
+            page_id,
+            slot,
+            tuple,
+            old_tuple,
+        };
+        Self::with_payload(tx_id, prev_lsn, LogType::HeapUpdate, &payload)
+    }
+
+    /// Create a compensation log record
+    pub fn compensation(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        undo_next_lsn: LSN,
+        action: CompensationAction,
+    ) -> Self {
+        let payload = CompensationPayload {
+            undo_next_lsn: undo_next_lsn.raw(),
+            action,
+        };
+        Self::with_payload(tx_id, prev_lsn, LogType::Compensation, &payload)
+    }
+
+    /// Create an index insert (`insert == true`) or index delete log
+    pub fn index_entry(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        insert: bool,
+        payload: IndexEntryPayload,
+    ) -> Self {
+        let log_type = if insert {
+            LogType::IndexInsert
+        } else {
+            LogType::IndexDelete
+        };
+        Self::with_payload(tx_id, prev_lsn, log_type, &payload)
+    }
+
+    /// Create a full page image log
+    pub fn full_page_image(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        page_id: PageId,
+        page: &[u8],
+    ) -> Self {
+        let payload = FullPageImagePayload::new(page_id, page);
+        Self::with_payload(tx_id, prev_lsn, LogType::FullPageImage, &payload)
+    }
+
+    fn with_payload<T: PayloadCodec>(
+        tx_id: TransactionId,
+        prev_lsn: LSN,
+        log_type: LogType,
+        payload: &T,
+    ) -> Self {
+        let mut writer = PayloadWriter(Vec::new());
+        payload.encode(&mut writer);
+        let payload_bytes = writer.0;
 
         Self {
             header: LogRecordHeader {
                 lsn: LSN::invalid(),
                 tx_id,
                 prev_lsn,
-                log_type: LogType::PageRedo,
+                log_type,
                 payload_len: payload_bytes.len() as u32,
                 checksum: 0,
             },
@@ -113,12 +614,40 @@ impl LogRecord {
         }
     }
 
+    /// Decodes the payload according to the record type
+    pub fn payload(&self) -> Option<LogPayload> {
+        let bytes = &self.payload;
+        Some(match self.header.log_type {
+            LogType::TxBegin | LogType::TxCommit | LogType::TxAbort => LogPayload::None,
+            LogType::PageRedo => LogPayload::PageRedo(decode_payload(bytes)?),
+            LogType::HeapInsert => LogPayload::HeapInsert(decode_payload(bytes)?),
+            LogType::HeapDelete => LogPayload::HeapDelete(decode_payload(bytes)?),
+            LogType::HeapUpdate => LogPayload::HeapUpdate(decode_payload(bytes)?),
+            LogType::IndexInsert => LogPayload::IndexInsert(decode_payload(bytes)?),
+            LogType::IndexDelete => LogPayload::IndexDelete(decode_payload(bytes)?),
+            LogType::FullPageImage => LogPayload::FullPageImage(decode_payload(bytes)?),
+            LogType::Compensation => LogPayload::Compensation(decode_payload(bytes)?),
+        })
+    }
+
+    /// Whether rolling the transaction back has to reverse this record
+    pub fn is_undoable(&self) -> bool {
+        matches!(
+            self.header.log_type,
+            LogType::HeapInsert
+                | LogType::HeapDelete
+                | LogType::HeapUpdate
+                | LogType::IndexInsert
+                | LogType::IndexDelete
+        )
+    }
+
     /// Get the serialized size of this record
     pub fn serialized_size(&self) -> usize {
-        std::mem::size_of::<LogRecordHeader>() + self.payload.len()
+        LOG_RECORD_HEADER_SIZE + self.payload.len()
     }
 
-    /// Serialize the record to bytes
+    /// Serialize the record to bytes, checksum included
     pub fn serialize(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(self.serialized_size());
 
@@ -126,60 +655,187 @@ impl LogRecord {
         bytes.extend_from_slice(&self.header.lsn.raw().to_le_bytes());
         bytes.extend_from_slice(&self.header.tx_id.to_le_bytes());
         bytes.extend_from_slice(&self.header.prev_lsn.raw().to_le_bytes());
-        bytes.push(match self.header.log_type {
-            LogType::TxBegin => 0,
-            LogType::TxCommit => 1,
-            LogType::TxAbort => 2,
-            LogType::PageRedo => 3,
-        });
         bytes.extend_from_slice(&self.header.payload_len.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.push(self.header.log_type.to_u8());
+        bytes.resize(LOG_RECORD_HEADER_SIZE, 0);
 
         // Payload
         bytes.extend_from_slice(&self.payload);
 
+        seal(&mut bytes, self.header.lsn);
         bytes
     }
 
     /// Deserialize from bytes
+    ///
+    /// Returns None if the bytes are short or fail the checksum.
     pub fn deserialize(data: &[u8]) -> Option<Self> {
-        if data.len() < 32 {
-            return None;
-        }
-
-        let lsn = LSN::from_raw(u64::from_le_bytes([
-            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-        ]));
-        let tx_id = u64::from_le_bytes([
-            data[8], data[9], data[10], data[11], data[12], data[13], data[14], data[15],
-        ]);
-        let prev_lsn = LSN::from_raw(u64::from_le_bytes([
-            data[16], data[17], data[18], data[19], data[20], data[21], data[22], data[23],
-        ]));
-        let log_type = match data[24] {
-            0 => LogType::TxBegin,
-            1 => LogType::TxCommit,
-            2 => LogType::TxAbort,
-            3 => LogType::PageRedo,
-            _ => return None,
-        };
-        let payload_len = u32::from_le_bytes([data[25], data[26], data[27], data[28]]);
+        let header = data.get(..LOG_RECORD_HEADER_SIZE)?;
+        let payload_len = read_u32(header, PAYLOAD_LEN_OFFSET);
+        let end = LOG_RECORD_HEADER_SIZE.checked_add(payload_len as usize)?;
+        let data = data.get(..end)?;
 
-        if data.len() < 32 + payload_len as usize {
+        let checksum = read_u32(header, CHECKSUM_OFFSET);
+        if checksum != record_checksum(data) {
             return None;
         }
 
-        let payload = data[32..32 + payload_len as usize].to_vec();
-
         Some(Self {
             header: LogRecordHeader {
-                lsn,
-                tx_id,
-                prev_lsn,
-                log_type,
+                lsn: LSN::from_raw(read_u64(header, LSN_OFFSET)),
+                tx_id: read_u64(header, 8),
+                prev_lsn: LSN::from_raw(read_u64(header, 16)),
+                log_type: LogType::from_u8(header[LOG_TYPE_OFFSET])?,
                 payload_len,
-                checksum: 0,
+                checksum,
             },
-            payload,
+            payload: data[LOG_RECORD_HEADER_SIZE..].to_vec(),
         })
     }
 }
+
+/// LSN stamped into a serialized record header
+pub fn stamped_lsn(header: &[u8]) -> LSN {
+    LSN::from_raw(read_u64(header, LSN_OFFSET))
+}
+
+/// Payload length recorded in a serialized record header
+pub fn payload_len(header: &[u8]) -> usize {
+    read_u32(header, PAYLOAD_LEN_OFFSET) as usize
+}
+
+/// Stamps `lsn` into a serialized record and recomputes its checksum
+///
+/// The LSN is only known once the log file manager places the record, so
+/// the checksum is (re)computed at that point.
+pub fn seal(data: &mut [u8], lsn: LSN) {
+    data[LSN_OFFSET..LSN_OFFSET + 8].copy_from_slice(&lsn.raw().to_le_bytes());
+    let checksum = record_checksum(data);
+    data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// CRC32C over header and payload, with the checksum field taken as zero
+fn record_checksum(data: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(&data[..CHECKSUM_OFFSET]);
+    let crc = crc32c::crc32c_append(crc, &[0u8; 4]);
+    crc32c::crc32c_append(crc, &data[CHECKSUM_OFFSET + 4..])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = LogRecord::heap_insert(7, LSN::new(0, 64), 0x1_0000_0003, 5, vec![1, 2, 3]);
+        let bytes = record.serialize();
+        assert_eq!(bytes.len(), record.serialized_size());
+
+        let decoded = LogRecord::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.header.tx_id, 7);
+        assert_eq!(decoded.header.prev_lsn, LSN::new(0, 64));
+        assert_eq!(decoded.header.log_type, LogType::HeapInsert);
+        match decoded.payload() {
+            Some(LogPayload::HeapInsert(p)) => {
+                assert_eq!((p.page_id, p.slot), (0x1_0000_0003, 5));
+                assert_eq!(p.tuple, vec![1, 2, 3]);
+            }
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compensation_round_trip() {
+        let action = CompensationAction::HeapUpdate(HeapTuplePayload {
+            page_id: 12,
+            slot: 1,
+            tuple: vec![4],
+            old_tuple: vec![5, 6],
+        });
+        let record = LogRecord::compensation(3, LSN::new(0, 900), LSN::new(0, 400), action.clone());
+        let decoded = LogRecord::deserialize(&record.serialize()).unwrap();
+        assert!(!decoded.is_undoable());
+        match decoded.payload() {
+            Some(LogPayload::Compensation(clr)) => {
+                assert_eq!(clr.undo_next_lsn(), LSN::new(0, 400));
+                assert_eq!(clr.action, action);
+            }
+            other => panic!("unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_payloads_round_trip() {
+        let index = IndexEntryPayload {
+            index_id: 3,
+            key: vec![9, 8, 7],
+            page_id: 0x2_0000_0001,
+            slot: 4,
+        };
+        let mut page = vec![0u8; crate::types::PAGE_SIZE];
+        page[..10].fill(1);
+        let records = [
+            LogRecord::tx_begin(1, LSN::invalid()),
+            LogRecord::page_redo(1, LSN::new(0, 40), 5, 128, vec![1, 2]),
+            LogRecord::heap_delete(1, LSN::new(0, 80), 5, 2, vec![3, 4]),
+            LogRecord::heap_update(1, LSN::new(0, 90), 5, 2, vec![5], vec![6, 7]),
+            LogRecord::index_entry(1, LSN::new(0, 99), false, index.clone()),
+            LogRecord::full_page_image(0, LSN::invalid(), 5, &page),
+        ];
+        for record in &records {
+            let decoded = LogRecord::deserialize(&record.serialize()).unwrap();
+            assert_eq!(decoded.header.log_type, record.header.log_type);
+            assert_eq!(decoded.payload, record.payload);
+            assert!(decoded.payload().is_some());
+        }
+        match records[4].payload() {
+            Some(LogPayload::IndexDelete(p)) => assert_eq!(p, index),
+            other => panic!("unexpected payload: {:?}", other),
+        }
+
+        // A payload with bytes left over does not decode
+        let mut record = records[2].clone();
+        record.payload.push(0);
+        assert!(record.payload().is_none());
+    }
+
+    #[test]
+    fn test_checksum_detects_damage() {
+        let record = LogRecord::heap_insert(7, LSN::invalid(), 11, 0, vec![1, 2, 3]);
+        let mut bytes = record.serialize();
+        seal(&mut bytes, LSN::new(0, 16));
+        let decoded = LogRecord::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.header.lsn, LSN::new(0, 16));
+
+        for pos in [0, 12, LOG_TYPE_OFFSET, bytes.len() - 1] {
+            let mut damaged = bytes.clone();
+            damaged[pos] ^= 0x40;
+            assert!(LogRecord::deserialize(&damaged).is_none(), "byte {}", pos);
+        }
+        assert!(LogRecord::deserialize(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_full_page_image_hole() {
+        let mut page = vec![0u8; crate::types::PAGE_SIZE];
+        page[..100].fill(0xAB);
+        page[7000..].fill(0xCD);
+
+        let image = FullPageImagePayload::new(9, &page);
+        assert_eq!((image.hole_offset, image.hole_len), (100, 6900));
+        assert_eq!(image.data.len(), page.len() - 6900);
+
+        let mut restored = vec![0xFFu8; page.len()];
+        image.restore(&mut restored);
+        assert_eq!(restored, page);
+    }
+}
@@ -6,15 +6,16 @@ pub mod checkpoint;
 pub mod config;
 pub mod log_buffer;
 pub mod log_file;
+pub mod log_reader;
 pub mod log_record;
 pub mod lsn;
 pub mod recovery;
 
 use checkpoint::CheckpointManager;
-use config::WalConfig;
+use config::{SyncMode, WalConfig};
 use log_buffer::LogBuffer;
 use log_file::LogFileManager;
-use log_record::LogRecord;
+use log_record::{CompensationAction, IndexEntryPayload, LogPayload, LogRecord, LogType};
 use lsn::LSN;
 use recovery::{RecoveryManager, RecoveryResult};
 
@@ -24,8 +25,6 @@ use crate::vfs::VfsInterface;
 use parking_lot::RwLock;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
 
 /// WAL error
 #[derive(Debug)]
@@ -33,6 +32,11 @@ pub enum WalError {
     IoError(String),
     NotFound(String),
     InvalidState(String),
+    /// A damaged record in the middle of the log
+    Corrupted {
+        lsn: LSN,
+        reason: String,
+    },
 }
 
 impl std::fmt::Display for WalError {
@@ -41,19 +45,30 @@ impl std::fmt::Display for WalError {
             WalError::IoError(e) => write!(f, "WAL I/O error: {}", e),
             WalError::NotFound(e) => write!(f, "WAL not found: {}", e),
             WalError::InvalidState(e) => write!(f, "WAL invalid state: {}", e),
+            WalError::Corrupted { lsn, reason } => {
+                write!(f, "WAL corrupted at {}: {}", lsn, reason)
+            }
         }
     }
 }
 
 impl std::error::Error for WalError {}
 
+impl From<crate::vfs::VfsError> for WalError {
+    fn from(e: crate::vfs::VfsError) -> Self {
+        WalError::IoError(e.to_string())
+    }
+}
+
 pub type WalResult<T> = Result<T, WalError>;
 
 /// Transaction LSN tracking
-#[allow(dead_code)]
 struct TxLsn {
+    /// Last record the transaction wrote; becomes `prev_lsn` of its next one
     prev_lsn: LSN,
-    commit_lsn: LSN, // Kept for future use: tracking commit LSN for recovery
+    /// First record the transaction wrote; log files from here on are kept
+    /// so it can still be rolled back
+    first_lsn: LSN,
 }
 
 /// WAL Manager
@@ -73,16 +88,22 @@ impl WalManager {
         let config = WalConfig::new()
             .with_log_dir(data_dir.join("wal"))
             .with_checkpoint_interval(60);
+        Self::with_config(data_dir, vfs, config)
+    }
 
-        let file_mgr = Arc::new(
-            LogFileManager::new(config.clone(), Arc::clone(&vfs))
-                .map_err(|e| WalError::IoError(e.to_string()))?,
-        );
+    /// Create a WAL manager with custom settings; checkpoints are kept in
+    /// `data_dir`, log files in `config.log_dir`
+    pub fn with_config(
+        data_dir: PathBuf,
+        vfs: Arc<dyn VfsInterface>,
+        config: WalConfig,
+    ) -> WalResult<Self> {
+        let file_mgr = Arc::new(LogFileManager::new(config.clone(), Arc::clone(&vfs))?);
 
         let buffer = LogBuffer::new(config.clone(), Arc::clone(&file_mgr));
 
         let checkpoint_dir = data_dir.join("checkpoint");
-        let checkpoint_mgr = CheckpointManager::new(checkpoint_dir, Arc::clone(&vfs));
+        let checkpoint_mgr = CheckpointManager::open(checkpoint_dir, Arc::clone(&vfs));
 
         let recovery_mgr = RecoveryManager::new(
             config.clone(),
@@ -92,75 +113,71 @@ impl WalManager {
         );
 
         let manager = Self {
+            enabled: config.enabled,
             config,
             file_mgr,
             buffer,
             checkpoint_mgr: RwLock::new(checkpoint_mgr),
             recovery_mgr: RwLock::new(Some(recovery_mgr)),
             tx_lsns: RwLock::new(std::collections::HashMap::new()),
-            enabled: true,
         };
 
-        manager.start_checkpoint_timer();
-
         Ok(manager)
     }
 
     /// Append a log record
-    pub fn append(&self, tx_id: TransactionId, record: LogRecord) -> LSN {
+    ///
+    /// Records of a transaction are chained through `prev_lsn`. Transaction
+    /// 0 stands for work outside any transaction and is not tracked.
+    pub fn append(&self, tx_id: TransactionId, mut record: LogRecord) -> WalResult<LSN> {
         if !self.enabled {
-            return LSN::invalid();
+            return Ok(LSN::invalid());
         }
 
-        let prev_lsn = self
-            .tx_lsns
-            .read()
-            .get(&tx_id)
-            .map(|t| t.prev_lsn)
-            .unwrap_or(LSN::invalid());
+        let mut tx_lsns = self.tx_lsns.write();
+        if tx_id != 0 {
+            record.header.prev_lsn = tx_lsns
+                .get(&tx_id)
+                .map(|t| t.prev_lsn)
+                .unwrap_or(LSN::invalid());
+        }
 
         let data = record.serialize();
-        let lsn = match self.buffer.append(tx_id, data, None) {
-            Ok(lsn) => lsn,
-            Err(e) => {
-                eprintln!("WAL: Failed to append record: {}", e);
-                return LSN::invalid();
-            }
-        };
-
-        self.tx_lsns.write().insert(
-            tx_id,
-            TxLsn {
-                prev_lsn,
-                commit_lsn: lsn,
-            },
-        );
+        let lsn = self
+            .buffer
+            .append(tx_id, data, None)
+            .map_err(WalError::IoError)?;
+
+        if tx_id != 0 {
+            tx_lsns
+                .entry(tx_id)
+                .or_insert(TxLsn {
+                    prev_lsn: lsn,
+                    first_lsn: lsn,
+                })
+                .prev_lsn = lsn;
+        }
 
-        lsn
+        Ok(lsn)
     }
 
     /// Begin a transaction
-    pub fn tx_begin(&self, tx_id: TransactionId) -> LSN {
+    pub fn tx_begin(&self, tx_id: TransactionId) -> WalResult<LSN> {
         let record = LogRecord::tx_begin(tx_id, LSN::invalid());
         self.append(tx_id, record)
     }
 
-    /// Commit a transaction
+    /// Commit a transaction, returning the LSN of its commit record
     pub fn commit(&self, tx_id: TransactionId) -> WalResult<LSN> {
         if !self.enabled {
             return Ok(LSN::invalid());
         }
 
-        let tx_lsn = self.tx_lsns.write().remove(&tx_id);
-        let (prev_lsn, commit_lsn) = tx_lsn
-            .as_ref()
-            .map(|t| (t.prev_lsn, t.commit_lsn))
-            .unwrap_or((LSN::invalid(), LSN::invalid()));
-
-        let record = LogRecord::tx_commit(tx_id, prev_lsn);
-        self.append(tx_id, record);
+        let record = LogRecord::tx_commit(tx_id, LSN::invalid());
+        let commit_lsn = self.append(tx_id, record)?;
+        self.tx_lsns.write().remove(&tx_id);
 
-        self.buffer.flush().map_err(|e| WalError::IoError(e))?;
+        self.sync_on_commit()?;
 
         Ok(commit_lsn)
     }
@@ -171,20 +188,24 @@ impl WalManager {
             return Ok(());
         }
 
-        let tx_lsn = self.tx_lsns.write().remove(&tx_id);
-        let prev_lsn = tx_lsn
-            .as_ref()
-            .map(|t| t.prev_lsn)
-            .unwrap_or(LSN::invalid());
-
-        let record = LogRecord::tx_abort(tx_id, prev_lsn);
-        self.append(tx_id, record);
+        let record = LogRecord::tx_abort(tx_id, LSN::invalid());
+        self.append(tx_id, record)?;
+        self.tx_lsns.write().remove(&tx_id);
 
-        self.buffer.flush().map_err(|e| WalError::IoError(e))?;
+        self.sync_on_commit()?;
 
         Ok(())
     }
 
+    /// Sync the log for a commit or abort, unless the sync mode leaves it
+    /// to the group commit thread
+    fn sync_on_commit(&self) -> WalResult<()> {
+        match self.config.sync_mode {
+            SyncMode::Full => self.buffer.flush().map_err(WalError::IoError),
+            SyncMode::Normal => Ok(()),
+        }
+    }
+
     /// Write page redo log
     pub fn write_page_redo(
         &self,
@@ -192,9 +213,9 @@ impl WalManager {
         page_id: PageId,
         offset: u32,
         data: &[u8],
-    ) -> LSN {
+    ) -> WalResult<LSN> {
         if !self.enabled {
-            return LSN::invalid();
+            return Ok(LSN::invalid());
         }
 
         let prev_lsn = self
@@ -208,6 +229,174 @@ impl WalManager {
         self.append(tx_id, record)
     }
 
+    /// Log a tuple placed in heap slot `slot` of `page_id`
+    pub fn log_heap_insert(
+        &self,
+        tx_id: TransactionId,
+        page_id: PageId,
+        slot: usize,
+        tuple: &[u8],
+    ) -> WalResult<LSN> {
+        let record =
+            LogRecord::heap_insert(tx_id, LSN::invalid(), page_id, slot as u32, tuple.to_vec());
+        self.append(tx_id, record)
+    }
+
+    /// Log heap slot `slot` of `page_id` being cleared; `old_tuple` is what
+    /// it held
+    pub fn log_heap_delete(
+        &self,
+        tx_id: TransactionId,
+        page_id: PageId,
+        slot: usize,
+        old_tuple: &[u8],
+    ) -> WalResult<LSN> {
+        let record = LogRecord::heap_delete(
+            tx_id,
+            LSN::invalid(),
+            page_id,
+            slot as u32,
+            old_tuple.to_vec(),
+        );
+        self.append(tx_id, record)
+    }
+
+    /// Log heap slot `slot` of `page_id` being rewritten from `old_tuple` to
+    /// `tuple`
+    pub fn log_heap_update(
+        &self,
+        tx_id: TransactionId,
+        page_id: PageId,
+        slot: usize,
+        tuple: &[u8],
+        old_tuple: &[u8],
+    ) -> WalResult<LSN> {
+        let record = LogRecord::heap_update(
+            tx_id,
+            LSN::invalid(),
+            page_id,
+            slot as u32,
+            tuple.to_vec(),
+            old_tuple.to_vec(),
+        );
+        self.append(tx_id, record)
+    }
+
+    /// Log an index entry being added (`insert == true`) or removed
+    pub fn log_index_entry(
+        &self,
+        tx_id: TransactionId,
+        insert: bool,
+        index_id: u64,
+        key: &[u8],
+        rid: (PageId, usize),
+    ) -> WalResult<LSN> {
+        let payload = IndexEntryPayload {
+            index_id,
+            key: key.to_vec(),
+            page_id: rid.0,
+            slot: rid.1 as u32,
+        };
+        let record = LogRecord::index_entry(tx_id, LSN::invalid(), insert, payload);
+        self.append(tx_id, record)
+    }
+
+    /// Log the full image of a page
+    pub fn log_full_page_image(
+        &self,
+        tx_id: TransactionId,
+        page_id: PageId,
+        page: &[u8],
+    ) -> WalResult<LSN> {
+        let record = LogRecord::full_page_image(tx_id, LSN::invalid(), page_id, page);
+        self.append(tx_id, record)
+    }
+
+    /// Log a compensation record for an undone change; rollback continues
+    /// at `undo_next_lsn`
+    pub fn log_compensation(
+        &self,
+        tx_id: TransactionId,
+        undo_next_lsn: LSN,
+        action: CompensationAction,
+    ) -> WalResult<LSN> {
+        let record = LogRecord::compensation(tx_id, LSN::invalid(), undo_next_lsn, action);
+        self.append(tx_id, record)
+    }
+
+    /// LSN of the last record `tx_id` wrote, or an invalid LSN if none
+    pub fn last_lsn(&self, tx_id: TransactionId) -> LSN {
+        self.tx_lsns
+            .read()
+            .get(&tx_id)
+            .map(|t| t.prev_lsn)
+            .unwrap_or(LSN::invalid())
+    }
+
+    /// Read the record at `lsn`; None at the end of the log
+    pub fn read_record(&self, lsn: LSN) -> WalResult<Option<LogRecord>> {
+        self.file_mgr.read_record(lsn)
+    }
+
+    /// Roll back `tx_id` by following its `prev_lsn` chain from `from_lsn`
+    ///
+    /// Every undoable record is handed to `undo`, which reverses the change
+    /// and logs a compensation record for it. Compensation records on the
+    /// chain are never undone themselves; the walk jumps to their
+    /// `undo_next_lsn`, so a rollback cut short by a crash resumes where it
+    /// stopped. Returns the number of records undone. The caller still
+    /// writes the abort record.
+    pub fn rollback<F>(&self, tx_id: TransactionId, from_lsn: LSN, mut undo: F) -> WalResult<usize>
+    where
+        F: FnMut(LSN, &LogRecord) -> Result<(), String>,
+    {
+        if !self.enabled {
+            return Ok(0);
+        }
+
+        // After a restart the transaction is only known from the log; its
+        // compensation records chain on from its last record
+        self.tx_lsns.write().entry(tx_id).or_insert(TxLsn {
+            prev_lsn: from_lsn,
+            first_lsn: from_lsn,
+        });
+
+        let mut undone = 0;
+        let mut lsn = from_lsn;
+        while lsn.is_valid() {
+            let record = self
+                .file_mgr
+                .read_record(lsn)?
+                .ok_or_else(|| WalError::NotFound(format!("log record at {}", lsn)))?;
+
+            if record.header.log_type == LogType::TxBegin {
+                break;
+            }
+            if let Some(LogPayload::Compensation(clr)) = record.payload() {
+                lsn = clr.undo_next_lsn();
+                continue;
+            }
+            if record.is_undoable() {
+                undo(lsn, &record).map_err(WalError::InvalidState)?;
+                undone += 1;
+            }
+
+            lsn = record.header.prev_lsn;
+        }
+
+        Ok(undone)
+    }
+
+    /// Whether a page last stamped with `page_lsn` must be logged as a full
+    /// image before its next change
+    ///
+    /// Recovery starts at the last checkpoint, so the first change to a page
+    /// after it needs a complete copy in case the page is torn on disk.
+    pub fn needs_full_page_image(&self, page_lsn: LSN) -> bool {
+        let checkpoint_lsn = self.last_checkpoint_lsn();
+        self.enabled && checkpoint_lsn.is_valid() && page_lsn < checkpoint_lsn
+    }
+
     /// Flush dirty pages and write redo logs
     pub fn flush_dirty_pages(
         &self,
@@ -222,7 +411,7 @@ impl WalManager {
 
         for page_id in dirty_pages {
             if let Some(page_data) = get_page_data(page_id) {
-                self.write_page_redo(0, page_id, 0, &page_data);
+                self.write_page_redo(0, page_id, 0, &page_data)?;
             }
         }
 
@@ -235,51 +424,67 @@ impl WalManager {
         self.buffer.flush().map_err(|e| WalError::IoError(e))
     }
 
+    /// End of the durable part of the log
+    ///
+    /// A page whose LSN is below it may be written to disk: the record
+    /// that last changed it has been flushed (the WAL rule).
+    pub fn flushed_lsn(&self) -> LSN {
+        self.buffer.flushed_lsn()
+    }
+
     /// Get current LSN
     pub fn current_lsn(&self) -> LSN {
         self.file_mgr.current_lsn()
     }
 
+    /// LSN of the last checkpoint; invalid before the first one
+    pub fn last_checkpoint_lsn(&self) -> LSN {
+        self.checkpoint_mgr.read().last_checkpoint_lsn()
+    }
+
     /// Perform checkpoint with provided dirty pages
+    ///
+    /// Log files that neither recovery from this checkpoint nor the
+    /// rollback of a running transaction can need are archived. If that
+    /// fails the checkpoint itself is already taken; the error is returned
+    /// and the files are tried again at the next checkpoint.
     pub fn checkpoint(&self, dirty_pages: Vec<PageId>) -> WalResult<LSN> {
-        let active_transactions: Vec<u64> = self.tx_lsns.read().keys().cloned().collect();
+        let tx_lsns = self.tx_lsns.read();
+        let active_transactions: Vec<(u64, LSN)> = tx_lsns
+            .iter()
+            .map(|(tx_id, t)| (*tx_id, t.prev_lsn))
+            .collect();
+        let oldest_tx_lsn = tx_lsns.values().map(|t| t.first_lsn).min();
+        drop(tx_lsns);
 
         let lsn = self.file_mgr.current_lsn();
+        let keep_lsn = oldest_tx_lsn.map_or(lsn, |first| first.min(lsn));
 
         let mut mgr = self.checkpoint_mgr.write();
         mgr.checkpoint(lsn, dirty_pages, active_transactions)
             .map_err(|e| WalError::IoError(e.to_string()))?;
 
-        let _ = self.file_mgr.cleanup_old_logs(lsn);
+        self.file_mgr
+            .archive_old_logs(keep_lsn)
+            .map_err(|e| WalError::IoError(format!("archiving old log files: {}", e)))?;
 
         Ok(lsn)
     }
 
-    /// Recover from crash
-    pub fn recover(&self) -> RecoveryResult {
+    /// Recover from crash: analysis, then redo of the records from the last
+    /// checkpoint through `redo`
+    ///
+    /// The transactions left in `active_transactions` of the result are
+    /// for the caller to undo with [`WalManager::rollback`].
+    pub fn recover<F>(&self, redo: F) -> WalResult<RecoveryResult>
+    where
+        F: FnMut(LSN, &LogRecord) -> Result<(), String>,
+    {
         if let Some(ref mgr) = *self.recovery_mgr.read() {
-            mgr.recover(|_, _| Ok(()))
+            mgr.recover(redo)
         } else {
-            RecoveryResult {
-                checkpoint_lsn: LSN::invalid(),
-                replayed_records: 0,
-                rolled_back_transactions: Vec::new(),
-            }
-        }
-    }
-
-    /// Start checkpoint timer
-    fn start_checkpoint_timer(&self) {
-        let interval = self.config.checkpoint_interval_sec;
-        if interval == 0 {
-            return;
+            Ok(RecoveryResult::empty())
         }
-
-        let buffer = Arc::clone(&self.buffer);
-        thread::spawn(move || loop {
-            thread::sleep(Duration::from_secs(interval));
-            let _ = buffer.flush();
-        });
     }
 
     /// Enable/disable WAL
@@ -5,8 +5,11 @@ use crate::vfs::VfsInterface;
 use crate::wal::checkpoint::CheckpointManager;
 use crate::wal::config::WalConfig;
 use crate::wal::log_file::LogFileManager;
-use crate::wal::log_record::{LogRecord, LogType, PageRedoPayload};
+use crate::wal::log_reader::LogReader;
+use crate::wal::log_record::{LogRecord, LogType};
 use crate::wal::lsn::LSN;
+use crate::wal::{WalError, WalResult};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 /// Recovery result
@@ -14,7 +17,23 @@ use std::sync::Arc;
 pub struct RecoveryResult {
     pub checkpoint_lsn: LSN,
     pub replayed_records: usize,
-    pub rolled_back_transactions: Vec<u64>,
+    /// Transactions with neither a commit nor an abort record, each with the
+    /// LSN of its last record; these still have to be undone
+    pub active_transactions: Vec<(u64, LSN)>,
+    /// Pages the log may hold newer changes for, with the LSN of the first
+    /// such change (the page's rec_lsn)
+    pub dirty_pages: HashMap<PageId, LSN>,
+}
+
+impl RecoveryResult {
+    pub(crate) fn empty() -> Self {
+        Self {
+            checkpoint_lsn: LSN::invalid(),
+            replayed_records: 0,
+            active_transactions: Vec::new(),
+            dirty_pages: HashMap::new(),
+        }
+    }
 }
 
 /// Recovery manager
@@ -40,10 +59,20 @@ impl RecoveryManager {
         }
     }
 
-    /// Perform recovery with page writer callback
-    pub fn recover<F>(&self, write_page: F) -> RecoveryResult
+    /// Run the analysis and redo passes from the last checkpoint (or the
+    /// start of the log)
+    ///
+    /// Analysis rebuilds the transaction table and the dirty page table.
+    /// Redo then hands `redo` every record, in log order, that changes a
+    /// page in the dirty page table at or after its rec_lsn, plus every
+    /// record not tied to a page. Undoing the transactions left in
+    /// `active_transactions` is up to the caller.
+    ///
+    /// Both passes stop at the end of the log; a damaged record in the
+    /// middle of it fails recovery.
+    pub fn recover<F>(&self, mut redo: F) -> WalResult<RecoveryResult>
     where
-        F: Fn(PageId, &[u8]) -> Result<(), String>,
+        F: FnMut(LSN, &LogRecord) -> Result<(), String>,
     {
         let checkpoint = self.checkpoint_mgr.load_latest();
 
@@ -51,63 +80,97 @@ impl RecoveryManager {
             .as_ref()
             .map(|c| c.begin_lsn)
             .unwrap_or(LSN::invalid());
-
-        let mut replayed_records = 0;
-        let mut rolled_back = Vec::new();
-
+        let start_lsn = if checkpoint_lsn.is_valid() {
+            checkpoint_lsn
+        } else {
+            self.file_mgr.first_lsn()
+        };
+
+        let mut transactions: HashMap<u64, LSN> = HashMap::new();
+        let mut dirty_pages: HashMap<PageId, LSN> = HashMap::new();
         if let Some(cp) = checkpoint {
-            replayed_records = self.replay_from_lsn(cp.begin_lsn, &write_page);
-            rolled_back = cp.active_transactions;
+            transactions.extend(cp.active_transactions);
+            for page_id in cp.dirty_pages {
+                dirty_pages.insert(page_id, checkpoint_lsn);
+            }
         }
 
-        RecoveryResult {
+        self.analyze(start_lsn, &mut transactions, &mut dirty_pages)?;
+        let replayed_records = self.replay_from_lsn(start_lsn, &dirty_pages, &mut redo)?;
+
+        let mut active_transactions: Vec<(u64, LSN)> = transactions.into_iter().collect();
+        active_transactions.sort();
+
+        Ok(RecoveryResult {
             checkpoint_lsn,
             replayed_records,
-            rolled_back_transactions: rolled_back,
+            active_transactions,
+            dirty_pages,
+        })
+    }
+
+    /// Analysis pass: tracks the last record of every unfinished
+    /// transaction and the first record that touched every page
+    fn analyze(
+        &self,
+        lsn: LSN,
+        transactions: &mut HashMap<u64, LSN>,
+        dirty_pages: &mut HashMap<PageId, LSN>,
+    ) -> WalResult<()> {
+        for item in LogReader::new(&self.file_mgr, lsn) {
+            let (current_lsn, record) = item?;
+            let tx_id = record.header.tx_id;
+            match record.header.log_type {
+                LogType::TxCommit | LogType::TxAbort => {
+                    transactions.remove(&tx_id);
+                }
+                _ if tx_id != 0 => {
+                    transactions.insert(tx_id, current_lsn);
+                }
+                _ => {}
+            }
+
+            if let Some(page_id) = record.payload().and_then(|p| p.page_id()) {
+                dirty_pages.entry(page_id).or_insert(current_lsn);
+            }
         }
+
+        Ok(())
     }
 
-    /// Replay log records from a specific LSN
-    fn replay_from_lsn<F>(&self, lsn: LSN, write_page: &F) -> usize
+    /// Redo pass: replays log records from a specific LSN
+    ///
+    /// A record that fails to redo stops recovery, since the pages after
+    /// it would be replayed on top of a missing change.
+    fn replay_from_lsn<F>(
+        &self,
+        lsn: LSN,
+        dirty_pages: &HashMap<PageId, LSN>,
+        redo: &mut F,
+    ) -> WalResult<usize>
     where
-        F: Fn(PageId, &[u8]) -> Result<(), String>,
+        F: FnMut(LSN, &LogRecord) -> Result<(), String>,
     {
         let mut count = 0;
-        let mut current_lsn = lsn;
-
-        loop {
-            let data = match self.file_mgr.read_from(current_lsn) {
-                Ok(d) => d,
-                Err(_) => break,
-            };
 
-            if data.is_empty() {
-                break;
-            }
-
-            let record = match LogRecord::deserialize(&data) {
-                Some(r) => r,
-                None => break,
+        for item in LogReader::new(&self.file_mgr, lsn) {
+            let (current_lsn, record) = item?;
+            let needs_redo = match record.payload().and_then(|p| p.page_id()) {
+                Some(page_id) => dirty_pages
+                    .get(&page_id)
+                    .is_some_and(|rec_lsn| current_lsn >= *rec_lsn),
+                None => true,
             };
 
-            match record.header.log_type {
-                LogType::PageRedo => {
-                    if let Ok(payload) = serde_json::from_slice::<PageRedoPayload>(&record.payload)
-                    {
-                        if write_page(payload.page_id, &payload.data).is_ok() {
-                            count += 1;
-                        }
-                    }
-                }
-                LogType::TxCommit => {
-                    count += 1;
-                }
-                _ => {}
+            if needs_redo {
+                redo(current_lsn, &record).map_err(|e| WalError::Corrupted {
+                    lsn: current_lsn,
+                    reason: format!("redo failed: {}", e),
+                })?;
+                count += 1;
             }
-
-            current_lsn = current_lsn + data.len() as u64;
         }
 
-        count
+        Ok(count)
     }
 }